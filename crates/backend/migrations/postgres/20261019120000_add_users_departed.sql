@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN departed BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,15 @@
+CREATE TABLE cabinets (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	name VARCHAR NOT NULL UNIQUE,
+	description VARCHAR
+);
+
+CREATE TABLE slots (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	cabinet_id BIGINT NOT NULL,
+	number INTEGER NOT NULL,
+	UNIQUE(cabinet_id, number),
+	FOREIGN KEY(cabinet_id) REFERENCES cabinets (id) ON DELETE CASCADE
+);
+
+ALTER TABLE keys ADD COLUMN slot_id BIGINT UNIQUE REFERENCES slots (id) ON DELETE SET NULL;
@@ -0,0 +1,24 @@
+ALTER TABLE keys
+    ADD COLUMN deposit_amount BIGINT,
+    ADD COLUMN deposit_currency VARCHAR(3),
+    ADD CHECK ((deposit_amount IS NULL) = (deposit_currency IS NULL));
+
+ALTER TABLE assignments
+    ADD COLUMN deposit_collected BIGINT,
+    ADD COLUMN deposit_refunded BIGINT,
+    ADD COLUMN deposit_currency VARCHAR(3),
+    ADD CHECK (deposit_collected IS NULL OR deposit_currency IS NOT NULL),
+    ADD CHECK (deposit_refunded IS NULL OR deposit_currency IS NOT NULL);
+
+CREATE TABLE charges (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	"user" VARCHAR NOT NULL,
+	key VARCHAR,
+	amount BIGINT NOT NULL,
+	currency VARCHAR(3) NOT NULL,
+	date DATE NOT NULL,
+	description VARCHAR,
+	paid BOOLEAN NOT NULL DEFAULT false,
+	FOREIGN KEY(key) REFERENCES keys (name) ON DELETE SET NULL,
+	FOREIGN KEY("user") REFERENCES users (username) ON DELETE CASCADE
+);
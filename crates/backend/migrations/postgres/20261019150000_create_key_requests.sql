@@ -0,0 +1,25 @@
+CREATE TYPE request_status AS ENUM ('pending', 'approved', 'denied', 'fulfilled');
+
+CREATE TABLE key_requests (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	"user" VARCHAR NOT NULL,
+	justification VARCHAR NOT NULL,
+	date_from DATE NOT NULL,
+	date_to DATE,
+	approver VARCHAR,
+	status request_status NOT NULL DEFAULT 'pending',
+	decided_by VARCHAR,
+	decision_note VARCHAR,
+	CHECK (date_to IS NULL OR date_to >= date_from),
+	FOREIGN KEY("user") REFERENCES users (username) ON DELETE CASCADE,
+	FOREIGN KEY(approver) REFERENCES users (username) ON DELETE SET NULL,
+	FOREIGN KEY(decided_by) REFERENCES users (username) ON DELETE SET NULL
+);
+
+CREATE TABLE key_request_items (
+	request_id BIGINT NOT NULL,
+	key VARCHAR NOT NULL,
+	PRIMARY KEY (request_id, key),
+	FOREIGN KEY(request_id) REFERENCES key_requests (id) ON DELETE CASCADE,
+	FOREIGN KEY(key) REFERENCES keys (name) ON DELETE CASCADE
+);
@@ -0,0 +1,8 @@
+ALTER TABLE keys
+    ADD COLUMN restricted BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE assignments
+    ADD COLUMN pending BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN created_by VARCHAR REFERENCES users (username) ON DELETE SET NULL,
+    ADD COLUMN confirmed_by VARCHAR REFERENCES users (username) ON DELETE SET NULL,
+    ADD CHECK (NOT pending OR confirmed_by IS NULL);
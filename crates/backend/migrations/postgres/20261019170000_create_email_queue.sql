@@ -0,0 +1,25 @@
+CREATE TYPE notification_kind AS ENUM ('key_assigned', 'key_due', 'key_overdue', 'admin_summary');
+
+ALTER TABLE assignments
+    ADD COLUMN date_due DATE;
+
+CREATE TABLE email_queue (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	kind notification_kind NOT NULL,
+	recipient VARCHAR NOT NULL,
+	subject VARCHAR NOT NULL,
+	body VARCHAR NOT NULL,
+	dedupe_key VARCHAR UNIQUE,
+	attempts INT NOT NULL DEFAULT 0,
+	last_error VARCHAR,
+	next_attempt TIMESTAMPTZ NOT NULL DEFAULT now(),
+	sent_at TIMESTAMPTZ,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE notification_opt_outs (
+	"user" VARCHAR NOT NULL,
+	kind notification_kind NOT NULL,
+	PRIMARY KEY ("user", kind),
+	FOREIGN KEY("user") REFERENCES users (username) ON DELETE CASCADE ON UPDATE CASCADE
+);
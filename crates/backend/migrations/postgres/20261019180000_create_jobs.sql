@@ -0,0 +1,16 @@
+CREATE TABLE jobs (
+	name VARCHAR PRIMARY KEY,
+	schedule VARCHAR NOT NULL,
+	next_run TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE job_runs (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	job VARCHAR NOT NULL,
+	scheduled_for TIMESTAMPTZ NOT NULL,
+	started_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	finished_at TIMESTAMPTZ,
+	error VARCHAR,
+	UNIQUE (job, scheduled_for),
+	FOREIGN KEY(job) REFERENCES jobs (name) ON DELETE CASCADE
+);
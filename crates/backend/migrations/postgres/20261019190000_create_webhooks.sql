@@ -0,0 +1,27 @@
+ALTER TABLE assignments
+    ADD COLUMN lost BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE webhooks (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	url VARCHAR NOT NULL,
+	events VARCHAR[] NOT NULL DEFAULT '{}',
+	secret VARCHAR NOT NULL,
+	active BOOLEAN NOT NULL DEFAULT true
+);
+
+CREATE TABLE webhook_deliveries (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	webhook_id BIGINT NOT NULL,
+	event VARCHAR NOT NULL,
+	payload VARCHAR NOT NULL,
+	attempts INT NOT NULL DEFAULT 0,
+	status_code INT,
+	last_error VARCHAR,
+	next_attempt TIMESTAMPTZ NOT NULL DEFAULT now(),
+	delivered_at TIMESTAMPTZ,
+	created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	FOREIGN KEY(webhook_id) REFERENCES webhooks (id) ON DELETE CASCADE
+);
+
+CREATE INDEX webhook_deliveries_pending_idx ON webhook_deliveries (next_attempt)
+	WHERE delivered_at IS NULL;
@@ -0,0 +1,32 @@
+CREATE TYPE audit_result AS ENUM ('verified', 'missing', 'wrong_holder');
+
+CREATE TABLE audits (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	name VARCHAR NOT NULL,
+	started_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	started_by VARCHAR,
+	closed_at TIMESTAMPTZ,
+	closed_by VARCHAR,
+	FOREIGN KEY(started_by) REFERENCES users (username) ON DELETE SET NULL,
+	FOREIGN KEY(closed_by) REFERENCES users (username) ON DELETE SET NULL
+);
+
+-- Items are a snapshot of the open assignments when the audit started, so the holder and key
+-- are copied rather than referenced.
+CREATE TABLE audit_items (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	audit_id BIGINT NOT NULL,
+	assignment_id BIGINT,
+	"user" VARCHAR NOT NULL,
+	key VARCHAR NOT NULL,
+	result audit_result,
+	actual_holder VARCHAR,
+	note VARCHAR,
+	checked_by VARCHAR,
+	checked_at TIMESTAMPTZ,
+	lost_reported BOOLEAN NOT NULL DEFAULT false,
+	UNIQUE (audit_id, assignment_id),
+	FOREIGN KEY(audit_id) REFERENCES audits (id) ON DELETE CASCADE,
+	FOREIGN KEY(assignment_id) REFERENCES assignments (id) ON DELETE SET NULL,
+	FOREIGN KEY(checked_by) REFERENCES users (username) ON DELETE SET NULL
+);
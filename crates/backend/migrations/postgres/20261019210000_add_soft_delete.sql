@@ -0,0 +1,7 @@
+ALTER TABLE keys ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE assignments ADD COLUMN deleted_at TIMESTAMPTZ;
+
+-- Assignments in the trash shouldn't block assigning the same key to the same user again
+ALTER TABLE assignments DROP CONSTRAINT assignments_user_key_key;
+CREATE UNIQUE INDEX assignments_user_key_key ON assignments ("user", key) WHERE deleted_at IS NULL;
@@ -0,0 +1,55 @@
+-- Keys get a surrogate id so their names can be changed. Assignments reference keys and users
+-- by id, and the remaining references by name follow renames.
+
+ALTER TABLE assignments DROP CONSTRAINT assignments_key_fkey;
+ALTER TABLE charges DROP CONSTRAINT charges_key_fkey;
+ALTER TABLE key_request_items DROP CONSTRAINT key_request_items_key_fkey;
+
+ALTER TABLE keys DROP CONSTRAINT keys_pkey;
+ALTER TABLE keys ADD COLUMN id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY;
+ALTER TABLE keys ADD CONSTRAINT keys_name_key UNIQUE (name);
+
+ALTER TABLE assignments
+    ADD COLUMN user_id BIGINT REFERENCES users (id) ON DELETE CASCADE,
+    ADD COLUMN key_id BIGINT REFERENCES keys (id) ON DELETE CASCADE;
+
+UPDATE assignments a SET
+    user_id = (SELECT id FROM users u WHERE u.username = a."user"),
+    key_id = (SELECT id FROM keys k WHERE k.name = a.key);
+
+ALTER TABLE assignments
+    ALTER COLUMN user_id SET NOT NULL,
+    ALTER COLUMN key_id SET NOT NULL;
+
+DROP INDEX assignments_user_key_key;
+ALTER TABLE assignments DROP COLUMN "user", DROP COLUMN key;
+CREATE UNIQUE INDEX assignments_user_key_key ON assignments (user_id, key_id) WHERE deleted_at IS NULL;
+
+ALTER TABLE charges
+    ADD CONSTRAINT charges_key_fkey FOREIGN KEY (key) REFERENCES keys (name) ON UPDATE CASCADE ON DELETE SET NULL;
+ALTER TABLE key_request_items
+    ADD CONSTRAINT key_request_items_key_fkey FOREIGN KEY (key) REFERENCES keys (name) ON UPDATE CASCADE ON DELETE CASCADE;
+
+ALTER TABLE assignments
+    DROP CONSTRAINT assignments_created_by_fkey,
+    DROP CONSTRAINT assignments_confirmed_by_fkey,
+    ADD CONSTRAINT assignments_created_by_fkey FOREIGN KEY (created_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL,
+    ADD CONSTRAINT assignments_confirmed_by_fkey FOREIGN KEY (confirmed_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL;
+ALTER TABLE charges
+    DROP CONSTRAINT charges_user_fkey,
+    ADD CONSTRAINT charges_user_fkey FOREIGN KEY ("user") REFERENCES users (username) ON UPDATE CASCADE ON DELETE CASCADE;
+ALTER TABLE key_requests
+    DROP CONSTRAINT key_requests_user_fkey,
+    DROP CONSTRAINT key_requests_approver_fkey,
+    DROP CONSTRAINT key_requests_decided_by_fkey,
+    ADD CONSTRAINT key_requests_user_fkey FOREIGN KEY ("user") REFERENCES users (username) ON UPDATE CASCADE ON DELETE CASCADE,
+    ADD CONSTRAINT key_requests_approver_fkey FOREIGN KEY (approver) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL,
+    ADD CONSTRAINT key_requests_decided_by_fkey FOREIGN KEY (decided_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL;
+ALTER TABLE audits
+    DROP CONSTRAINT audits_started_by_fkey,
+    DROP CONSTRAINT audits_closed_by_fkey,
+    ADD CONSTRAINT audits_started_by_fkey FOREIGN KEY (started_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL,
+    ADD CONSTRAINT audits_closed_by_fkey FOREIGN KEY (closed_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL;
+ALTER TABLE audit_items
+    DROP CONSTRAINT audit_items_checked_by_fkey,
+    ADD CONSTRAINT audit_items_checked_by_fkey FOREIGN KEY (checked_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL;
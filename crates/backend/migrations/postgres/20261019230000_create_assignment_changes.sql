@@ -0,0 +1,14 @@
+CREATE TABLE assignment_changes (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	assignment_id BIGINT NOT NULL,
+	changed_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+	changed_by VARCHAR,
+	action VARCHAR NOT NULL, -- created, updated, confirmed or lost
+	field VARCHAR, -- The column changed by an update
+	old_value VARCHAR,
+	new_value VARCHAR,
+	FOREIGN KEY(assignment_id) REFERENCES assignments (id) ON DELETE CASCADE,
+	FOREIGN KEY(changed_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL
+);
+
+CREATE INDEX assignment_changes_assignment_id_idx ON assignment_changes (assignment_id);
@@ -0,0 +1,56 @@
+-- Organisations let one deployment serve several clients. Keys, cabinets, audits, key
+-- requests, charges and webhooks belong to an org, and assignments to their key's org. Users
+-- can belong to several orgs, so membership holds their role and whether they are in that
+-- org's trash. Everything which already exists goes into the first org.
+
+CREATE TABLE orgs (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	name VARCHAR NOT NULL UNIQUE
+);
+
+INSERT INTO orgs (name) VALUES ('Default');
+
+CREATE TABLE org_members (
+	org_id BIGINT NOT NULL REFERENCES orgs (id) ON DELETE CASCADE,
+	user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	admin BOOLEAN NOT NULL DEFAULT false,
+	deleted_at TIMESTAMPTZ,
+	PRIMARY KEY (org_id, user_id)
+);
+
+INSERT INTO org_members (org_id, user_id, admin, deleted_at)
+SELECT 1, id, admin, deleted_at FROM users;
+
+ALTER TABLE users DROP COLUMN admin, DROP COLUMN deleted_at;
+
+-- Names only need to be unique within an org
+ALTER TABLE keys ADD COLUMN org_id BIGINT NOT NULL DEFAULT 1 REFERENCES orgs (id) ON DELETE CASCADE;
+ALTER TABLE keys ALTER COLUMN org_id DROP DEFAULT;
+ALTER TABLE cabinets ADD COLUMN org_id BIGINT NOT NULL DEFAULT 1 REFERENCES orgs (id) ON DELETE CASCADE;
+ALTER TABLE cabinets ALTER COLUMN org_id DROP DEFAULT;
+
+-- Key names are no longer unique on their own, so the references by name become references
+-- by id
+ALTER TABLE charges ADD COLUMN key_id BIGINT REFERENCES keys (id) ON DELETE SET NULL;
+UPDATE charges c SET key_id = (SELECT id FROM keys k WHERE k.name = c.key);
+ALTER TABLE charges DROP COLUMN key;
+
+ALTER TABLE key_request_items ADD COLUMN key_id BIGINT REFERENCES keys (id) ON DELETE CASCADE;
+UPDATE key_request_items i SET key_id = (SELECT id FROM keys k WHERE k.name = i.key);
+ALTER TABLE key_request_items DROP COLUMN key;
+ALTER TABLE key_request_items ALTER COLUMN key_id SET NOT NULL;
+ALTER TABLE key_request_items ADD PRIMARY KEY (request_id, key_id);
+
+ALTER TABLE keys DROP CONSTRAINT keys_name_key;
+ALTER TABLE keys ADD CONSTRAINT keys_name_key UNIQUE (org_id, name);
+ALTER TABLE cabinets DROP CONSTRAINT cabinets_name_key;
+ALTER TABLE cabinets ADD CONSTRAINT cabinets_name_key UNIQUE (org_id, name);
+
+ALTER TABLE charges ADD COLUMN org_id BIGINT NOT NULL DEFAULT 1 REFERENCES orgs (id) ON DELETE CASCADE;
+ALTER TABLE charges ALTER COLUMN org_id DROP DEFAULT;
+ALTER TABLE key_requests ADD COLUMN org_id BIGINT NOT NULL DEFAULT 1 REFERENCES orgs (id) ON DELETE CASCADE;
+ALTER TABLE key_requests ALTER COLUMN org_id DROP DEFAULT;
+ALTER TABLE audits ADD COLUMN org_id BIGINT NOT NULL DEFAULT 1 REFERENCES orgs (id) ON DELETE CASCADE;
+ALTER TABLE audits ALTER COLUMN org_id DROP DEFAULT;
+ALTER TABLE webhooks ADD COLUMN org_id BIGINT NOT NULL DEFAULT 1 REFERENCES orgs (id) ON DELETE CASCADE;
+ALTER TABLE webhooks ALTER COLUMN org_id DROP DEFAULT;
@@ -0,0 +1,24 @@
+-- Groups are an org's departments and teams. Keys shared by a team are assigned to its group
+-- rather than to one of its members, so an assignment holds exactly one of user_id and
+-- group_id. Groups holding keys can't be deleted, so assignment history isn't lost with them.
+
+CREATE TABLE user_groups (
+	id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+	org_id BIGINT NOT NULL REFERENCES orgs (id) ON DELETE CASCADE,
+	name VARCHAR NOT NULL,
+	description VARCHAR,
+	CONSTRAINT user_groups_name_key UNIQUE (org_id, name)
+);
+
+CREATE TABLE user_group_members (
+	group_id BIGINT NOT NULL REFERENCES user_groups (id) ON DELETE CASCADE,
+	user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	PRIMARY KEY (group_id, user_id)
+);
+
+ALTER TABLE assignments
+	ALTER COLUMN user_id DROP NOT NULL,
+	ADD COLUMN group_id BIGINT REFERENCES user_groups (id),
+	ADD CONSTRAINT assignments_holder_check CHECK ((user_id IS NULL) <> (group_id IS NULL));
+
+CREATE UNIQUE INDEX assignments_group_key_key ON assignments (group_id, key_id) WHERE deleted_at IS NULL;
@@ -0,0 +1,217 @@
+-- The SQLite schema starts out at the same version as the last Postgres migration, so that
+-- backups can be moved between the two. New migrations go in both directories with the same
+-- version.
+--
+-- Timestamps are stored as RFC 3339 text in UTC, which sorts the same as the times themselves.
+-- Enums are text checked against their values, and arrays are JSON text.
+
+CREATE TABLE users (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	username VARCHAR(64) NOT NULL UNIQUE,
+	display_name VARCHAR(120) UNIQUE,
+	email VARCHAR(120) UNIQUE,
+	password_hash VARCHAR(128),
+	can_login BOOLEAN NOT NULL DEFAULT false,
+	admin BOOLEAN NOT NULL DEFAULT false,
+	departed BOOLEAN NOT NULL DEFAULT false,
+	deleted_at TIMESTAMP
+);
+
+CREATE TABLE cabinets (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	name VARCHAR NOT NULL UNIQUE,
+	description VARCHAR
+);
+
+CREATE TABLE slots (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	cabinet_id BIGINT NOT NULL,
+	number INTEGER NOT NULL,
+	UNIQUE(cabinet_id, number),
+	FOREIGN KEY(cabinet_id) REFERENCES cabinets (id) ON DELETE CASCADE
+);
+
+CREATE TABLE keys (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	name VARCHAR NOT NULL UNIQUE,
+	description VARCHAR,
+	active BOOLEAN NOT NULL DEFAULT true,
+	slot_id BIGINT UNIQUE REFERENCES slots (id) ON DELETE SET NULL,
+	deposit_amount BIGINT,
+	deposit_currency VARCHAR(3),
+	restricted BOOLEAN NOT NULL DEFAULT false,
+	deleted_at TIMESTAMP,
+	CHECK ((deposit_amount IS NULL) = (deposit_currency IS NULL))
+);
+
+CREATE TABLE assignments (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	key_id BIGINT NOT NULL REFERENCES keys (id) ON DELETE CASCADE,
+	date_out DATE NOT NULL,
+	date_in DATE,
+	date_due DATE,
+	deposit_collected BIGINT,
+	deposit_refunded BIGINT,
+	deposit_currency VARCHAR(3),
+	pending BOOLEAN NOT NULL DEFAULT false,
+	created_by VARCHAR REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL,
+	confirmed_by VARCHAR REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL,
+	lost BOOLEAN NOT NULL DEFAULT false,
+	deleted_at TIMESTAMP,
+	CHECK (deposit_collected IS NULL OR deposit_currency IS NOT NULL),
+	CHECK (deposit_refunded IS NULL OR deposit_currency IS NOT NULL),
+	CHECK (NOT pending OR confirmed_by IS NULL)
+);
+
+-- Assignments in the trash shouldn't block assigning the same key to the same user again
+CREATE UNIQUE INDEX assignments_user_key_key ON assignments (user_id, key_id) WHERE deleted_at IS NULL;
+
+CREATE TABLE assignment_changes (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	assignment_id BIGINT NOT NULL,
+	changed_at TIMESTAMP NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+	changed_by VARCHAR,
+	action VARCHAR NOT NULL, -- created, updated, confirmed or lost
+	field VARCHAR, -- The column changed by an update
+	old_value VARCHAR,
+	new_value VARCHAR,
+	FOREIGN KEY(assignment_id) REFERENCES assignments (id) ON DELETE CASCADE,
+	FOREIGN KEY(changed_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL
+);
+
+CREATE INDEX assignment_changes_assignment_id_idx ON assignment_changes (assignment_id);
+
+CREATE TABLE charges (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	"user" VARCHAR NOT NULL,
+	key VARCHAR,
+	amount BIGINT NOT NULL,
+	currency VARCHAR(3) NOT NULL,
+	date DATE NOT NULL,
+	description VARCHAR,
+	paid BOOLEAN NOT NULL DEFAULT false,
+	FOREIGN KEY(key) REFERENCES keys (name) ON UPDATE CASCADE ON DELETE SET NULL,
+	FOREIGN KEY("user") REFERENCES users (username) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE key_requests (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	"user" VARCHAR NOT NULL,
+	justification VARCHAR NOT NULL,
+	date_from DATE NOT NULL,
+	date_to DATE,
+	approver VARCHAR,
+	status VARCHAR NOT NULL DEFAULT 'pending'
+		CHECK (status IN ('pending', 'approved', 'denied', 'fulfilled')),
+	decided_by VARCHAR,
+	decision_note VARCHAR,
+	CHECK (date_to IS NULL OR date_to >= date_from),
+	FOREIGN KEY("user") REFERENCES users (username) ON UPDATE CASCADE ON DELETE CASCADE,
+	FOREIGN KEY(approver) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL,
+	FOREIGN KEY(decided_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL
+);
+
+CREATE TABLE key_request_items (
+	request_id BIGINT NOT NULL,
+	key VARCHAR NOT NULL,
+	PRIMARY KEY (request_id, key),
+	FOREIGN KEY(request_id) REFERENCES key_requests (id) ON DELETE CASCADE,
+	FOREIGN KEY(key) REFERENCES keys (name) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE email_queue (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	kind VARCHAR NOT NULL
+		CHECK (kind IN ('key_assigned', 'key_due', 'key_overdue', 'admin_summary')),
+	recipient VARCHAR NOT NULL,
+	subject VARCHAR NOT NULL,
+	body VARCHAR NOT NULL,
+	dedupe_key VARCHAR UNIQUE,
+	attempts INTEGER NOT NULL DEFAULT 0,
+	last_error VARCHAR,
+	next_attempt TIMESTAMP NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+	sent_at TIMESTAMP,
+	created_at TIMESTAMP NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
+
+CREATE TABLE notification_opt_outs (
+	"user" VARCHAR NOT NULL,
+	kind VARCHAR NOT NULL
+		CHECK (kind IN ('key_assigned', 'key_due', 'key_overdue', 'admin_summary')),
+	PRIMARY KEY ("user", kind),
+	FOREIGN KEY("user") REFERENCES users (username) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE TABLE jobs (
+	name VARCHAR PRIMARY KEY,
+	schedule VARCHAR NOT NULL,
+	next_run TIMESTAMP NOT NULL
+);
+
+CREATE TABLE job_runs (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	job VARCHAR NOT NULL,
+	scheduled_for TIMESTAMP NOT NULL,
+	started_at TIMESTAMP NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+	finished_at TIMESTAMP,
+	error VARCHAR,
+	UNIQUE (job, scheduled_for),
+	FOREIGN KEY(job) REFERENCES jobs (name) ON DELETE CASCADE
+);
+
+CREATE TABLE webhooks (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	url VARCHAR NOT NULL,
+	events TEXT NOT NULL DEFAULT '[]',
+	secret VARCHAR NOT NULL,
+	active BOOLEAN NOT NULL DEFAULT true
+);
+
+CREATE TABLE webhook_deliveries (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	webhook_id BIGINT NOT NULL,
+	event VARCHAR NOT NULL,
+	payload VARCHAR NOT NULL,
+	attempts INTEGER NOT NULL DEFAULT 0,
+	status_code INTEGER,
+	last_error VARCHAR,
+	next_attempt TIMESTAMP NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+	delivered_at TIMESTAMP,
+	created_at TIMESTAMP NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+	FOREIGN KEY(webhook_id) REFERENCES webhooks (id) ON DELETE CASCADE
+);
+
+CREATE INDEX webhook_deliveries_pending_idx ON webhook_deliveries (next_attempt)
+	WHERE delivered_at IS NULL;
+
+CREATE TABLE audits (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	name VARCHAR NOT NULL,
+	started_at TIMESTAMP NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+	started_by VARCHAR,
+	closed_at TIMESTAMP,
+	closed_by VARCHAR,
+	FOREIGN KEY(started_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL,
+	FOREIGN KEY(closed_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL
+);
+
+-- Items are a snapshot of the open assignments when the audit started, so the holder and key
+-- are copied rather than referenced.
+CREATE TABLE audit_items (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	audit_id BIGINT NOT NULL,
+	assignment_id BIGINT,
+	"user" VARCHAR NOT NULL,
+	key VARCHAR NOT NULL,
+	result VARCHAR CHECK (result IN ('verified', 'missing', 'wrong_holder')),
+	actual_holder VARCHAR,
+	note VARCHAR,
+	checked_by VARCHAR,
+	checked_at TIMESTAMP,
+	lost_reported BOOLEAN NOT NULL DEFAULT false,
+	UNIQUE (audit_id, assignment_id),
+	FOREIGN KEY(audit_id) REFERENCES audits (id) ON DELETE CASCADE,
+	FOREIGN KEY(assignment_id) REFERENCES assignments (id) ON DELETE SET NULL,
+	FOREIGN KEY(checked_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL
+);
@@ -0,0 +1,248 @@
+-- See the Postgres migration of the same version. SQLite can't change a table's constraints
+-- or add a column referencing another table with a default, so the tables which change are
+-- rebuilt. Renaming a table makes the tables referring to it refer to the renamed one, which
+-- dropping would then cascade to, so everything referring to a rebuilt table is rebuilt too.
+-- The old tables are dropped once their rows are copied, children first.
+
+CREATE TABLE orgs (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	name VARCHAR NOT NULL UNIQUE
+);
+
+INSERT INTO orgs (name) VALUES ('Default');
+
+CREATE TABLE org_members (
+	org_id BIGINT NOT NULL REFERENCES orgs (id) ON DELETE CASCADE,
+	user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	admin BOOLEAN NOT NULL DEFAULT false,
+	deleted_at TIMESTAMP,
+	PRIMARY KEY (org_id, user_id)
+);
+
+INSERT INTO org_members (org_id, user_id, admin, deleted_at)
+SELECT 1, id, admin, deleted_at FROM users;
+
+ALTER TABLE users DROP COLUMN admin;
+ALTER TABLE users DROP COLUMN deleted_at;
+
+ALTER TABLE cabinets RENAME TO old_cabinets;
+ALTER TABLE slots RENAME TO old_slots;
+ALTER TABLE keys RENAME TO old_keys;
+ALTER TABLE assignments RENAME TO old_assignments;
+ALTER TABLE assignment_changes RENAME TO old_assignment_changes;
+ALTER TABLE charges RENAME TO old_charges;
+ALTER TABLE key_requests RENAME TO old_key_requests;
+ALTER TABLE key_request_items RENAME TO old_key_request_items;
+ALTER TABLE webhooks RENAME TO old_webhooks;
+ALTER TABLE webhook_deliveries RENAME TO old_webhook_deliveries;
+ALTER TABLE audits RENAME TO old_audits;
+ALTER TABLE audit_items RENAME TO old_audit_items;
+
+CREATE TABLE cabinets (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	org_id BIGINT NOT NULL REFERENCES orgs (id) ON DELETE CASCADE,
+	name VARCHAR NOT NULL,
+	description VARCHAR,
+	CONSTRAINT cabinets_name_key UNIQUE (org_id, name)
+);
+
+CREATE TABLE slots (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	cabinet_id BIGINT NOT NULL,
+	number INTEGER NOT NULL,
+	UNIQUE(cabinet_id, number),
+	FOREIGN KEY(cabinet_id) REFERENCES cabinets (id) ON DELETE CASCADE
+);
+
+CREATE TABLE keys (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	org_id BIGINT NOT NULL REFERENCES orgs (id) ON DELETE CASCADE,
+	name VARCHAR NOT NULL,
+	description VARCHAR,
+	active BOOLEAN NOT NULL DEFAULT true,
+	slot_id BIGINT UNIQUE REFERENCES slots (id) ON DELETE SET NULL,
+	deposit_amount BIGINT,
+	deposit_currency VARCHAR(3),
+	restricted BOOLEAN NOT NULL DEFAULT false,
+	deleted_at TIMESTAMP,
+	CONSTRAINT keys_name_key UNIQUE (org_id, name),
+	CHECK ((deposit_amount IS NULL) = (deposit_currency IS NULL))
+);
+
+CREATE TABLE assignments (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	key_id BIGINT NOT NULL REFERENCES keys (id) ON DELETE CASCADE,
+	date_out DATE NOT NULL,
+	date_in DATE,
+	date_due DATE,
+	deposit_collected BIGINT,
+	deposit_refunded BIGINT,
+	deposit_currency VARCHAR(3),
+	pending BOOLEAN NOT NULL DEFAULT false,
+	created_by VARCHAR REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL,
+	confirmed_by VARCHAR REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL,
+	lost BOOLEAN NOT NULL DEFAULT false,
+	deleted_at TIMESTAMP,
+	CHECK (deposit_collected IS NULL OR deposit_currency IS NOT NULL),
+	CHECK (deposit_refunded IS NULL OR deposit_currency IS NOT NULL),
+	CHECK (NOT pending OR confirmed_by IS NULL)
+);
+
+CREATE TABLE assignment_changes (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	assignment_id BIGINT NOT NULL,
+	changed_at TIMESTAMP NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+	changed_by VARCHAR,
+	action VARCHAR NOT NULL, -- created, updated, confirmed or lost
+	field VARCHAR, -- The column changed by an update
+	old_value VARCHAR,
+	new_value VARCHAR,
+	FOREIGN KEY(assignment_id) REFERENCES assignments (id) ON DELETE CASCADE,
+	FOREIGN KEY(changed_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL
+);
+
+CREATE TABLE charges (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	org_id BIGINT NOT NULL REFERENCES orgs (id) ON DELETE CASCADE,
+	"user" VARCHAR NOT NULL,
+	key_id BIGINT REFERENCES keys (id) ON DELETE SET NULL,
+	amount BIGINT NOT NULL,
+	currency VARCHAR(3) NOT NULL,
+	date DATE NOT NULL,
+	description VARCHAR,
+	paid BOOLEAN NOT NULL DEFAULT false,
+	FOREIGN KEY("user") REFERENCES users (username) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE key_requests (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	org_id BIGINT NOT NULL REFERENCES orgs (id) ON DELETE CASCADE,
+	"user" VARCHAR NOT NULL,
+	justification VARCHAR NOT NULL,
+	date_from DATE NOT NULL,
+	date_to DATE,
+	approver VARCHAR,
+	status VARCHAR NOT NULL DEFAULT 'pending'
+		CHECK (status IN ('pending', 'approved', 'denied', 'fulfilled')),
+	decided_by VARCHAR,
+	decision_note VARCHAR,
+	CHECK (date_to IS NULL OR date_to >= date_from),
+	FOREIGN KEY("user") REFERENCES users (username) ON UPDATE CASCADE ON DELETE CASCADE,
+	FOREIGN KEY(approver) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL,
+	FOREIGN KEY(decided_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL
+);
+
+CREATE TABLE key_request_items (
+	request_id BIGINT NOT NULL,
+	key_id BIGINT NOT NULL,
+	PRIMARY KEY (request_id, key_id),
+	FOREIGN KEY(request_id) REFERENCES key_requests (id) ON DELETE CASCADE,
+	FOREIGN KEY(key_id) REFERENCES keys (id) ON DELETE CASCADE
+);
+
+CREATE TABLE webhooks (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	org_id BIGINT NOT NULL REFERENCES orgs (id) ON DELETE CASCADE,
+	url VARCHAR NOT NULL,
+	events TEXT NOT NULL DEFAULT '[]',
+	secret VARCHAR NOT NULL,
+	active BOOLEAN NOT NULL DEFAULT true
+);
+
+CREATE TABLE webhook_deliveries (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	webhook_id BIGINT NOT NULL,
+	event VARCHAR NOT NULL,
+	payload VARCHAR NOT NULL,
+	attempts INTEGER NOT NULL DEFAULT 0,
+	status_code INTEGER,
+	last_error VARCHAR,
+	next_attempt TIMESTAMP NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+	delivered_at TIMESTAMP,
+	created_at TIMESTAMP NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+	FOREIGN KEY(webhook_id) REFERENCES webhooks (id) ON DELETE CASCADE
+);
+
+CREATE TABLE audits (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	org_id BIGINT NOT NULL REFERENCES orgs (id) ON DELETE CASCADE,
+	name VARCHAR NOT NULL,
+	started_at TIMESTAMP NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+	started_by VARCHAR,
+	closed_at TIMESTAMP,
+	closed_by VARCHAR,
+	FOREIGN KEY(started_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL,
+	FOREIGN KEY(closed_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL
+);
+
+CREATE TABLE audit_items (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	audit_id BIGINT NOT NULL,
+	assignment_id BIGINT,
+	"user" VARCHAR NOT NULL,
+	key VARCHAR NOT NULL,
+	result VARCHAR CHECK (result IN ('verified', 'missing', 'wrong_holder')),
+	actual_holder VARCHAR,
+	note VARCHAR,
+	checked_by VARCHAR,
+	checked_at TIMESTAMP,
+	lost_reported BOOLEAN NOT NULL DEFAULT false,
+	UNIQUE (audit_id, assignment_id),
+	FOREIGN KEY(audit_id) REFERENCES audits (id) ON DELETE CASCADE,
+	FOREIGN KEY(assignment_id) REFERENCES assignments (id) ON DELETE SET NULL,
+	FOREIGN KEY(checked_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL
+);
+
+INSERT INTO cabinets (id, org_id, name, description)
+SELECT id, 1, name, description FROM old_cabinets;
+
+INSERT INTO slots SELECT * FROM old_slots;
+
+INSERT INTO keys (id, org_id, name, description, active, slot_id, deposit_amount, deposit_currency, restricted, deleted_at)
+SELECT id, 1, name, description, active, slot_id, deposit_amount, deposit_currency, restricted, deleted_at
+FROM old_keys;
+
+INSERT INTO assignments SELECT * FROM old_assignments;
+
+INSERT INTO assignment_changes SELECT * FROM old_assignment_changes;
+
+INSERT INTO charges (id, org_id, "user", key_id, amount, currency, date, description, paid)
+SELECT c.id, 1, c."user", k.id, c.amount, c.currency, c.date, c.description, c.paid
+FROM old_charges c LEFT JOIN keys k ON k.name = c.key;
+
+INSERT INTO key_requests (id, org_id, "user", justification, date_from, date_to, approver, status, decided_by, decision_note)
+SELECT id, 1, "user", justification, date_from, date_to, approver, status, decided_by, decision_note
+FROM old_key_requests;
+
+INSERT INTO key_request_items (request_id, key_id)
+SELECT i.request_id, k.id FROM old_key_request_items i JOIN keys k ON k.name = i.key;
+
+INSERT INTO webhooks (id, org_id, url, events, secret, active)
+SELECT id, 1, url, events, secret, active FROM old_webhooks;
+
+INSERT INTO webhook_deliveries SELECT * FROM old_webhook_deliveries;
+
+INSERT INTO audits (id, org_id, name, started_at, started_by, closed_at, closed_by)
+SELECT id, 1, name, started_at, started_by, closed_at, closed_by FROM old_audits;
+
+INSERT INTO audit_items SELECT * FROM old_audit_items;
+
+DROP TABLE old_audit_items;
+DROP TABLE old_audits;
+DROP TABLE old_webhook_deliveries;
+DROP TABLE old_webhooks;
+DROP TABLE old_key_request_items;
+DROP TABLE old_key_requests;
+DROP TABLE old_charges;
+DROP TABLE old_assignment_changes;
+DROP TABLE old_assignments;
+DROP TABLE old_keys;
+DROP TABLE old_slots;
+DROP TABLE old_cabinets;
+
+-- Assignments in the trash shouldn't block assigning the same key to the same user again
+CREATE UNIQUE INDEX assignments_user_key_key ON assignments (user_id, key_id) WHERE deleted_at IS NULL;
+CREATE INDEX assignment_changes_assignment_id_idx ON assignment_changes (assignment_id);
+CREATE INDEX webhook_deliveries_pending_idx ON webhook_deliveries (next_attempt)
+	WHERE delivered_at IS NULL;
@@ -0,0 +1,91 @@
+-- See the Postgres migration of the same version. SQLite can't drop a NOT NULL constraint or
+-- add a CHECK constraint, so assignments are rebuilt along with the tables referring to them,
+-- as in the migration creating orgs.
+
+CREATE TABLE user_groups (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	org_id BIGINT NOT NULL REFERENCES orgs (id) ON DELETE CASCADE,
+	name VARCHAR NOT NULL,
+	description VARCHAR,
+	CONSTRAINT user_groups_name_key UNIQUE (org_id, name)
+);
+
+CREATE TABLE user_group_members (
+	group_id BIGINT NOT NULL REFERENCES user_groups (id) ON DELETE CASCADE,
+	user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+	PRIMARY KEY (group_id, user_id)
+);
+
+ALTER TABLE assignments RENAME TO old_assignments;
+ALTER TABLE assignment_changes RENAME TO old_assignment_changes;
+ALTER TABLE audit_items RENAME TO old_audit_items;
+
+CREATE TABLE assignments (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	user_id BIGINT REFERENCES users (id) ON DELETE CASCADE,
+	group_id BIGINT REFERENCES user_groups (id),
+	key_id BIGINT NOT NULL REFERENCES keys (id) ON DELETE CASCADE,
+	date_out DATE NOT NULL,
+	date_in DATE,
+	date_due DATE,
+	deposit_collected BIGINT,
+	deposit_refunded BIGINT,
+	deposit_currency VARCHAR(3),
+	pending BOOLEAN NOT NULL DEFAULT false,
+	created_by VARCHAR REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL,
+	confirmed_by VARCHAR REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL,
+	lost BOOLEAN NOT NULL DEFAULT false,
+	deleted_at TIMESTAMP,
+	CHECK (deposit_collected IS NULL OR deposit_currency IS NOT NULL),
+	CHECK (deposit_refunded IS NULL OR deposit_currency IS NOT NULL),
+	CHECK (NOT pending OR confirmed_by IS NULL),
+	CONSTRAINT assignments_holder_check CHECK ((user_id IS NULL) <> (group_id IS NULL))
+);
+
+CREATE TABLE assignment_changes (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	assignment_id BIGINT NOT NULL,
+	changed_at TIMESTAMP NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+	changed_by VARCHAR,
+	action VARCHAR NOT NULL, -- created, updated, confirmed or lost
+	field VARCHAR, -- The column changed by an update
+	old_value VARCHAR,
+	new_value VARCHAR,
+	FOREIGN KEY(assignment_id) REFERENCES assignments (id) ON DELETE CASCADE,
+	FOREIGN KEY(changed_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL
+);
+
+CREATE TABLE audit_items (
+	id INTEGER PRIMARY KEY AUTOINCREMENT,
+	audit_id BIGINT NOT NULL,
+	assignment_id BIGINT,
+	"user" VARCHAR NOT NULL,
+	key VARCHAR NOT NULL,
+	result VARCHAR CHECK (result IN ('verified', 'missing', 'wrong_holder')),
+	actual_holder VARCHAR,
+	note VARCHAR,
+	checked_by VARCHAR,
+	checked_at TIMESTAMP,
+	lost_reported BOOLEAN NOT NULL DEFAULT false,
+	UNIQUE (audit_id, assignment_id),
+	FOREIGN KEY(audit_id) REFERENCES audits (id) ON DELETE CASCADE,
+	FOREIGN KEY(assignment_id) REFERENCES assignments (id) ON DELETE SET NULL,
+	FOREIGN KEY(checked_by) REFERENCES users (username) ON UPDATE CASCADE ON DELETE SET NULL
+);
+
+INSERT INTO assignments (id, user_id, key_id, date_out, date_in, date_due, deposit_collected, deposit_refunded, deposit_currency, pending, created_by, confirmed_by, lost, deleted_at)
+SELECT id, user_id, key_id, date_out, date_in, date_due, deposit_collected, deposit_refunded, deposit_currency, pending, created_by, confirmed_by, lost, deleted_at
+FROM old_assignments;
+
+INSERT INTO assignment_changes SELECT * FROM old_assignment_changes;
+
+INSERT INTO audit_items SELECT * FROM old_audit_items;
+
+DROP TABLE old_audit_items;
+DROP TABLE old_assignment_changes;
+DROP TABLE old_assignments;
+
+-- Assignments in the trash shouldn't block assigning the same key to the same holder again
+CREATE UNIQUE INDEX assignments_user_key_key ON assignments (user_id, key_id) WHERE deleted_at IS NULL;
+CREATE UNIQUE INDEX assignments_group_key_key ON assignments (group_id, key_id) WHERE deleted_at IS NULL;
+CREATE INDEX assignment_changes_assignment_id_idx ON assignment_changes (assignment_id);
@@ -0,0 +1,623 @@
+use std::{env, fs, io::Write};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    config::Config,
+    mailer,
+    models::{self, currency_for, is_unique_violation, Assignment, Backup, Db, Key, Org, User},
+    webhooks,
+};
+
+pub static USAGE: &str = "Usage: backend [--config <path>] [--print-config] [<command>]
+
+Commands:
+  serve                                   Run the server (the default)
+  migrate                                 Apply pending database migrations
+  check-db                                Check the database is reachable and fully migrated
+  create-org <name>                       Create an org
+  create-user <user> [--admin] [--password-stdin] [--org <name>]
+                                          Create a user who can log in to an org
+  reset-password <user> [--password-stdin]
+                                          Set a user's password
+  list-keys [--all] [--org <name>]        List active keys, or every key with --all
+  assign <user> <key> [--org <name>]      Assign a key to a user from today
+  export [--org <name>]                   Print an org's keys and users as JSON
+  import <path> [--org <name>]            Create the keys and users in a file written by export
+  backup [--passwords]                    Print a backup of all data as JSON, with password
+                                          hashes if --passwords is given
+  restore <path>                          Load a backup into an empty database
+
+Passwords are read from the first line of stdin with --password-stdin, or else from
+KEYMASTER_PASSWORD. When neither is given, a password is generated and printed. Commands which
+work on an org use the first org unless --org is given.
+
+Options:
+  --config <path>   Read settings from <path> instead of KEYMASTER_CONFIG or ./keymaster.toml
+  --print-config    Print the effective config with secrets redacted and exit
+  --help            Print this message and exit";
+
+/// Options given on the command line.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    pub config: Option<String>,
+    pub print_config: bool,
+    pub command: Command,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub enum Command {
+    #[default]
+    Serve,
+    Help,
+    Migrate,
+    CheckDb,
+    CreateOrg {
+        name: String,
+    },
+    CreateUser {
+        username: String,
+        admin: bool,
+        password_stdin: bool,
+        org: Option<String>,
+    },
+    ResetPassword {
+        username: String,
+        password_stdin: bool,
+    },
+    ListKeys {
+        all: bool,
+        org: Option<String>,
+    },
+    Assign {
+        user: String,
+        key: String,
+        org: Option<String>,
+    },
+    Export {
+        org: Option<String>,
+    },
+    Import {
+        path: String,
+        org: Option<String>,
+    },
+    Backup {
+        passwords: bool,
+    },
+    Restore {
+        path: String,
+    },
+}
+
+/// The file written by `export` and read by `import`. Password hashes are left out.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Inventory {
+    keys: Vec<Key>,
+    users: Vec<User>,
+}
+
+/// Parses the command line arguments, not including the program name. The global options may
+/// come before or after the command.
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    let mut words = Vec::new();
+    let mut admin = false;
+    let mut all = false;
+    let mut passwords = false;
+    let mut password_stdin = false;
+    let mut org = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => match args.next() {
+                Some(path) => parsed.config = Some(path),
+                None => return Err("--config requires a path".into()),
+            },
+            "--print-config" => parsed.print_config = true,
+            "--help" | "-h" => {
+                return Ok(Args {
+                    command: Command::Help,
+                    ..Default::default()
+                })
+            }
+            "--admin" => admin = true,
+            "--all" => all = true,
+            "--passwords" => passwords = true,
+            "--password-stdin" => password_stdin = true,
+            "--org" => match args.next() {
+                Some(o) => org = Some(o),
+                None => return Err("--org requires a name".into()),
+            },
+            a => match a.strip_prefix("--config=") {
+                Some(path) => parsed.config = Some(path.into()),
+                None if a.starts_with('-') => return Err(format!("Unknown argument '{}'", a)),
+                None => words.push(a.to_string()),
+            },
+        }
+    }
+
+    let mut words = words.into_iter();
+    let name = words.next();
+    let mut operand = |what: &str| {
+        words.next().ok_or_else(|| {
+            format!(
+                "{} requires a {}",
+                name.as_deref().unwrap_or_default(),
+                what
+            )
+        })
+    };
+
+    parsed.command = match name.as_deref() {
+        None | Some("serve") => Command::Serve,
+        Some("migrate") => Command::Migrate,
+        Some("check-db") => Command::CheckDb,
+        Some("create-org") => Command::CreateOrg {
+            name: operand("name")?,
+        },
+        Some("create-user") => Command::CreateUser {
+            username: operand("username")?,
+            admin,
+            password_stdin,
+            org: org.take(),
+        },
+        Some("reset-password") => Command::ResetPassword {
+            username: operand("username")?,
+            password_stdin,
+        },
+        Some("list-keys") => Command::ListKeys {
+            all,
+            org: org.take(),
+        },
+        Some("assign") => Command::Assign {
+            user: operand("username")?,
+            key: operand("key name")?,
+            org: org.take(),
+        },
+        Some("export") => Command::Export { org: org.take() },
+        Some("import") => Command::Import {
+            path: operand("path")?,
+            org: org.take(),
+        },
+        Some("backup") => Command::Backup { passwords },
+        Some("restore") => Command::Restore {
+            path: operand("path")?,
+        },
+        Some(c) => return Err(format!("Unknown command '{}'", c)),
+    };
+
+    if let Some(extra) = words.next() {
+        return Err(format!("Unexpected argument '{}'", extra));
+    }
+    let used_admin = matches!(parsed.command, Command::CreateUser { .. });
+    let used_all = matches!(parsed.command, Command::ListKeys { .. });
+    let used_passwords = matches!(parsed.command, Command::Backup { .. });
+    let used_password_stdin = matches!(
+        parsed.command,
+        Command::CreateUser { .. } | Command::ResetPassword { .. }
+    );
+    if (admin && !used_admin)
+        || (all && !used_all)
+        || (passwords && !used_passwords)
+        || (password_stdin && !used_password_stdin)
+        || org.is_some()
+    {
+        return Err("Option not supported by this command".into());
+    }
+
+    Ok(parsed)
+}
+
+/// Runs any command other than `serve` and prints its results to stdout.
+pub async fn run(command: Command, config: &Config) -> Result<()> {
+    let mut out = std::io::stdout();
+    match command {
+        Command::Migrate => {
+            let pool = models::connect(&config.database).await?;
+            match models::migrate(&pool).await? {
+                0 => writeln!(out, "Database is up to date")?,
+                n => writeln!(out, "Applied {} migrations", n)?,
+            }
+        }
+        Command::CheckDb => {
+            let pool = models::connect(&config.database).await?;
+            match models::pending_migrations(&pool).await? {
+                0 => writeln!(out, "Database is reachable and up to date")?,
+                n => bail!("{} migrations pending. Run `backend migrate`", n),
+            }
+        }
+        command => {
+            let pool = models::db(&config.database).await?;
+            execute(command, &pool, config, &mut out).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs a command which works on the data rather than the schema.
+async fn execute(command: Command, pool: &Db, config: &Config, out: &mut impl Write) -> Result<()> {
+    match command {
+        Command::CreateOrg { name } => {
+            let mut org = Org {
+                name: name.clone(),
+                ..Default::default()
+            };
+            org.create(pool).await.map_err(|e| match e.to_string() {
+                _ if is_unique_violation(&e) => anyhow!("Org '{}' already exists", name),
+                _ => e.into(),
+            })?;
+            writeln!(out, "Created org '{}'", name)?;
+        }
+        Command::CreateUser {
+            username,
+            admin,
+            password_stdin,
+            org,
+        } => {
+            let org = find_org(pool, org.as_deref()).await?;
+            let mut user = User::default();
+            user.username = username.clone();
+            user.can_login = true;
+            user.admin = admin;
+            user.validate()?;
+            let password = new_password(read_password(password_stdin)?)?;
+            user.create(pool, org.id)
+                .await
+                .map_err(|e| match e.to_string() {
+                    _ if is_unique_violation(&e) => anyhow!(
+                        "User '{}' already exists. They may be in the trash or belong to another org",
+                        username
+                    ),
+                    _ => e.into(),
+                })?;
+            let mut user = User::get(pool, org.id, &username).await?;
+            set_password(pool, &mut user, password, out).await?;
+            webhooks::emit(pool, org.id, "user.created", &user).await;
+        }
+        Command::ResetPassword {
+            username,
+            password_stdin,
+        } => {
+            let mut user = User::find(pool, &username)
+                .await
+                .with_context(|| format!("User '{}' not found", username))?;
+            let password = new_password(read_password(password_stdin)?)?;
+            set_password(pool, &mut user, password, out).await?;
+        }
+        Command::ListKeys { all, org } => {
+            let org = find_org(pool, org.as_deref()).await?;
+            let keys = if all {
+                Key::get_all(pool, org.id).await?
+            } else {
+                Key::get_all_active(pool, org.id, true).await?
+            };
+            for k in keys {
+                writeln!(
+                    out,
+                    "{:<24} {:<8} {}",
+                    k.name,
+                    if k.active { "active" } else { "inactive" },
+                    k.description.unwrap_or_default()
+                )?;
+            }
+        }
+        Command::Assign { user, key, org } => {
+            let org = find_org(pool, org.as_deref()).await?.id;
+            let mut a = Assignment::new(&user, &key, Local::today().naive_local());
+            if let Ok(k) = Key::get(pool, org, &key).await {
+                if k.restricted {
+                    bail!(
+                        "Key '{}' is restricted. Assign it from the web app so a second admin can \
+                         confirm it",
+                        key
+                    );
+                }
+                a.deposit_collected = k.deposit_amount;
+                a.deposit_currency = currency_for(
+                    k.deposit_amount,
+                    k.deposit_currency,
+                    &config.finance.currency,
+                )
+                .map_err(|e| anyhow!(e))?;
+            }
+            a.validate(pool, pool, org, None, config.assignments.max_days_ahead)
+                .await?;
+            a.create(pool, org).await.map_err(|e| match e.to_string() {
+                _ if is_unique_violation(&e) => {
+                    anyhow!("Key '{}' already assigned to {}", key, user)
+                }
+                _ => e.into(),
+            })?;
+            if config.smtp.enabled() {
+                mailer::notify_key_assigned(pool, org, &user, &key).await;
+            }
+            webhooks::emit(pool, org, "assignment.created", &a).await;
+            writeln!(out, "Assigned key '{}' to {}", key, user)?;
+        }
+        Command::Export { org } => {
+            let org = find_org(pool, org.as_deref()).await?;
+            let inventory = Inventory {
+                keys: Key::get_all(pool, org.id).await?,
+                users: User::get_all(pool, org.id).await?,
+            };
+            serde_json::to_writer_pretty(&mut *out, &inventory)?;
+            writeln!(out)?;
+        }
+        Command::Import { path, org } => {
+            let org = find_org(pool, org.as_deref()).await?.id;
+            let file =
+                fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+            let inventory: Inventory = serde_json::from_str(&file)
+                .with_context(|| format!("{} is not a KeyMaster export", path))?;
+            let (mut created, mut skipped) = (0, 0);
+
+            for mut k in inventory.keys {
+                if Key::get(pool, org, &k.name).await.is_ok() {
+                    skipped += 1;
+                    continue;
+                }
+                // Slot ids belong to the database the file was exported from
+                k.slot_id = None;
+                k.create(pool, org)
+                    .await
+                    .with_context(|| format!("Failed to create key '{}'", k.name))?;
+                created += 1;
+            }
+            for u in inventory.users {
+                if User::get(pool, org, &u.username).await.is_ok() {
+                    skipped += 1;
+                    continue;
+                }
+                // Users who belong to another org are added to this one
+                if User::find(pool, &u.username).await.is_ok() {
+                    Org::add_member(pool, org, &u.username, u.admin).await?;
+                    created += 1;
+                    continue;
+                }
+                u.validate()?;
+                u.create(pool, org)
+                    .await
+                    .with_context(|| format!("Failed to create user '{}'", u.username))?;
+                created += 1;
+            }
+            writeln!(
+                out,
+                "Created {} keys and users, skipped {} which already exist. Imported users need a password set before they can log in",
+                created, skipped
+            )?;
+        }
+        Command::Backup { passwords } => {
+            let backup = Backup::create(pool, passwords).await?;
+            serde_json::to_writer(&mut *out, &backup)?;
+            writeln!(out)?;
+        }
+        Command::Restore { path } => {
+            let file =
+                fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+            let backup: Backup = serde_json::from_str(&file)
+                .with_context(|| format!("{} is not a KeyMaster backup", path))?;
+            backup.restore(pool).await?;
+            let rows: usize = backup.tables.values().map(Vec::len).sum();
+            writeln!(out, "Restored {} rows from {}", rows, path)?;
+            if !backup.includes_passwords {
+                writeln!(
+                    out,
+                    "The backup has no password hashes. Set passwords with reset-password before users log in"
+                )?;
+            }
+        }
+        Command::Serve | Command::Help | Command::Migrate | Command::CheckDb => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Finds the org named on the command line, or the first org when none was named.
+async fn find_org(pool: &Db, name: Option<&str>) -> Result<Org> {
+    match name {
+        Some(n) => Org::get_by_name(pool, n)
+            .await
+            .with_context(|| format!("Org '{}' not found", n)),
+        None => Ok(Org::first(pool).await?),
+    }
+}
+
+/// Reads a password from the first line of stdin if `stdin` is set, or else from
+/// KEYMASTER_PASSWORD, so that it stays out of the process list and shell history.
+fn read_password(stdin: bool) -> Result<Option<String>> {
+    if !stdin {
+        return Ok(env::var("KEYMASTER_PASSWORD")
+            .ok()
+            .filter(|p| !p.is_empty()));
+    }
+    let mut line = String::new();
+    std::io::stdin()
+        .read_line(&mut line)
+        .context("Failed to read the password from stdin")?;
+    match line.trim_end_matches(['\r', '\n']) {
+        "" => bail!("No password given on stdin"),
+        p => Ok(Some(p.to_string())),
+    }
+}
+
+/// Checks the given password, or generates one when none was given. The flag is set if the
+/// password was generated.
+fn new_password(password: Option<String>) -> Result<(String, bool)> {
+    match password {
+        Some(p) if p.len() < 8 => bail!("Password must be at least 8 characters long"),
+        Some(p) => Ok((p, false)),
+        None => Ok((pwgen::generate("full", 14), true)),
+    }
+}
+
+/// Sets the password, printing it if it was generated since it isn't shown anywhere else.
+async fn set_password(
+    pool: &Db,
+    user: &mut User,
+    (password, generated): (String, bool),
+    out: &mut impl Write,
+) -> Result<()> {
+    user.set_password(pool, &password).await?;
+
+    if generated {
+        writeln!(out, "Password for '{}': {}", user.username, password)?;
+    } else {
+        writeln!(out, "Password set for '{}'", user.username)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use std::io::Write;
+
+    use crate::db_test;
+    use anyhow::Result;
+
+    use super::{execute, new_password, parse_args, Command};
+    use crate::config::Config;
+    use crate::models::{
+        Assignment, AssignmentQuery, Credentials, Key, Org, User, UserRepository, DEFAULT_ORG,
+    };
+
+    fn parse(args: &str) -> Result<Command, String> {
+        parse_args(args.split_whitespace().map(String::from)).map(|a| a.command)
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Ok(Command::Serve), parse(""));
+        assert_eq!(Ok(Command::Help), parse("create-user --help"));
+        assert_eq!(Ok(Command::CheckDb), parse("--config k.toml check-db"));
+        assert_eq!(
+            Ok(Command::CreateUser {
+                username: "bob".into(),
+                admin: true,
+                password_stdin: true,
+                org: None,
+            }),
+            parse("create-user bob --admin --password-stdin")
+        );
+        assert_eq!(
+            Ok(Command::Assign {
+                user: "bob".into(),
+                key: "k1".into(),
+                org: Some("Acme".into()),
+            }),
+            parse("assign bob k1 --org Acme")
+        );
+        assert_eq!(
+            Ok(Command::ListKeys {
+                all: true,
+                org: None
+            }),
+            parse("list-keys --all")
+        );
+        assert_eq!(
+            Ok(Command::CreateOrg {
+                name: "Acme".into()
+            }),
+            parse("create-org Acme")
+        );
+        assert_eq!(
+            Ok(Command::Backup { passwords: true }),
+            parse("backup --passwords")
+        );
+
+        assert!(parse("assign bob").is_err());
+        assert!(parse("list-keys extra").is_err());
+        assert!(parse("list-keys --admin").is_err());
+        assert!(parse("export --passwords").is_err());
+        assert!(parse("create-user bob --password hunter22").is_err());
+        assert!(parse("list-keys --password-stdin").is_err());
+        assert!(parse("backup --org Acme").is_err());
+        assert!(parse("frobnicate").is_err());
+    }
+
+    db_test! {
+        #[sqlx::test]
+        async fn run_commands(pool: Db) -> Result<()> {
+            let mut out = Vec::new();
+            let config = Config::default();
+            let mut user = User::default();
+            user.username = "user1".into();
+            user.create(&pool, DEFAULT_ORG).await?;
+            let mut key = Key {
+                name: "key1".into(),
+                active: true,
+                ..Default::default()
+            };
+            key.create(&pool, DEFAULT_ORG).await?;
+
+            let create = Command::CreateUser {
+                username: "newadmin".into(),
+                admin: true,
+                password_stdin: false,
+                org: None,
+            };
+            execute(create, &pool, &config, &mut out).await?;
+            let output = String::from_utf8(out.clone())?;
+            let password = output.trim().rsplit(' ').next().unwrap();
+            let creds = Credentials {
+                username: "newadmin".into(),
+                password: password.into(),
+            };
+            let user = pool.authenticate(creds).await.unwrap();
+            assert!(User::get(&pool, DEFAULT_ORG, &user.username).await?.admin);
+
+            assert!(new_password(Some("short".into())).is_err());
+
+            let assign = Command::Assign {
+                user: "user1".into(),
+                key: "key1".into(),
+                org: None,
+            };
+            execute(assign, &pool, &config, &mut out).await?;
+            let filter = AssignmentQuery {
+                user: Some("user1".into()),
+                ..Default::default()
+            };
+            let assignments = Assignment::get_all(&pool, DEFAULT_ORG, filter).await?;
+            assert!(assignments.iter().any(|a| a.key == "key1"));
+
+            out.clear();
+            execute(Command::Export { org: None }, &pool, &config, &mut out).await?;
+            let dir = std::env::temp_dir().join(format!("keymaster-export-{}", std::process::id()));
+            std::fs::create_dir_all(&dir)?;
+            let path = dir.join("export.json");
+            std::fs::File::create(&path)?.write_all(&out)?;
+            let export = String::from_utf8(out.clone())?;
+            pool.execute("DELETE FROM users WHERE username = 'newadmin'")
+                .await?;
+
+            out.clear();
+            let import = Command::Import {
+                path: path.to_string_lossy().into(),
+                org: None,
+            };
+            execute(import, &pool, &config, &mut out).await?;
+            std::fs::remove_dir_all(&dir)?;
+            assert!(String::from_utf8(out.clone())?.starts_with("Created 1 keys and users"));
+            let imported = User::get(&pool, DEFAULT_ORG, "newadmin").await?;
+            assert!(imported.admin && !imported.validate_password(password));
+
+            // Keys and users can be imported into another org
+            execute(Command::CreateOrg { name: "Acme".into() }, &pool, &config, &mut out).await?;
+            let acme = Org::get_by_name(&pool, "Acme").await?;
+            std::fs::create_dir_all(&dir)?;
+            std::fs::File::create(&path)?.write_all(export.as_bytes())?;
+            let import = Command::Import {
+                path: path.to_string_lossy().into(),
+                org: Some("Acme".into()),
+            };
+            execute(import, &pool, &config, &mut out).await?;
+            std::fs::remove_dir_all(&dir)?;
+            assert_eq!(1, Key::get_all(&pool, acme.id).await?.len());
+            assert_eq!(2, User::get_all(&pool, acme.id).await?.len());
+
+            Ok(())
+        }
+    }
+}
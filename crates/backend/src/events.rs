@@ -0,0 +1,41 @@
+use async_trait::async_trait;
+use serde_json::Value;
+
+use crate::{mailer, models::Db, webhooks};
+
+/// What the routes tell the outside world about changes: webhook events and notification
+/// emails. Neither may fail the request which caused them.
+#[async_trait]
+pub trait Events: Send + Sync {
+    /// Sends `event` to every webhook of the org subscribed to it.
+    async fn emit(&self, org: i64, event: &str, data: Value);
+
+    /// Lets `user` know that the org's `key` was assigned to them.
+    async fn key_assigned(&self, org: i64, user: &str, key: &str);
+}
+
+/// Sends events from the database: webhooks always, and emails when an SMTP server is
+/// configured.
+pub struct DbEvents {
+    pool: Db,
+    email: bool,
+}
+
+impl DbEvents {
+    pub fn new(pool: Db, email: bool) -> Self {
+        Self { pool, email }
+    }
+}
+
+#[async_trait]
+impl Events for DbEvents {
+    async fn emit(&self, org: i64, event: &str, data: Value) {
+        webhooks::emit(&self.pool, org, event, &data).await;
+    }
+
+    async fn key_assigned(&self, org: i64, user: &str, key: &str) {
+        if self.email {
+            mailer::notify_key_assigned(&self.pool, org, user, key).await;
+        }
+    }
+}
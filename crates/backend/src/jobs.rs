@@ -0,0 +1,263 @@
+use std::{future::Future, pin::Pin, rc::Rc, str::FromStr, time::Duration as StdDuration};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, Utc};
+use cron::Schedule;
+use tracing::{debug, error, info};
+
+use crate::{
+    config::Config,
+    mailer::{self, Mailer},
+    models::{Db, Job, JobRun, QueuedEmail, WebhookDelivery},
+    webhooks,
+};
+
+/// How often the scheduler checks for jobs which are due.
+static TICK_SECONDS: u64 = 15;
+
+/// Number of days sent emails, job runs and delivered webhooks are kept before the cleanup job
+/// deletes them.
+static SENT_EMAIL_RETENTION_DAYS: i64 = 30;
+static JOB_RUN_RETENTION_DAYS: i64 = 90;
+static WEBHOOK_DELIVERY_RETENTION_DAYS: i64 = 30;
+
+type Task = Box<dyn Fn(Db) -> Pin<Box<dyn Future<Output = Result<()>>>>>;
+
+struct ScheduledTask {
+    name: &'static str,
+    expression: String,
+    schedule: Schedule,
+    task: Task,
+}
+
+impl ScheduledTask {
+    /// Returns the first time the task is scheduled after now.
+    fn next_run(&self) -> Result<DateTime<Utc>> {
+        self.schedule
+            .after(&Local::now())
+            .next()
+            .map(|t| t.with_timezone(&Utc))
+            .ok_or_else(|| anyhow!("Schedule '{}' never runs again", self.expression))
+    }
+}
+
+/// Runs jobs on cron schedules. Every run is recorded in the database so that a restart
+/// doesn't run a job twice, and each job holds an advisory lock while it runs so that only one
+/// instance of the backend runs it.
+#[derive(Default)]
+pub struct Scheduler {
+    tasks: Vec<ScheduledTask>,
+}
+
+impl Scheduler {
+    /// Adds a job which runs on the cron `schedule` (`sec min hour day month weekday`). The job
+    /// is skipped when the schedule is `off`.
+    pub fn add<F, Fut>(&mut self, name: &'static str, schedule: &str, task: F) -> Result<()>
+    where
+        F: Fn(Db) -> Fut + 'static,
+        Fut: Future<Output = Result<()>> + 'static,
+    {
+        if schedule.eq_ignore_ascii_case("off") {
+            info!("Job '{}' is disabled", name);
+            return Ok(());
+        }
+
+        let parsed = Schedule::from_str(schedule)
+            .map_err(|e| anyhow!("Invalid schedule '{}' for job '{}'. {}", schedule, name, e))?;
+
+        self.tasks.push(ScheduledTask {
+            name,
+            expression: schedule.into(),
+            schedule: parsed,
+            task: Box::new(move |pool| Box::pin(task(pool))),
+        });
+        Ok(())
+    }
+
+    /// Registers the jobs and runs them whenever they are due. Jobs run one at a time.
+    pub async fn run(self, pool: Db) {
+        for t in &self.tasks {
+            let registered = match t.next_run() {
+                Ok(next_run) => Job::register(&pool, t.name, &t.expression, next_run)
+                    .await
+                    .map_err(|e| e.into()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = registered {
+                error!("Failed to register job '{}'. {}", t.name, e);
+            }
+        }
+
+        let mut interval = actix_web::rt::time::interval(StdDuration::from_secs(TICK_SECONDS));
+        loop {
+            interval.tick().await;
+            for t in &self.tasks {
+                if let Err(e) = run_if_due(&pool, t).await {
+                    error!("Failed to run job '{}'. {}", t.name, e);
+                }
+            }
+        }
+    }
+}
+
+async fn run_if_due(pool: &Db, t: &ScheduledTask) -> Result<()> {
+    let mut job = Job::get(pool, t.name).await?;
+    if job.next_run > Utc::now() {
+        return Ok(());
+    }
+
+    let mut conn = match pool {
+        Db::Postgres(p) => p.acquire().await?,
+        // A SQLite database is only used by one instance, so there is no one to lock out
+        Db::Sqlite(_) => return run_locked(pool, t, &mut job).await,
+    };
+    if !job.try_lock(&mut conn).await? {
+        return Ok(());
+    }
+    let res = run_locked(pool, t, &mut job).await;
+    job.unlock(&mut conn).await?;
+    res
+}
+
+async fn run_locked(pool: &Db, t: &ScheduledTask, job: &mut Job) -> Result<()> {
+    let mut run = match job.claim(pool, t.next_run()?).await? {
+        Some(r) => r,
+        None => return Ok(()),
+    };
+
+    debug!("Running job '{}'", t.name);
+    let error = (t.task)(pool.clone()).await.err().map(|e| e.to_string());
+    if let Some(e) = &error {
+        error!("Job '{}' failed. {}", t.name, e);
+    }
+
+    run.finish(pool, error).await?;
+    Ok(())
+}
+
+/// Builds the scheduler with the backend's periodic jobs on their schedules in `config.jobs`.
+/// The email jobs are only added when an SMTP server is configured.
+pub fn scheduler(mailer: Option<Mailer>, config: &Config) -> Result<Scheduler> {
+    let mut scheduler = Scheduler::default();
+
+    if let Some(m) = mailer {
+        let m = Rc::new(m);
+        scheduler.add(
+            "send_emails",
+            config.jobs.schedule("send_emails", "0 * * * * *"),
+            move |pool| {
+                let m = m.clone();
+                async move {
+                    let sent = mailer::process_queue(&pool, &m).await?;
+                    if sent > 0 {
+                        info!("Sent {} emails", sent);
+                    }
+                    Ok(())
+                }
+            },
+        )?;
+
+        let reminder_days = config.smtp.reminder_days;
+        scheduler.add(
+            "reminders",
+            config.jobs.schedule("reminders", "0 0 7 * * *"),
+            move |pool| async move {
+                let today = Local::today().naive_local();
+                mailer::queue_reminders(&pool, today, reminder_days).await?;
+                Ok(())
+            },
+        )?;
+
+        scheduler.add(
+            "admin_summary",
+            config.jobs.schedule("admin_summary", "0 0 7 * * *"),
+            |pool| async move {
+                let today = Local::today().naive_local();
+                mailer::queue_admin_summary(&pool, today).await?;
+                Ok(())
+            },
+        )?;
+    }
+
+    scheduler.add(
+        "send_webhooks",
+        config.jobs.schedule("send_webhooks", "*/30 * * * * *"),
+        |pool| async move {
+            let attempted = webhooks::process_queue(&pool).await?;
+            if attempted > 0 {
+                info!("Retried {} webhook deliveries", attempted);
+            }
+            Ok(())
+        },
+    )?;
+
+    scheduler.add(
+        "cleanup",
+        config.jobs.schedule("cleanup", "0 0 3 * * *"),
+        |pool| async move {
+            QueuedEmail::delete_sent(&pool, SENT_EMAIL_RETENTION_DAYS).await?;
+            JobRun::delete_older_than(&pool, JOB_RUN_RETENTION_DAYS).await?;
+            WebhookDelivery::delete_delivered(&pool, WEBHOOK_DELIVERY_RETENTION_DAYS).await?;
+            Ok(())
+        },
+    )?;
+
+    Ok(scheduler)
+}
+
+#[cfg(test)]
+mod jobs_tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use crate::db_test;
+    use crate::jobs::{run_if_due, Scheduler};
+    use crate::models::{Job, JobStatus};
+    use anyhow::{anyhow, Result};
+    use chrono::{Duration, Utc};
+
+    db_test! {
+        #[sqlx::test]
+        async fn run_due_job(pool: Db) -> Result<()> {
+            let runs = Rc::new(Cell::new(0));
+            let mut scheduler = Scheduler::default();
+            {
+                let runs = runs.clone();
+                scheduler.add("test", "0 0 3 * * *", move |_| {
+                    runs.set(runs.get() + 1);
+                    async { Err(anyhow!("Nothing to do")) }
+                })?;
+            }
+            let task = &scheduler.tasks[0];
+
+            // Not due yet
+            let next_run = task.next_run()?;
+            Job::register(&pool, "test", &task.expression, next_run).await?;
+            run_if_due(&pool, task).await?;
+            assert_eq!(0, runs.get());
+
+            // Due runs only happen once
+            pool.execute(&format!(
+                "UPDATE jobs SET next_run = '{}'",
+                (Utc::now() - Duration::minutes(1)).to_rfc3339()
+            ))
+            .await?;
+            run_if_due(&pool, task).await?;
+            run_if_due(&pool, task).await?;
+            assert_eq!(1, runs.get());
+
+            let status = JobStatus::get_all(&pool).await?;
+            assert_eq!(Some("Nothing to do".into()), status[0].last_error);
+            assert_eq!(next_run, status[0].next_run);
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn invalid_schedule() {
+        let mut scheduler = Scheduler::default();
+        assert!(scheduler
+            .add("test", "every day", |_| async { Ok(()) })
+            .is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use std::fmt::Write;
+
+use anyhow::Result;
+use qrcode::{Color, QrCode};
+
+/// Blank modules left around a QR code so scanners can find its edges.
+static QUIET_ZONE: usize = 4;
+
+/// Size of a label sheet page in points (A4).
+static PAGE_WIDTH: f64 = 595.0;
+static PAGE_HEIGHT: f64 = 842.0;
+static MARGIN: f64 = 36.0;
+
+/// Labels on each sheet.
+static COLUMNS: usize = 3;
+static ROWS: usize = 8;
+
+/// Side of the QR code on a label sheet, in points.
+static QR_SIZE: f64 = 72.0;
+
+/// The dark modules of the QR code encoding `text`, as (x, y) coordinates from the top left,
+/// along with the width of the code in modules.
+fn modules(text: &str) -> Result<(usize, Vec<(usize, usize)>)> {
+    let code = QrCode::new(text.as_bytes())?;
+    let width = code.width();
+    let dark = code
+        .to_colors()
+        .into_iter()
+        .enumerate()
+        .filter(|(_, c)| *c == Color::Dark)
+        .map(|(i, _)| (i % width, i / width))
+        .collect();
+    Ok((width, dark))
+}
+
+/// Renders a label for a key as an SVG: a QR code of the key name with the name printed
+/// underneath.
+pub fn key_label_svg(key_name: &str) -> Result<String> {
+    let (width, dark) = modules(key_name)?;
+    let size = width + QUIET_ZONE * 2;
+    let height = size + 4;
+
+    let mut path = String::new();
+    for (x, y) in dark {
+        write!(path, "M{} {}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE)?;
+    }
+
+    Ok(format!(
+        r##"<?xml version="1.0" encoding="UTF-8"?>
+<svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{w}" height="{h}" viewBox="0 0 {size} {height}" shape-rendering="crispEdges">
+<rect width="{size}" height="{height}" fill="#fff"/>
+<path d="{path}" fill="#000"/>
+<text x="{center}" y="{baseline}" font-family="sans-serif" font-size="3" text-anchor="middle">{name}</text>
+</svg>
+"##,
+        w = size * 8,
+        h = height * 8,
+        center = size as f64 / 2.0,
+        baseline = size + 2,
+        name = escape_xml(key_name),
+    ))
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Renders a printable A4 sheet of labels, one for each key, with a QR code of the key name
+/// next to the name. Keys which don't fit on one page continue on the next.
+pub fn label_sheet_pdf(key_names: &[String]) -> Result<Vec<u8>> {
+    let cell_width = (PAGE_WIDTH - MARGIN * 2.0) / COLUMNS as f64;
+    let cell_height = (PAGE_HEIGHT - MARGIN * 2.0) / ROWS as f64;
+    let per_page = COLUMNS * ROWS;
+
+    // An empty sheet rather than an invalid document when there are no keys
+    let chunks: Vec<&[String]> = if key_names.is_empty() {
+        vec![&[]]
+    } else {
+        key_names.chunks(per_page).collect()
+    };
+
+    let mut pages = Vec::new();
+    for chunk in chunks {
+        let mut content = String::new();
+        for (i, name) in chunk.iter().enumerate() {
+            let left = MARGIN + (i % COLUMNS) as f64 * cell_width;
+            let top = PAGE_HEIGHT - MARGIN - (i / COLUMNS) as f64 * cell_height;
+            let qr_bottom = top - (cell_height + QR_SIZE) / 2.0;
+
+            let (width, dark) = modules(name)?;
+            let module = QR_SIZE / width as f64;
+            content.push_str("0 g\n");
+            for (x, y) in dark {
+                writeln!(
+                    content,
+                    "{:.2} {:.2} {:.2} {:.2} re",
+                    left + x as f64 * module,
+                    qr_bottom + QR_SIZE - (y + 1) as f64 * module,
+                    module,
+                    module
+                )?;
+            }
+            content.push_str("f\n");
+
+            // Shrink long names so they fit beside the code
+            let text_width = cell_width - QR_SIZE - 12.0;
+            let font_size = (text_width / (0.6 * name.chars().count().max(1) as f64)).min(14.0);
+            writeln!(
+                content,
+                "BT /F1 {:.1} Tf {:.2} {:.2} Td ({}) Tj ET",
+                font_size,
+                left + QR_SIZE + 6.0,
+                qr_bottom + (QR_SIZE - font_size) / 2.0,
+                escape_pdf(name)
+            )?;
+        }
+        pages.push(content);
+    }
+
+    Ok(write_pdf(&pages))
+}
+
+/// Escapes a PDF string literal. Characters outside Latin-1 can't be shown with the standard
+/// fonts and are replaced with `?`.
+fn escape_pdf(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c if (c as u32) < 256 => escaped.push(c),
+            _ => escaped.push('?'),
+        }
+    }
+    escaped
+}
+
+/// Assembles a PDF document with one page for each content stream.
+fn write_pdf(pages: &[String]) -> Vec<u8> {
+    // Objects 1-3 are the catalog, page tree and font. Each page then takes two objects: the
+    // page itself and its content stream.
+    let mut objects: Vec<Vec<u8>> = Vec::new();
+    let kids: Vec<String> = (0..pages.len())
+        .map(|i| format!("{} 0 R", 4 + i * 2))
+        .collect();
+
+    objects.push(b"<< /Type /Catalog /Pages 2 0 R >>".to_vec());
+    objects.push(
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        )
+        .into_bytes(),
+    );
+    objects.push(
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+    );
+    for (i, content) in pages.iter().enumerate() {
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH,
+                PAGE_HEIGHT,
+                5 + i * 2
+            )
+            .into_bytes(),
+        );
+        // Content streams are Latin-1, matching the font's encoding
+        let content: Vec<u8> = content.chars().map(|c| c as u8).collect();
+        let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+        stream.extend_from_slice(&content);
+        stream.extend_from_slice(b"\nendstream");
+        objects.push(stream);
+    }
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+        pdf.extend_from_slice(object);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+
+    let xref = pdf.len();
+    pdf.extend_from_slice(
+        format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+    );
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        )
+        .as_bytes(),
+    );
+    pdf
+}
+
+#[cfg(test)]
+mod labels_tests {
+    use crate::labels::{key_label_svg, label_sheet_pdf};
+    use anyhow::Result;
+
+    #[test]
+    fn svg_label() -> Result<()> {
+        let svg = key_label_svg("Front <Door> & Co")?;
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.contains(">Front &lt;Door&gt; &amp; Co</text>"));
+        Ok(())
+    }
+
+    #[test]
+    fn pdf_label_sheet() -> Result<()> {
+        let keys: Vec<String> = (0..25).map(|i| format!("key{}", i)).collect();
+        let pdf = label_sheet_pdf(&keys)?;
+        let text = String::from_utf8_lossy(&pdf);
+
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.contains("/Count 2"));
+        assert!(text.contains("(key24) Tj"));
+
+        // The cross reference table points at each object
+        let xref: usize = text
+            .rsplit("startxref\n")
+            .next()
+            .and_then(|s| s.lines().next())
+            .unwrap()
+            .parse()?;
+        assert!(text[xref..].starts_with("xref"));
+        let first = text[xref..].lines().nth(3).unwrap();
+        let offset: usize = first[..10].parse()?;
+        assert!(text[offset..].starts_with("1 0 obj"));
+
+        // An empty selection still makes a valid document
+        assert!(String::from_utf8_lossy(&label_sheet_pdf(&[])?).contains("/Count 1"));
+        Ok(())
+    }
+}
@@ -0,0 +1,306 @@
+//! The KeyMaster backend. `serve` runs the standalone server. To embed the API in another actix
+//! service, build an `AppState` and register it with `AppState::app_data` next to `configure`,
+//! or use `build_app` for the exact app the standalone server runs.
+
+use std::sync::Arc;
+
+use actix_session::{storage::CookieSessionStore, SessionMiddleware};
+use actix_web::{
+    body::MessageBody,
+    cookie::{Key, SameSite},
+    dev::{ServiceFactory, ServiceRequest, ServiceResponse},
+    middleware::NormalizePath,
+    web::{scope, Data, ServiceConfig},
+    App, HttpServer,
+};
+use actix_web_lab::{middleware::from_fn, web::spa};
+use config::{AssignmentsConfig, Config, FinanceConfig, SessionConfig};
+use events::DbEvents;
+use tracing::{error, info};
+
+pub mod cli;
+pub mod config;
+mod events;
+mod jobs;
+mod labels;
+mod logging;
+mod mailer;
+mod metrics;
+mod models;
+mod routes;
+mod tls;
+mod webhooks;
+
+pub use models::{db, Db};
+
+/// What the handlers share: the database, the settings they read and the key session cookies
+/// are signed with. Create it once and clone it into each worker's app, or sessions won't
+/// survive between workers.
+#[derive(Clone)]
+pub struct AppState {
+    pool: Db,
+    secret_key: Key,
+    email: bool,
+    assignments: AssignmentsConfig,
+    finance: FinanceConfig,
+}
+
+impl AppState {
+    pub fn new(config: &Config, pool: Db) -> Self {
+        Self {
+            pool,
+            secret_key: get_secret_key(&config.session),
+            email: config.smtp.enabled(),
+            assignments: config.assignments.clone(),
+            finance: config.finance.clone(),
+        }
+    }
+
+    /// Registers the database, settings and repositories the handlers take as `web::Data`.
+    pub fn app_data(&self) -> impl FnOnce(&mut ServiceConfig) {
+        let pool = self.pool.clone();
+        let events = Arc::new(DbEvents::new(pool.clone(), self.email));
+        let assignments = self.assignments.clone();
+        let finance = self.finance.clone();
+        move |cfg| {
+            cfg.app_data(Data::new(pool.clone()))
+                .app_data(Data::new(assignments))
+                .app_data(Data::new(finance))
+                .configure(routes::repositories(Arc::new(pool), events));
+        }
+    }
+
+    /// The cookie session the handlers keep logins in.
+    pub fn session_middleware(&self, config: &Config) -> SessionMiddleware<CookieSessionStore> {
+        SessionMiddleware::builder(CookieSessionStore::default(), self.secret_key.clone())
+            .cookie_secure(config.cookie_secure())
+            .cookie_http_only(false)
+            .cookie_same_site(SameSite::Strict)
+            .build()
+    }
+}
+
+/// Registers the health checks and the `/api` scope. The handlers need the data registered by
+/// `AppState::app_data` and a session middleware.
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(routes::health::healthz)
+        .service(routes::health::readyz)
+        .service(routes::health::get_metrics)
+        .service(
+            scope("/api")
+                .service(routes::keys::get)
+                .service(routes::keys::get_all)
+                .service(routes::keys::update)
+                .service(routes::keys::create)
+                .service(routes::keys::delete)
+                .service(routes::keys::get_assignments)
+                .service(routes::keys::get_location)
+                .service(routes::keys::get_label)
+                .service(routes::keys::get_labels)
+                .service(routes::users::get)
+                .service(routes::users::get_all)
+                .service(routes::users::update)
+                .service(routes::users::create)
+                .service(routes::users::delete)
+                .service(routes::users::set_password)
+                .service(routes::users::get_assignments)
+                .service(routes::users::get_notifications)
+                .service(routes::users::set_notifications)
+                .service(routes::assignments::get)
+                .service(routes::assignments::get_all)
+                .service(routes::assignments::get_history)
+                .service(routes::assignments::update)
+                .service(routes::assignments::create)
+                .service(routes::assignments::delete)
+                .service(routes::assignments::confirm)
+                .service(routes::assignments::report_lost)
+                .service(routes::audits::get)
+                .service(routes::audits::get_all)
+                .service(routes::audits::create)
+                .service(routes::audits::delete)
+                .service(routes::audits::get_items)
+                .service(routes::audits::check_item)
+                .service(routes::audits::close)
+                .service(routes::audits::report)
+                .service(routes::audits::report_csv)
+                .service(routes::cabinets::get)
+                .service(routes::cabinets::get_all)
+                .service(routes::cabinets::update)
+                .service(routes::cabinets::create)
+                .service(routes::cabinets::delete)
+                .service(routes::cabinets::get_slots)
+                .service(routes::cabinets::get_all_slots)
+                .service(routes::groups::get)
+                .service(routes::groups::get_all)
+                .service(routes::groups::update)
+                .service(routes::groups::create)
+                .service(routes::groups::delete)
+                .service(routes::groups::add_member)
+                .service(routes::groups::remove_member)
+                .service(routes::finance::get)
+                .service(routes::finance::get_all)
+                .service(routes::finance::update)
+                .service(routes::finance::create)
+                .service(routes::finance::delete)
+                .service(routes::finance::balances)
+                .service(routes::finance::group_balances)
+                .service(routes::finance::finance_csv)
+                .service(routes::requests::get)
+                .service(routes::requests::get_all)
+                .service(routes::requests::create)
+                .service(routes::requests::approve)
+                .service(routes::requests::deny)
+                .service(routes::requests::fulfill)
+                .service(routes::requests::delete)
+                .service(routes::jobs::get_all)
+                .service(routes::orgs::get_all)
+                .service(routes::orgs::create)
+                .service(routes::orgs::switch)
+                .service(routes::orgs::add_member)
+                .service(routes::trash::get_all)
+                .service(routes::trash::restore)
+                .service(routes::trash::purge)
+                .service(routes::webhooks::get)
+                .service(routes::webhooks::get_all)
+                .service(routes::webhooks::update)
+                .service(routes::webhooks::create)
+                .service(routes::webhooks::delete)
+                .service(routes::webhooks::get_deliveries)
+                .service(routes::webhooks::resend)
+                .service(routes::backup::get)
+                .service(routes::login)
+                .service(routes::logout)
+                .service(routes::session_info)
+                .service(routes::docs::get_openapi)
+                .service(routes::docs::get_docs),
+        );
+}
+
+/// The app the standalone server runs: `configure` plus the middleware and the frontend.
+pub fn build_app(
+    config: &Config,
+    state: AppState,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let static_dir = &config.static_files.dir;
+
+    App::new()
+        .wrap(from_fn(metrics::record_request))
+        .wrap(NormalizePath::trim())
+        .wrap(state.session_middleware(config))
+        .wrap(from_fn(logging::trace_request))
+        .configure(state.app_data())
+        .configure(configure)
+        .service(
+            spa()
+                .index_file(format!("{}/index.html", static_dir))
+                .static_resources_mount("/")
+                .static_resources_location(static_dir.clone())
+                .finish(),
+        )
+}
+
+/// Connects to the database, starts the background jobs and serves `build_app` until the
+/// server is stopped.
+pub async fn serve(config: Config) -> std::io::Result<()> {
+    logging::init(&config.logging);
+
+    let pool = match models::db(&config.database).await {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Failed to connect to the database. {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = models::initialize_admin(&pool, &config.admin).await {
+        error!("Failed to create admin user. {}", e);
+        std::process::exit(1);
+    }
+
+    let mailer = match mailer::Mailer::new(&config.smtp) {
+        Ok(m) => {
+            if m.is_none() {
+                info!("smtp.host not set. Email notifications disabled.");
+            }
+            m
+        }
+        Err(e) => {
+            error!("Failed to configure email. {}", e);
+            std::process::exit(1);
+        }
+    };
+    match jobs::scheduler(mailer, &config) {
+        Ok(s) => {
+            actix_web::rt::spawn(s.run(pool.clone()));
+        }
+        Err(e) => {
+            error!("Failed to configure jobs. {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let state = AppState::new(&config, pool);
+    let app_config = config.clone();
+    let server = HttpServer::new(move || build_app(&app_config, state.clone()));
+
+    let address = (config.server.bind.as_str(), config.server.port);
+    let server = match (&config.tls.cert, &config.tls.key) {
+        (Some(cert), Some(key)) => {
+            let resolver = match tls::CertResolver::load(cert, key) {
+                Ok(r) => Arc::new(r),
+                Err(e) => {
+                    error!("Failed to configure TLS. {}", e);
+                    std::process::exit(1);
+                }
+            };
+            actix_web::rt::spawn(tls::reload_on_hangup(resolver.clone()));
+
+            if let (Some(redirect_port), Some(public_host)) =
+                (config.tls.redirect_port, &config.server.public_host)
+            {
+                info!(
+                    "Redirecting HTTP on {}:{} to HTTPS on {}",
+                    config.server.bind, redirect_port, public_host
+                );
+                actix_web::rt::spawn(tls::redirect_server(
+                    &config.server.bind,
+                    redirect_port,
+                    public_host,
+                    config.server.port,
+                )?);
+            }
+
+            info!("Listening on https://{}:{}", address.0, address.1);
+            server.bind_rustls(address, tls::server_config(resolver))?
+        }
+        _ => {
+            info!("Listening on http://{}:{}", address.0, address.1);
+            server.bind(address)?
+        }
+    };
+
+    server.run().await
+}
+
+/// Generates the key session cookies are signed with from the configured secret, or randomly
+/// if there isn't one. The secret's length is checked when the config is loaded.
+fn get_secret_key(config: &SessionConfig) -> Key {
+    match &config.secret_key {
+        Some(s) => {
+            info!("Generating secret key from config");
+            Key::from(s.as_bytes())
+        }
+        None => {
+            info!("Generating random secret key");
+            Key::generate()
+        }
+    }
+}
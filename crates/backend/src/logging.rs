@@ -0,0 +1,127 @@
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use actix_web_lab::middleware::Next;
+use tracing::{error, info, info_span, Instrument};
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+
+use crate::config::{LogFormat, LoggingConfig};
+
+/// Header carrying the request id. An id sent by a client or proxy is kept, otherwise one is
+/// generated. Either way it is returned on the response.
+static REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Sends log lines to stdout in the configured format. `RUST_LOG` overrides the configured
+/// level. Lines from crates using `log` are included.
+pub fn init(config: &LoggingConfig) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.level));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match config.format {
+        LogFormat::Pretty => builder.init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .init(),
+    }
+}
+
+/// Runs each request in a span holding its id, so that everything logged while handling it can
+/// be tied back to the request, and logs the outcome once it's done.
+pub async fn trace_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let request_id = req
+        .headers()
+        .get(&REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| valid_request_id(v))
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let span = info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.path(),
+    );
+    let start = Instant::now();
+
+    let result = next.call(req).instrument(span.clone()).await;
+    let latency_ms = start.elapsed().as_millis() as u64;
+
+    span.in_scope(|| match result {
+        Ok(mut res) => {
+            info!(
+                status = res.status().as_u16(),
+                latency_ms, "Request completed"
+            );
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut().insert(REQUEST_ID.clone(), value);
+            }
+            Ok(res)
+        }
+        Err(e) => {
+            error!(latency_ms, error = %e, "Request failed");
+            Err(e)
+        }
+    })
+}
+
+/// Accepts ids of up to 64 letters, digits, dashes and underscores so that clients can't
+/// inject arbitrary text into the logs.
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod logging_tests {
+    use actix_web::{
+        test::{call_service, init_service, TestRequest},
+        web, App, HttpResponse,
+    };
+    use actix_web_lab::middleware::from_fn;
+
+    use super::trace_request;
+
+    #[actix_web::test]
+    async fn request_id() {
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(trace_request))
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let resp = call_service(&app, TestRequest::get().uri("/").to_request()).await;
+        let generated = resp.headers().get("x-request-id").unwrap();
+        assert_eq!(36, generated.len());
+
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header(("x-request-id", "from-proxy_1"))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!("from-proxy_1", resp.headers().get("x-request-id").unwrap());
+
+        let req = TestRequest::get()
+            .uri("/")
+            .insert_header(("x-request-id", "not valid\""))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_ne!("not valid\"", resp.headers().get("x-request-id").unwrap());
+    }
+}
@@ -0,0 +1,304 @@
+use anyhow::{anyhow, Result};
+use chrono::{Duration, NaiveDate};
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use tracing::error;
+
+use crate::{
+    config::{SmtpConfig, SmtpSecurity},
+    models::{AdminSummary, AssignmentNotice, Db, NotificationKind, Org, QueuedEmail, Recipient},
+};
+
+/// Number of queued emails sent per pass over the queue.
+static BATCH_SIZE: i64 = 50;
+
+static KEY_ASSIGNED: &str = include_str!("../templates/email/key_assigned.txt");
+static KEY_DUE: &str = include_str!("../templates/email/key_due.txt");
+static KEY_OVERDUE: &str = include_str!("../templates/email/key_overdue.txt");
+static ADMIN_SUMMARY: &str = include_str!("../templates/email/admin_summary.txt");
+
+/// Sends emails through the SMTP server in the `[smtp]` config.
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    /// Builds a mailer from the config. Returns `None` if no SMTP host is set, in which case
+    /// email notifications are disabled.
+    pub fn new(config: &SmtpConfig) -> Result<Option<Self>> {
+        let host = match &config.host {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+
+        let mut builder = match config.security {
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        };
+
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+
+        if let Some(username) = &config.username {
+            let password = config.password.clone().unwrap_or_default();
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+
+        let from = config
+            .from
+            .clone()
+            .unwrap_or_else(|| format!("KeyMaster <keymaster@{}>", host))
+            .parse()
+            .map_err(|e| anyhow!("Invalid smtp.from. {}", e))?;
+
+        Ok(Some(Mailer {
+            transport: builder.build(),
+            from,
+        }))
+    }
+
+    pub async fn send(&self, to: &str, subject: &str, body: &str) -> Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to.parse()?)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body.to_string())?;
+
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// Fills in a template's `{{name}}` placeholders and splits it into its subject and body. The
+/// first line of a template is its subject, prefixed with `Subject:`.
+fn render(template: &str, vars: &[(&str, String)]) -> (String, String) {
+    let mut text = template.to_string();
+    for (name, value) in vars {
+        text = text.replace(&format!("{{{{{}}}}}", name), value);
+    }
+
+    let (subject, body) = text.split_once('\n').unwrap_or((&text, ""));
+    (
+        subject.trim_start_matches("Subject:").trim().to_string(),
+        body.trim().to_string(),
+    )
+}
+
+fn format_date(date: Option<NaiveDate>) -> String {
+    date.map(|d| d.format("%b %e, %Y").to_string())
+        .unwrap_or_else(|| "no set date".into())
+}
+
+async fn queue_notice(
+    pool: &Db,
+    kind: NotificationKind,
+    template: &str,
+    notice: &AssignmentNotice,
+    days: i64,
+) -> Result<bool, sqlx::Error> {
+    let (subject, body) = render(
+        template,
+        &[
+            (
+                "name",
+                notice
+                    .display_name
+                    .clone()
+                    .unwrap_or_else(|| notice.username.clone()),
+            ),
+            ("key", notice.key.clone()),
+            ("date_out", format_date(Some(notice.date_out))),
+            ("date_due", format_date(notice.date_due)),
+            ("days", days.to_string()),
+        ],
+    );
+    let dedupe_key = match kind {
+        NotificationKind::KeyAssigned => format!("key_assigned:{}", notice.assignment_id),
+        _ => format!(
+            "{}:{}:{}",
+            match kind {
+                NotificationKind::KeyDue => "key_due",
+                _ => "key_overdue",
+            },
+            notice.assignment_id,
+            notice.date_due.unwrap_or_default()
+        ),
+    };
+
+    QueuedEmail::enqueue(
+        pool,
+        kind,
+        &notice.email,
+        &subject,
+        &body,
+        Some(&dedupe_key),
+    )
+    .await
+}
+
+/// Queues an email telling `user` that the org's `key` was assigned to them. Nothing is queued
+/// while the assignment is pending confirmation.
+pub async fn queue_key_assigned(
+    pool: &Db,
+    org: i64,
+    user: &str,
+    key: &str,
+) -> Result<bool, sqlx::Error> {
+    match AssignmentNotice::get_open(pool, org, user, key, NotificationKind::KeyAssigned).await? {
+        Some(n) => queue_notice(pool, NotificationKind::KeyAssigned, KEY_ASSIGNED, &n, 0).await,
+        None => Ok(false),
+    }
+}
+
+/// Queues the key assigned email. Failures are logged rather than returned so they never fail
+/// the assignment itself. Only call this when an SMTP server is configured, as nothing sends
+/// the queue otherwise.
+pub async fn notify_key_assigned(pool: &Db, org: i64, user: &str, key: &str) {
+    if let Err(e) = queue_key_assigned(pool, org, user, key).await {
+        error!(
+            "Failed to queue assignment email for key '{}' to user '{}'. {}",
+            key, user, e
+        );
+    }
+}
+
+/// Queues reminders for keys due back in `days` days and for overdue keys. Each reminder is
+/// only queued once per assignment and due date. Returns the number of emails queued.
+pub async fn queue_reminders(pool: &Db, today: NaiveDate, days: i64) -> Result<usize, sqlx::Error> {
+    let mut count = 0;
+
+    let due_on = today + Duration::days(days);
+    for n in
+        AssignmentNotice::get_due_between(pool, due_on, due_on, NotificationKind::KeyDue).await?
+    {
+        if queue_notice(pool, NotificationKind::KeyDue, KEY_DUE, &n, days).await? {
+            count += 1;
+        }
+    }
+
+    for n in AssignmentNotice::get_due_between(
+        pool,
+        NaiveDate::from_ymd(1970, 1, 1),
+        today - Duration::days(1),
+        NotificationKind::KeyOverdue,
+    )
+    .await?
+    {
+        if queue_notice(pool, NotificationKind::KeyOverdue, KEY_OVERDUE, &n, 0).await? {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Queues the daily summary of each org for every admin of it. Returns the number of emails
+/// queued.
+pub async fn queue_admin_summary(pool: &Db, today: NaiveDate) -> Result<usize, sqlx::Error> {
+    let mut count = 0;
+    for org in Org::get_all(pool).await? {
+        count += queue_org_summary(pool, &org, today).await?;
+    }
+    Ok(count)
+}
+
+async fn queue_org_summary(pool: &Db, org: &Org, today: NaiveDate) -> Result<usize, sqlx::Error> {
+    let summary = AdminSummary::get(pool, org.id, today).await?;
+    let mut count = 0;
+
+    for admin in Recipient::admins(pool, org.id, NotificationKind::AdminSummary).await? {
+        let (subject, body) = render(
+            ADMIN_SUMMARY,
+            &[
+                (
+                    "name",
+                    admin.display_name.unwrap_or_else(|| admin.username.clone()),
+                ),
+                ("org", org.name.clone()),
+                ("date", format_date(Some(today))),
+                ("open_assignments", summary.open_assignments.to_string()),
+                (
+                    "pending_confirmations",
+                    summary.pending_confirmations.to_string(),
+                ),
+                ("pending_requests", summary.pending_requests.to_string()),
+                (
+                    "overdue_assignments",
+                    summary.overdue_assignments.to_string(),
+                ),
+            ],
+        );
+        let dedupe_key = format!("admin_summary:{}:{}:{}", org.id, admin.username, today);
+
+        if QueuedEmail::enqueue(
+            pool,
+            NotificationKind::AdminSummary,
+            &admin.email,
+            &subject,
+            &body,
+            Some(&dedupe_key),
+        )
+        .await?
+        {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Attempts to send every queued email which is due. Failed emails are retried later. Returns
+/// the number of emails sent.
+pub async fn process_queue(pool: &Db, mailer: &Mailer) -> Result<usize, sqlx::Error> {
+    let mut sent = 0;
+
+    for mut email in QueuedEmail::get_due(pool, BATCH_SIZE).await? {
+        match mailer
+            .send(&email.recipient, &email.subject, &email.body)
+            .await
+        {
+            Ok(_) => {
+                email.mark_sent(pool).await?;
+                sent += 1;
+            }
+            Err(e) => {
+                error!(
+                    "Failed to send email {} to {}. {}",
+                    email.id, email.recipient, e
+                );
+                email.mark_failed(pool, &e.to_string()).await?;
+            }
+        }
+    }
+
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod mailer_tests {
+    use crate::mailer::{render, KEY_DUE};
+
+    #[test]
+    fn render_template() {
+        let (subject, body) = render(
+            KEY_DUE,
+            &[
+                ("name", "User Juan".into()),
+                ("key", "key1".into()),
+                ("date_due", "Nov  3, 1988".into()),
+                ("days", "3".into()),
+            ],
+        );
+
+        assert_eq!("Key key1 is due back on Nov  3, 1988", subject);
+        assert!(body.starts_with("Hi User Juan,"));
+        assert!(body.contains("due back in 3 days"));
+    }
+}
@@ -0,0 +1,160 @@
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    Error,
+};
+use actix_web_lab::middleware::Next;
+use anyhow::Result;
+use chrono::Local;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+
+use crate::models::{AdminSummary, Db, Key, Org};
+
+lazy_static! {
+    static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "keymaster_http_requests_total",
+        "HTTP requests handled, by route and status",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "keymaster_http_request_duration_seconds",
+        "Time taken to handle HTTP requests, by route",
+        &["method", "route"]
+    )
+    .unwrap();
+    static ref LOGINS: IntCounterVec = register_int_counter_vec!(
+        "keymaster_logins_total",
+        "Login attempts, by result",
+        &["result"]
+    )
+    .unwrap();
+    static ref DB_CONNECTIONS: IntGaugeVec = register_int_gauge_vec!(
+        "keymaster_db_connections",
+        "Database pool connections, by state",
+        &["state"]
+    )
+    .unwrap();
+    static ref ASSIGNMENTS: IntGaugeVec = register_int_gauge_vec!(
+        "keymaster_assignments",
+        "Assignments which haven't been returned, by state",
+        &["state"]
+    )
+    .unwrap();
+    static ref ACTIVE_KEYS: IntGauge =
+        register_int_gauge!("keymaster_active_keys", "Keys marked active").unwrap();
+}
+
+/// Records the count and duration of every request. Requests are labelled with the pattern of
+/// the route they matched so that paths containing names and ids don't each get a series.
+/// Handlers which return an error are counted with the status of the error's response.
+pub async fn record_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let route = req.match_pattern().unwrap_or_else(|| "other".into());
+
+    let res = next.call(req).await;
+
+    let status = match &res {
+        Ok(r) => r.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    HTTP_REQUESTS
+        .with_label_values(&[&method, &route, status.as_str()])
+        .inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+
+    res
+}
+
+/// Counts a login attempt.
+pub fn record_login(success: bool) {
+    LOGINS
+        .with_label_values(&[if success { "success" } else { "failure" }])
+        .inc();
+}
+
+/// Updates the pool and inventory gauges and returns every metric in the Prometheus text
+/// format. The inventory gauges count every org's assignments and keys.
+pub async fn render(pool: &Db) -> Result<String> {
+    let idle = pool.num_idle() as i64;
+    DB_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+    DB_CONNECTIONS
+        .with_label_values(&["in_use"])
+        .set(pool.size() as i64 - idle);
+
+    let (mut open, mut overdue, mut pending) = (0, 0, 0);
+    for org in Org::get_all(pool).await? {
+        let summary = AdminSummary::get(pool, org.id, Local::today().naive_local()).await?;
+        open += summary.open_assignments;
+        overdue += summary.overdue_assignments;
+        pending += summary.pending_confirmations;
+    }
+    ASSIGNMENTS.with_label_values(&["open"]).set(open);
+    ASSIGNMENTS.with_label_values(&["overdue"]).set(overdue);
+    ASSIGNMENTS.with_label_values(&["pending"]).set(pending);
+    ACTIVE_KEYS.set(Key::count_active(pool).await?);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use actix_web::{
+        body::MessageBody,
+        dev::{Service, ServiceRequest, ServiceResponse},
+        error::ErrorServiceUnavailable,
+        get,
+        test::{init_service, TestRequest},
+        App, Error, HttpResponse,
+    };
+    use actix_web_lab::middleware::{from_fn, Next};
+
+    use super::{record_request, HTTP_REQUESTS};
+
+    #[get("/metrics-test/{id}")]
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    /// Fails the way the session middleware does when it can't load a session.
+    async fn unavailable(
+        _req: ServiceRequest,
+        _next: Next<impl MessageBody>,
+    ) -> Result<ServiceResponse<impl MessageBody>, Error> {
+        Err::<ServiceResponse, _>(ErrorServiceUnavailable("Unavailable"))
+    }
+
+    #[actix_web::test]
+    async fn count_errors() {
+        let app = init_service(
+            App::new()
+                .wrap(from_fn(unavailable))
+                .wrap(from_fn(record_request))
+                .service(ok),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/metrics-test/1").to_request();
+        assert!(app.call(req).await.is_err());
+        assert_eq!(
+            1,
+            HTTP_REQUESTS
+                .with_label_values(&["GET", "/metrics-test/{id}", "503"])
+                .get()
+        );
+    }
+}
@@ -1,243 +1,1192 @@
+use std::fmt;
+
 use anyhow::Result;
-use chrono::NaiveDate;
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgQueryResult, query, query_as, FromRow, PgPool, Postgres, QueryBuilder};
+use sqlx::{
+    database::HasArguments, Database, Encode, FromRow, Postgres, QueryBuilder, Sqlite, Type,
+};
+use tracing::debug;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::models::{Db, KeyRepository, Tx, UserRepository};
 
-#[derive(Debug, Clone, PartialEq, Eq, FromRow, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Assignment {
     #[serde(skip_deserializing)]
-    id: i64,
-    pub user: String, // Foreign key to User::username
-    pub key: String,  // Foreign key to Key::name
+    pub(super) id: i64,
+    #[serde(default)]
+    pub user: Option<String>, // Foreign key to User::username, unless held by a group
+    #[serde(default)]
+    pub group: Option<String>, // Foreign key to Group::name, unless held by a user
+    pub key: String, // Foreign key to Key::name
     // #[serde(with = "ymd_format")]
     pub date_out: NaiveDate,
     // #[serde(with = "ymd_format_option")]
     pub date_in: Option<NaiveDate>,
+    #[serde(default)]
+    pub date_due: Option<NaiveDate>,
+    #[serde(default)]
+    pub deposit_collected: Option<i64>, // Minor units of deposit_currency
+    #[serde(default)]
+    pub deposit_refunded: Option<i64>, // Minor units of deposit_currency
+    #[serde(default)]
+    pub deposit_currency: Option<String>,
+    #[serde(skip_deserializing)]
+    pub pending: bool, // Awaiting confirmation by a second admin
+    #[serde(skip_deserializing)]
+    pub created_by: Option<String>, // Foreign key to User::username
+    #[serde(skip_deserializing)]
+    pub confirmed_by: Option<String>, // Foreign key to User::username
+    #[serde(skip_deserializing)]
+    pub lost: bool, // Closed because the key was reported lost
+}
+
+/// Reasons an assignment is refused by `Assignment::validate`.
+#[derive(Debug)]
+pub enum AssignmentError {
+    InactiveKey(String),
+    InvalidHolder,
+    DateInBeforeDateOut,
+    DateOutTooFarAhead(i64),
+    UserDeparted(String),
+    KeyNotFound(String),
+    UserNotFound(String),
+    Database(sqlx::Error),
 }
 
-#[derive(Default, Deserialize, Clone)]
+impl AssignmentError {
+    /// A short, stable identifier for the error which clients can match on.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AssignmentError::InactiveKey(_) => "inactive_key",
+            AssignmentError::InvalidHolder => "invalid_holder",
+            AssignmentError::DateInBeforeDateOut => "date_in_before_date_out",
+            AssignmentError::DateOutTooFarAhead(_) => "date_out_too_far_ahead",
+            AssignmentError::UserDeparted(_) => "user_departed",
+            AssignmentError::KeyNotFound(_) => "key_not_found",
+            AssignmentError::UserNotFound(_) => "user_not_found",
+            AssignmentError::Database(_) => "database_error",
+        }
+    }
+}
+
+impl fmt::Display for AssignmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssignmentError::InactiveKey(k) => write!(f, "Key '{}' is not active", k),
+            AssignmentError::InvalidHolder => {
+                write!(f, "Assign the key to either a user or a group, not both")
+            }
+            AssignmentError::DateInBeforeDateOut => write!(f, "Date in cannot be before date out"),
+            AssignmentError::DateOutTooFarAhead(d) => {
+                write!(f, "Date out cannot be more than {} days in the future", d)
+            }
+            AssignmentError::UserDeparted(u) => write!(f, "User '{}' has departed", u),
+            AssignmentError::KeyNotFound(k) => write!(f, "Key '{}' does not exist", k),
+            AssignmentError::UserNotFound(u) => write!(f, "User '{}' does not exist", u),
+            AssignmentError::Database(e) => write!(f, "Database error. {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AssignmentError {}
+
+#[derive(Default, Deserialize, Clone, IntoParams)]
 pub struct AssignmentQuery {
     pub id: Option<i64>,
     pub user: Option<String>,
+    pub group: Option<String>,
+    /// Only assignments held by groups the user belongs to
+    pub member: Option<String>,
     pub key: Option<String>,
+    pub pending: Option<bool>,
     pub sort: Option<String>,
 }
 
 impl Assignment {
+    /// Creates a new, open assignment of `key` to `user` without a deposit.
+    pub fn new(user: &str, key: &str, date_out: NaiveDate) -> Self {
+        Assignment {
+            id: 0,
+            user: Some(user.into()),
+            group: None,
+            key: key.into(),
+            date_out,
+            date_in: None,
+            date_due: None,
+            deposit_collected: None,
+            deposit_refunded: None,
+            deposit_currency: None,
+            pending: false,
+            created_by: None,
+            confirmed_by: None,
+            lost: false,
+        }
+    }
+
+    /// Creates a new, open assignment of `key` to every member of `group` without a deposit.
+    #[cfg(test)]
+    pub fn for_group(group: &str, key: &str, date_out: NaiveDate) -> Self {
+        Assignment {
+            user: None,
+            group: Some(group.into()),
+            ..Assignment::new("", key, date_out)
+        }
+    }
+
     pub fn id(&self) -> i64 {
         self.id
     }
 
-    pub async fn create(&self, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
-        query!(
-            r#"INSERT INTO assignments ("user", key, date_out) VALUES ($1, $2, $3)"#,
-            &self.user,
+    /// The username or group name of whoever holds the key.
+    pub fn holder(&self) -> &str {
+        self.user
+            .as_deref()
+            .or(self.group.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Checks that the assignment may be saved. `original` is the assignment as it currently
+    /// exists in the database when updating, so that returning a key which has since been
+    /// deactivated, or which is held by a user who has since departed, is still allowed. The
+    /// group isn't looked up here, saving an assignment to one which doesn't exist violates
+    /// `assignments_holder_check` instead.
+    pub async fn validate(
+        &self,
+        keys: &dyn KeyRepository,
+        users: &dyn UserRepository,
+        org: i64,
+        original: Option<&Assignment>,
+        max_days_ahead: i64,
+    ) -> Result<(), AssignmentError> {
+        if self.user.is_some() == self.group.is_some() {
+            return Err(AssignmentError::InvalidHolder);
+        }
+
+        if let Some(date_in) = self.date_in {
+            if date_in < self.date_out {
+                return Err(AssignmentError::DateInBeforeDateOut);
+            }
+        }
+
+        if original
+            .map(|o| o.date_out != self.date_out)
+            .unwrap_or(true)
+        {
+            let limit = Local::today().naive_local() + Duration::days(max_days_ahead);
+            if self.date_out > limit {
+                return Err(AssignmentError::DateOutTooFarAhead(max_days_ahead));
+            }
+        }
+
+        if original.map(|o| o.key != self.key).unwrap_or(true) {
+            let key = keys.get(org, &self.key).await.map_err(|e| match e {
+                sqlx::Error::RowNotFound => AssignmentError::KeyNotFound(self.key.clone()),
+                e => AssignmentError::Database(e),
+            })?;
+            if !key.active {
+                return Err(AssignmentError::InactiveKey(key.name));
+            }
+        }
+
+        if let Some(username) = &self.user {
+            if original.map(|o| o.user != self.user).unwrap_or(true) {
+                let user = users.get(org, username).await.map_err(|e| match e {
+                    sqlx::Error::RowNotFound => AssignmentError::UserNotFound(username.clone()),
+                    e => AssignmentError::Database(e),
+                })?;
+                if user.departed {
+                    return Err(AssignmentError::UserDeparted(user.username));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates the assignment of one of the org's keys. Unless the assignment is pending, the
+    /// key is taken out of whichever slot it was stored in.
+    pub async fn create(&mut self, pool: &Db, org: i64) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        self.id = db_query_scalar!(
+            i64,
+            fetch_one,
+            &mut tx,
+            r#"INSERT INTO assignments (user_id, group_id, key_id, date_out, date_due, deposit_collected, deposit_currency, pending, created_by)
+            VALUES (
+                (SELECT u.id FROM users u
+                    JOIN org_members m ON m.user_id = u.id
+                    WHERE m.org_id = $9 AND u.username = $1 AND m.deleted_at IS NULL),
+                (SELECT id FROM user_groups WHERE org_id = $9 AND name = $10),
+                (SELECT id FROM keys WHERE org_id = $9 AND name = $2),
+                $3, $4, $5, $6, $7, $8
+            )
+            RETURNING id"#,
+            self.user,
             &self.key,
             &self.date_out,
+            self.date_due,
+            self.deposit_collected,
+            self.deposit_currency,
+            self.pending,
+            self.created_by,
+            org,
+            self.group,
+        )?;
+
+        AssignmentChange::record(
+            &mut tx,
+            self.id,
+            self.created_by.as_deref(),
+            "created",
+            None,
         )
-        .execute(pool)
-        .await
+        .await?;
+
+        if !self.pending {
+            db_execute!(
+                &mut tx,
+                "UPDATE keys SET slot_id = NULL WHERE id = (SELECT key_id FROM assignments WHERE id = $1)",
+                self.id
+            )?;
+        }
+
+        tx.commit().await
+    }
+
+    /// Confirms a pending assignment of a restricted key and takes the key out of its slot.
+    /// Returns false, changing nothing, if the assignment isn't pending or if `confirmed_by` is
+    /// the admin who created it.
+    pub async fn confirm(&mut self, pool: &Db, confirmed_by: &str) -> Result<bool, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let confirmed = db_query_scalar!(
+            i64,
+            fetch_optional,
+            &mut tx,
+            r#"UPDATE assignments
+            SET pending = false, confirmed_by = $1
+            WHERE id = $2 AND pending AND created_by IS DISTINCT FROM $1
+            RETURNING id"#,
+            confirmed_by,
+            self.id
+        )?;
+        if confirmed.is_none() {
+            return Ok(false);
+        }
+
+        AssignmentChange::record(&mut tx, self.id, Some(confirmed_by), "confirmed", None).await?;
+
+        db_execute!(
+            &mut tx,
+            "UPDATE keys SET slot_id = NULL WHERE id = (SELECT key_id FROM assignments WHERE id = $1)",
+            self.id
+        )?;
+
+        tx.commit().await?;
+        self.pending = false;
+        self.confirmed_by = Some(confirmed_by.into());
+        Ok(true)
+    }
+
+    /// Closes the assignment on `date` because the key was lost and deactivates the key.
+    /// Returns false, changing nothing, if the assignment is already closed.
+    pub async fn report_lost(
+        &mut self,
+        pool: &Db,
+        date: NaiveDate,
+        reported_by: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let reported = db_query_scalar!(
+            i64,
+            fetch_optional,
+            &mut tx,
+            r#"UPDATE assignments
+            SET date_in = $1, lost = true
+            WHERE id = $2 AND date_in IS NULL
+            RETURNING id"#,
+            date,
+            self.id
+        )?;
+        if reported.is_none() {
+            return Ok(false);
+        }
+
+        AssignmentChange::record(
+            &mut tx,
+            self.id,
+            Some(reported_by),
+            "lost",
+            Some(("date_in", None, Some(date.to_string()))),
+        )
+        .await?;
+
+        db_execute!(
+            &mut tx,
+            "UPDATE keys SET active = false WHERE id = (SELECT key_id FROM assignments WHERE id = $1)",
+            self.id
+        )?;
+
+        tx.commit().await?;
+        self.date_in = Some(date);
+        self.lost = true;
+        Ok(true)
     }
 
-    pub async fn get(pool: &PgPool, id: i64) -> Result<Self, sqlx::Error> {
-        query_as!(
+    pub async fn get(pool: &Db, org: i64, id: i64) -> Result<Self, sqlx::Error> {
+        db_query_as!(
             Self,
+            fetch_one,
+            pool,
             r#"SELECT
-                id,
-                "user",
-                key,
-                date_out,
-                date_in as "date_in?"
-            FROM assignments
-            WHERE id = $1"#,
+                a.id,
+                u.username as "user?",
+                g.name as "group?",
+                k.name as key,
+                a.date_out,
+                a.date_in as "date_in?",
+                a.date_due,
+                a.deposit_collected,
+                a.deposit_refunded,
+                a.deposit_currency,
+                a.pending,
+                a.created_by,
+                a.confirmed_by,
+                a.lost
+            FROM assignments a
+            LEFT JOIN users u ON u.id = a.user_id
+            LEFT JOIN user_groups g ON g.id = a.group_id
+            JOIN keys k ON k.id = a.key_id
+            WHERE a.id = $1 AND k.org_id = $2 AND a.deleted_at IS NULL"#,
             id,
+            org,
         )
-        .fetch_one(pool)
-        .await
     }
 
-    pub async fn get_all(pool: &PgPool, filter: AssignmentQuery) -> Result<Vec<Self>, sqlx::Error> {
-        let valid_columns = vec!["id", "user", "key", "date_out", "date_in"];
+    pub async fn get_all(
+        pool: &Db,
+        org: i64,
+        filter: AssignmentQuery,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        match pool {
+            Db::Postgres(p) => {
+                let mut query = Self::filtered::<Postgres>(org, filter);
+                debug!(sql = query.sql(), "Querying assignments");
+                query.build_query_as().fetch_all(p).await
+            }
+            Db::Sqlite(p) => {
+                let mut query = Self::filtered::<Sqlite>(org, filter);
+                debug!(sql = query.sql(), "Querying assignments");
+                query.build_query_as().fetch_all(p).await
+            }
+        }
+    }
+
+    /// Builds the query for `get_all` in either backend's dialect.
+    fn filtered<'a, DB>(org: i64, filter: AssignmentQuery) -> QueryBuilder<'a, DB>
+    where
+        DB: Database,
+        <DB as HasArguments<'a>>::Arguments: Default,
+        String: Encode<'a, DB> + Type<DB>,
+        i64: Encode<'a, DB> + Type<DB>,
+        bool: Encode<'a, DB> + Type<DB>,
+    {
+        let valid_columns = ["id", "user", "group", "key", "date_out", "date_in"];
 
-        let mut query: QueryBuilder<Postgres> = QueryBuilder::new(
+        let mut query = QueryBuilder::new(
             r#"SELECT
-                id,
-                "user",
-                key,
-                date_out,
-                date_in
-            FROM assignments
+                a.id,
+                u.username as "user",
+                g.name as "group",
+                k.name as key,
+                a.date_out,
+                a.date_in,
+                a.date_due,
+                a.deposit_collected,
+                a.deposit_refunded,
+                a.deposit_currency,
+                a.pending,
+                a.created_by,
+                a.confirmed_by,
+                a.lost
+            FROM assignments a
+            LEFT JOIN users u ON u.id = a.user_id
+            LEFT JOIN user_groups g ON g.id = a.group_id
+            JOIN keys k ON k.id = a.key_id
+            WHERE a.deleted_at IS NULL
             "#,
         );
+        query.push(" AND k.org_id =").push_bind(org);
 
-        let mut where_clause = false;
         if let Some(u) = filter.user {
-            if where_clause {
-                query.push("AND ");
-            } else {
-                where_clause = true;
-                query.push("WHERE ");
-            }
-            query.push(r#""user" ="#).push_bind(u);
+            query.push(" AND u.username =").push_bind(u);
+        }
+
+        if let Some(g) = filter.group {
+            query.push(" AND g.name =").push_bind(g);
+        }
+
+        if let Some(m) = filter.member {
+            query
+                .push(
+                    " AND a.group_id IN (SELECT gm.group_id FROM user_group_members gm JOIN users mu ON mu.id = gm.user_id WHERE mu.username =",
+                )
+                .push_bind(m)
+                .push(")");
         }
 
         if let Some(k) = filter.key {
-            if where_clause {
-                query.push("AND ");
-            } else {
-                where_clause = true;
-                query.push("WHERE ");
-            }
-            query.push("key =").push_bind(k);
+            query.push(" AND k.name =").push_bind(k);
         }
 
         if let Some(id) = filter.id {
-            if where_clause {
-                query.push("AND ");
-            } else {
-                query.push("WHERE ");
-            }
-            query.push("id =").push_bind(id);
+            query.push(" AND a.id =").push_bind(id);
+        }
+
+        if let Some(p) = filter.pending {
+            query.push(" AND a.pending =").push_bind(p);
         }
 
         if let Some(s) = filter.sort {
-            if valid_columns.iter().any(|c| *c == s.as_str()) {
-                query.push(format!(r#"ORDER BY "{}""#, s));
+            if valid_columns.contains(&s.as_str()) {
+                query.push(format!(r#" ORDER BY "{}""#, s));
             }
         };
 
-        log::warn!("{}", query.sql());
-        query.build_query_as::<Assignment>().fetch_all(pool).await
-        // log::warn!("{}", query.into_sql());
-        // Ok(vec![Assignment {
-        //     id: 12,
-        //     user: "".into(),
-        //     key: "".into(),
-        //     date_out: chrono::NaiveDate::default(),
-        //     date_in: None,
-        // }])
+        query
     }
 
-    pub async fn update(&mut self, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
+    /// Saves the assignment and records each changed field in its history. `org` is the org
+    /// whose keys the assignment's key is looked up in.
+    pub async fn update(
+        &mut self,
+        pool: &Db,
+        org: i64,
+        changed_by: &str,
+    ) -> Result<(), sqlx::Error> {
         if self.id == 0 {
             return Err(sqlx::Error::RowNotFound);
         };
 
-        query!(
+        let mut tx = pool.begin().await?;
+
+        let old = db_query_as!(
+            Self,
+            fetch_one,
+            &mut tx,
+            r#"SELECT
+                a.id,
+                u.username as "user?",
+                g.name as "group?",
+                k.name as key,
+                a.date_out,
+                a.date_in as "date_in?",
+                a.date_due,
+                a.deposit_collected,
+                a.deposit_refunded,
+                a.deposit_currency,
+                a.pending,
+                a.created_by,
+                a.confirmed_by,
+                a.lost
+            FROM assignments a
+            LEFT JOIN users u ON u.id = a.user_id
+            LEFT JOIN user_groups g ON g.id = a.group_id
+            JOIN keys k ON k.id = a.key_id
+            WHERE a.id = $1
+            FOR UPDATE OF a"#,
+            self.id,
+        )?;
+
+        db_execute!(
+            &mut tx,
             r#"UPDATE assignments
             SET
-                "user" = $1,
-                key = $2,
+                user_id = (
+                    SELECT u.id FROM users u
+                    JOIN org_members m ON m.user_id = u.id
+                    WHERE m.org_id = $10 AND u.username = $1 AND m.deleted_at IS NULL
+                ),
+                group_id = (SELECT id FROM user_groups WHERE org_id = $10 AND name = $11),
+                key_id = (SELECT id FROM keys WHERE org_id = $10 AND name = $2),
                 date_out = $3,
-                date_in = $4
-            WHERE id = $5"#,
+                date_in = $4,
+                date_due = $5,
+                deposit_collected = $6,
+                deposit_refunded = $7,
+                deposit_currency = $8
+            WHERE id = $9"#,
             self.user,
             self.key,
             self.date_out,
             self.date_in,
+            self.date_due,
+            self.deposit_collected,
+            self.deposit_refunded,
+            self.deposit_currency,
             self.id,
-        )
-        .execute(pool)
-        .await
+            org,
+            self.group,
+        )?;
+
+        for change in self.changes_from(&old) {
+            AssignmentChange::record(&mut tx, self.id, Some(changed_by), "updated", Some(change))
+                .await?;
+        }
+
+        tx.commit().await
     }
 
-    pub async fn delete(&self, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
-        query!(
-            r#"DELETE FROM assignments WHERE "user" = $1 AND key = $2"#,
-            self.user,
-            self.key
+    /// Each field which differs from `old`, with its old and new values as text.
+    pub(super) fn changes_from(
+        &self,
+        old: &Assignment,
+    ) -> Vec<(&'static str, Option<String>, Option<String>)> {
+        fn text<T: ToString>(v: &Option<T>) -> Option<String> {
+            v.as_ref().map(|v| v.to_string())
+        }
+        [
+            ("user", old.user.clone(), self.user.clone()),
+            ("group", old.group.clone(), self.group.clone()),
+            ("key", Some(old.key.clone()), Some(self.key.clone())),
+            (
+                "date_out",
+                Some(old.date_out.to_string()),
+                Some(self.date_out.to_string()),
+            ),
+            ("date_in", text(&old.date_in), text(&self.date_in)),
+            ("date_due", text(&old.date_due), text(&self.date_due)),
+            (
+                "deposit_collected",
+                text(&old.deposit_collected),
+                text(&self.deposit_collected),
+            ),
+            (
+                "deposit_refunded",
+                text(&old.deposit_refunded),
+                text(&self.deposit_refunded),
+            ),
+            (
+                "deposit_currency",
+                old.deposit_currency.clone(),
+                self.deposit_currency.clone(),
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, old_value, new_value)| old_value != new_value)
+        .collect()
+    }
+
+    /// Moves the assignment to the trash.
+    pub async fn delete(&self, pool: &Db) -> Result<u64, sqlx::Error> {
+        db_execute!(
+            pool,
+            "UPDATE assignments SET deleted_at = now() WHERE id = $1 AND deleted_at IS NULL",
+            self.id
         )
-        .execute(pool)
-        .await
+    }
+
+    /// Takes an assignment out of the trash. Fails with `RowNotFound` if the assignment isn't
+    /// in the trash or if its user or key still is. Groups are never in the trash.
+    pub async fn restore(pool: &Db, org: i64, id: i64) -> Result<(), sqlx::Error> {
+        db_query_scalar!(
+            i64,
+            fetch_one,
+            pool,
+            r#"UPDATE assignments SET deleted_at = NULL
+            WHERE id = $1 AND deleted_at IS NOT NULL
+            AND EXISTS (
+                SELECT 1 FROM keys k
+                WHERE k.id = assignments.key_id AND k.org_id = $2 AND k.deleted_at IS NULL
+            )
+            AND (user_id IS NULL OR EXISTS (
+                SELECT 1 FROM org_members m
+                WHERE m.user_id = assignments.user_id AND m.org_id = $2 AND m.deleted_at IS NULL
+            ))
+            RETURNING id"#,
+            id,
+            org
+        )?;
+        Ok(())
+    }
+
+    /// Permanently deletes an assignment in the trash. Fails with `RowNotFound` if the
+    /// assignment isn't in the trash.
+    pub async fn purge(pool: &Db, org: i64, id: i64) -> Result<(), sqlx::Error> {
+        db_query_scalar!(
+            i64,
+            fetch_one,
+            pool,
+            r#"DELETE FROM assignments
+            WHERE id = $1 AND deleted_at IS NOT NULL
+            AND key_id IN (SELECT id FROM keys WHERE org_id = $2)
+            RETURNING id"#,
+            id,
+            org
+        )?;
+        Ok(())
     }
 
     pub async fn get_assignments_by_user(
-        pool: &PgPool,
+        pool: &Db,
+        org: i64,
         username: &str,
     ) -> Result<Vec<Assignment>, sqlx::Error> {
-        query_as!(
+        db_query_as!(
             Assignment,
+            fetch_all,
+            pool,
             r#"SELECT
-                *
-                FROM assignments
-                WHERE "user" = $1
-                AND date_in is null
-                ORDER BY key"#,
-            username
+                a.id,
+                u.username as "user?",
+                g.name as "group?",
+                k.name as key,
+                a.date_out,
+                a.date_in as "date_in?",
+                a.date_due,
+                a.deposit_collected,
+                a.deposit_refunded,
+                a.deposit_currency,
+                a.pending,
+                a.created_by,
+                a.confirmed_by,
+                a.lost
+                FROM assignments a
+                LEFT JOIN users u ON u.id = a.user_id
+                LEFT JOIN user_groups g ON g.id = a.group_id
+                JOIN keys k ON k.id = a.key_id
+                WHERE u.username = $1
+                AND k.org_id = $2
+                AND a.date_in is null
+                AND a.deleted_at IS NULL
+                ORDER BY k.name"#,
+            username,
+            org
         )
-        .fetch_all(pool)
-        .await
     }
 
     pub async fn get_assignments_by_key(
-        pool: &PgPool,
+        pool: &Db,
+        org: i64,
         key_name: &str,
     ) -> Result<Vec<Assignment>, sqlx::Error> {
-        query_as!(
+        db_query_as!(
             Assignment,
+            fetch_all,
+            pool,
             r#"SELECT
-                *
-                FROM assignments
-                WHERE key = $1
-                AND date_in is null
-                ORDER BY "user""#,
-            key_name
+                a.id,
+                u.username as "user?",
+                g.name as "group?",
+                k.name as key,
+                a.date_out,
+                a.date_in as "date_in?",
+                a.date_due,
+                a.deposit_collected,
+                a.deposit_refunded,
+                a.deposit_currency,
+                a.pending,
+                a.created_by,
+                a.confirmed_by,
+                a.lost
+                FROM assignments a
+                LEFT JOIN users u ON u.id = a.user_id
+                LEFT JOIN user_groups g ON g.id = a.group_id
+                JOIN keys k ON k.id = a.key_id
+                WHERE k.name = $1
+                AND k.org_id = $2
+                AND a.date_in is null
+                AND a.deleted_at IS NULL
+                ORDER BY u.username, g.name"#,
+            key_name,
+            org
         )
-        .fetch_all(pool)
-        .await
+    }
+}
+
+/// An entry in an assignment's history. For updates, `field`, `old_value` and `new_value`
+/// describe the column which changed. Reporting a key lost records the date it was closed.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow, Serialize, ToSchema)]
+pub struct AssignmentChange {
+    pub id: i64,
+    pub assignment_id: i64,
+    pub changed_at: DateTime<Utc>,
+    pub changed_by: Option<String>, // Foreign key to User::username
+    pub action: String,             // created, updated, confirmed or lost
+    pub field: Option<String>,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}
+
+impl AssignmentChange {
+    /// Returns the assignment's history, oldest first.
+    pub async fn get_by_assignment(
+        pool: &Db,
+        assignment_id: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT id, assignment_id, changed_at, changed_by, action, field, old_value, new_value
+            FROM assignment_changes
+            WHERE assignment_id = $1
+            ORDER BY changed_at, id"#,
+            assignment_id
+        )
+    }
+
+    pub(super) async fn record(
+        tx: &mut Tx,
+        assignment_id: i64,
+        changed_by: Option<&str>,
+        action: &str,
+        change: Option<(&str, Option<String>, Option<String>)>,
+    ) -> Result<(), sqlx::Error> {
+        let (field, old_value, new_value) = match change {
+            Some((f, o, n)) => (Some(f), o, n),
+            None => (None, None, None),
+        };
+        db_execute!(
+            &mut *tx,
+            r#"INSERT INTO assignment_changes (assignment_id, changed_by, action, field, old_value, new_value)
+            VALUES ($1, $2, $3, $4, $5, $6)"#,
+            assignment_id,
+            changed_by,
+            action,
+            field,
+            old_value,
+            new_value
+        )?;
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod assignment_tests {
-    use crate::models::{Assignment, Key, User};
+    use crate::models::{
+        Assignment, AssignmentChange, AssignmentError, AssignmentQuery, Cabinet, Key, KeyLocation,
+        Org, Slot, User, DEFAULT_ORG,
+    };
     use anyhow::Result;
-    use chrono::NaiveDate;
-    use sqlx::{query, PgPool};
+    use chrono::{Duration, Local, NaiveDate};
 
-    #[sqlx::test(fixtures("users", "keys"))]
-    async fn create_assignment(pool: PgPool) -> Result<()> {
-        let user1 = User::get(&pool, "user1").await?;
-        let key1 = Key::get(&pool, "key1").await?;
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn create_assignment(pool: Db) -> Result<()> {
+            let user1 = User::get(&pool, DEFAULT_ORG, "user1").await?;
+            let key1 = Key::get(&pool, DEFAULT_ORG, "key1").await?;
 
-        let date_out = NaiveDate::from_ymd(1988, 10, 3);
-        let a = Assignment {
-            id: 0,
-            user: user1.username,
-            key: key1.name,
-            date_out,
-            date_in: None,
-        };
-        a.create(&pool).await?;
+            let date_out = NaiveDate::from_ymd(1988, 10, 3);
+            let mut a = Assignment {
+                id: 0,
+                user: Some(user1.username),
+                group: None,
+                key: key1.name,
+                date_out,
+                date_in: None,
+                date_due: None,
+                deposit_collected: None,
+                deposit_refunded: None,
+                deposit_currency: None,
+                pending: false,
+                created_by: None,
+                confirmed_by: None,
+                lost: false,
+            };
+            a.create(&pool, DEFAULT_ORG).await?;
 
-        Ok(())
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("users", "keys", "assignments"))]
-    async fn get_assignment(pool: PgPool) -> Result<()> {
-        let date_out = NaiveDate::from_ymd(1988, 10, 3);
-        let assgn1 = Assignment::get(&pool, 1).await?;
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn holders_come_from_the_org(pool: Db) -> Result<()> {
+            let mut acme = Org {
+                name: "Acme".into(),
+                ..Default::default()
+            };
+            acme.create(&pool).await?;
+            Org::add_member(&pool, acme.id, "userNoPass", false).await?;
+            let outsider = User::get(&pool, DEFAULT_ORG, "userNoPass").await?;
+            outsider.delete(&pool, DEFAULT_ORG).await?;
 
-        assert_eq!("user1", assgn1.user);
-        assert_eq!("key1", assgn1.key);
-        assert_eq!(date_out, assgn1.date_out);
-        assert_eq!(None, assgn1.date_in);
+            let date_out = NaiveDate::from_ymd(1988, 10, 3);
+            let mut a = Assignment::new("userNoPass", "key1", date_out);
+            let e = a.create(&pool, DEFAULT_ORG).await.unwrap_err();
+            assert!(e.to_string().contains("assignments_holder_check"));
 
-        Ok(())
+            let mut a = Assignment::new("user1", "key1", date_out);
+            a.create(&pool, DEFAULT_ORG).await?;
+            a.user = Some("userNoPass".into());
+            let e = a.update(&pool, DEFAULT_ORG, "user1").await.unwrap_err();
+            assert!(e.to_string().contains("assignments_holder_check"));
+
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("users", "keys", "assignments"))]
-    async fn delete_assignment(pool: PgPool) -> Result<()> {
-        let assgn1 = Assignment::get(&pool, 1).await?;
-        assgn1.delete(&pool).await?;
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn create_assignment_takes_key_off_hook(pool: Db) -> Result<()> {
+            let mut cabinet = Cabinet {
+                name: "B".into(),
+                ..Default::default()
+            };
+            cabinet.create(&pool, DEFAULT_ORG, 20).await?;
+            let slot = Slot::get_by_cabinet(&pool, cabinet.id).await?[16].clone();
+            slot.store_key(&pool, "key1").await?;
 
-        let res = query("SELECT * FROM assignments WHERE id = $1")
-            .bind(1)
-            .execute(&pool)
-            .await?;
+            let mut a = Assignment {
+                id: 0,
+                user: Some("user1".into()),
+                group: None,
+                key: "key1".into(),
+                date_out: NaiveDate::from_ymd(1988, 10, 3),
+                date_in: None,
+                date_due: None,
+                deposit_collected: None,
+                deposit_refunded: None,
+                deposit_currency: None,
+                pending: false,
+                created_by: None,
+                confirmed_by: None,
+                lost: false,
+            };
+            a.create(&pool, DEFAULT_ORG).await?;
 
-        assert_eq!(res.rows_affected(), 0);
+            assert_eq!(None, Key::get(&pool, DEFAULT_ORG, "key1").await?.slot_id);
+            assert_eq!(None, Slot::get(&pool, DEFAULT_ORG, slot.id).await?.key);
+            assert_eq!(
+                KeyLocation::User {
+                    username: "user1".into()
+                },
+                KeyLocation::get(&pool, DEFAULT_ORG, "key1").await?
+            );
 
-        Ok(())
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn confirm_pending_assignment(pool: Db) -> Result<()> {
+            let mut cabinet = Cabinet {
+                name: "B".into(),
+                ..Default::default()
+            };
+            cabinet.create(&pool, DEFAULT_ORG, 20).await?;
+            let slot = Slot::get_by_cabinet(&pool, cabinet.id).await?[0].clone();
+            slot.store_key(&pool, "key1").await?;
+
+            let mut a = Assignment::new("userNoPass", "key1", NaiveDate::from_ymd(1988, 10, 3));
+            a.pending = true;
+            a.created_by = Some("user1".into());
+            a.create(&pool, DEFAULT_ORG).await?;
+
+            let mut a = Assignment::get_all(
+                &pool,
+                DEFAULT_ORG,
+                AssignmentQuery {
+                    pending: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await?
+            .remove(0);
+
+            // Pending keys stay on their hook
+            assert_eq!(Some("key1".into()), Slot::get(&pool, DEFAULT_ORG, slot.id).await?.key);
+
+            // The creator cannot confirm their own assignment
+            assert!(!a.confirm(&pool, "user1").await?);
+
+            assert!(a.confirm(&pool, "userCanLogin").await?);
+            let confirmed = Assignment::get(&pool, DEFAULT_ORG, a.id()).await?;
+
+            assert!(!confirmed.pending);
+            assert_eq!(Some("user1".into()), confirmed.created_by);
+            assert_eq!(Some("userCanLogin".into()), confirmed.confirmed_by);
+            assert_eq!(None, Slot::get(&pool, DEFAULT_ORG, slot.id).await?.key);
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys", "assignments"))]
+        async fn get_assignment(pool: Db) -> Result<()> {
+            let date_out = NaiveDate::from_ymd(1988, 10, 3);
+            let assgn1 = Assignment::get(&pool, DEFAULT_ORG, 1).await?;
+
+            assert_eq!(Some("user1".to_string()), assgn1.user);
+            assert_eq!("key1", assgn1.key);
+            assert_eq!(date_out, assgn1.date_out);
+            assert_eq!(None, assgn1.date_in);
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys", "assignments"))]
+        async fn delete_assignment(pool: Db) -> Result<()> {
+            let assgn1 = Assignment::get(&pool, DEFAULT_ORG, 1).await?;
+            assgn1.delete(&pool).await?;
+            assert!(Assignment::get(&pool, DEFAULT_ORG, 1).await.is_err());
+
+            Assignment::restore(&pool, DEFAULT_ORG, 1).await?;
+            assert_eq!(assgn1, Assignment::get(&pool, DEFAULT_ORG, 1).await?);
+
+            // Assignments can't be restored while their user is in the trash
+            assgn1.delete(&pool).await?;
+            User::get(&pool, DEFAULT_ORG, "user1").await?.delete(&pool, DEFAULT_ORG).await?;
+            assert!(Assignment::restore(&pool, DEFAULT_ORG, 1).await.is_err());
+
+            Assignment::purge(&pool, DEFAULT_ORG, 1).await?;
+            let count = "SELECT count(*) FROM assignments WHERE id = 1";
+
+            assert_eq!(pool.count(count).await?, 0);
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys", "assignments"))]
+        async fn delete_by_id(pool: Db) -> Result<()> {
+            let first = Assignment::get(&pool, DEFAULT_ORG, 1).await?;
+            first.delete(&pool).await?;
+
+            let mut second = Assignment::new("user1", "key1", NaiveDate::from_ymd(1988, 11, 3));
+            second.create(&pool, DEFAULT_ORG).await?;
+
+            // Deleting the first assignment again leaves the second one alone
+            first.delete(&pool).await?;
+            assert_eq!(second, Assignment::get(&pool, DEFAULT_ORG, second.id()).await?);
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn assignment_history(pool: Db) -> Result<()> {
+            let mut a = Assignment::new("user1", "key1", NaiveDate::from_ymd(1988, 10, 3));
+            a.created_by = Some("userCanLogin".into());
+            a.create(&pool, DEFAULT_ORG).await?;
+
+            a.user = Some("userNoPass".into());
+            a.date_due = Some(NaiveDate::from_ymd(1988, 11, 3));
+            a.update(&pool, DEFAULT_ORG, "user1").await?;
+
+            // Saving without changes adds nothing
+            a.update(&pool, DEFAULT_ORG, "user1").await?;
+
+            let history = AssignmentChange::get_by_assignment(&pool, a.id()).await?;
+            let entries: Vec<String> = history
+                .iter()
+                .map(|c| {
+                    format!(
+                        "{} {} {} {}",
+                        c.action,
+                        c.field.as_deref().unwrap_or("-"),
+                        c.old_value.as_deref().unwrap_or("-"),
+                        c.new_value.as_deref().unwrap_or("-")
+                    )
+                })
+                .collect();
+
+            assert_eq!(
+                vec![
+                    "created - - -",
+                    "updated user user1 userNoPass",
+                    "updated date_due - 1988-11-03",
+                ],
+                entries
+            );
+            assert_eq!(Some("userCanLogin".into()), history[0].changed_by);
+            assert_eq!(Some("user1".into()), history[1].changed_by);
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn validate_inactive_key(pool: Db) -> Result<()> {
+            let a = Assignment {
+                id: 0,
+                user: Some("user1".into()),
+                group: None,
+                key: "key2".into(),
+                date_out: NaiveDate::from_ymd(1988, 10, 3),
+                date_in: None,
+                date_due: None,
+                deposit_collected: None,
+                deposit_refunded: None,
+                deposit_currency: None,
+                pending: false,
+                created_by: None,
+                confirmed_by: None,
+                lost: false,
+            };
+            let res = a.validate(&pool, &pool, DEFAULT_ORG, None, 30).await;
+
+            assert!(matches!(res, Err(AssignmentError::InactiveKey(_))));
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn validate_date_in_before_date_out(pool: Db) -> Result<()> {
+            let a = Assignment {
+                id: 0,
+                user: Some("user1".into()),
+                group: None,
+                key: "key1".into(),
+                date_out: NaiveDate::from_ymd(1988, 10, 3),
+                date_in: Some(NaiveDate::from_ymd(1988, 10, 2)),
+                date_due: None,
+                deposit_collected: None,
+                deposit_refunded: None,
+                deposit_currency: None,
+                pending: false,
+                created_by: None,
+                confirmed_by: None,
+                lost: false,
+            };
+            let res = a.validate(&pool, &pool, DEFAULT_ORG, None, 30).await;
+
+            assert!(matches!(res, Err(AssignmentError::DateInBeforeDateOut)));
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn validate_date_out_too_far_ahead(pool: Db) -> Result<()> {
+            let today = Local::today().naive_local();
+            let mut a = Assignment {
+                id: 0,
+                user: Some("user1".into()),
+                group: None,
+                key: "key1".into(),
+                date_out: today + Duration::days(8),
+                date_in: None,
+                date_due: None,
+                deposit_collected: None,
+                deposit_refunded: None,
+                deposit_currency: None,
+                pending: false,
+                created_by: None,
+                confirmed_by: None,
+                lost: false,
+            };
+            let res = a.validate(&pool, &pool, DEFAULT_ORG, None, 7).await;
+
+            assert!(matches!(res, Err(AssignmentError::DateOutTooFarAhead(7))));
+
+            a.date_out = today + Duration::days(7);
+            a.validate(&pool, &pool, DEFAULT_ORG, None, 7).await?;
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn validate_departed_user(pool: Db) -> Result<()> {
+            let a = Assignment {
+                id: 0,
+                user: Some("userDeparted".into()),
+                group: None,
+                key: "key1".into(),
+                date_out: NaiveDate::from_ymd(1988, 10, 3),
+                date_in: None,
+                date_due: None,
+                deposit_collected: None,
+                deposit_refunded: None,
+                deposit_currency: None,
+                pending: false,
+                created_by: None,
+                confirmed_by: None,
+                lost: false,
+            };
+            let res = a.validate(&pool, &pool, DEFAULT_ORG, None, 30).await;
+
+            assert!(matches!(res, Err(AssignmentError::UserDeparted(_))));
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn validate_holder(pool: Db) -> Result<()> {
+            let mut a = Assignment::for_group("Janitorial", "key1", NaiveDate::from_ymd(1988, 10, 3));
+            a.validate(&pool, &pool, DEFAULT_ORG, None, 30).await?;
+
+            a.user = Some("user1".into());
+            let res = a.validate(&pool, &pool, DEFAULT_ORG, None, 30).await;
+            assert!(matches!(res, Err(AssignmentError::InvalidHolder)));
+
+            a.user = None;
+            a.group = None;
+            let res = a.validate(&pool, &pool, DEFAULT_ORG, None, 30).await;
+            assert!(matches!(res, Err(AssignmentError::InvalidHolder)));
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys", "assignments"))]
+        async fn validate_return_of_deactivated_key(pool: Db) -> Result<()> {
+            let original = Assignment::get(&pool, DEFAULT_ORG, 1).await?;
+            let mut key = Key::get(&pool, DEFAULT_ORG, &original.key).await?;
+            key.active = false;
+            key.update(&pool).await?;
+
+            let mut a = original.clone();
+            a.date_in = Some(NaiveDate::from_ymd(1988, 11, 3));
+            a.validate(&pool, &pool, DEFAULT_ORG, Some(&original), 30).await?;
+
+            a.key = "key2".into();
+            let res = a.validate(&pool, &pool, DEFAULT_ORG, Some(&original), 30).await;
+
+            assert!(matches!(res, Err(AssignmentError::InactiveKey(_))));
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn report_lost_key(pool: Db) -> Result<()> {
+            let mut a = Assignment::new("user1", "key1", NaiveDate::from_ymd(1988, 10, 3));
+            a.create(&pool, DEFAULT_ORG).await?;
+
+            let lost_on = NaiveDate::from_ymd(1988, 11, 3);
+            assert!(a.report_lost(&pool, lost_on, "user1").await?);
+            let stored = Assignment::get(&pool, DEFAULT_ORG, a.id()).await?;
+
+            assert!(stored.lost);
+            assert_eq!(Some(lost_on), stored.date_in);
+            assert!(!Key::get(&pool, DEFAULT_ORG, "key1").await?.active);
+
+            // Closed assignments cannot be reported lost
+            assert!(!a.report_lost(&pool, lost_on, "user1").await?);
+
+            Ok(())
+        }
     }
 }
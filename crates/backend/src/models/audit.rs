@@ -0,0 +1,351 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use super::Db;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "audit_result", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum AuditResult {
+    Verified,
+    Missing,
+    WrongHolder,
+}
+
+/// A physical check that every holder still has the keys assigned to them. Starting an audit
+/// snapshots the open assignments into a checklist of items.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct Audit {
+    #[serde(skip_deserializing)]
+    pub id: i64,
+    pub name: String,
+    #[serde(skip_deserializing)]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(skip_deserializing)]
+    pub started_by: Option<String>, // Foreign key to User::username
+    #[serde(skip_deserializing)]
+    pub closed_at: Option<DateTime<Utc>>,
+    #[serde(skip_deserializing)]
+    pub closed_by: Option<String>, // Foreign key to User::username
+    #[serde(skip_deserializing)]
+    pub items: i64,
+    #[serde(skip_deserializing)]
+    pub unchecked: i64,
+    #[serde(skip_deserializing)]
+    pub discrepancies: i64,
+}
+
+impl Audit {
+    pub async fn get(pool: &Db, org: i64, id: i64) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            r#"SELECT
+                a.id,
+                a.name,
+                a.started_at as "started_at?",
+                a.started_by,
+                a.closed_at,
+                a.closed_by,
+                count(i.id) as "items!",
+                count(i.id) FILTER (WHERE i.result IS NULL) as "unchecked!",
+                count(i.id) FILTER (WHERE i.result <> 'verified') as "discrepancies!"
+            FROM audits a
+            LEFT JOIN audit_items i ON i.audit_id = a.id
+            WHERE a.id = $1 AND a.org_id = $2
+            GROUP BY a.id"#,
+            id,
+            org
+        )
+    }
+
+    pub async fn get_all(pool: &Db, org: i64) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT
+                a.id,
+                a.name,
+                a.started_at as "started_at?",
+                a.started_by,
+                a.closed_at,
+                a.closed_by,
+                count(i.id) as "items!",
+                count(i.id) FILTER (WHERE i.result IS NULL) as "unchecked!",
+                count(i.id) FILTER (WHERE i.result <> 'verified') as "discrepancies!"
+            FROM audits a
+            LEFT JOIN audit_items i ON i.audit_id = a.id
+            WHERE a.org_id = $1
+            GROUP BY a.id
+            ORDER BY a.id DESC"#,
+            org
+        )
+    }
+
+    /// Starts the audit with an item for every open, confirmed assignment of the org's keys.
+    pub async fn create(&mut self, pool: &Db, org: i64) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        self.id = db_query_scalar!(
+            i64,
+            fetch_one,
+            &mut tx,
+            "INSERT INTO audits (org_id, name, started_by) VALUES ($1, $2, $3) RETURNING id",
+            org,
+            self.name,
+            self.started_by
+        )?;
+
+        db_execute!(
+            &mut tx,
+            r#"INSERT INTO audit_items (audit_id, assignment_id, "user", key)
+            SELECT $1, a.id, COALESCE(u.username, g.name), k.name
+            FROM assignments a
+            LEFT JOIN users u ON u.id = a.user_id
+            LEFT JOIN user_groups g ON g.id = a.group_id
+            JOIN keys k ON k.id = a.key_id
+            WHERE k.org_id = $2 AND a.date_in IS NULL AND NOT a.pending AND a.deleted_at IS NULL
+            ORDER BY 3, k.name"#,
+            self.id,
+            org
+        )?;
+
+        tx.commit().await
+    }
+
+    /// Closes the audit so its items can no longer be checked. Returns false if the audit is
+    /// already closed.
+    pub async fn close(&mut self, pool: &Db, closed_by: &str) -> Result<bool, sqlx::Error> {
+        let closed_at = db_query_scalar!(
+            Option<DateTime<Utc>>,
+            fetch_optional,
+            pool,
+            r#"UPDATE audits SET closed_at = now(), closed_by = $1
+            WHERE id = $2 AND closed_at IS NULL
+            RETURNING closed_at"#,
+            closed_by,
+            self.id
+        )?;
+
+        match closed_at {
+            Some(c) => {
+                self.closed_at = c;
+                self.closed_by = Some(closed_by.into());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub async fn delete(&self, pool: &Db) -> Result<u64, sqlx::Error> {
+        db_execute!(pool, "DELETE FROM audits WHERE id = $1", self.id)
+    }
+}
+
+/// A single assignment to be checked during an audit.
+#[derive(Debug, PartialEq, Eq, Clone, FromRow, Serialize, ToSchema)]
+pub struct AuditItem {
+    pub id: i64,
+    pub audit_id: i64,
+    pub assignment_id: Option<i64>,
+    pub user: String, // Username of the holder, or the group's name for keys held by a group
+    pub key: String,
+    pub result: Option<AuditResult>,
+    pub actual_holder: Option<String>,
+    pub note: Option<String>,
+    pub checked_by: Option<String>, // Foreign key to User::username
+    pub checked_at: Option<DateTime<Utc>>,
+    pub lost_reported: bool,
+}
+
+impl AuditItem {
+    pub async fn get(pool: &Db, id: i64) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            r#"SELECT
+                id,
+                audit_id,
+                assignment_id,
+                "user",
+                key,
+                result as "result: AuditResult",
+                actual_holder,
+                note,
+                checked_by,
+                checked_at,
+                lost_reported
+            FROM audit_items
+            WHERE id = $1"#,
+            id
+        )
+    }
+
+    /// Returns the audit's items ordered by holder then key. With `discrepancies`, only items
+    /// which weren't verified are returned, including those which were never checked.
+    pub async fn get_by_audit(
+        pool: &Db,
+        audit_id: i64,
+        discrepancies: bool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT
+                id,
+                audit_id,
+                assignment_id,
+                "user",
+                key,
+                result as "result: AuditResult",
+                actual_holder,
+                note,
+                checked_by,
+                checked_at,
+                lost_reported
+            FROM audit_items
+            WHERE audit_id = $1 AND (NOT $2 OR result IS DISTINCT FROM 'verified')
+            ORDER BY "user", key"#,
+            audit_id,
+            discrepancies
+        )
+    }
+
+    /// Records the outcome of checking the item. `actual_holder` is only kept for keys found
+    /// with the wrong holder.
+    pub async fn check(
+        &mut self,
+        pool: &Db,
+        result: AuditResult,
+        actual_holder: Option<String>,
+        note: Option<String>,
+        checked_by: &str,
+    ) -> Result<(), sqlx::Error> {
+        let actual_holder = match result {
+            AuditResult::WrongHolder => actual_holder,
+            _ => None,
+        };
+
+        self.checked_at = db_query_scalar!(
+            Option<DateTime<Utc>>,
+            fetch_one,
+            pool,
+            r#"UPDATE audit_items
+            SET result = $1, actual_holder = $2, note = $3, checked_by = $4, checked_at = now()
+            WHERE id = $5
+            RETURNING checked_at"#,
+            result as AuditResult,
+            actual_holder,
+            note,
+            checked_by,
+            self.id
+        )?;
+
+        self.result = Some(result);
+        self.actual_holder = actual_holder;
+        self.note = note;
+        self.checked_by = Some(checked_by.into());
+        Ok(())
+    }
+
+    pub async fn mark_lost_reported(&mut self, pool: &Db) -> Result<(), sqlx::Error> {
+        db_execute!(
+            pool,
+            "UPDATE audit_items SET lost_reported = true WHERE id = $1",
+            self.id
+        )?;
+
+        self.lost_reported = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod audit_tests {
+    use crate::models::{Assignment, Audit, AuditItem, AuditResult, DEFAULT_ORG};
+    use anyhow::Result;
+    use chrono::NaiveDate;
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys", "assignments"))]
+        async fn snapshot_open_assignments(pool: Db) -> Result<()> {
+            let mut returned = Assignment::new("user1", "key3", NaiveDate::from_ymd(1988, 10, 3));
+            returned.create(&pool, DEFAULT_ORG).await?;
+            returned.date_in = Some(NaiveDate::from_ymd(1988, 11, 3));
+            returned.update(&pool, DEFAULT_ORG, "user1").await?;
+
+            let mut audit = Audit {
+                name: "Annual audit".into(),
+                started_by: Some("user1".into()),
+                ..Default::default()
+            };
+            audit.create(&pool, DEFAULT_ORG).await?;
+
+            let items = AuditItem::get_by_audit(&pool, audit.id, false).await?;
+            assert_eq!(1, items.len());
+            assert_eq!("key1", items[0].key);
+
+            // Later assignments are not part of the audit
+            let mut later = Assignment::new("userCanLogin", "key3", NaiveDate::from_ymd(1988, 12, 3));
+            later.create(&pool, DEFAULT_ORG).await?;
+            assert_eq!(1, Audit::get(&pool, DEFAULT_ORG, audit.id).await?.items);
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys", "assignments"))]
+        async fn discrepancy_report(pool: Db) -> Result<()> {
+            let mut a = Assignment::new("userCanLogin", "key3", NaiveDate::from_ymd(1988, 10, 3));
+            a.create(&pool, DEFAULT_ORG).await?;
+
+            let mut audit = Audit {
+                name: "Annual audit".into(),
+                ..Default::default()
+            };
+            audit.create(&pool, DEFAULT_ORG).await?;
+            let mut items = AuditItem::get_by_audit(&pool, audit.id, false).await?;
+            assert_eq!(2, Audit::get(&pool, DEFAULT_ORG, audit.id).await?.unchecked);
+
+            items[0]
+                .check(
+                    &pool,
+                    AuditResult::Verified,
+                    Some("user1".into()),
+                    None,
+                    "user1",
+                )
+                .await?;
+            assert_eq!(None, items[0].actual_holder);
+            items[1]
+                .check(
+                    &pool,
+                    AuditResult::WrongHolder,
+                    Some("user1".into()),
+                    Some("Borrowed".into()),
+                    "user1",
+                )
+                .await?;
+
+            let report = AuditItem::get_by_audit(&pool, audit.id, true).await?;
+            assert_eq!(vec![items[1].clone()], report);
+
+            let stored = Audit::get(&pool, DEFAULT_ORG, audit.id).await?;
+            assert_eq!(0, stored.unchecked);
+            assert_eq!(1, stored.discrepancies);
+
+            assert!(audit.close(&pool, "user1").await?);
+            assert!(!audit.close(&pool, "user1").await?);
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use std::{collections::BTreeMap, fmt};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{
+    postgres::PgConnection,
+    query::Query,
+    sqlite::{SqliteArguments, SqliteConnection},
+    Sqlite,
+};
+use utoipa::ToSchema;
+
+use super::{Db, Tx};
+
+/// Version of the backup layout. Bump it when the layout changes in a way older restores can't
+/// read.
+pub const BACKUP_FORMAT: u32 = 1;
+
+/// Tables in the order they are restored, so that the rows each one refers to already exist.
+/// Queued emails, job runs and webhook deliveries are left out since they only matter to the
+/// server that produced them.
+const TABLES: &[&str] = &[
+    "orgs",
+    "users",
+    "org_members",
+    "user_groups",
+    "user_group_members",
+    "cabinets",
+    "slots",
+    "keys",
+    "assignments",
+    "assignment_changes",
+    "charges",
+    "key_requests",
+    "key_request_items",
+    "notification_opt_outs",
+    "audits",
+    "audit_items",
+    "webhooks",
+];
+
+/// Columns which SQLite stores as JSON text rather than as arrays.
+const JSON_COLUMNS: &[(&str, &str)] = &[("webhooks", "events")];
+
+/// Every row of every table holding KeyMaster's data, as JSON objects keyed by column name.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Backup {
+    pub format: u32,
+    /// The last migration applied to the database the backup was taken from.
+    pub schema_version: i64,
+    pub created_at: DateTime<Utc>,
+    pub includes_passwords: bool,
+    #[schema(value_type = Object)]
+    pub tables: BTreeMap<String, Vec<Value>>,
+}
+
+/// Reasons a backup is refused by `Backup::restore`.
+#[derive(Debug)]
+pub enum BackupError {
+    UnsupportedFormat(u32),
+    SchemaMismatch { backup: i64, database: i64 },
+    UnknownTable(String),
+    InvalidRow(String),
+    NotEmpty(String),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackupError::UnsupportedFormat(v) => write!(
+                f,
+                "Backup format {} is not supported. Expected {}",
+                v, BACKUP_FORMAT
+            ),
+            BackupError::SchemaMismatch { backup, database } => write!(
+                f,
+                "Backup was taken at schema version {} but the database is at {}. Restore it with the KeyMaster release it was taken with",
+                backup, database
+            ),
+            BackupError::UnknownTable(t) => write!(f, "Backup contains unknown table '{}'", t),
+            BackupError::InvalidRow(t) => {
+                write!(f, "Backup contains a row in '{}' which isn't an object", t)
+            }
+            BackupError::NotEmpty(t) => write!(
+                f,
+                "Table '{}' is not empty. Backups can only be restored into an empty database",
+                t
+            ),
+            BackupError::Database(e) => write!(f, "Database error. {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+impl From<sqlx::Error> for BackupError {
+    fn from(e: sqlx::Error) -> Self {
+        BackupError::Database(e)
+    }
+}
+
+impl Backup {
+    /// Reads every table in one transaction so that the backup is consistent. Password hashes
+    /// are left out unless `include_passwords` is set.
+    pub async fn create(pool: &Db, include_passwords: bool) -> Result<Self, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        if let Tx::Postgres(tx) = &mut tx {
+            sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+                .execute(&mut **tx)
+                .await?;
+        }
+
+        let mut tables = BTreeMap::new();
+        for table in TABLES {
+            let rows = match &mut tx {
+                Tx::Postgres(tx) => read_postgres(tx, table, include_passwords).await?,
+                Tx::Sqlite(tx) => read_sqlite(tx, table, include_passwords).await?,
+            };
+            let rows = rows
+                .iter()
+                .map(|r| serde_json::from_str(r))
+                .collect::<Result<_, _>>()
+                .map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
+            tables.insert(table.to_string(), rows);
+        }
+
+        Ok(Backup {
+            format: BACKUP_FORMAT,
+            schema_version: schema_version(&mut tx).await?,
+            created_at: Utc::now(),
+            includes_passwords: include_passwords,
+            tables,
+        })
+    }
+
+    /// Loads the backup into a database which has been migrated to the same schema version but
+    /// holds no data. Nothing is written unless every table is restored.
+    pub async fn restore(&self, pool: &Db) -> Result<(), BackupError> {
+        if self.format != BACKUP_FORMAT {
+            return Err(BackupError::UnsupportedFormat(self.format));
+        }
+        for (table, rows) in &self.tables {
+            if !TABLES.contains(&table.as_str()) {
+                return Err(BackupError::UnknownTable(table.clone()));
+            }
+            if !rows.iter().all(Value::is_object) {
+                return Err(BackupError::InvalidRow(table.clone()));
+            }
+        }
+
+        let mut tx = pool.begin().await?;
+
+        let database = schema_version(&mut tx).await?;
+        if self.schema_version != database {
+            return Err(BackupError::SchemaMismatch {
+                backup: self.schema_version,
+                database,
+            });
+        }
+
+        // A freshly migrated database already has the default org, which the backup's orgs
+        // replace once nothing else is found
+        for table in TABLES.iter().filter(|t| **t != "orgs") {
+            let query = format!("SELECT EXISTS (SELECT 1 FROM {})", table);
+            let exists: bool = match &mut tx {
+                Tx::Postgres(tx) => sqlx::query_scalar(&query).fetch_one(&mut **tx).await?,
+                Tx::Sqlite(tx) => sqlx::query_scalar(&query).fetch_one(tx).await?,
+            };
+            if exists {
+                return Err(BackupError::NotEmpty(table.to_string()));
+            }
+        }
+        db_execute!(&mut tx, "DELETE FROM orgs")?;
+
+        for table in TABLES {
+            let rows = match self.tables.get(*table) {
+                Some(r) if !r.is_empty() => r,
+                _ => continue,
+            };
+            match &mut tx {
+                Tx::Postgres(tx) => write_postgres(tx, table, rows).await?,
+                Tx::Sqlite(tx) => write_sqlite(tx, table, rows).await?,
+            }
+        }
+
+        Ok(tx.commit().await?)
+    }
+}
+
+async fn schema_version(tx: &mut Tx) -> Result<i64, sqlx::Error> {
+    db_query_scalar!(
+        i64,
+        fetch_one,
+        &mut *tx,
+        r#"SELECT max(version) as "version!" FROM _sqlx_migrations WHERE success"#
+    )
+}
+
+/// Returns the rows of `table` as JSON text.
+async fn read_postgres(
+    conn: &mut PgConnection,
+    table: &str,
+    include_passwords: bool,
+) -> Result<Vec<String>, sqlx::Error> {
+    let row = if table == "users" && !include_passwords {
+        "to_jsonb(t) - 'password_hash'"
+    } else {
+        "to_jsonb(t)"
+    };
+    sqlx::query_scalar(&format!(
+        "SELECT ({})::text FROM {} t ORDER BY 1",
+        row, table
+    ))
+    .fetch_all(conn)
+    .await
+}
+
+/// Returns the rows of `table` as JSON text. SQLite stores booleans as integers and arrays as
+/// JSON text, so those columns are converted to match the Postgres backups.
+async fn read_sqlite(
+    conn: &mut SqliteConnection,
+    table: &str,
+    include_passwords: bool,
+) -> Result<Vec<String>, sqlx::Error> {
+    let columns: Vec<(String, String)> =
+        sqlx::query_as("SELECT name, type FROM pragma_table_info($1) ORDER BY cid")
+            .bind(table)
+            .fetch_all(&mut *conn)
+            .await?;
+
+    let fields = columns
+        .iter()
+        .filter(|(c, _)| include_passwords || !(table == "users" && c == "password_hash"))
+        .map(|(c, ty)| {
+            let value = if ty == "BOOLEAN" {
+                format!(
+                    r#"json(CASE WHEN t."{0}" IS NULL THEN 'null' WHEN t."{0}" THEN 'true' ELSE 'false' END)"#,
+                    c
+                )
+            } else if JSON_COLUMNS.contains(&(table, c.as_str())) {
+                format!(r#"json(t."{}")"#, c)
+            } else {
+                format!(r#"t."{}""#, c)
+            };
+            format!("'{}', {}", c, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    sqlx::query_scalar(&format!(
+        "SELECT json_object({}) FROM {} t ORDER BY 1",
+        fields, table
+    ))
+    .fetch_all(conn)
+    .await
+}
+
+async fn write_postgres(
+    conn: &mut PgConnection,
+    table: &str,
+    rows: &[Value],
+) -> Result<(), sqlx::Error> {
+    let rows = serde_json::to_string(rows).map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
+    // Ids are kept so that the rows referring to them still line up
+    sqlx::query(&format!(
+        "INSERT INTO {0} OVERRIDING SYSTEM VALUE SELECT * FROM jsonb_populate_recordset(NULL::{0}, $1::jsonb)",
+        table
+    ))
+    .bind(rows)
+    .execute(&mut *conn)
+    .await?;
+
+    let identity: Option<String> = sqlx::query_scalar(
+        "SELECT column_name::text FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1 AND is_identity = 'YES'",
+    )
+    .bind(table)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(column) = identity {
+        sqlx::query(&format!(
+            "SELECT setval(pg_get_serial_sequence('{0}', '{1}'), max({1})) FROM {0}",
+            table, column
+        ))
+        .execute(conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Inserts the rows one at a time. Like `jsonb_populate_recordset`, fields which aren't columns
+/// of the table are ignored.
+async fn write_sqlite(
+    conn: &mut SqliteConnection,
+    table: &str,
+    rows: &[Value],
+) -> Result<(), sqlx::Error> {
+    let columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info($1)")
+        .bind(table)
+        .fetch_all(&mut *conn)
+        .await?;
+
+    for row in rows.iter().filter_map(Value::as_object) {
+        let fields: Vec<_> = row.iter().filter(|(c, _)| columns.contains(c)).collect();
+        let names = fields
+            .iter()
+            .map(|(c, _)| format!(r#""{}""#, c))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let params = vec!["?"; fields.len()].join(", ");
+        let sql = format!("INSERT INTO {} ({}) VALUES ({})", table, names, params);
+
+        let mut query = sqlx::query(&sql);
+        for (_, value) in fields {
+            query = bind_json(query, value);
+        }
+        query.execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+fn bind_json<'q>(
+    query: Query<'q, Sqlite, SqliteArguments<'q>>,
+    value: &'q Value,
+) -> Query<'q, Sqlite, SqliteArguments<'q>> {
+    match value {
+        Value::Null => query.bind(None::<String>),
+        Value::Bool(b) => query.bind(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => query.bind(i),
+            None => query.bind(n.as_f64()),
+        },
+        Value::String(s) => query.bind(s.as_str()),
+        v => query.bind(v.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod backup_tests {
+    use anyhow::Result;
+
+    use super::{BackupError, TABLES};
+    use crate::models::{Assignment, AssignmentQuery, Backup, Key, User, DEFAULT_ORG};
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys", "assignments"))]
+        async fn backup_and_restore(pool: Db) -> Result<()> {
+            let backup = Backup::create(&pool, true).await?;
+            assert_eq!(3, backup.tables["keys"].len());
+
+            let without = Backup::create(&pool, false).await?;
+            assert!(without.tables["users"][0].get("password_hash").is_none());
+
+            assert!(matches!(
+                backup.restore(&pool).await,
+                Err(BackupError::NotEmpty(t)) if t == "users"
+            ));
+
+            let json = serde_json::to_string(&backup)?;
+            for table in TABLES.iter().rev() {
+                pool.execute(&format!("DELETE FROM {}", table)).await?;
+            }
+            // As freshly migrated
+            pool.execute("INSERT INTO orgs (name) VALUES ('Default')").await?;
+            let mut backup: Backup = serde_json::from_str(&json)?;
+            backup.restore(&pool).await?;
+
+            let restored = Backup::create(&pool, true).await?;
+            assert_eq!(backup.tables, restored.tables);
+            let user = User::get(&pool, DEFAULT_ORG, "userCanLogin").await?;
+            assert!(user.can_login);
+            let assignments = Assignment::get_all(&pool, DEFAULT_ORG, AssignmentQuery::default()).await?;
+            assert!(!assignments.is_empty());
+
+            // New rows get ids after the restored ones
+            let mut key = Key {
+                name: "key4".into(),
+                ..Default::default()
+            };
+            key.create(&pool, DEFAULT_ORG).await?;
+            assert!(key.id > 3);
+
+            backup.format += 1;
+            assert!(matches!(
+                backup.restore(&pool).await,
+                Err(BackupError::UnsupportedFormat(_))
+            ));
+
+            Ok(())
+        }
+    }
+}
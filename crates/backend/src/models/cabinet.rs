@@ -0,0 +1,371 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use super::Db;
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct Cabinet {
+    #[serde(skip_deserializing)]
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+}
+
+/// A numbered hook or slot in a cabinet along with the key currently stored in it.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct Slot {
+    pub id: i64,
+    pub cabinet_id: i64, // Foreign key to Cabinet::id
+    pub cabinet: String, // Cabinet::name
+    pub number: i32,
+    pub key: Option<String>, // Key::name of the stored key
+}
+
+/// Where a key currently is. A key with an open assignment is with the assigned user or group
+/// regardless of any slot it may also have been given.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KeyLocation {
+    User {
+        username: String,
+    },
+    Group {
+        name: String,
+    },
+    Slot {
+        cabinet_id: i64,
+        cabinet: String,
+        number: i32,
+    },
+    Unknown,
+}
+
+/// SQLite has no `generate_series`, so slots are numbered with a recursive query instead.
+const SQLITE_INSERT_SLOTS: &str = r#"WITH RECURSIVE n(number) AS (
+        SELECT 1 WHERE $2 >= 1
+        UNION ALL SELECT number + 1 FROM n WHERE number < $2
+    )
+    INSERT INTO slots (cabinet_id, number) SELECT $1, number FROM n"#;
+
+impl Cabinet {
+    pub async fn get(pool: &Db, org: i64, id: i64) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            "SELECT id, name, description FROM cabinets WHERE id = $1 AND org_id = $2",
+            id,
+            org
+        )
+    }
+
+    pub async fn get_all(pool: &Db, org: i64) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            "SELECT id, name, description FROM cabinets WHERE org_id = $1 ORDER BY name",
+            org
+        )
+    }
+
+    /// Creates the cabinet with slots numbered `1..=slots`.
+    pub async fn create(&mut self, pool: &Db, org: i64, slots: i32) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        self.id = db_query_scalar!(
+            i64,
+            fetch_one,
+            &mut tx,
+            "INSERT INTO cabinets (org_id, name, description) VALUES ($1, $2, $3) RETURNING id",
+            org,
+            self.name,
+            self.description
+        )?;
+
+        db_execute!(
+            &mut tx,
+            "INSERT INTO slots (cabinet_id, number) SELECT $1, generate_series(1, $2)",
+            sqlite = SQLITE_INSERT_SLOTS,
+            self.id,
+            slots
+        )?;
+
+        tx.commit().await
+    }
+
+    pub async fn update(&self, pool: &Db) -> Result<u64, sqlx::Error> {
+        db_execute!(
+            pool,
+            "UPDATE cabinets SET name = $1, description = $2 WHERE id = $3",
+            self.name,
+            self.description,
+            self.id
+        )
+    }
+
+    /// Adds or removes slots at the end of the cabinet so that it has `slots` slots. Returns
+    /// false, changing nothing, if a slot which would be removed still holds a key.
+    pub async fn set_slot_count(&self, pool: &Db, slots: i32) -> Result<bool, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let occupied = db_query_scalar!(
+            i64,
+            fetch_one,
+            &mut tx,
+            r#"SELECT count(*) as "count!"
+            FROM slots s
+            JOIN keys k ON k.slot_id = s.id
+            WHERE s.cabinet_id = $1 AND s.number > $2"#,
+            self.id,
+            slots
+        )?;
+        if occupied > 0 {
+            return Ok(false);
+        }
+
+        db_execute!(
+            &mut tx,
+            "DELETE FROM slots WHERE cabinet_id = $1 AND number > $2",
+            self.id,
+            slots
+        )?;
+
+        db_execute!(
+            &mut tx,
+            r#"INSERT INTO slots (cabinet_id, number)
+            SELECT $1, n FROM generate_series(1, $2) n
+            ON CONFLICT DO NOTHING"#,
+            sqlite = &SQLITE_INSERT_SLOTS.replacen("INSERT", "INSERT OR IGNORE", 1),
+            self.id,
+            slots
+        )?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    pub async fn delete(&self, pool: &Db) -> Result<u64, sqlx::Error> {
+        db_execute!(pool, "DELETE FROM cabinets WHERE id = $1", self.id)
+    }
+}
+
+impl Slot {
+    pub async fn get(pool: &Db, org: i64, id: i64) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            r#"SELECT
+                s.id,
+                s.cabinet_id,
+                c.name as cabinet,
+                s.number,
+                k.name as "key?"
+            FROM slots s
+            JOIN cabinets c ON c.id = s.cabinet_id
+            LEFT JOIN keys k ON k.slot_id = s.id
+            WHERE s.id = $1 AND c.org_id = $2"#,
+            id,
+            org
+        )
+    }
+
+    pub async fn get_all(pool: &Db, org: i64) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT
+                s.id,
+                s.cabinet_id,
+                c.name as cabinet,
+                s.number,
+                k.name as "key?"
+            FROM slots s
+            JOIN cabinets c ON c.id = s.cabinet_id
+            LEFT JOIN keys k ON k.slot_id = s.id
+            WHERE c.org_id = $1
+            ORDER BY c.name, s.number"#,
+            org
+        )
+    }
+
+    pub async fn get_by_cabinet(pool: &Db, cabinet_id: i64) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT
+                s.id,
+                s.cabinet_id,
+                c.name as cabinet,
+                s.number,
+                k.name as "key?"
+            FROM slots s
+            JOIN cabinets c ON c.id = s.cabinet_id
+            LEFT JOIN keys k ON k.slot_id = s.id
+            WHERE s.cabinet_id = $1
+            ORDER BY s.number"#,
+            cabinet_id
+        )
+    }
+
+    /// Stores the given key of the cabinet's org in this slot, taking it out of any slot it was
+    /// previously in.
+    pub async fn store_key(&self, pool: &Db, key_name: &str) -> Result<u64, sqlx::Error> {
+        db_execute!(
+            pool,
+            r#"UPDATE keys SET slot_id = $1
+            WHERE name = $2 AND org_id = (SELECT org_id FROM cabinets WHERE id = $3)"#,
+            self.id,
+            key_name,
+            self.cabinet_id
+        )
+    }
+}
+
+impl KeyLocation {
+    pub async fn get(pool: &Db, org: i64, key_name: &str) -> Result<Self, sqlx::Error> {
+        let holder = db_query_scalar!(
+            String,
+            fetch_optional,
+            pool,
+            r#"SELECT u.username
+            FROM assignments a
+            JOIN users u ON u.id = a.user_id
+            JOIN keys k ON k.id = a.key_id
+            WHERE k.name = $1 AND k.org_id = $2
+            AND a.date_in IS NULL AND NOT a.pending AND a.deleted_at IS NULL
+            ORDER BY a.date_out DESC
+            LIMIT 1"#,
+            key_name,
+            org
+        )?;
+        if let Some(username) = holder {
+            return Ok(KeyLocation::User { username });
+        }
+
+        let group = db_query_scalar!(
+            String,
+            fetch_optional,
+            pool,
+            r#"SELECT g.name
+            FROM assignments a
+            JOIN user_groups g ON g.id = a.group_id
+            JOIN keys k ON k.id = a.key_id
+            WHERE k.name = $1 AND k.org_id = $2
+            AND a.date_in IS NULL AND NOT a.pending AND a.deleted_at IS NULL
+            ORDER BY a.date_out DESC
+            LIMIT 1"#,
+            key_name,
+            org
+        )?;
+        if let Some(name) = group {
+            return Ok(KeyLocation::Group { name });
+        }
+
+        let slot = db_query_as!(
+            Slot,
+            fetch_optional,
+            pool,
+            r#"SELECT
+                s.id,
+                s.cabinet_id,
+                c.name as cabinet,
+                s.number,
+                k.name as "key?"
+            FROM keys k
+            JOIN slots s ON s.id = k.slot_id
+            JOIN cabinets c ON c.id = s.cabinet_id
+            WHERE k.name = $1 AND k.org_id = $2"#,
+            key_name,
+            org
+        )?;
+
+        Ok(match slot {
+            Some(s) => KeyLocation::Slot {
+                cabinet_id: s.cabinet_id,
+                cabinet: s.cabinet,
+                number: s.number,
+            },
+            None => KeyLocation::Unknown,
+        })
+    }
+}
+
+#[cfg(test)]
+mod cabinet_tests {
+    use crate::models::{Cabinet, Db, KeyLocation, Slot, DEFAULT_ORG};
+    use anyhow::Result;
+
+    async fn cabinet_b(pool: &Db) -> Result<Cabinet> {
+        let mut cabinet = Cabinet {
+            name: "B".into(),
+            description: Some("Lock box by the front desk".into()),
+            ..Default::default()
+        };
+        cabinet.create(pool, DEFAULT_ORG, 20).await?;
+        Ok(cabinet)
+    }
+
+    db_test! {
+        #[sqlx::test]
+        async fn create_cabinet(pool: Db) -> Result<()> {
+            let cabinet = cabinet_b(&pool).await?;
+            let slots = Slot::get_by_cabinet(&pool, cabinet.id).await?;
+
+            assert_eq!(20, slots.len());
+            assert_eq!(1, slots[0].number);
+            assert_eq!(20, slots[19].number);
+            assert!(slots.iter().all(|s| s.key.is_none()));
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("keys"))]
+        async fn set_slot_count(pool: Db) -> Result<()> {
+            let cabinet = cabinet_b(&pool).await?;
+            let slots = Slot::get_by_cabinet(&pool, cabinet.id).await?;
+            slots[16].store_key(&pool, "key1").await?;
+
+            assert!(cabinet.set_slot_count(&pool, 25).await?);
+            assert_eq!(25, Slot::get_by_cabinet(&pool, cabinet.id).await?.len());
+
+            assert!(!cabinet.set_slot_count(&pool, 10).await?);
+            assert_eq!(25, Slot::get_by_cabinet(&pool, cabinet.id).await?.len());
+
+            assert!(cabinet.set_slot_count(&pool, 17).await?);
+            assert_eq!(17, Slot::get_by_cabinet(&pool, cabinet.id).await?.len());
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("keys"))]
+        async fn key_location(pool: Db) -> Result<()> {
+            assert_eq!(KeyLocation::Unknown, KeyLocation::get(&pool, DEFAULT_ORG, "key1").await?);
+
+            let cabinet = cabinet_b(&pool).await?;
+            let slot = Slot::get_by_cabinet(&pool, cabinet.id).await?[16].clone();
+            slot.store_key(&pool, "key1").await?;
+
+            assert_eq!(
+                KeyLocation::Slot {
+                    cabinet_id: cabinet.id,
+                    cabinet: "B".into(),
+                    number: 17
+                },
+                KeyLocation::get(&pool, DEFAULT_ORG, "key1").await?
+            );
+            assert_eq!(Some("key1".into()), Slot::get(&pool, DEFAULT_ORG, slot.id).await?.key);
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,438 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use super::Db;
+
+/// Returns the currency to store alongside an optional amount. Amounts given without a currency
+/// use `default`, the configured `finance.currency`, and the currency is dropped when there is
+/// no amount.
+pub fn currency_for(
+    amount: Option<i64>,
+    currency: Option<String>,
+    default: &str,
+) -> Result<Option<String>, String> {
+    match amount {
+        None => Ok(None),
+        Some(a) if a < 0 => Err("Amounts cannot be negative.".into()),
+        Some(_) => {
+            let currency = currency
+                .filter(|c| !c.is_empty())
+                .unwrap_or_else(|| default.into())
+                .to_uppercase();
+            if currency.len() == 3 && currency.chars().all(|c| c.is_ascii_alphabetic()) {
+                Ok(Some(currency))
+            } else {
+                Err(format!("'{}' is not a valid currency code.", currency))
+            }
+        }
+    }
+}
+
+/// A fee charged to a user, such as for a lost key. Amounts are in minor units of the currency.
+#[derive(Debug, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct Charge {
+    #[serde(skip_deserializing)]
+    pub id: i64,
+    pub user: String,        // Foreign key to User::username
+    pub key: Option<String>, // Foreign key to Key::name
+    pub amount: i64,
+    #[serde(default)]
+    pub currency: String,
+    pub date: NaiveDate,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub paid: bool,
+}
+
+/// A user's deposits held and charges owed in one currency. Deposits on keys held by a group
+/// aren't any one user's, so they only show up in the group's `GroupBalance`.
+#[derive(Debug, PartialEq, Eq, Clone, FromRow, Serialize, ToSchema)]
+pub struct Balance {
+    pub user: String,
+    pub currency: String,
+    pub deposits_held: i64,
+    pub charges_outstanding: i64,
+    pub charges_paid: i64,
+}
+
+/// The balances of a group's members rolled up in one currency, along with deposits on keys
+/// the group holds. Users in several groups count towards each of them.
+#[derive(Debug, PartialEq, Eq, Clone, FromRow, Serialize, ToSchema)]
+pub struct GroupBalance {
+    pub group: String,
+    pub currency: String,
+    pub deposits_held: i64,
+    pub charges_outstanding: i64,
+    pub charges_paid: i64,
+}
+
+/// A single money movement for the finance export. Deposits on keys held by a group have a
+/// `group` instead of a `user`.
+#[derive(Debug, PartialEq, Eq, Clone, FromRow, Serialize)]
+pub struct FinanceEntry {
+    pub date: Option<NaiveDate>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub key: Option<String>,
+    pub entry_type: String,
+    pub amount: i64,
+    pub currency: String,
+    pub paid: Option<bool>,
+}
+
+impl Charge {
+    pub async fn get(pool: &Db, org: i64, id: i64) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            r#"SELECT c.id, c."user", k.name as "key?", c.amount, c.currency, c.date, c.description, c.paid
+            FROM charges c
+            LEFT JOIN keys k ON k.id = c.key_id
+            WHERE c.id = $1 AND c.org_id = $2"#,
+            id,
+            org
+        )
+    }
+
+    pub async fn get_all(
+        pool: &Db,
+        org: i64,
+        user: Option<&str>,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT c.id, c."user", k.name as "key?", c.amount, c.currency, c.date, c.description, c.paid
+            FROM charges c
+            LEFT JOIN keys k ON k.id = c.key_id
+            WHERE c.org_id = $1 AND (CAST($2 AS VARCHAR) IS NULL OR c."user" = $2)
+            ORDER BY c.date DESC, c.id DESC"#,
+            org,
+            user
+        )
+    }
+
+    pub async fn create(&mut self, pool: &Db, org: i64) -> Result<(), sqlx::Error> {
+        self.id = db_query_scalar!(
+            i64,
+            fetch_one,
+            pool,
+            r#"INSERT INTO charges (org_id, "user", key_id, amount, currency, date, description, paid)
+            VALUES ($1, $2, (SELECT id FROM keys WHERE org_id = $1 AND name = $3), $4, $5, $6, $7, $8)
+            RETURNING id"#,
+            org,
+            self.user,
+            self.key,
+            self.amount,
+            self.currency,
+            self.date,
+            self.description,
+            self.paid
+        )?;
+        Ok(())
+    }
+
+    pub async fn update(&self, pool: &Db) -> Result<u64, sqlx::Error> {
+        db_execute!(
+            pool,
+            r#"UPDATE charges
+            SET
+                key_id = (SELECT id FROM keys WHERE org_id = charges.org_id AND name = $1),
+                amount = $2,
+                currency = $3,
+                date = $4,
+                description = $5,
+                paid = $6
+            WHERE id = $7"#,
+            self.key,
+            self.amount,
+            self.currency,
+            self.date,
+            self.description,
+            self.paid,
+            self.id
+        )
+    }
+
+    pub async fn delete(&self, pool: &Db) -> Result<u64, sqlx::Error> {
+        db_execute!(pool, "DELETE FROM charges WHERE id = $1", self.id)
+    }
+}
+
+impl Balance {
+    pub async fn get_all(pool: &Db, org: i64) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"WITH deposits AS (
+                SELECT
+                    u.username AS "user",
+                    a.deposit_currency AS currency,
+                    SUM(COALESCE(a.deposit_collected, 0) - COALESCE(a.deposit_refunded, 0)) AS held
+                FROM assignments a
+                JOIN users u ON u.id = a.user_id
+                JOIN keys k ON k.id = a.key_id
+                WHERE k.org_id = $1 AND a.deposit_currency IS NOT NULL AND a.deleted_at IS NULL
+                GROUP BY u.username, a.deposit_currency
+            ),
+            owed AS (
+                SELECT
+                    "user",
+                    currency,
+                    SUM(amount) FILTER (WHERE NOT paid) AS outstanding,
+                    SUM(amount) FILTER (WHERE paid) AS paid
+                FROM charges
+                WHERE org_id = $1
+                GROUP BY "user", currency
+            ),
+            balances AS (
+                SELECT "user", currency FROM deposits
+                UNION
+                SELECT "user", currency FROM owed
+            )
+            SELECT
+                b."user" as "user!",
+                b.currency as "currency!",
+                CAST(COALESCE(d.held, 0) AS BIGINT) as "deposits_held!",
+                CAST(COALESCE(o.outstanding, 0) AS BIGINT) as "charges_outstanding!",
+                CAST(COALESCE(o.paid, 0) AS BIGINT) as "charges_paid!"
+            FROM balances b
+            LEFT JOIN deposits d ON d."user" = b."user" AND d.currency = b.currency
+            LEFT JOIN owed o ON o."user" = b."user" AND o.currency = b.currency
+            ORDER BY 1, 2"#,
+            org
+        )
+    }
+}
+
+impl GroupBalance {
+    pub async fn get_all(pool: &Db, org: i64) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"WITH holders AS (
+                SELECT id, group_id FROM assignments WHERE group_id IS NOT NULL
+                UNION ALL
+                SELECT a.id, gm.group_id
+                FROM assignments a
+                JOIN user_group_members gm ON gm.user_id = a.user_id
+            ),
+            deposits AS (
+                SELECT
+                    h.group_id,
+                    a.deposit_currency AS currency,
+                    SUM(COALESCE(a.deposit_collected, 0) - COALESCE(a.deposit_refunded, 0)) AS held
+                FROM holders h
+                JOIN assignments a ON a.id = h.id
+                JOIN keys k ON k.id = a.key_id
+                WHERE k.org_id = $1 AND a.deposit_currency IS NOT NULL AND a.deleted_at IS NULL
+                GROUP BY h.group_id, a.deposit_currency
+            ),
+            owed AS (
+                SELECT
+                    gm.group_id,
+                    c.currency,
+                    SUM(c.amount) FILTER (WHERE NOT c.paid) AS outstanding,
+                    SUM(c.amount) FILTER (WHERE c.paid) AS paid
+                FROM charges c
+                JOIN users u ON u.username = c."user"
+                JOIN user_group_members gm ON gm.user_id = u.id
+                WHERE c.org_id = $1
+                GROUP BY gm.group_id, c.currency
+            ),
+            balances AS (
+                SELECT group_id, currency FROM deposits
+                UNION
+                SELECT group_id, currency FROM owed
+            )
+            SELECT
+                g.name as "group!",
+                b.currency as "currency!",
+                CAST(COALESCE(d.held, 0) AS BIGINT) as "deposits_held!",
+                CAST(COALESCE(o.outstanding, 0) AS BIGINT) as "charges_outstanding!",
+                CAST(COALESCE(o.paid, 0) AS BIGINT) as "charges_paid!"
+            FROM balances b
+            JOIN user_groups g ON g.id = b.group_id
+            LEFT JOIN deposits d ON d.group_id = b.group_id AND d.currency = b.currency
+            LEFT JOIN owed o ON o.group_id = b.group_id AND o.currency = b.currency
+            WHERE g.org_id = $1
+            ORDER BY 1, 2"#,
+            org
+        )
+    }
+}
+
+impl FinanceEntry {
+    pub async fn get_all(pool: &Db, org: i64) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT
+                a.date_out as "date?",
+                u.username as "user?",
+                g.name as "group?",
+                k.name as key,
+                'deposit_collected' as "entry_type!",
+                a.deposit_collected as "amount!",
+                a.deposit_currency as "currency!",
+                CAST(NULL AS BOOLEAN) as paid
+            FROM assignments a
+            LEFT JOIN users u ON u.id = a.user_id
+            LEFT JOIN user_groups g ON g.id = a.group_id
+            JOIN keys k ON k.id = a.key_id
+            WHERE k.org_id = $1 AND a.deposit_collected IS NOT NULL AND a.deleted_at IS NULL
+            UNION ALL
+            SELECT
+                a.date_in,
+                u.username,
+                g.name,
+                k.name,
+                'deposit_refunded',
+                a.deposit_refunded,
+                a.deposit_currency,
+                CAST(NULL AS BOOLEAN)
+            FROM assignments a
+            LEFT JOIN users u ON u.id = a.user_id
+            LEFT JOIN user_groups g ON g.id = a.group_id
+            JOIN keys k ON k.id = a.key_id
+            WHERE k.org_id = $1 AND a.deposit_refunded IS NOT NULL AND a.deleted_at IS NULL
+            UNION ALL
+            SELECT
+                c.date,
+                c."user",
+                CAST(NULL AS VARCHAR),
+                k.name,
+                'charge',
+                c.amount,
+                c.currency,
+                c.paid
+            FROM charges c
+            LEFT JOIN keys k ON k.id = c.key_id
+            WHERE c.org_id = $1
+            ORDER BY 1 NULLS LAST, 2"#,
+            org
+        )
+    }
+}
+
+#[cfg(test)]
+mod finance_tests {
+    use crate::models::{
+        currency_for, Assignment, Balance, Charge, FinanceEntry, Group, GroupBalance, DEFAULT_ORG,
+    };
+    use anyhow::Result;
+    use chrono::NaiveDate;
+
+    fn lost_key_charge(user: &str, amount: i64, paid: bool) -> Charge {
+        Charge {
+            id: 0,
+            user: user.into(),
+            key: Some("key1".into()),
+            amount,
+            currency: "USD".into(),
+            date: NaiveDate::from_ymd(1988, 11, 3),
+            description: Some("Lost key".into()),
+            paid,
+        }
+    }
+
+    #[test]
+    fn test_currency_for() {
+        assert_eq!(Ok(None), currency_for(None, Some("EUR".into()), "USD"));
+        assert_eq!(
+            Ok(Some("EUR".into())),
+            currency_for(Some(500), Some("eur".into()), "USD")
+        );
+        assert_eq!(Ok(Some("GBP".into())), currency_for(Some(500), None, "GBP"));
+        assert!(currency_for(Some(-1), Some("EUR".into()), "USD").is_err());
+        assert!(currency_for(Some(500), Some("EURO".into()), "USD").is_err());
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn create_charge(pool: Db) -> Result<()> {
+            let mut charge = lost_key_charge("user1", 2500, false);
+            charge.create(&pool, DEFAULT_ORG).await?;
+
+            assert_eq!(charge, Charge::get(&pool, DEFAULT_ORG, charge.id).await?);
+            assert_eq!(1, Charge::get_all(&pool, DEFAULT_ORG, Some("user1")).await?.len());
+            assert_eq!(0, Charge::get_all(&pool, DEFAULT_ORG, Some("userNoPass")).await?.len());
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys", "assignments"))]
+        async fn get_balances(pool: Db) -> Result<()> {
+            pool.execute(
+                r#"UPDATE assignments
+                SET deposit_collected = 2000, deposit_refunded = 500, deposit_currency = 'USD'
+                WHERE id = 1"#,
+            )
+            .await?;
+            lost_key_charge("user1", 2500, false).create(&pool, DEFAULT_ORG).await?;
+            lost_key_charge("user1", 1000, true).create(&pool, DEFAULT_ORG).await?;
+            lost_key_charge("userNoPass", 300, false)
+                .create(&pool, DEFAULT_ORG)
+                .await?;
+
+            let balances = Balance::get_all(&pool, DEFAULT_ORG).await?;
+
+            assert_eq!(2, balances.len());
+            assert_eq!(
+                Balance {
+                    user: "user1".into(),
+                    currency: "USD".into(),
+                    deposits_held: 1500,
+                    charges_outstanding: 2500,
+                    charges_paid: 1000,
+                },
+                balances[0]
+            );
+            assert_eq!(0, balances[1].deposits_held);
+            assert_eq!(300, balances[1].charges_outstanding);
+
+            let entries = FinanceEntry::get_all(&pool, DEFAULT_ORG).await?;
+
+            assert_eq!(5, entries.len());
+            assert_eq!("deposit_collected", entries[0].entry_type);
+
+            // Groups roll up their members' balances along with deposits on their own keys
+            let mut group = Group {
+                name: "Janitorial".into(),
+                ..Default::default()
+            };
+            group.create(&pool, DEFAULT_ORG).await?;
+            group.add_member(&pool, "user1").await?;
+            let mut a = Assignment::for_group("Janitorial", "key3", NaiveDate::from_ymd(1988, 10, 4));
+            a.deposit_collected = Some(700);
+            a.deposit_currency = Some("USD".into());
+            a.create(&pool, DEFAULT_ORG).await?;
+
+            assert_eq!(2, Balance::get_all(&pool, DEFAULT_ORG).await?.len());
+            assert_eq!(
+                vec![GroupBalance {
+                    group: "Janitorial".into(),
+                    currency: "USD".into(),
+                    deposits_held: 2200,
+                    charges_outstanding: 2500,
+                    charges_paid: 1000,
+                }],
+                GroupBalance::get_all(&pool, DEFAULT_ORG).await?
+            );
+            let entries = FinanceEntry::get_all(&pool, DEFAULT_ORG).await?;
+            assert_eq!(Some("Janitorial".into()), entries[1].group);
+
+            Ok(())
+        }
+    }
+}
@@ -1,6 +1,6 @@
-INSERT INTO assignments ("user", key, date_out)
+INSERT INTO assignments (user_id, key_id, date_out)
 VALUES (
-    'user1',
-    'key1',
+    (SELECT id FROM users WHERE username = 'user1'),
+    (SELECT id FROM keys WHERE name = 'key1'),
     '1988-10-03'
 );
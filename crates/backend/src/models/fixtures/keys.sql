@@ -1,4 +1,4 @@
-INSERT INTO keys (name, description, active) VALUES
-    ('key1', 'this is a key', 't'),
-    ('key2', 'this is also a key', 'f'),
-    ('key3', 'this is the third', 't');
+INSERT INTO keys (org_id, name, description, active) VALUES
+    (1, 'key1', 'this is a key', true),
+    (1, 'key2', 'this is also a key', false),
+    (1, 'key3', 'this is the third', true);
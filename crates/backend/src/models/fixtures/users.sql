@@ -1,26 +1,37 @@
-INSERT INTO users (username, display_name, email, password_hash, can_login, admin)
+INSERT INTO users (username, display_name, email, password_hash, can_login, departed)
 VALUES (
     'user1',
     'User Juan',
     'user@email.com',
     '46a9d5bde718bf366178313019f04a753bad00685d38e3ec81c8628f35dfcb1b',
-    'f',
-    'f'
+    false,
+    false
 ),
 (
     'userNoPass',
     'User NoPass',
     'usernopass@email.com',
     '',
-    'f',
-    'f'
+    false,
+    false
 ),
 (
     'userCanLogin',
     'User CanLogin',
     'usercanlog@email.com',
     '$argon2i$v=19$m=65536,t=3,p=1$6JGByse/9Ous9DCnkgfFnA$lrixZa334c0rLb0k8SWK67q6TtSWoYjwXje67aKK0cU',
-    't',
-    'f'
+    true,
+    false
+),
+(
+    'userDeparted',
+    'User Departed',
+    'userdeparted@email.com',
+    '',
+    false,
+    true
 )
 ;
+
+INSERT INTO org_members (org_id, user_id, admin)
+SELECT 1, id, false FROM users;
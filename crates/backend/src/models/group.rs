@@ -0,0 +1,198 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, types::Json, FromRow, Row};
+use utoipa::ToSchema;
+
+use super::Db;
+
+/// A department or team of the org's users. Keys a whole team shares, like a janitorial
+/// closet key, are assigned to its group rather than to one of its members.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Group {
+    #[serde(skip_deserializing)]
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(skip_deserializing)]
+    pub members: Vec<String>, // Foreign keys to User::username
+}
+
+/// SQLite has no arrays, so the members are read from a JSON array.
+impl FromRow<'_, SqliteRow> for Group {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        let Json(mut members): Json<Vec<String>> = row.try_get("members")?;
+        members.sort();
+        Ok(Group {
+            id: row.try_get("id")?,
+            name: row.try_get("name")?,
+            description: row.try_get("description")?,
+            members,
+        })
+    }
+}
+
+impl Group {
+    pub async fn get(pool: &Db, org: i64, name: &str) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            r#"SELECT
+                g.id,
+                g.name,
+                g.description,
+                COALESCE(array_agg(u.username ORDER BY u.username) FILTER (WHERE u.username IS NOT NULL), '{}') as "members!"
+            FROM user_groups g
+            LEFT JOIN user_group_members gm ON gm.group_id = g.id
+            LEFT JOIN org_members m ON m.user_id = gm.user_id AND m.org_id = g.org_id AND m.deleted_at IS NULL
+            LEFT JOIN users u ON u.id = m.user_id
+            WHERE g.org_id = $1 AND g.name = $2
+            GROUP BY g.id"#,
+            sqlite = r#"SELECT
+                g.id,
+                g.name,
+                g.description,
+                json_group_array(u.username) FILTER (WHERE u.username IS NOT NULL) as members
+            FROM user_groups g
+            LEFT JOIN user_group_members gm ON gm.group_id = g.id
+            LEFT JOIN org_members m ON m.user_id = gm.user_id AND m.org_id = g.org_id AND m.deleted_at IS NULL
+            LEFT JOIN users u ON u.id = m.user_id
+            WHERE g.org_id = $1 AND g.name = $2
+            GROUP BY g.id"#,
+            org,
+            name
+        )
+    }
+
+    /// Returns the org's groups along with their members. Users in the trash are left out.
+    pub async fn get_all(pool: &Db, org: i64) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT
+                g.id,
+                g.name,
+                g.description,
+                COALESCE(array_agg(u.username ORDER BY u.username) FILTER (WHERE u.username IS NOT NULL), '{}') as "members!"
+            FROM user_groups g
+            LEFT JOIN user_group_members gm ON gm.group_id = g.id
+            LEFT JOIN org_members m ON m.user_id = gm.user_id AND m.org_id = g.org_id AND m.deleted_at IS NULL
+            LEFT JOIN users u ON u.id = m.user_id
+            WHERE g.org_id = $1
+            GROUP BY g.id
+            ORDER BY g.name"#,
+            sqlite = r#"SELECT
+                g.id,
+                g.name,
+                g.description,
+                json_group_array(u.username) FILTER (WHERE u.username IS NOT NULL) as members
+            FROM user_groups g
+            LEFT JOIN user_group_members gm ON gm.group_id = g.id
+            LEFT JOIN org_members m ON m.user_id = gm.user_id AND m.org_id = g.org_id AND m.deleted_at IS NULL
+            LEFT JOIN users u ON u.id = m.user_id
+            WHERE g.org_id = $1
+            GROUP BY g.id
+            ORDER BY g.name"#,
+            org
+        )
+    }
+
+    /// Creates the group without any members.
+    pub async fn create(&mut self, pool: &Db, org: i64) -> Result<(), sqlx::Error> {
+        self.id = db_query_scalar!(
+            i64,
+            fetch_one,
+            pool,
+            "INSERT INTO user_groups (org_id, name, description) VALUES ($1, $2, $3) RETURNING id",
+            org,
+            self.name,
+            self.description
+        )?;
+        Ok(())
+    }
+
+    pub async fn update(&self, pool: &Db) -> Result<u64, sqlx::Error> {
+        db_execute!(
+            pool,
+            "UPDATE user_groups SET name = $1, description = $2 WHERE id = $3",
+            self.name,
+            self.description,
+            self.id
+        )
+    }
+
+    /// Deletes the group. Fails with a foreign key violation while any assignment, including
+    /// one in the trash, is to the group.
+    pub async fn delete(&self, pool: &Db) -> Result<u64, sqlx::Error> {
+        db_execute!(pool, "DELETE FROM user_groups WHERE id = $1", self.id)
+    }
+
+    /// Adds a member of the group's org to the group. Returns 0 if `username` isn't in the org
+    /// or is already in the group.
+    pub async fn add_member(&self, pool: &Db, username: &str) -> Result<u64, sqlx::Error> {
+        db_execute!(
+            pool,
+            r#"INSERT INTO user_group_members (group_id, user_id)
+            SELECT g.id, m.user_id
+            FROM user_groups g
+            JOIN org_members m ON m.org_id = g.org_id
+            JOIN users u ON u.id = m.user_id
+            WHERE g.id = $1 AND u.username = $2 AND m.deleted_at IS NULL
+            ON CONFLICT DO NOTHING"#,
+            self.id,
+            username
+        )
+    }
+
+    pub async fn remove_member(&self, pool: &Db, username: &str) -> Result<u64, sqlx::Error> {
+        db_execute!(
+            pool,
+            r#"DELETE FROM user_group_members
+            WHERE group_id = $1 AND user_id = (SELECT id FROM users WHERE username = $2)"#,
+            self.id,
+            username
+        )
+    }
+}
+
+#[cfg(test)]
+mod group_tests {
+    use crate::models::{is_foreign_key_violation, Assignment, Group, DEFAULT_ORG};
+    use anyhow::Result;
+    use chrono::NaiveDate;
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn group_membership(pool: Db) -> Result<()> {
+            let mut group = Group {
+                name: "Janitorial".into(),
+                ..Default::default()
+            };
+            group.create(&pool, DEFAULT_ORG).await?;
+
+            assert_eq!(1, group.add_member(&pool, "userNoPass").await?);
+            assert_eq!(1, group.add_member(&pool, "user1").await?);
+            assert_eq!(0, group.add_member(&pool, "user1").await?);
+            assert_eq!(0, group.add_member(&pool, "nobody").await?);
+
+            let group = Group::get(&pool, DEFAULT_ORG, "Janitorial").await?;
+            assert_eq!(vec!["user1", "userNoPass"], group.members);
+
+            assert_eq!(1, group.remove_member(&pool, "userNoPass").await?);
+            assert_eq!(vec!["user1"], Group::get_all(&pool, DEFAULT_ORG).await?[0].members);
+
+            // Groups with assignments can't be deleted, even once they're in the trash
+            let mut a = Assignment::for_group("Janitorial", "key1", NaiveDate::from_ymd(1988, 10, 3));
+            a.create(&pool, DEFAULT_ORG).await?;
+            a.delete(&pool).await?;
+            let e = group.delete(&pool).await.unwrap_err();
+            assert!(is_foreign_key_violation(&e));
+
+            Assignment::purge(&pool, DEFAULT_ORG, a.id()).await?;
+            assert_eq!(1, group.delete(&pool).await?);
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sqlx::{query_scalar, FromRow, PgConnection};
+use utoipa::ToSchema;
+
+use super::Db;
+
+/// A periodic job and the next time it is due to run. Jobs are registered by the scheduler on
+/// startup so that the schedule survives restarts.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow, Serialize)]
+pub struct Job {
+    pub name: String,
+    pub schedule: String,
+    pub next_run: DateTime<Utc>,
+}
+
+impl Job {
+    /// Registers a job. A job which is already registered keeps its next run unless its
+    /// schedule has changed.
+    pub async fn register(
+        pool: &Db,
+        name: &str,
+        schedule: &str,
+        next_run: DateTime<Utc>,
+    ) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            r#"INSERT INTO jobs (name, schedule, next_run)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO UPDATE
+            SET
+                schedule = EXCLUDED.schedule,
+                next_run = CASE
+                    WHEN jobs.schedule = EXCLUDED.schedule THEN jobs.next_run
+                    ELSE EXCLUDED.next_run
+                END
+            RETURNING name, schedule, next_run"#,
+            name,
+            schedule,
+            next_run
+        )
+    }
+
+    pub async fn get(pool: &Db, name: &str) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            "SELECT name, schedule, next_run FROM jobs WHERE name = $1",
+            name
+        )
+    }
+
+    /// Claims the run which is currently due and moves the job on to `next_run`. Returns `None`
+    /// if another instance has already claimed it.
+    pub async fn claim(
+        &mut self,
+        pool: &Db,
+        next_run: DateTime<Utc>,
+    ) -> Result<Option<JobRun>, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let res = db_execute!(
+            &mut tx,
+            "UPDATE jobs SET next_run = $1 WHERE name = $2 AND next_run = $3",
+            next_run,
+            self.name,
+            self.next_run
+        )?;
+        if res == 0 {
+            return Ok(None);
+        }
+
+        let run = db_query_as!(
+            JobRun,
+            fetch_optional,
+            &mut tx,
+            r#"INSERT INTO job_runs (job, scheduled_for)
+            VALUES ($1, $2)
+            ON CONFLICT (job, scheduled_for) DO NOTHING
+            RETURNING id, job, scheduled_for, started_at, finished_at, error"#,
+            self.name,
+            self.next_run
+        )?;
+
+        tx.commit().await?;
+        self.next_run = next_run;
+        Ok(run)
+    }
+
+    /// Takes the advisory lock for the job on `conn`. Only one instance can hold it at a time.
+    /// SQLite databases belong to a single instance, so there is no lock to take there.
+    pub async fn try_lock(&self, conn: &mut PgConnection) -> Result<bool, sqlx::Error> {
+        query_scalar!(
+            r#"SELECT pg_try_advisory_lock(hashtext('keymaster_job:' || $1)) as "locked!""#,
+            self.name
+        )
+        .fetch_one(conn)
+        .await
+    }
+
+    pub async fn unlock(&self, conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+        query_scalar!(
+            "SELECT pg_advisory_unlock(hashtext('keymaster_job:' || $1))",
+            self.name
+        )
+        .fetch_one(conn)
+        .await?;
+        Ok(())
+    }
+}
+
+/// A single run of a job.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow, Serialize)]
+pub struct JobRun {
+    pub id: i64,
+    pub job: String,
+    pub scheduled_for: DateTime<Utc>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+impl JobRun {
+    pub async fn finish(&mut self, pool: &Db, error: Option<String>) -> Result<(), sqlx::Error> {
+        self.finished_at = db_query_scalar!(
+            Option<DateTime<Utc>>,
+            fetch_one,
+            pool,
+            "UPDATE job_runs SET finished_at = now(), error = $1 WHERE id = $2 RETURNING finished_at",
+            error,
+            self.id
+        )?;
+
+        self.error = error;
+        Ok(())
+    }
+
+    /// Deletes runs which started more than `days` days ago.
+    pub async fn delete_older_than(pool: &Db, days: i64) -> Result<u64, sqlx::Error> {
+        db_execute!(
+            pool,
+            "DELETE FROM job_runs WHERE started_at < $1",
+            Utc::now() - Duration::days(days)
+        )
+    }
+}
+
+/// A job along with its most recent run and most recent failure, shown to admins.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow, Serialize, ToSchema)]
+pub struct JobStatus {
+    pub name: String,
+    pub schedule: String,
+    pub next_run: DateTime<Utc>,
+    pub last_started: Option<DateTime<Utc>>,
+    pub last_finished: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+impl JobStatus {
+    pub async fn get_all(pool: &Db) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT
+                j.name,
+                j.schedule,
+                j.next_run,
+                (
+                    SELECT started_at FROM job_runs
+                    WHERE job = j.name ORDER BY started_at DESC LIMIT 1
+                ) as "last_started?",
+                (
+                    SELECT finished_at FROM job_runs
+                    WHERE job = j.name ORDER BY started_at DESC LIMIT 1
+                ) as "last_finished?",
+                (
+                    SELECT error FROM job_runs
+                    WHERE job = j.name AND error IS NOT NULL ORDER BY started_at DESC LIMIT 1
+                ) as "last_error?",
+                (
+                    SELECT started_at FROM job_runs
+                    WHERE job = j.name AND error IS NOT NULL ORDER BY started_at DESC LIMIT 1
+                ) as "last_error_at?"
+            FROM jobs j
+            ORDER BY j.name"#
+        )
+    }
+}
+
+#[cfg(test)]
+mod job_tests {
+    use crate::models::{Job, JobStatus};
+    use anyhow::Result;
+    use chrono::{Duration, Utc};
+    use sqlx::PgPool;
+
+    use crate::models::Db;
+
+    db_test! {
+        #[sqlx::test]
+        async fn claim_run(pool: Db) -> Result<()> {
+            let due = Utc::now() - Duration::minutes(1);
+            let mut job = Job::register(&pool, "cleanup", "0 0 3 * * *", due).await?;
+
+            // Registering again keeps the next run while the schedule is unchanged
+            let later = Utc::now() + Duration::days(1);
+            assert_eq!(
+                job,
+                Job::register(&pool, "cleanup", "0 0 3 * * *", later).await?
+            );
+
+            let mut other = Job::get(&pool, "cleanup").await?;
+            let mut run = job.claim(&pool, later).await?.unwrap();
+
+            // The same run cannot be claimed twice
+            assert!(other.claim(&pool, later).await?.is_none());
+
+            run.finish(&pool, Some("Failed".into())).await?;
+            let status = JobStatus::get_all(&pool).await?;
+            assert_eq!(1, status.len());
+            assert_eq!(Some("Failed".into()), status[0].last_error);
+            assert!(status[0].last_finished.is_some());
+
+            Ok(())
+        }
+    }
+
+    // Advisory locks only exist on Postgres
+    #[sqlx::test(migrations = "migrations/postgres")]
+    async fn advisory_lock(pool: PgPool) -> Result<()> {
+        let db = Db::from(pool.clone());
+        let job = Job::register(&db, "cleanup", "0 0 3 * * *", Utc::now()).await?;
+        let mut conn1 = pool.acquire().await?;
+        let mut conn2 = pool.acquire().await?;
+
+        assert!(job.try_lock(&mut conn1).await?);
+        assert!(!job.try_lock(&mut conn2).await?);
+        job.unlock(&mut conn1).await?;
+        assert!(job.try_lock(&mut conn2).await?);
+
+        Ok(())
+    }
+}
@@ -1,13 +1,27 @@
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgQueryResult, query, query_as, FromRow, PgPool};
+use sqlx::FromRow;
+use utoipa::ToSchema;
 
-#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize)]
+use super::{trash::Deleted, Db};
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Key {
+    #[serde(skip_deserializing)]
+    pub id: i64,
     pub name: String,
     pub description: Option<String>,
     #[serde(default = "_default_true")]
     pub active: bool,
+    #[serde(default)]
+    pub slot_id: Option<i64>, // Foreign key to Slot::id
+    #[serde(default)]
+    pub deposit_amount: Option<i64>, // Minor units of deposit_currency
+    #[serde(default)]
+    pub deposit_currency: Option<String>,
+    #[serde(default)]
+    pub restricted: bool, // Assignments must be confirmed by a second admin
 }
 
 fn _default_true() -> bool {
@@ -15,132 +29,306 @@ fn _default_true() -> bool {
 }
 
 impl Key {
-    pub async fn get(pool: &PgPool, name: &str) -> Result<Self, sqlx::Error> {
-        query_as!(
+    pub async fn get(pool: &Db, org: i64, name: &str) -> Result<Self, sqlx::Error> {
+        db_query_as!(
             Self,
-            "SELECT name, description, active FROM keys WHERE name = $1",
+            fetch_one,
+            pool,
+            "SELECT id, name, description, active, slot_id, deposit_amount, deposit_currency, restricted FROM keys WHERE org_id = $1 AND name = $2 AND deleted_at IS NULL",
+            org,
             name
         )
-        .fetch_one(pool)
-        .await
     }
 
-    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>, sqlx::Error> {
-        query_as!(
+    pub async fn get_all(pool: &Db, org: i64) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
             Self,
-            "SELECT name, description, active FROM keys ORDER BY name"
+            fetch_all,
+            pool,
+            "SELECT id, name, description, active, slot_id, deposit_amount, deposit_currency, restricted FROM keys WHERE org_id = $1 AND deleted_at IS NULL ORDER BY name",
+            org
         )
-        .fetch_all(pool)
-        .await
     }
 
-    pub async fn get_all_active(pool: &PgPool, active: bool) -> Result<Vec<Self>, sqlx::Error> {
-        query_as!(
+    pub async fn get_all_active(
+        pool: &Db,
+        org: i64,
+        active: bool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
             Self,
-            "SELECT name, description, active FROM keys where active = $1 ORDER BY name",
+            fetch_all,
+            pool,
+            "SELECT id, name, description, active, slot_id, deposit_amount, deposit_currency, restricted FROM keys WHERE org_id = $1 AND active = $2 AND deleted_at IS NULL ORDER BY name",
+            org,
             active
         )
-        .fetch_all(pool)
-        .await
     }
 
-    pub async fn create(&self, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
-        query!(
-            "INSERT INTO keys (name, description, active) VALUES ($1, $2, $3)",
+    pub async fn count_active(pool: &Db) -> Result<i64, sqlx::Error> {
+        db_query_scalar!(
+            i64,
+            fetch_one,
+            pool,
+            r#"SELECT count(*) as "count!" FROM keys WHERE active AND deleted_at IS NULL"#
+        )
+    }
+
+    pub async fn create(&mut self, pool: &Db, org: i64) -> Result<(), sqlx::Error> {
+        self.id = db_query_scalar!(
+            i64,
+            fetch_one,
+            pool,
+            r#"INSERT INTO keys (org_id, name, description, active, slot_id, deposit_amount, deposit_currency, restricted)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id"#,
+            org,
             self.name,
             self.description,
-            self.active
-        )
-        .execute(pool)
-        .await
+            self.active,
+            self.slot_id,
+            self.deposit_amount,
+            self.deposit_currency,
+            self.restricted
+        )?;
+        Ok(())
     }
 
-    pub async fn update(&self, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
-        query!(
-            "UPDATE keys SET description = $1, active = $2 WHERE name = $3",
+    /// Saves the key. The key is found by id, so its name can be changed.
+    pub async fn update(&self, pool: &Db) -> Result<u64, sqlx::Error> {
+        db_execute!(
+            pool,
+            r#"UPDATE keys
+            SET
+                name = $1,
+                description = $2,
+                active = $3,
+                slot_id = $4,
+                deposit_amount = $5,
+                deposit_currency = $6,
+                restricted = $7
+            WHERE id = $8"#,
+            self.name,
             self.description,
             self.active,
-            self.name
+            self.slot_id,
+            self.deposit_amount,
+            self.deposit_currency,
+            self.restricted,
+            self.id
         )
-        .execute(pool)
-        .await
     }
 
-    pub async fn delete(&self, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
-        query!("DELETE FROM keys WHERE name = $1", self.name)
-            .execute(pool)
-            .await
+    /// Moves the key to the trash along with its assignments. The key is taken out of its slot.
+    pub async fn delete(&self, pool: &Db) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let deleted_at = db_query_scalar!(
+            DateTime<Utc>,
+            fetch_one,
+            &mut tx,
+            r#"UPDATE keys SET deleted_at = now(), slot_id = NULL
+            WHERE id = $1 AND deleted_at IS NULL
+            RETURNING deleted_at as "deleted_at!""#,
+            self.id
+        )?;
+
+        db_execute!(
+            &mut tx,
+            "UPDATE assignments SET deleted_at = $1 WHERE key_id = $2 AND deleted_at IS NULL",
+            deleted_at,
+            self.id
+        )?;
+
+        tx.commit().await
+    }
+
+    /// Takes a key out of the trash along with the assignments which were deleted with it.
+    /// Fails with `RowNotFound` if the key isn't in the trash.
+    pub async fn restore(pool: &Db, org: i64, name: &str) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let Deleted { id, deleted_at } = db_query_as!(
+            Deleted,
+            fetch_one,
+            &mut tx,
+            r#"SELECT id, deleted_at as "deleted_at!" FROM keys
+            WHERE org_id = $1 AND name = $2 AND deleted_at IS NOT NULL
+            FOR UPDATE"#,
+            org,
+            name
+        )?;
+
+        db_execute!(
+            &mut tx,
+            "UPDATE keys SET deleted_at = NULL WHERE id = $1",
+            id
+        )?;
+
+        db_execute!(
+            &mut tx,
+            r#"UPDATE assignments SET deleted_at = NULL
+            WHERE key_id = $1 AND deleted_at = $2
+            AND (user_id IS NULL OR user_id IN (
+                SELECT user_id FROM org_members WHERE org_id = $3 AND deleted_at IS NULL
+            ))"#,
+            id,
+            deleted_at,
+            org
+        )?;
+
+        tx.commit().await
+    }
+
+    /// Permanently deletes a key in the trash, along with all of its assignments. Fails with
+    /// `RowNotFound` if the key isn't in the trash.
+    pub async fn purge(pool: &Db, org: i64, name: &str) -> Result<(), sqlx::Error> {
+        db_query_scalar!(
+            String,
+            fetch_one,
+            pool,
+            "DELETE FROM keys WHERE org_id = $1 AND name = $2 AND deleted_at IS NOT NULL RETURNING name",
+            org,
+            name
+        )?;
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod key_tests {
-    use crate::models::Key;
+    use crate::models::{Assignment, Key, DEFAULT_ORG};
     use anyhow::Result;
-    use sqlx::{query, PgPool};
-
-    #[sqlx::test()]
-    async fn create_key(pool: PgPool) -> Result<()> {
-        let name = "k1";
-        let description = "this is a key, the first of many";
-        let k1 = Key {
-            name: name.to_string(),
-            description: Some(description.to_string()),
-            active: true,
-        };
-        k1.create(&pool).await?;
 
-        Ok(())
+    db_test! {
+        #[sqlx::test]
+        async fn create_key(pool: Db) -> Result<()> {
+            let name = "k1";
+            let description = "this is a key, the first of many";
+            let mut k1 = Key {
+                id: 0,
+                name: name.to_string(),
+                description: Some(description.to_string()),
+                active: true,
+                slot_id: None,
+                deposit_amount: None,
+                deposit_currency: None,
+                restricted: false,
+            };
+            k1.create(&pool, DEFAULT_ORG).await?;
+
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("keys"))]
-    async fn get_key(pool: PgPool) -> Result<()> {
-        let key = Key::get(&pool, "key1").await?;
+    db_test! {
+        #[sqlx::test(fixtures("keys"))]
+        async fn get_key(pool: Db) -> Result<()> {
+            let key = Key::get(&pool, DEFAULT_ORG, "key1").await?;
 
-        assert_eq!("key1", key.name);
-        assert_eq!(Some("this is a key".into()), key.description);
-        assert!(key.active);
+            assert_eq!("key1", key.name);
+            assert_eq!(Some("this is a key".into()), key.description);
+            assert!(key.active);
 
-        Ok(())
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("keys"))]
-    async fn get_all_active_keys(pool: PgPool) -> Result<()> {
-        let active_keys = Key::get_all_active(&pool, true).await?;
-        let inactive_keys = Key::get_all_active(&pool, false).await?;
+    db_test! {
+        #[sqlx::test(fixtures("keys"))]
+        async fn get_all_active_keys(pool: Db) -> Result<()> {
+            let active_keys = Key::get_all_active(&pool, DEFAULT_ORG, true).await?;
+            let inactive_keys = Key::get_all_active(&pool, DEFAULT_ORG, false).await?;
 
-        assert_eq!(2, active_keys.len());
-        assert_eq!(1, inactive_keys.len());
+            assert_eq!(2, active_keys.len());
+            assert_eq!(1, inactive_keys.len());
 
-        Ok(())
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("keys"))]
-    async fn update_key(pool: PgPool) -> Result<()> {
-        let new_desc = "it does stuff";
-        let mut key = Key::get(&pool, "key1").await?;
-        key.description = Some(new_desc.to_string());
-        key.update(&pool).await?;
+    db_test! {
+        #[sqlx::test(fixtures("keys"))]
+        async fn update_key(pool: Db) -> Result<()> {
+            let new_desc = "it does stuff";
+            let mut key = Key::get(&pool, DEFAULT_ORG, "key1").await?;
+            key.description = Some(new_desc.to_string());
+            key.update(&pool).await?;
 
-        let updated_key = Key::get(&pool, "key1").await?;
+            let updated_key = Key::get(&pool, DEFAULT_ORG, "key1").await?;
 
-        assert_eq!(Some(new_desc.into()), updated_key.description);
+            assert_eq!(Some(new_desc.into()), updated_key.description);
 
-        Ok(())
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("keys"))]
-    async fn delete_key(pool: PgPool) -> Result<()> {
-        let key = Key::get(&pool, "key1").await?;
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys", "assignments"))]
+        async fn rename_key(pool: Db) -> Result<()> {
+            let mut key = Key::get(&pool, DEFAULT_ORG, "key1").await?;
+            key.name = "front door".into();
+            key.update(&pool).await?;
 
-        key.delete(&pool).await?;
-        let res = query("SELECT * FROM keys WHERE name = $1")
-            .bind(key.name)
-            .execute(&pool)
-            .await?;
+            assert!(Key::get(&pool, DEFAULT_ORG, "key1").await.is_err());
+            assert_eq!(key, Key::get(&pool, DEFAULT_ORG, "front door").await?);
 
-        assert_eq!(res.rows_affected(), 0);
+            // Assignments follow the key
+            let assignments = Assignment::get_assignments_by_key(&pool, DEFAULT_ORG, "front door").await?;
+            assert_eq!(1, assignments.len());
+            assert_eq!("front door", assignments[0].key);
 
-        Ok(())
+            // Names are still unique
+            key.name = "key3".into();
+            assert!(key.update(&pool).await.is_err());
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("keys"))]
+        async fn delete_key(pool: Db) -> Result<()> {
+            let key = Key::get(&pool, DEFAULT_ORG, "key1").await?;
+
+            key.delete(&pool).await?;
+            assert!(Key::get(&pool, DEFAULT_ORG, "key1").await.is_err());
+
+            // The key is kept until it's purged from the trash
+            let count = format!("SELECT count(*) FROM keys WHERE name = '{}'", key.name);
+            assert_eq!(pool.count(&count).await?, 1);
+
+            Key::purge(&pool, DEFAULT_ORG, &key.name).await?;
+
+            assert_eq!(pool.count(&count).await?, 0);
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys", "assignments"))]
+        async fn restore_key(pool: Db) -> Result<()> {
+            let key = Key::get(&pool, DEFAULT_ORG, "key1").await?;
+            key.delete(&pool).await?;
+            assert!(Assignment::get_assignments_by_key(&pool, DEFAULT_ORG, "key1")
+                .await?
+                .is_empty());
+
+            // Only keys in the trash can be restored or purged
+            assert!(Key::restore(&pool, DEFAULT_ORG, "key3").await.is_err());
+            assert!(Key::purge(&pool, DEFAULT_ORG, "key3").await.is_err());
+
+            Key::restore(&pool, DEFAULT_ORG, "key1").await?;
+            assert_eq!(key, Key::get(&pool, DEFAULT_ORG, "key1").await?);
+            assert_eq!(
+                1,
+                Assignment::get_assignments_by_key(&pool, DEFAULT_ORG, "key1")
+                    .await?
+                    .len()
+            );
+
+            Ok(())
+        }
     }
 }
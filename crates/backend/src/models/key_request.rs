@@ -0,0 +1,390 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, types::Json, FromRow, Row};
+use utoipa::ToSchema;
+
+use super::{AssignmentChange, Db};
+
+#[derive(
+    Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, sqlx::Type, ToSchema,
+)]
+#[sqlx(type_name = "request_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum RequestStatus {
+    #[default]
+    Pending,
+    Approved,
+    Denied,
+    Fulfilled,
+}
+
+/// A user's request for one or more keys. Once approved, the keys are assigned to the user
+/// when they are handed over.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct KeyRequest {
+    #[serde(skip_deserializing)]
+    pub id: i64,
+    #[serde(skip_deserializing)]
+    pub user: String, // Foreign key to User::username
+    pub keys: Vec<String>, // Foreign keys to Key::name
+    pub justification: String,
+    pub date_from: NaiveDate,
+    pub date_to: Option<NaiveDate>,
+    pub approver: Option<String>, // Foreign key to User::username
+    #[serde(skip_deserializing)]
+    pub status: RequestStatus,
+    #[serde(skip_deserializing)]
+    pub decided_by: Option<String>, // Foreign key to User::username
+    #[serde(skip_deserializing)]
+    pub decision_note: Option<String>,
+}
+
+/// SQLite has no arrays, so the requested keys are read from a JSON array.
+impl FromRow<'_, SqliteRow> for KeyRequest {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        let Json(mut keys): Json<Vec<String>> = row.try_get("keys")?;
+        keys.sort();
+        Ok(KeyRequest {
+            id: row.try_get("id")?,
+            user: row.try_get("user")?,
+            keys,
+            justification: row.try_get("justification")?,
+            date_from: row.try_get("date_from")?,
+            date_to: row.try_get("date_to")?,
+            approver: row.try_get("approver")?,
+            status: row.try_get("status")?,
+            decided_by: row.try_get("decided_by")?,
+            decision_note: row.try_get("decision_note")?,
+        })
+    }
+}
+
+impl KeyRequest {
+    pub async fn get(pool: &Db, org: i64, id: i64) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            r#"SELECT
+                r.id,
+                r."user",
+                COALESCE(array_agg(k.name ORDER BY k.name) FILTER (WHERE k.name IS NOT NULL), '{}') as "keys!",
+                r.justification,
+                r.date_from,
+                r.date_to,
+                r.approver,
+                r.status as "status: RequestStatus",
+                r.decided_by,
+                r.decision_note
+            FROM key_requests r
+            LEFT JOIN key_request_items i ON i.request_id = r.id
+            LEFT JOIN keys k ON k.id = i.key_id
+            WHERE r.id = $1 AND r.org_id = $2
+            GROUP BY r.id"#,
+            sqlite = r#"SELECT
+                r.id,
+                r."user",
+                json_group_array(k.name) FILTER (WHERE k.name IS NOT NULL) as keys,
+                r.justification,
+                r.date_from,
+                r.date_to,
+                r.approver,
+                r.status as "status: RequestStatus",
+                r.decided_by,
+                r.decision_note
+            FROM key_requests r
+            LEFT JOIN key_request_items i ON i.request_id = r.id
+            LEFT JOIN keys k ON k.id = i.key_id
+            WHERE r.id = $1 AND r.org_id = $2
+            GROUP BY r.id"#,
+            id,
+            org
+        )
+    }
+
+    /// Returns all of the org's requests, or only those made by or awaiting approval from
+    /// `user`.
+    pub async fn get_all(
+        pool: &Db,
+        org: i64,
+        user: Option<&str>,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT
+                r.id,
+                r."user",
+                COALESCE(array_agg(k.name ORDER BY k.name) FILTER (WHERE k.name IS NOT NULL), '{}') as "keys!",
+                r.justification,
+                r.date_from,
+                r.date_to,
+                r.approver,
+                r.status as "status: RequestStatus",
+                r.decided_by,
+                r.decision_note
+            FROM key_requests r
+            LEFT JOIN key_request_items i ON i.request_id = r.id
+            LEFT JOIN keys k ON k.id = i.key_id
+            WHERE r.org_id = $1
+            AND (CAST($2 AS VARCHAR) IS NULL OR r."user" = $2 OR r.approver = $2)
+            GROUP BY r.id
+            ORDER BY r.id DESC"#,
+            sqlite = r#"SELECT
+                r.id,
+                r."user",
+                json_group_array(k.name) FILTER (WHERE k.name IS NOT NULL) as keys,
+                r.justification,
+                r.date_from,
+                r.date_to,
+                r.approver,
+                r.status as "status: RequestStatus",
+                r.decided_by,
+                r.decision_note
+            FROM key_requests r
+            LEFT JOIN key_request_items i ON i.request_id = r.id
+            LEFT JOIN keys k ON k.id = i.key_id
+            WHERE r.org_id = $1
+            AND (CAST($2 AS VARCHAR) IS NULL OR r."user" = $2 OR r.approver = $2)
+            GROUP BY r.id
+            ORDER BY r.id DESC"#,
+            org,
+            user
+        )
+    }
+
+    /// Creates the request for keys of the org. Names which aren't one of the org's keys are
+    /// left out.
+    pub async fn create(&mut self, pool: &Db, org: i64) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        self.id = db_query_scalar!(
+            i64,
+            fetch_one,
+            &mut tx,
+            r#"INSERT INTO key_requests (org_id, "user", justification, date_from, date_to, approver)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id"#,
+            org,
+            self.user,
+            self.justification,
+            self.date_from,
+            self.date_to,
+            self.approver
+        )?;
+
+        for key in &self.keys {
+            db_execute!(
+                &mut tx,
+                r#"INSERT INTO key_request_items (request_id, key_id)
+                SELECT $1, id FROM keys WHERE org_id = $2 AND name = $3
+                ON CONFLICT DO NOTHING"#,
+                self.id,
+                org,
+                key
+            )?;
+        }
+
+        tx.commit().await
+    }
+
+    /// Approves or denies a pending request. Returns false if the request has already been
+    /// decided.
+    pub async fn decide(
+        &mut self,
+        pool: &Db,
+        status: RequestStatus,
+        decided_by: &str,
+        note: Option<String>,
+    ) -> Result<bool, sqlx::Error> {
+        let decided = db_query_scalar!(
+            i64,
+            fetch_optional,
+            pool,
+            r#"UPDATE key_requests
+            SET status = $1, decided_by = $2, decision_note = $3
+            WHERE id = $4 AND status = 'pending'
+            RETURNING id"#,
+            status as RequestStatus,
+            decided_by,
+            note,
+            self.id
+        )?;
+        if decided.is_none() {
+            return Ok(false);
+        }
+
+        self.status = status;
+        self.decided_by = Some(decided_by.into());
+        self.decision_note = note;
+        Ok(true)
+    }
+
+    /// Assigns the requested keys to the user from `date_out` until the request's `date_to`, taking them off their hooks and
+    /// collecting each key's deposit. Assignments of restricted keys are left pending until a
+    /// second admin confirms them. Returns false, changing nothing, unless the request is
+    /// approved.
+    pub async fn fulfill(
+        &mut self,
+        pool: &Db,
+        date_out: NaiveDate,
+        created_by: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let fulfilled = db_query_scalar!(
+            i64,
+            fetch_optional,
+            &mut tx,
+            r#"UPDATE key_requests
+            SET status = 'fulfilled'
+            WHERE id = $1 AND status = 'approved'
+            RETURNING id"#,
+            self.id
+        )?;
+        if fulfilled.is_none() {
+            return Ok(false);
+        }
+
+        let created = db_query_scalar!(
+            i64,
+            fetch_all,
+            &mut tx,
+            r#"INSERT INTO assignments (user_id, key_id, date_out, date_due, deposit_collected, deposit_currency, pending, created_by)
+            SELECT u.id, k.id, $2, $3, k.deposit_amount, k.deposit_currency, k.restricted, $4
+            FROM key_request_items i
+            JOIN keys k ON k.id = i.key_id
+            JOIN users u ON u.username = $1
+            WHERE i.request_id = $5
+            RETURNING id"#,
+            self.user,
+            date_out,
+            self.date_to,
+            created_by,
+            self.id
+        )?;
+        for id in created {
+            AssignmentChange::record(&mut tx, id, Some(created_by), "created", None).await?;
+        }
+
+        db_execute!(
+            &mut tx,
+            r#"UPDATE keys SET slot_id = NULL
+            WHERE NOT restricted
+            AND id IN (SELECT key_id FROM key_request_items WHERE request_id = $1)"#,
+            self.id
+        )?;
+
+        tx.commit().await?;
+        self.status = RequestStatus::Fulfilled;
+        Ok(true)
+    }
+
+    pub async fn delete(&self, pool: &Db) -> Result<u64, sqlx::Error> {
+        db_execute!(pool, "DELETE FROM key_requests WHERE id = $1", self.id)
+    }
+}
+
+#[cfg(test)]
+mod key_request_tests {
+    use crate::models::{Assignment, AssignmentQuery, Db, KeyRequest, RequestStatus, DEFAULT_ORG};
+    use anyhow::Result;
+    use chrono::NaiveDate;
+
+    async fn request_keys(pool: &Db) -> Result<KeyRequest> {
+        let mut request = KeyRequest {
+            user: "userCanLogin".into(),
+            keys: vec!["key3".into(), "key1".into()],
+            justification: "Night shift".into(),
+            date_from: NaiveDate::from_ymd(1988, 11, 3),
+            approver: Some("user1".into()),
+            ..Default::default()
+        };
+        request.create(pool, DEFAULT_ORG).await?;
+        Ok(request)
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn create_request(pool: Db) -> Result<()> {
+            let request = request_keys(&pool).await?;
+            let stored = KeyRequest::get(&pool, DEFAULT_ORG, request.id).await?;
+
+            assert_eq!(vec!["key1".to_string(), "key3".to_string()], stored.keys);
+            assert_eq!(RequestStatus::Pending, stored.status);
+            assert_eq!(1, KeyRequest::get_all(&pool, DEFAULT_ORG, Some("user1")).await?.len());
+            assert_eq!(
+                1,
+                KeyRequest::get_all(&pool, DEFAULT_ORG, Some("userCanLogin"))
+                    .await?
+                    .len()
+            );
+            assert_eq!(
+                0,
+                KeyRequest::get_all(&pool, DEFAULT_ORG, Some("userNoPass")).await?.len()
+            );
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn decide_request(pool: Db) -> Result<()> {
+            let mut request = request_keys(&pool).await?;
+
+            // Only approved requests can be fulfilled
+            assert!(!request
+                .fulfill(&pool, NaiveDate::from_ymd(1988, 11, 4), "user1")
+                .await?);
+
+            assert!(request
+                .decide(&pool, RequestStatus::Denied, "user1", Some("No".into()))
+                .await?);
+            let stored = KeyRequest::get(&pool, DEFAULT_ORG, request.id).await?;
+            assert_eq!(RequestStatus::Denied, stored.status);
+            assert_eq!(Some("user1".into()), stored.decided_by);
+            assert_eq!(Some("No".into()), stored.decision_note);
+
+            // Decisions are final
+            assert!(!request
+                .decide(&pool, RequestStatus::Approved, "user1", None)
+                .await?);
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn fulfill_request(pool: Db) -> Result<()> {
+            let mut request = request_keys(&pool).await?;
+            assert!(request
+                .decide(&pool, RequestStatus::Approved, "user1", None)
+                .await?);
+            assert!(request
+                .fulfill(&pool, NaiveDate::from_ymd(1988, 11, 4), "user1")
+                .await?);
+
+            let assignments = Assignment::get_all(
+                &pool,
+                DEFAULT_ORG,
+                AssignmentQuery {
+                    user: Some("userCanLogin".into()),
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+            assert_eq!(2, assignments.len());
+            assert_eq!(
+                RequestStatus::Fulfilled,
+                KeyRequest::get(&pool, DEFAULT_ORG, request.id).await?.status
+            );
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,996 @@
+use std::{collections::HashMap, error::Error as StdError, fmt, sync::Mutex};
+
+use async_trait::async_trait;
+use chrono::{NaiveDate, Utc};
+use serde_json::Value;
+use sqlx::error::DatabaseError;
+
+use super::{
+    Assignment, AssignmentChange, AssignmentQuery, AssignmentRepository, Group, Key, KeyLocation,
+    KeyRepository, Membership, NotificationSettings, Org, Slot, User, UserRepository, DEFAULT_ORG,
+};
+use crate::events::Events;
+
+/// Keys, users and assignments kept in memory, so routes can be tested without a database.
+/// It enforces the same constraints as the schema and fails with errors worded like
+/// Postgres', so routes handle them the same way. Events are recorded rather than sent. Like a
+/// freshly migrated database, it starts with the default org.
+pub struct MemoryRepository {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    last_id: i64,
+    orgs: Vec<Org>,
+    keys: Vec<Row<Key>>,
+    users: Vec<User>,
+    members: Vec<Row<Member>>,
+    groups: Vec<Row<Group>>,
+    assignments: Vec<Row<Assignment>>,
+    changes: Vec<AssignmentChange>,
+    slots: Vec<Row<Slot>>,
+    notifications: HashMap<String, NotificationSettings>,
+    events: Vec<(i64, String, Value)>,
+}
+
+/// A stored item, the org it belongs to and whether it is in that org's trash.
+struct Row<T> {
+    value: T,
+    org: i64,
+    deleted: bool,
+}
+
+impl<T> Row<T> {
+    fn new(value: T, org: i64) -> Self {
+        Row {
+            value,
+            org,
+            deleted: false,
+        }
+    }
+}
+
+/// A user's membership of the org of its row. Users in an org's trash are deleted members.
+struct Member {
+    user_id: i64,
+    admin: bool,
+}
+
+#[derive(Debug)]
+struct MemoryError(String);
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl StdError for MemoryError {}
+
+impl DatabaseError for MemoryError {
+    fn message(&self) -> &str {
+        &self.0
+    }
+
+    fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+        self
+    }
+}
+
+fn unique_violation(constraint: &str) -> sqlx::Error {
+    sqlx::Error::Database(Box::new(MemoryError(format!(
+        r#"duplicate key value violates unique constraint "{}""#,
+        constraint
+    ))))
+}
+
+fn foreign_key_violation(table: &str, constraint: &str) -> sqlx::Error {
+    sqlx::Error::Database(Box::new(MemoryError(format!(
+        r#"insert or update on table "{}" violates foreign key constraint "{}""#,
+        table, constraint
+    ))))
+}
+
+fn not_null_violation(column: &str) -> sqlx::Error {
+    sqlx::Error::Database(Box::new(MemoryError(format!(
+        r#"null value in column "{}" of relation "assignments" violates not-null constraint"#,
+        column
+    ))))
+}
+
+fn check_violation(constraint: &str) -> sqlx::Error {
+    sqlx::Error::Database(Box::new(MemoryError(format!(
+        r#"new row for relation "assignments" violates check constraint "{}""#,
+        constraint
+    ))))
+}
+
+impl Default for MemoryRepository {
+    fn default() -> Self {
+        let state = State {
+            orgs: vec![Org {
+                id: DEFAULT_ORG,
+                name: "Default".into(),
+            }],
+            ..Default::default()
+        };
+        MemoryRepository {
+            state: Mutex::new(state),
+        }
+    }
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_org(&self, name: &str) -> Org {
+        let mut state = self.state.lock().unwrap();
+        let org = Org {
+            id: state.next_id(),
+            name: name.into(),
+        };
+        state.orgs.push(org.clone());
+        org
+    }
+
+    /// Adds a user who can log in to the default org.
+    pub fn add_user(&self, username: &str, admin: bool) -> User {
+        let mut state = self.state.lock().unwrap();
+        let user = User {
+            id: state.next_id(),
+            username: username.into(),
+            can_login: true,
+            ..Default::default()
+        };
+        state.users.push(user.clone());
+        state.members.push(Row::new(
+            Member {
+                user_id: user.id,
+                admin,
+            },
+            DEFAULT_ORG,
+        ));
+        User { admin, ..user }
+    }
+
+    /// Makes an existing user a member of another org.
+    pub fn add_member(&self, org: i64, username: &str, admin: bool) {
+        let mut state = self.state.lock().unwrap();
+        let user_id = state
+            .users
+            .iter()
+            .find(|u| u.username == username)
+            .expect("no such user")
+            .id;
+        state.members.push(Row::new(Member { user_id, admin }, org));
+    }
+
+    pub fn add_key(&self, name: &str) -> Key {
+        self.add_key_to(DEFAULT_ORG, name)
+    }
+
+    pub fn add_key_to(&self, org: i64, name: &str) -> Key {
+        let mut state = self.state.lock().unwrap();
+        let key = Key {
+            id: state.next_id(),
+            name: name.into(),
+            active: true,
+            ..Default::default()
+        };
+        state.keys.push(Row::new(key.clone(), org));
+        key
+    }
+
+    /// Adds a group of the default org with the given members.
+    pub fn add_group(&self, name: &str, members: &[&str]) -> Group {
+        let mut state = self.state.lock().unwrap();
+        let group = Group {
+            id: state.next_id(),
+            name: name.into(),
+            description: None,
+            members: members.iter().map(|m| m.to_string()).collect(),
+        };
+        state.groups.push(Row::new(group.clone(), DEFAULT_ORG));
+        group
+    }
+
+    /// Adds an open assignment of a key in the default org to `user`.
+    pub fn add_assignment(&self, user: &str, key: &str, date_out: NaiveDate) -> Assignment {
+        let mut state = self.state.lock().unwrap();
+        let mut assignment = Assignment::new(user, key, date_out);
+        assignment.id = state.next_id();
+        state
+            .assignments
+            .push(Row::new(assignment.clone(), DEFAULT_ORG));
+        assignment
+    }
+
+    /// Adds an empty slot to the named cabinet in the default org.
+    pub fn add_slot(&self, cabinet: &str, number: i32) -> Slot {
+        self.add_slot_to(DEFAULT_ORG, cabinet, number)
+    }
+
+    pub fn add_slot_to(&self, org: i64, cabinet: &str, number: i32) -> Slot {
+        let mut state = self.state.lock().unwrap();
+        let cabinet_id = match state.slots.iter().find(|s| s.value.cabinet == cabinet) {
+            Some(s) => s.value.cabinet_id,
+            None => state.next_id(),
+        };
+        let slot = Slot {
+            id: state.next_id(),
+            cabinet_id,
+            cabinet: cabinet.into(),
+            number,
+            key: None,
+        };
+        state.slots.push(Row::new(slot.clone(), org));
+        slot
+    }
+
+    /// The events emitted so far in any org, oldest first.
+    pub fn events(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state.events.iter().map(|(_, e, _)| e.clone()).collect()
+    }
+}
+
+impl State {
+    fn next_id(&mut self) -> i64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn key(&self, org: i64, name: &str) -> Result<&Key, sqlx::Error> {
+        self.keys
+            .iter()
+            .find(|k| !k.deleted && k.org == org && k.value.name == name)
+            .map(|k| &k.value)
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    fn member(&self, org: i64, user_id: i64) -> Option<&Row<Member>> {
+        self.members
+            .iter()
+            .find(|m| m.org == org && m.value.user_id == user_id)
+    }
+
+    /// The user with their role in the org, if they are a member who isn't in its trash.
+    fn user(&self, org: i64, username: &str) -> Result<User, sqlx::Error> {
+        self.users
+            .iter()
+            .filter(|u| u.username == username)
+            .find_map(|u| match self.member(org, u.id) {
+                Some(m) if !m.deleted => Some(User {
+                    admin: m.value.admin,
+                    ..u.clone()
+                }),
+                _ => None,
+            })
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    fn assignment(&mut self, org: i64, id: i64) -> Result<&mut Assignment, sqlx::Error> {
+        self.assignments
+            .iter_mut()
+            .find(|a| !a.deleted && a.org == org && a.value.id == id)
+            .map(|a| &mut a.value)
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    /// Checks the constraints on a key of the org about to be saved. Names are unique within
+    /// an org.
+    fn check_key(&self, org: i64, key: &Key) -> Result<(), sqlx::Error> {
+        let others = self.keys.iter().filter(|k| k.value.id != key.id);
+        for other in others {
+            if other.org == org && other.value.name == key.name {
+                return Err(unique_violation("keys_name_key"));
+            }
+            if key.slot_id.is_some() && other.value.slot_id == key.slot_id {
+                return Err(unique_violation("keys_slot_id_key"));
+            }
+        }
+        if let Some(slot_id) = key.slot_id {
+            if !self.slots.iter().any(|s| s.value.id == slot_id) {
+                return Err(foreign_key_violation("keys", "keys_slot_id_fkey"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the constraints on a user about to be saved.
+    fn check_user(&self, user: &User) -> Result<(), sqlx::Error> {
+        for other in self.users.iter().filter(|u| u.id != user.id) {
+            if other.username == user.username {
+                return Err(unique_violation("users_username_key"));
+            }
+            if user.display_name.is_some() && other.display_name == user.display_name {
+                return Err(unique_violation("users_display_name_key"));
+            }
+            if user.email.is_some() && other.email == user.email {
+                return Err(unique_violation("users_email_key"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the constraints on an assignment about to be saved. Its user, group and key are
+    /// looked up by name, the group and key among the org's, so ones which don't exist leave
+    /// the ids null.
+    fn check_assignment(&self, org: i64, assignment: &Assignment) -> Result<(), sqlx::Error> {
+        let user = assignment
+            .user
+            .as_ref()
+            .filter(|name| self.users.iter().any(|u| &&u.username == name));
+        let group = assignment.group.as_ref().filter(|name| {
+            self.groups
+                .iter()
+                .any(|g| g.org == org && &&g.value.name == name)
+        });
+        if user.is_some() == group.is_some() {
+            return Err(check_violation("assignments_holder_check"));
+        }
+        if !self
+            .keys
+            .iter()
+            .any(|k| k.org == org && k.value.name == assignment.key)
+        {
+            return Err(not_null_violation("key_id"));
+        }
+        let duplicate = self.assignments.iter().any(|a| {
+            !a.deleted
+                && a.org == org
+                && a.value.id != assignment.id
+                && a.value.user == assignment.user
+                && a.value.group == assignment.group
+                && a.value.key == assignment.key
+        });
+        if duplicate && user.is_some() {
+            return Err(unique_violation("assignments_user_key_key"));
+        }
+        if duplicate {
+            return Err(unique_violation("assignments_group_key_key"));
+        }
+        Ok(())
+    }
+
+    fn record(
+        &mut self,
+        assignment_id: i64,
+        changed_by: Option<&str>,
+        action: &str,
+        change: Option<(&str, Option<String>, Option<String>)>,
+    ) {
+        let (field, old_value, new_value) = match change {
+            Some((f, o, n)) => (Some(f.to_string()), o, n),
+            None => (None, None, None),
+        };
+        let id = self.next_id();
+        self.changes.push(AssignmentChange {
+            id,
+            assignment_id,
+            changed_at: Utc::now(),
+            changed_by: changed_by.map(Into::into),
+            action: action.into(),
+            field,
+            old_value,
+            new_value,
+        });
+    }
+
+    /// Takes the org's key out of whichever slot it was stored in.
+    fn take_key(&mut self, org: i64, name: &str) {
+        for k in self
+            .keys
+            .iter_mut()
+            .filter(|k| k.org == org && k.value.name == name)
+        {
+            k.value.slot_id = None;
+        }
+    }
+
+    /// Assignments refer to their user and key by id, so renames show up in them.
+    fn rename_key(&mut self, org: i64, old: &str, new: &str) {
+        for a in self
+            .assignments
+            .iter_mut()
+            .filter(|a| a.org == org && a.value.key == old)
+        {
+            a.value.key = new.into();
+        }
+    }
+
+    fn rename_user(&mut self, old: &str, new: &str) {
+        for a in self.assignments.iter_mut() {
+            for name in [
+                a.value.user.as_mut(),
+                a.value.created_by.as_mut(),
+                a.value.confirmed_by.as_mut(),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                if *name == old {
+                    *name = new.into();
+                }
+            }
+        }
+        if let Some(n) = self.notifications.remove(old) {
+            self.notifications.insert(new.into(), n);
+        }
+    }
+}
+
+#[async_trait]
+impl KeyRepository for MemoryRepository {
+    async fn get(&self, org: i64, name: &str) -> Result<Key, sqlx::Error> {
+        self.state.lock().unwrap().key(org, name).cloned()
+    }
+
+    async fn get_all(&self, org: i64) -> Result<Vec<Key>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        let mut keys: Vec<Key> = state
+            .keys
+            .iter()
+            .filter(|k| !k.deleted && k.org == org)
+            .map(|k| k.value.clone())
+            .collect();
+        keys.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(keys)
+    }
+
+    async fn get_all_active(&self, org: i64, active: bool) -> Result<Vec<Key>, sqlx::Error> {
+        let keys = KeyRepository::get_all(self, org).await?;
+        Ok(keys.into_iter().filter(|k| k.active == active).collect())
+    }
+
+    async fn create(&self, org: i64, key: &mut Key) -> Result<(), sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        key.id = 0;
+        state.check_key(org, key)?;
+        key.id = state.next_id();
+        state.keys.push(Row::new(key.clone(), org));
+        Ok(())
+    }
+
+    async fn update(&self, key: &Key) -> Result<u64, sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        let (org, old) = match state.keys.iter().find(|k| k.value.id == key.id) {
+            Some(k) => (k.org, k.value.name.clone()),
+            None => return Ok(0),
+        };
+        state.check_key(org, key)?;
+        state.rename_key(org, &old, &key.name);
+        if let Some(k) = state.keys.iter_mut().find(|k| k.value.id == key.id) {
+            k.value = key.clone();
+        }
+        Ok(1)
+    }
+
+    async fn delete(&self, key: &Key) -> Result<(), sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        let row = state
+            .keys
+            .iter_mut()
+            .find(|k| !k.deleted && k.value.id == key.id)
+            .ok_or(sqlx::Error::RowNotFound)?;
+        row.deleted = true;
+        row.value.slot_id = None;
+        let (org, name) = (row.org, row.value.name.clone());
+        for a in state
+            .assignments
+            .iter_mut()
+            .filter(|a| a.org == org && a.value.key == name)
+        {
+            a.deleted = true;
+        }
+        Ok(())
+    }
+
+    async fn location(&self, org: i64, name: &str) -> Result<KeyLocation, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        let holder = state
+            .assignments
+            .iter()
+            .filter(|a| !a.deleted && a.org == org)
+            .map(|a| &a.value)
+            .filter(|a| a.key == name && a.date_in.is_none() && !a.pending)
+            .max_by_key(|a| a.date_out);
+        if let Some(a) = holder {
+            return Ok(match (&a.user, &a.group) {
+                (Some(username), _) => KeyLocation::User {
+                    username: username.clone(),
+                },
+                (None, group) => KeyLocation::Group {
+                    name: group.clone().unwrap_or_default(),
+                },
+            });
+        }
+
+        let slot_id = state
+            .keys
+            .iter()
+            .find(|k| k.org == org && k.value.name == name)
+            .and_then(|k| k.value.slot_id);
+        Ok(
+            match state.slots.iter().find(|s| Some(s.value.id) == slot_id) {
+                Some(s) => KeyLocation::Slot {
+                    cabinet_id: s.value.cabinet_id,
+                    cabinet: s.value.cabinet.clone(),
+                    number: s.value.number,
+                },
+                None => KeyLocation::Unknown,
+            },
+        )
+    }
+
+    async fn slot(&self, org: i64, id: i64) -> Result<Slot, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        let mut slot = state
+            .slots
+            .iter()
+            .find(|s| s.org == org && s.value.id == id)
+            .map(|s| s.value.clone())
+            .ok_or(sqlx::Error::RowNotFound)?;
+        slot.key = state
+            .keys
+            .iter()
+            .find(|k| k.value.slot_id == Some(id))
+            .map(|k| k.value.name.clone());
+        Ok(slot)
+    }
+
+    async fn store(&self, slot: &Slot, key_name: &str) -> Result<u64, sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        let org = match state.slots.iter().find(|s| s.value.id == slot.id) {
+            Some(s) => s.org,
+            None => return Ok(0),
+        };
+        let mut stored = 0;
+        for k in state.keys.iter_mut() {
+            if k.org == org && k.value.name == key_name {
+                k.value.slot_id = Some(slot.id);
+                stored += 1;
+            } else if k.value.slot_id == Some(slot.id) {
+                return Err(unique_violation("keys_slot_id_key"));
+            }
+        }
+        Ok(stored)
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryRepository {
+    async fn get(&self, org: i64, username: &str) -> Result<User, sqlx::Error> {
+        self.state.lock().unwrap().user(org, username)
+    }
+
+    async fn find(&self, username: &str) -> Result<User, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        state
+            .users
+            .iter()
+            .find(|u| u.username == username)
+            .cloned()
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn get_all(&self, org: i64) -> Result<Vec<User>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        let mut users: Vec<User> = state
+            .users
+            .iter()
+            .filter_map(|u| state.user(org, &u.username).ok())
+            .collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(users)
+    }
+
+    async fn create(&self, org: i64, user: &User) -> Result<u64, sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        let mut user = user.clone();
+        user.id = 0;
+        state.check_user(&user)?;
+        user.id = state.next_id();
+        state.members.push(Row::new(
+            Member {
+                user_id: user.id,
+                admin: user.admin,
+            },
+            org,
+        ));
+        state.users.push(User {
+            admin: false,
+            ..user
+        });
+        Ok(1)
+    }
+
+    async fn update(&self, org: i64, user: &User) -> Result<u64, sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        state.check_user(user)?;
+        let old = match state.users.iter().find(|u| u.id == user.id) {
+            Some(u) => u.clone(),
+            None => return Ok(0),
+        };
+        state.rename_user(&old.username, &user.username);
+        if let Some(u) = state.users.iter_mut().find(|u| u.id == user.id) {
+            *u = User {
+                password_hash: old.password_hash,
+                admin: false,
+                ..user.clone()
+            };
+        }
+        if let Some(m) = state
+            .members
+            .iter_mut()
+            .find(|m| m.org == org && m.value.user_id == user.id)
+        {
+            m.value.admin = user.admin;
+        }
+        Ok(1)
+    }
+
+    async fn delete(&self, org: i64, user: &User) -> Result<(), sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        let row = state
+            .members
+            .iter_mut()
+            .find(|m| !m.deleted && m.org == org && m.value.user_id == user.id)
+            .ok_or(sqlx::Error::RowNotFound)?;
+        row.deleted = true;
+        for a in state
+            .assignments
+            .iter_mut()
+            .filter(|a| a.org == org && a.value.user.as_ref() == Some(&user.username))
+        {
+            a.deleted = true;
+        }
+        Ok(())
+    }
+
+    async fn set_password(&self, user: &mut User, password: &str) -> Result<u64, sqlx::Error> {
+        user.hash_password(password)?;
+
+        let mut state = self.state.lock().unwrap();
+        let mut updated = 0;
+        for u in state
+            .users
+            .iter_mut()
+            .filter(|u| u.username == user.username)
+        {
+            u.password_hash = user.password_hash.clone();
+            updated += 1;
+        }
+        Ok(updated)
+    }
+
+    async fn count_admins(&self, org: i64) -> Result<i64, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .members
+            .iter()
+            .filter(|m| !m.deleted && m.org == org && m.value.admin)
+            .count() as i64)
+    }
+
+    async fn memberships(&self, username: &str) -> Result<Vec<Membership>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        let mut memberships: Vec<Membership> = state
+            .orgs
+            .iter()
+            .filter_map(|o| {
+                state.user(o.id, username).ok().map(|u| Membership {
+                    org_id: o.id,
+                    org: o.name.clone(),
+                    admin: u.admin,
+                })
+            })
+            .collect();
+        memberships.sort_by_key(|m| m.org_id);
+        Ok(memberships)
+    }
+
+    async fn add_member(&self, org: i64, username: &str, admin: bool) -> Result<u64, sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        let user_id = match state.users.iter().find(|u| u.username == username) {
+            Some(u) => u.id,
+            None => return Ok(0),
+        };
+        match state
+            .members
+            .iter_mut()
+            .find(|m| m.org == org && m.value.user_id == user_id)
+        {
+            Some(m) => {
+                m.value.admin = admin;
+                m.deleted = false;
+            }
+            None => state.members.push(Row::new(Member { user_id, admin }, org)),
+        }
+        Ok(1)
+    }
+
+    async fn is_site_admin(&self, username: &str) -> Result<bool, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        let first = state.orgs.iter().map(|o| o.id).min().unwrap_or(DEFAULT_ORG);
+        Ok(state
+            .user(first, username)
+            .map(|u| u.admin)
+            .unwrap_or(false))
+    }
+
+    async fn notification_settings(
+        &self,
+        username: &str,
+    ) -> Result<NotificationSettings, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .notifications
+            .get(username)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn set_notification_settings(
+        &self,
+        username: &str,
+        settings: &NotificationSettings,
+    ) -> Result<(), sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        if !state.users.iter().any(|u| u.username == username) {
+            return Err(foreign_key_violation(
+                "notification_opt_outs",
+                "notification_opt_outs_user_fkey",
+            ));
+        }
+        state
+            .notifications
+            .insert(username.into(), settings.clone());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AssignmentRepository for MemoryRepository {
+    async fn get(&self, org: i64, id: i64) -> Result<Assignment, sqlx::Error> {
+        self.state.lock().unwrap().assignment(org, id).cloned()
+    }
+
+    async fn get_all(
+        &self,
+        org: i64,
+        filter: AssignmentQuery,
+    ) -> Result<Vec<Assignment>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        let mut assignments: Vec<Assignment> = state
+            .assignments
+            .iter()
+            .filter(|a| !a.deleted && a.org == org)
+            .map(|a| a.value.clone())
+            .filter(|a| filter.user.is_none() || a.user == filter.user)
+            .filter(|a| filter.group.is_none() || a.group == filter.group)
+            .filter(|a| {
+                filter.member.as_ref().is_none_or(|m| {
+                    state.groups.iter().any(|g| {
+                        g.org == org
+                            && Some(&g.value.name) == a.group.as_ref()
+                            && g.value.members.contains(m)
+                    })
+                })
+            })
+            .filter(|a| filter.key.as_ref().is_none_or(|k| &a.key == k))
+            .filter(|a| filter.id.is_none_or(|id| a.id == id))
+            .filter(|a| filter.pending.is_none_or(|p| a.pending == p))
+            .collect();
+        match filter.sort.as_deref() {
+            Some("id") => assignments.sort_by_key(|a| a.id),
+            Some("key") => assignments.sort_by(|a, b| a.key.cmp(&b.key)),
+            Some("date_out") => assignments.sort_by_key(|a| a.date_out),
+            // Postgres sorts nulls last
+            Some("user") => assignments.sort_by_key(|a| (a.user.is_none(), a.user.clone())),
+            Some("group") => assignments.sort_by_key(|a| (a.group.is_none(), a.group.clone())),
+            Some("date_in") => assignments.sort_by_key(|a| (a.date_in.is_none(), a.date_in)),
+            _ => (),
+        }
+        Ok(assignments)
+    }
+
+    async fn get_by_user(&self, org: i64, username: &str) -> Result<Vec<Assignment>, sqlx::Error> {
+        let mut assignments = AssignmentRepository::get_all(
+            self,
+            org,
+            AssignmentQuery {
+                user: Some(username.into()),
+                sort: Some("key".into()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assignments.retain(|a| a.date_in.is_none());
+        Ok(assignments)
+    }
+
+    async fn get_by_key(&self, org: i64, key_name: &str) -> Result<Vec<Assignment>, sqlx::Error> {
+        let mut assignments = AssignmentRepository::get_all(
+            self,
+            org,
+            AssignmentQuery {
+                key: Some(key_name.into()),
+                sort: Some("user".into()),
+                ..Default::default()
+            },
+        )
+        .await?;
+        assignments.retain(|a| a.date_in.is_none());
+        Ok(assignments)
+    }
+
+    async fn history(&self, id: i64) -> Result<Vec<AssignmentChange>, sqlx::Error> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .changes
+            .iter()
+            .filter(|c| c.assignment_id == id)
+            .cloned()
+            .collect())
+    }
+
+    async fn create(&self, org: i64, assignment: &mut Assignment) -> Result<(), sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        assignment.id = 0;
+        state.check_assignment(org, assignment)?;
+        assignment.id = state.next_id();
+        state.assignments.push(Row::new(assignment.clone(), org));
+        state.record(
+            assignment.id,
+            assignment.created_by.as_deref(),
+            "created",
+            None,
+        );
+        if !assignment.pending {
+            state.take_key(org, &assignment.key);
+        }
+        Ok(())
+    }
+
+    async fn update(
+        &self,
+        org: i64,
+        assignment: &mut Assignment,
+        changed_by: &str,
+    ) -> Result<(), sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        let old = state
+            .assignments
+            .iter()
+            .find(|a| a.value.id == assignment.id)
+            .map(|a| a.value.clone())
+            .ok_or(sqlx::Error::RowNotFound)?;
+        state.check_assignment(org, assignment)?;
+
+        for change in assignment.changes_from(&old) {
+            state.record(assignment.id, Some(changed_by), "updated", Some(change));
+        }
+        if let Some(a) = state
+            .assignments
+            .iter_mut()
+            .find(|a| a.value.id == assignment.id)
+        {
+            a.value = Assignment {
+                id: old.id,
+                pending: old.pending,
+                created_by: old.created_by,
+                confirmed_by: old.confirmed_by,
+                lost: old.lost,
+                ..assignment.clone()
+            };
+        }
+        Ok(())
+    }
+
+    async fn confirm(
+        &self,
+        assignment: &mut Assignment,
+        confirmed_by: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        let row = state
+            .assignments
+            .iter_mut()
+            .find(|a| !a.deleted && a.value.id == assignment.id)
+            .ok_or(sqlx::Error::RowNotFound)?;
+        let stored = &mut row.value;
+        if !stored.pending || stored.created_by.as_deref() == Some(confirmed_by) {
+            return Ok(false);
+        }
+        stored.pending = false;
+        stored.confirmed_by = Some(confirmed_by.into());
+        let org = row.org;
+        state.record(assignment.id, Some(confirmed_by), "confirmed", None);
+        state.take_key(org, &assignment.key);
+
+        assignment.pending = false;
+        assignment.confirmed_by = Some(confirmed_by.into());
+        Ok(true)
+    }
+
+    async fn report_lost(
+        &self,
+        assignment: &mut Assignment,
+        date: NaiveDate,
+        reported_by: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        let row = match state
+            .assignments
+            .iter_mut()
+            .find(|a| a.value.id == assignment.id && a.value.date_in.is_none())
+        {
+            Some(r) => r,
+            None => return Ok(false),
+        };
+        row.value.date_in = Some(date);
+        row.value.lost = true;
+        let org = row.org;
+        state.record(
+            assignment.id,
+            Some(reported_by),
+            "lost",
+            Some(("date_in", None, Some(date.to_string()))),
+        );
+        for k in state
+            .keys
+            .iter_mut()
+            .filter(|k| k.org == org && k.value.name == assignment.key)
+        {
+            k.value.active = false;
+        }
+
+        assignment.date_in = Some(date);
+        assignment.lost = true;
+        Ok(true)
+    }
+
+    async fn delete(&self, assignment: &Assignment) -> Result<u64, sqlx::Error> {
+        let mut state = self.state.lock().unwrap();
+        match state
+            .assignments
+            .iter_mut()
+            .find(|a| !a.deleted && a.value.id == assignment.id)
+        {
+            Some(a) => {
+                a.deleted = true;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+#[async_trait]
+impl Events for MemoryRepository {
+    async fn emit(&self, org: i64, event: &str, data: Value) {
+        let mut state = self.state.lock().unwrap();
+        state.events.push((org, event.into(), data));
+    }
+
+    async fn key_assigned(&self, org: i64, user: &str, key: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.events.push((
+            org,
+            "key_assigned".into(),
+            serde_json::json!({ "user": user, "key": key }),
+        ));
+    }
+}
@@ -1,37 +1,404 @@
-use std::{env, process::exit};
+// use dotenvy::dotenv;
+use std::{str::FromStr, time::Duration};
 
 use anyhow::Result;
-// use dotenvy::dotenv;
-use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
+use lazy_static::lazy_static;
+use log::LevelFilter;
+use regex::Regex;
+use sqlx::{
+    migrate::Migrator,
+    postgres::{PgConnectOptions, PgPoolOptions},
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+    ConnectOptions, Database, PgPool, Pool, Postgres, Sqlite, SqlitePool, Transaction,
+};
+
+use crate::config::DatabaseConfig;
+
+/// Fetches from a SQLite query. A statement which isn't stepped to completion keeps its implicit
+/// transaction open, so the rows of `INSERT`, `UPDATE` and `DELETE` with `RETURNING` are always
+/// all read.
+macro_rules! sqlite_fetch {
+    (fetch_one, $q:expr, $e:expr) => {
+        $q.fetch_all($e)
+            .await
+            .and_then(|rows| rows.into_iter().next().ok_or(sqlx::Error::RowNotFound))
+    };
+    (fetch_optional, $q:expr, $e:expr) => {
+        $q.fetch_all($e).await.map(|rows| rows.into_iter().next())
+    };
+    (fetch_all, $q:expr, $e:expr) => {
+        $q.fetch_all($e).await
+    };
+}
+
+/// Runs a query returning rows on either backend and maps them into `$out`, which must derive
+/// `FromRow`. Postgres queries are checked at compile time. SQLite gets the same SQL with the
+/// Postgres only bits translated by `sqlite_sql`, or the SQL given with `sqlite =` when the
+/// dialects differ too much.
+macro_rules! db_query_as {
+    ($out:path, $fetch:ident, $conn:expr, $sql:literal, sqlite = $sqlite:expr $(, $arg:expr)* $(,)?) => {
+        match $crate::models::Conn::from($conn) {
+            $crate::models::Conn::Postgres(e) => {
+                match e {
+                    $crate::models::Exec::Pool(p) => sqlx::query_as!($out, $sql $(, $arg)*).$fetch(p).await,
+                    $crate::models::Exec::Conn(c) => sqlx::query_as!($out, $sql $(, $arg)*).$fetch(c).await,
+                }
+            }
+            $crate::models::Conn::Sqlite(e) => {
+                let sql = $crate::models::sqlite_sql($sqlite);
+                match e {
+                    $crate::models::Exec::Pool(p) => sqlite_fetch!($fetch, sqlx::query_as::<_, $out>(&sql) $(.bind(&$arg))*, p),
+                    $crate::models::Exec::Conn(c) => sqlite_fetch!($fetch, sqlx::query_as::<_, $out>(&sql) $(.bind(&$arg))*, c),
+                }
+            }
+        }
+    };
+    ($out:path, $fetch:ident, $conn:expr, $sql:literal $(, $arg:expr)* $(,)?) => {
+        db_query_as!($out, $fetch, $conn, $sql, sqlite = $sql $(, $arg)*)
+    };
+}
+
+/// Like `db_query_as` for queries returning a single column of type `$ty`.
+macro_rules! db_query_scalar {
+    ($ty:ty, $fetch:ident, $conn:expr, $sql:literal, sqlite = $sqlite:expr $(, $arg:expr)* $(,)?) => {
+        match $crate::models::Conn::from($conn) {
+            $crate::models::Conn::Postgres(e) => {
+                match e {
+                    $crate::models::Exec::Pool(p) => sqlx::query_scalar!($sql $(, $arg)*).$fetch(p).await,
+                    $crate::models::Exec::Conn(c) => sqlx::query_scalar!($sql $(, $arg)*).$fetch(c).await,
+                }
+            }
+            $crate::models::Conn::Sqlite(e) => {
+                let sql = $crate::models::sqlite_sql($sqlite);
+                match e {
+                    $crate::models::Exec::Pool(p) => sqlite_fetch!($fetch, sqlx::query_scalar::<_, $ty>(&sql) $(.bind(&$arg))*, p),
+                    $crate::models::Exec::Conn(c) => sqlite_fetch!($fetch, sqlx::query_scalar::<_, $ty>(&sql) $(.bind(&$arg))*, c),
+                }
+            }
+        }
+    };
+    ($ty:ty, $fetch:ident, $conn:expr, $sql:literal $(, $arg:expr)* $(,)?) => {
+        db_query_scalar!($ty, $fetch, $conn, $sql, sqlite = $sql $(, $arg)*)
+    };
+}
+
+/// Runs a statement on either backend and returns the number of rows it affected.
+macro_rules! db_execute {
+    ($conn:expr, $sql:literal, sqlite = $sqlite:expr $(, $arg:expr)* $(,)?) => {
+        match $crate::models::Conn::from($conn) {
+            $crate::models::Conn::Postgres(e) => {
+                match e {
+                    $crate::models::Exec::Pool(p) => sqlx::query!($sql $(, $arg)*).execute(p).await,
+                    $crate::models::Exec::Conn(c) => sqlx::query!($sql $(, $arg)*).execute(c).await,
+                }
+                .map(|r| r.rows_affected())
+            }
+            $crate::models::Conn::Sqlite(e) => {
+                let sql = $crate::models::sqlite_sql($sqlite);
+                match e {
+                    $crate::models::Exec::Pool(p) => sqlx::query(&sql) $(.bind(&$arg))*.execute(p).await,
+                    $crate::models::Exec::Conn(c) => sqlx::query(&sql) $(.bind(&$arg))*.execute(c).await,
+                }
+                .map(|r| r.rows_affected())
+            }
+        }
+    };
+    ($conn:expr, $sql:literal $(, $arg:expr)* $(,)?) => {
+        db_execute!($conn, $sql, sqlite = $sql $(, $arg)*)
+    };
+}
+
+/// Generates a test module named after the test which runs it once against Postgres and once
+/// against SQLite, each with its own migrations. The body gets the pool as a `Db`.
+#[cfg(test)]
+#[macro_export]
+macro_rules! db_test {
+    (#[sqlx::test$(($($arg:tt)*))?] async fn $name:ident($pool:ident: Db) $(-> $ret:ty)? $body:block) => {
+        mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            #[sqlx::test(migrations = "migrations/postgres", $($($arg)*)?)]
+            async fn postgres(pool: sqlx::PgPool) $(-> $ret)? {
+                let $pool = $crate::models::Db::from(pool);
+                $body
+            }
+
+            #[sqlx::test(migrations = "migrations/sqlite", $($($arg)*)?)]
+            async fn sqlite(pool: sqlx::SqlitePool) $(-> $ret)? {
+                let $pool = $crate::models::Db::from(pool);
+                $body
+            }
+        }
+    };
+}
 
 mod assignment;
+mod audit;
+mod backup;
+mod cabinet;
+mod finance;
+mod group;
+mod job;
 mod key;
+mod key_request;
+#[cfg(test)]
+mod memory;
+mod notification;
+mod org;
+mod repository;
+mod trash;
 mod user;
+mod webhook;
 
-pub use assignment::{Assignment, AssignmentQuery};
+pub use assignment::{Assignment, AssignmentChange, AssignmentError, AssignmentQuery};
+pub use audit::{Audit, AuditItem, AuditResult};
+pub use backup::Backup;
+pub use cabinet::{Cabinet, KeyLocation, Slot};
+pub use finance::{currency_for, Balance, Charge, FinanceEntry, GroupBalance};
+pub use group::Group;
+pub use job::{Job, JobRun, JobStatus};
 pub use key::Key;
+pub use key_request::{KeyRequest, RequestStatus};
+#[cfg(test)]
+pub use memory::MemoryRepository;
+pub use notification::{
+    AdminSummary, AssignmentNotice, NotificationKind, NotificationSettings, QueuedEmail, Recipient,
+};
+pub use org::{Membership, Org};
+pub use repository::{AssignmentRepository, KeyRepository, UserRepository};
+pub use trash::{TrashItem, TrashKind};
 pub use user::{initialize_admin, Credentials, User};
+pub use webhook::{Webhook, WebhookDelivery, WEBHOOK_EVENTS};
+
+/// The org the migrations create, which the fixtures fill.
+#[cfg(test)]
+pub const DEFAULT_ORG: i64 = 1;
 
-pub async fn db() -> Result<Pool<Postgres>> {
-    // dotenv()?;
-    let db_url = match env::var("DATABASE_URL") {
-        // Ok(u) => format!("{}_test", u),
-        Ok(u) => u,
-        Err(e) => {
-            eprintln!("Failed to get DATABASE_URL variable. {}", e);
-            exit(1);
+static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("migrations/postgres");
+static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("migrations/sqlite");
+
+/// A pool of connections to either backend. Which one is picked by the scheme of
+/// `database.url`.
+#[derive(Debug, Clone)]
+pub enum Db {
+    Postgres(PgPool),
+    Sqlite(SqlitePool),
+}
+
+impl From<PgPool> for Db {
+    fn from(pool: PgPool) -> Self {
+        Db::Postgres(pool)
+    }
+}
+
+impl From<SqlitePool> for Db {
+    fn from(pool: SqlitePool) -> Self {
+        Db::Sqlite(pool)
+    }
+}
+
+impl Db {
+    pub async fn begin(&self) -> Result<Tx, sqlx::Error> {
+        Ok(match self {
+            Db::Postgres(p) => Tx::Postgres(Box::new(p.begin().await?)),
+            Db::Sqlite(p) => Tx::Sqlite(p.begin().await?),
+        })
+    }
+
+    /// Number of open connections in the pool.
+    pub fn size(&self) -> u32 {
+        match self {
+            Db::Postgres(p) => p.size(),
+            Db::Sqlite(p) => p.size(),
         }
-    };
+    }
+
+    /// Number of open connections which aren't in use.
+    pub fn num_idle(&self) -> usize {
+        match self {
+            Db::Postgres(p) => p.num_idle(),
+            Db::Sqlite(p) => p.num_idle(),
+        }
+    }
+
+    fn migrator(&self) -> &'static Migrator {
+        match self {
+            Db::Postgres(_) => &POSTGRES_MIGRATOR,
+            Db::Sqlite(_) => &SQLITE_MIGRATOR,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Db {
+    /// Runs a statement written for Postgres, translated for SQLite like the models' queries.
+    pub async fn execute(&self, sql: &str) -> Result<u64, sqlx::Error> {
+        Ok(match self {
+            Db::Postgres(p) => sqlx::query(sql).execute(p).await?.rows_affected(),
+            Db::Sqlite(p) => sqlx::query(&sqlite_sql(sql))
+                .execute(p)
+                .await?
+                .rows_affected(),
+        })
+    }
+
+    /// Runs a query returning a single count.
+    pub async fn count(&self, sql: &str) -> Result<i64, sqlx::Error> {
+        match self {
+            Db::Postgres(p) => sqlx::query_scalar(sql).fetch_one(p).await,
+            Db::Sqlite(p) => sqlx::query_scalar(&sqlite_sql(sql)).fetch_one(p).await,
+        }
+    }
+}
+
+/// A transaction on either backend. It is rolled back if dropped without being committed.
+pub enum Tx {
+    Postgres(Box<Transaction<'static, Postgres>>),
+    Sqlite(Transaction<'static, Sqlite>),
+}
+
+impl Tx {
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        match self {
+            Tx::Postgres(tx) => tx.commit().await,
+            Tx::Sqlite(tx) => tx.commit().await,
+        }
+    }
+}
+
+/// Something a query can be run on: a pool or a transaction, for either backend. The query
+/// macros take anything which converts into one.
+pub enum Conn<'c> {
+    Postgres(Exec<'c, Postgres>),
+    Sqlite(Exec<'c, Sqlite>),
+}
+
+pub enum Exec<'c, DB: Database> {
+    Pool(&'c Pool<DB>),
+    Conn(&'c mut DB::Connection),
+}
+
+impl<'c> From<&'c Db> for Conn<'c> {
+    fn from(db: &'c Db) -> Self {
+        match db {
+            Db::Postgres(p) => Conn::Postgres(Exec::Pool(p)),
+            Db::Sqlite(p) => Conn::Sqlite(Exec::Pool(p)),
+        }
+    }
+}
+
+impl<'c> From<&'c mut Tx> for Conn<'c> {
+    fn from(tx: &'c mut Tx) -> Self {
+        match tx {
+            Tx::Postgres(tx) => Conn::Postgres(Exec::Conn(&mut ***tx)),
+            Tx::Sqlite(tx) => Conn::Sqlite(Exec::Conn(&mut **tx)),
+        }
+    }
+}
+
+lazy_static! {
+    /// Type overrides in column aliases, like `"name!"` or `"status: RequestStatus"`, which
+    /// only mean something to the Postgres query macros.
+    static ref TYPE_OVERRIDE: Regex = Regex::new(r#""(\w+)[?!]?(?:\s*:[^"]*)?""#).unwrap();
+    static ref FOR_UPDATE: Regex = Regex::new(r"\s+FOR UPDATE(?: OF \w+)?").unwrap();
+}
+
+/// Translates the bits of Postgres SQL used throughout the models which SQLite spells
+/// differently. SQLite takes `$1` style parameters as they are. SQLite locks the whole database
+/// for writes, so row locks are dropped.
+pub fn sqlite_sql(sql: &str) -> String {
+    let sql = TYPE_OVERRIDE.replace_all(sql, r#""$1""#);
+    FOR_UPDATE
+        .replace_all(&sql, "")
+        .replace("now()", "strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')")
+        .replace("IS NOT DISTINCT FROM", "IS")
+        .replace("IS DISTINCT FROM", "IS NOT")
+}
+
+/// Whether an error is a violated unique constraint.
+pub fn is_unique_violation(e: &sqlx::Error) -> bool {
+    let msg = e.to_string();
+    msg.contains("duplicate key") || msg.contains("UNIQUE constraint failed")
+}
+
+/// Whether an error is a violated foreign key, i.e. a row refers to one which doesn't exist.
+pub fn is_foreign_key_violation(e: &sqlx::Error) -> bool {
+    let msg = e.to_string();
+    msg.contains("violates foreign key") || msg.contains("FOREIGN KEY constraint failed")
+}
+
+/// Connects to the database and applies any pending migrations.
+pub async fn db(config: &DatabaseConfig) -> Result<Db> {
+    let pool = connect(config).await?;
+    migrate(&pool).await?;
+    Ok(pool)
+}
+
+/// Connects to the database without touching its schema. URLs starting with `sqlite:` open
+/// (and create) a SQLite database file, anything else is taken to be Postgres.
+pub async fn connect(config: &DatabaseConfig) -> Result<Db> {
+    // Every statement is logged at info by default, which drowns out everything else
+    if config.url.starts_with("sqlite:") {
+        let mut options = SqliteConnectOptions::from_str(&config.url)?
+            .create_if_missing(true)
+            .foreign_keys(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_secs(10));
+        options.log_statements(LevelFilter::Debug);
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(config.max_connections)
+            .connect_with(options)
+            .await?;
+        return Ok(Db::Sqlite(pool));
+    }
+
+    let mut options = PgConnectOptions::from_str(&config.url)?;
+    options.log_statements(LevelFilter::Debug);
 
     let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&db_url)
+        .max_connections(config.max_connections)
+        .connect_with(options)
         .await?;
 
-    let migrator = sqlx::migrate!();
-    migrator.run(&pool).await?;
+    Ok(Db::Postgres(pool))
+}
 
-    Ok(pool)
+/// Applies any pending migrations and returns how many there were.
+pub async fn migrate(pool: &Db) -> Result<usize> {
+    let pending = pending_migrations(pool).await?;
+    match pool {
+        Db::Postgres(p) => pool.migrator().run(p).await?,
+        Db::Sqlite(p) => pool.migrator().run(p).await?,
+    }
+    Ok(pending)
+}
+
+/// Returns the number of migrations built into the backend which haven't been applied to the
+/// database.
+pub async fn pending_migrations(pool: &Db) -> Result<usize, sqlx::Error> {
+    let applied = match db_query_scalar!(
+        i64,
+        fetch_all,
+        pool,
+        "SELECT version FROM _sqlx_migrations WHERE success"
+    ) {
+        Ok(a) => a,
+        // The table is created by the first migration run
+        Err(sqlx::Error::Database(e))
+            if e.code().as_deref() == Some("42P01") || e.message().contains("no such table") =>
+        {
+            Vec::new()
+        }
+        Err(e) => return Err(e),
+    };
+
+    Ok(pool
+        .migrator()
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .count())
 }
 
 // #[sqlx_macros::test]
@@ -0,0 +1,502 @@
+use anyhow::Result;
+use chrono::{Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use super::Db;
+
+/// Maximum number of attempts made to send a queued email before it is given up on.
+pub static MAX_SEND_ATTEMPTS: i32 = 5;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "notification_kind", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    KeyAssigned,
+    KeyDue,
+    KeyOverdue,
+    AdminSummary,
+}
+
+/// An email in the outgoing queue.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct QueuedEmail {
+    pub id: i64,
+    pub kind: NotificationKind,
+    pub recipient: String,
+    pub subject: String,
+    pub body: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+}
+
+impl QueuedEmail {
+    /// Adds an email to the queue. An email with a `dedupe_key` is only ever queued once, so
+    /// the same reminder can be queued repeatedly without being sent twice. Returns false if
+    /// the email was already queued.
+    pub async fn enqueue(
+        pool: &Db,
+        kind: NotificationKind,
+        recipient: &str,
+        subject: &str,
+        body: &str,
+        dedupe_key: Option<&str>,
+    ) -> Result<bool, sqlx::Error> {
+        let res = db_execute!(
+            pool,
+            r#"INSERT INTO email_queue (kind, recipient, subject, body, dedupe_key)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (dedupe_key) DO NOTHING"#,
+            kind as NotificationKind,
+            recipient,
+            subject,
+            body,
+            dedupe_key
+        )?;
+
+        Ok(res == 1)
+    }
+
+    /// Returns unsent emails which are due for another attempt, oldest first.
+    pub async fn get_due(pool: &Db, limit: i64) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT
+                id,
+                kind as "kind: NotificationKind",
+                recipient,
+                subject,
+                body,
+                attempts,
+                last_error
+            FROM email_queue
+            WHERE sent_at IS NULL AND attempts < $1 AND next_attempt <= now()
+            ORDER BY id
+            LIMIT $2"#,
+            MAX_SEND_ATTEMPTS,
+            limit
+        )
+    }
+
+    pub async fn mark_sent(&self, pool: &Db) -> Result<(), sqlx::Error> {
+        db_execute!(
+            pool,
+            "UPDATE email_queue SET sent_at = now(), attempts = attempts + 1 WHERE id = $1",
+            self.id
+        )?;
+        Ok(())
+    }
+
+    /// Records a failed attempt. The next attempt is delayed exponentially, starting at two
+    /// minutes and doubling with every failure.
+    pub async fn mark_failed(&mut self, pool: &Db, error: &str) -> Result<(), sqlx::Error> {
+        db_execute!(
+            pool,
+            r#"UPDATE email_queue
+            SET
+                attempts = attempts + 1,
+                last_error = $1,
+                next_attempt = now() + make_interval(mins => (2 ^ (attempts + 1))::INT)
+            WHERE id = $2"#,
+            sqlite = r#"UPDATE email_queue
+            SET
+                attempts = attempts + 1,
+                last_error = $1,
+                next_attempt = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now', '+' || (1 << (attempts + 1)) || ' minutes')
+            WHERE id = $2"#,
+            error,
+            self.id
+        )?;
+
+        self.attempts += 1;
+        self.last_error = Some(error.into());
+        Ok(())
+    }
+
+    /// Deletes emails which were sent more than `days` days ago.
+    pub async fn delete_sent(pool: &Db, days: i64) -> Result<u64, sqlx::Error> {
+        db_execute!(
+            pool,
+            "DELETE FROM email_queue WHERE sent_at < $1",
+            Utc::now() - Duration::days(days)
+        )
+    }
+}
+
+/// The notifications a user receives. Every kind is enabled unless the user opts out of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct NotificationSettings {
+    pub key_assigned: bool,
+    pub key_due: bool,
+    pub key_overdue: bool,
+    pub admin_summary: bool,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        NotificationSettings {
+            key_assigned: true,
+            key_due: true,
+            key_overdue: true,
+            admin_summary: true,
+        }
+    }
+}
+
+impl NotificationSettings {
+    pub async fn get(pool: &Db, username: &str) -> Result<Self, sqlx::Error> {
+        let opt_outs = db_query_scalar!(
+            NotificationKind,
+            fetch_all,
+            pool,
+            r#"SELECT kind as "kind: NotificationKind" FROM notification_opt_outs WHERE "user" = $1"#,
+            username
+        )?;
+
+        let mut settings = Self::default();
+        for kind in opt_outs {
+            match kind {
+                NotificationKind::KeyAssigned => settings.key_assigned = false,
+                NotificationKind::KeyDue => settings.key_due = false,
+                NotificationKind::KeyOverdue => settings.key_overdue = false,
+                NotificationKind::AdminSummary => settings.admin_summary = false,
+            }
+        }
+        Ok(settings)
+    }
+
+    pub async fn set(&self, pool: &Db, username: &str) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        db_execute!(
+            &mut tx,
+            r#"DELETE FROM notification_opt_outs WHERE "user" = $1"#,
+            username
+        )?;
+
+        for (kind, enabled) in [
+            (NotificationKind::KeyAssigned, self.key_assigned),
+            (NotificationKind::KeyDue, self.key_due),
+            (NotificationKind::KeyOverdue, self.key_overdue),
+            (NotificationKind::AdminSummary, self.admin_summary),
+        ] {
+            if !enabled {
+                db_execute!(
+                    &mut tx,
+                    r#"INSERT INTO notification_opt_outs ("user", kind) VALUES ($1, $2)"#,
+                    username,
+                    kind as NotificationKind
+                )?;
+            }
+        }
+
+        tx.commit().await
+    }
+}
+
+/// An open assignment along with the contact details of its holder, used to notify them
+/// about it. Only holders with an email address who haven't opted out are returned.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct AssignmentNotice {
+    pub assignment_id: i64,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub email: String,
+    pub key: String,
+    pub date_out: NaiveDate,
+    pub date_due: Option<NaiveDate>,
+}
+
+impl AssignmentNotice {
+    /// Returns the confirmed, open assignment of the org's `key` to `user`.
+    pub async fn get_open(
+        pool: &Db,
+        org: i64,
+        user: &str,
+        key: &str,
+        kind: NotificationKind,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_optional,
+            pool,
+            r#"SELECT
+                a.id as assignment_id,
+                u.username,
+                u.display_name,
+                u.email as "email!",
+                k.name as key,
+                a.date_out,
+                a.date_due
+            FROM assignments a
+            JOIN users u ON u.id = a.user_id
+            JOIN keys k ON k.id = a.key_id
+            WHERE u.username = $1 AND k.name = $2 AND k.org_id = $4
+            AND a.date_in IS NULL AND NOT a.pending AND a.deleted_at IS NULL
+            AND u.email IS NOT NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM notification_opt_outs o WHERE o."user" = u.username AND o.kind = $3
+            )"#,
+            user,
+            key,
+            kind as NotificationKind,
+            org
+        )
+    }
+
+    /// Returns the open assignments due back between `from` and `to`, inclusive.
+    pub async fn get_due_between(
+        pool: &Db,
+        from: NaiveDate,
+        to: NaiveDate,
+        kind: NotificationKind,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT
+                a.id as assignment_id,
+                u.username,
+                u.display_name,
+                u.email as "email!",
+                k.name as key,
+                a.date_out,
+                a.date_due
+            FROM assignments a
+            JOIN users u ON u.id = a.user_id
+            JOIN keys k ON k.id = a.key_id
+            WHERE a.date_due BETWEEN $1 AND $2
+            AND a.date_in IS NULL AND NOT a.pending AND a.deleted_at IS NULL
+            AND u.email IS NOT NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM notification_opt_outs o WHERE o."user" = u.username AND o.kind = $3
+            )
+            ORDER BY a.date_due, u.username, k.name"#,
+            from,
+            to,
+            kind as NotificationKind
+        )
+    }
+}
+
+/// A contact which receives notifications of a given kind.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct Recipient {
+    pub username: String,
+    pub display_name: Option<String>,
+    pub email: String,
+}
+
+impl Recipient {
+    /// Returns the org's admins with an email address who haven't opted out of `kind`.
+    pub async fn admins(
+        pool: &Db,
+        org: i64,
+        kind: NotificationKind,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT username, display_name, email as "email!"
+            FROM users u
+            JOIN org_members m ON m.user_id = u.id
+            WHERE m.org_id = $2 AND m.admin AND m.deleted_at IS NULL
+            AND NOT departed AND email IS NOT NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM notification_opt_outs o WHERE o."user" = u.username AND o.kind = $1
+            )
+            ORDER BY username"#,
+            kind as NotificationKind,
+            org
+        )
+    }
+}
+
+/// Figures for the daily summary sent to admins.
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+pub struct AdminSummary {
+    pub open_assignments: i64,
+    pub pending_confirmations: i64,
+    pub pending_requests: i64,
+    pub overdue_assignments: i64,
+}
+
+impl AdminSummary {
+    pub async fn get(pool: &Db, org: i64, today: NaiveDate) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            r#"WITH a AS (
+                SELECT a.* FROM assignments a
+                JOIN keys k ON k.id = a.key_id
+                WHERE k.org_id = $2 AND a.deleted_at IS NULL
+            )
+            SELECT
+                (SELECT count(*) FROM a WHERE date_in IS NULL AND NOT pending) as "open_assignments!",
+                (SELECT count(*) FROM a WHERE pending) as "pending_confirmations!",
+                (SELECT count(*) FROM key_requests WHERE org_id = $2 AND status = 'pending') as "pending_requests!",
+                (SELECT count(*) FROM a WHERE date_in IS NULL AND NOT pending AND date_due < $1) as "overdue_assignments!""#,
+            today,
+            org
+        )
+    }
+}
+
+#[cfg(test)]
+mod notification_tests {
+    use crate::mailer;
+    use crate::models::{
+        AdminSummary, Assignment, AssignmentNotice, NotificationKind, NotificationSettings,
+        QueuedEmail, DEFAULT_ORG,
+    };
+    use anyhow::Result;
+    use chrono::NaiveDate;
+
+    db_test! {
+        #[sqlx::test]
+        async fn queue_email(pool: Db) -> Result<()> {
+            let queued = QueuedEmail::enqueue(
+                &pool,
+                NotificationKind::KeyDue,
+                "user@email.com",
+                "Subject",
+                "Body",
+                Some("key_due:1"),
+            )
+            .await?;
+            assert!(queued);
+
+            // Emails with the same dedupe key are only queued once
+            let queued = QueuedEmail::enqueue(
+                &pool,
+                NotificationKind::KeyDue,
+                "user@email.com",
+                "Subject",
+                "Body",
+                Some("key_due:1"),
+            )
+            .await?;
+            assert!(!queued);
+
+            let mut due = QueuedEmail::get_due(&pool, 10).await?;
+            assert_eq!(1, due.len());
+
+            // Failed emails are retried later
+            due[0].mark_failed(&pool, "Connection refused").await?;
+            assert!(QueuedEmail::get_due(&pool, 10).await?.is_empty());
+
+            pool.execute("UPDATE email_queue SET next_attempt = now()")
+                .await?;
+            let due = QueuedEmail::get_due(&pool, 10).await?;
+            assert_eq!(1, due[0].attempts);
+            assert_eq!(Some("Connection refused".into()), due[0].last_error);
+
+            due[0].mark_sent(&pool).await?;
+            assert!(QueuedEmail::get_due(&pool, 10).await?.is_empty());
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users"))]
+        async fn opt_out(pool: Db) -> Result<()> {
+            assert_eq!(
+                NotificationSettings::default(),
+                NotificationSettings::get(&pool, "user1").await?
+            );
+
+            let settings = NotificationSettings {
+                key_due: false,
+                admin_summary: false,
+                ..Default::default()
+            };
+            settings.set(&pool, "user1").await?;
+            assert_eq!(settings, NotificationSettings::get(&pool, "user1").await?);
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn due_assignments(pool: Db) -> Result<()> {
+            let due = NaiveDate::from_ymd(1988, 11, 3);
+            let mut a = Assignment::new("user1", "key1", NaiveDate::from_ymd(1988, 10, 3));
+            a.date_due = Some(due);
+            a.create(&pool, DEFAULT_ORG).await?;
+
+            let notices =
+                AssignmentNotice::get_due_between(&pool, due, due, NotificationKind::KeyDue).await?;
+            assert_eq!(1, notices.len());
+            assert_eq!("user@email.com", notices[0].email);
+            assert!(
+                AssignmentNotice::get_open(&pool, DEFAULT_ORG, "user1", "key1", NotificationKind::KeyAssigned)
+                    .await?
+                    .is_some()
+            );
+            assert_eq!(
+                1,
+                AdminSummary::get(&pool, DEFAULT_ORG, NaiveDate::from_ymd(1988, 11, 4))
+                    .await?
+                    .overdue_assignments
+            );
+
+            // Users who opted out are left out
+            NotificationSettings {
+                key_due: false,
+                ..Default::default()
+            }
+            .set(&pool, "user1")
+            .await?;
+            assert!(
+                AssignmentNotice::get_due_between(&pool, due, due, NotificationKind::KeyDue)
+                    .await?
+                    .is_empty()
+            );
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn queue_reminders(pool: Db) -> Result<()> {
+            let mut a = Assignment::new("user1", "key1", NaiveDate::from_ymd(1988, 10, 3));
+            a.date_due = Some(NaiveDate::from_ymd(1988, 11, 3));
+            a.create(&pool, DEFAULT_ORG).await?;
+            let mut a = Assignment::new("userNoPass", "key3", NaiveDate::from_ymd(1988, 10, 3));
+            a.date_due = Some(NaiveDate::from_ymd(1988, 10, 30));
+            a.create(&pool, DEFAULT_ORG).await?;
+
+            let today = NaiveDate::from_ymd(1988, 10, 31);
+            assert_eq!(2, mailer::queue_reminders(&pool, today, 3).await?);
+
+            // Reminders are only queued once
+            assert_eq!(0, mailer::queue_reminders(&pool, today, 3).await?);
+
+            let queued = QueuedEmail::get_due(&pool, 10).await?;
+            assert_eq!("user@email.com", queued[0].recipient);
+            assert_eq!("usernopass@email.com", queued[1].recipient);
+            assert_eq!("Key key3 is overdue", queued[1].subject);
+
+            // Nothing is queued for users who opted out
+            NotificationSettings {
+                key_assigned: false,
+                ..Default::default()
+            }
+            .set(&pool, "user1")
+            .await?;
+            assert!(!mailer::queue_key_assigned(&pool, DEFAULT_ORG, "user1", "key1").await?);
+            assert!(mailer::queue_key_assigned(&pool, DEFAULT_ORG, "userNoPass", "key3").await?);
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use super::Db;
+
+/// A client whose keys the deployment manages. Keys, cabinets, audits, key requests, charges
+/// and webhooks belong to one org. Users belong to any number of orgs through memberships.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct Org {
+    #[serde(skip_deserializing)]
+    pub id: i64,
+    pub name: String,
+}
+
+/// An org a user belongs to and whether they administer it.
+#[derive(Debug, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct Membership {
+    pub org_id: i64, // Foreign key to Org::id
+    pub org: String, // Org::name
+    pub admin: bool,
+}
+
+impl Org {
+    pub async fn get_by_name(pool: &Db, name: &str) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            "SELECT id, name FROM orgs WHERE name = $1",
+            name
+        )
+    }
+
+    pub async fn get_all(pool: &Db) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            "SELECT id, name FROM orgs ORDER BY id"
+        )
+    }
+
+    /// The org created for the data which existed before there were orgs. Its admins look
+    /// after the deployment as a whole.
+    pub async fn first(pool: &Db) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            "SELECT id, name FROM orgs ORDER BY id LIMIT 1"
+        )
+    }
+
+    pub async fn create(&mut self, pool: &Db) -> Result<(), sqlx::Error> {
+        self.id = db_query_scalar!(
+            i64,
+            fetch_one,
+            pool,
+            "INSERT INTO orgs (name) VALUES ($1) RETURNING id",
+            self.name
+        )?;
+        Ok(())
+    }
+
+    /// Makes an existing user a member of the org, or changes their role if they already are
+    /// one. A membership in the trash is taken out of it. Returns 0 if there is no such user.
+    pub async fn add_member(
+        pool: &Db,
+        org: i64,
+        username: &str,
+        admin: bool,
+    ) -> Result<u64, sqlx::Error> {
+        db_execute!(
+            pool,
+            r#"INSERT INTO org_members (org_id, user_id, admin)
+            SELECT $1, id, $3 FROM users WHERE username = $2
+            ON CONFLICT (org_id, user_id) DO UPDATE SET admin = excluded.admin, deleted_at = NULL"#,
+            org,
+            username,
+            admin
+        )
+    }
+}
+
+impl Membership {
+    /// The orgs the user belongs to, oldest first. Orgs whose trash the user is in are left out.
+    pub async fn get_by_user(pool: &Db, username: &str) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT o.id as org_id, o.name as org, m.admin
+            FROM org_members m
+            JOIN orgs o ON o.id = m.org_id
+            JOIN users u ON u.id = m.user_id
+            WHERE u.username = $1 AND m.deleted_at IS NULL
+            ORDER BY o.id"#,
+            username
+        )
+    }
+}
+
+#[cfg(test)]
+mod org_tests {
+    use crate::models::{Key, Membership, Org, User};
+    use anyhow::Result;
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys"))]
+        async fn orgs_keep_their_keys_apart(pool: Db) -> Result<()> {
+            let default = Org::first(&pool).await?;
+            let mut other = Org {
+                name: "Other".into(),
+                ..Default::default()
+            };
+            other.create(&pool).await?;
+
+            // Names only need to be unique within an org
+            let mut key = Key {
+                name: "key1".into(),
+                active: true,
+                ..Default::default()
+            };
+            key.create(&pool, other.id).await?;
+            assert!(key.create(&pool, other.id).await.is_err());
+
+            assert_eq!(3, Key::get_all(&pool, default.id).await?.len());
+            assert_eq!(vec![key], Key::get_all(&pool, other.id).await?);
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users"))]
+        async fn memberships(pool: Db) -> Result<()> {
+            let default = Org::first(&pool).await?;
+            let mut other = Org {
+                name: "Other".into(),
+                ..Default::default()
+            };
+            other.create(&pool).await?;
+            assert!(User::get(&pool, other.id, "user1").await.is_err());
+
+            assert_eq!(1, Org::add_member(&pool, other.id, "user1", true).await?);
+            assert_eq!(0, Org::add_member(&pool, other.id, "nobody", true).await?);
+
+            let memberships = Membership::get_by_user(&pool, "user1").await?;
+            assert_eq!(
+                vec![(default.id, false), (other.id, true)],
+                memberships
+                    .iter()
+                    .map(|m| (m.org_id, m.admin))
+                    .collect::<Vec<_>>()
+            );
+            assert!(User::get(&pool, other.id, "user1").await?.admin);
+            assert!(!User::get(&pool, default.id, "user1").await?.admin);
+
+            // Deleting a user only takes them out of one org
+            let user = User::get(&pool, default.id, "user1").await?;
+            user.delete(&pool, default.id).await?;
+            assert!(User::get(&pool, default.id, "user1").await.is_err());
+            assert_eq!(1, Membership::get_by_user(&pool, "user1").await?.len());
+
+            User::purge(&pool, default.id, "user1").await?;
+            assert!(User::get(&pool, other.id, "user1").await.is_ok());
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use async_trait::async_trait;
+use chrono::NaiveDate;
+
+use super::{
+    Assignment, AssignmentChange, AssignmentQuery, Credentials, Db, Key, KeyLocation, Membership,
+    NotificationSettings, Org, Slot, User,
+};
+
+/// Persistence of keys and where they are stored. Routes get it through
+/// `web::Data<dyn KeyRepository>` so they can be tested against `MemoryRepository`.
+#[async_trait]
+pub trait KeyRepository: Send + Sync {
+    async fn get(&self, org: i64, name: &str) -> Result<Key, sqlx::Error>;
+    async fn get_all(&self, org: i64) -> Result<Vec<Key>, sqlx::Error>;
+    async fn get_all_active(&self, org: i64, active: bool) -> Result<Vec<Key>, sqlx::Error>;
+    async fn create(&self, org: i64, key: &mut Key) -> Result<(), sqlx::Error>;
+    async fn update(&self, key: &Key) -> Result<u64, sqlx::Error>;
+    async fn delete(&self, key: &Key) -> Result<(), sqlx::Error>;
+    async fn location(&self, org: i64, name: &str) -> Result<KeyLocation, sqlx::Error>;
+    async fn slot(&self, org: i64, id: i64) -> Result<Slot, sqlx::Error>;
+    async fn store(&self, slot: &Slot, key_name: &str) -> Result<u64, sqlx::Error>;
+}
+
+/// Persistence of users, the orgs they belong to and their notification settings.
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn get(&self, org: i64, username: &str) -> Result<User, sqlx::Error>;
+    async fn find(&self, username: &str) -> Result<User, sqlx::Error>;
+    async fn get_all(&self, org: i64) -> Result<Vec<User>, sqlx::Error>;
+    async fn create(&self, org: i64, user: &User) -> Result<u64, sqlx::Error>;
+    async fn update(&self, org: i64, user: &User) -> Result<u64, sqlx::Error>;
+    async fn delete(&self, org: i64, user: &User) -> Result<(), sqlx::Error>;
+    async fn set_password(&self, user: &mut User, password: &str) -> Result<u64, sqlx::Error>;
+    async fn count_admins(&self, org: i64) -> Result<i64, sqlx::Error>;
+    async fn memberships(&self, username: &str) -> Result<Vec<Membership>, sqlx::Error>;
+    async fn add_member(&self, org: i64, username: &str, admin: bool) -> Result<u64, sqlx::Error>;
+    /// Whether the user is an admin of the first org, whose admins look after the deployment
+    /// as a whole.
+    async fn is_site_admin(&self, username: &str) -> Result<bool, sqlx::Error>;
+    async fn notification_settings(
+        &self,
+        username: &str,
+    ) -> Result<NotificationSettings, sqlx::Error>;
+    async fn set_notification_settings(
+        &self,
+        username: &str,
+        settings: &NotificationSettings,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn authenticate(&self, creds: Credentials) -> Result<User, actix_web::Error> {
+        let user = self.find(&creds.username).await.ok();
+        User::check_login(user, &creds.password)
+    }
+}
+
+/// Persistence of assignments and their history.
+#[async_trait]
+pub trait AssignmentRepository: Send + Sync {
+    async fn get(&self, org: i64, id: i64) -> Result<Assignment, sqlx::Error>;
+    async fn get_all(
+        &self,
+        org: i64,
+        filter: AssignmentQuery,
+    ) -> Result<Vec<Assignment>, sqlx::Error>;
+    async fn get_by_user(&self, org: i64, username: &str) -> Result<Vec<Assignment>, sqlx::Error>;
+    async fn get_by_key(&self, org: i64, key_name: &str) -> Result<Vec<Assignment>, sqlx::Error>;
+    async fn history(&self, id: i64) -> Result<Vec<AssignmentChange>, sqlx::Error>;
+    async fn create(&self, org: i64, assignment: &mut Assignment) -> Result<(), sqlx::Error>;
+    async fn update(
+        &self,
+        org: i64,
+        assignment: &mut Assignment,
+        changed_by: &str,
+    ) -> Result<(), sqlx::Error>;
+    /// Returns false if the assignment can't be confirmed by `confirmed_by`.
+    async fn confirm(
+        &self,
+        assignment: &mut Assignment,
+        confirmed_by: &str,
+    ) -> Result<bool, sqlx::Error>;
+    /// Returns false if the assignment is already closed.
+    async fn report_lost(
+        &self,
+        assignment: &mut Assignment,
+        date: NaiveDate,
+        reported_by: &str,
+    ) -> Result<bool, sqlx::Error>;
+    async fn delete(&self, assignment: &Assignment) -> Result<u64, sqlx::Error>;
+}
+
+#[async_trait]
+impl KeyRepository for Db {
+    async fn get(&self, org: i64, name: &str) -> Result<Key, sqlx::Error> {
+        Key::get(self, org, name).await
+    }
+
+    async fn get_all(&self, org: i64) -> Result<Vec<Key>, sqlx::Error> {
+        Key::get_all(self, org).await
+    }
+
+    async fn get_all_active(&self, org: i64, active: bool) -> Result<Vec<Key>, sqlx::Error> {
+        Key::get_all_active(self, org, active).await
+    }
+
+    async fn create(&self, org: i64, key: &mut Key) -> Result<(), sqlx::Error> {
+        key.create(self, org).await
+    }
+
+    async fn update(&self, key: &Key) -> Result<u64, sqlx::Error> {
+        key.update(self).await
+    }
+
+    async fn delete(&self, key: &Key) -> Result<(), sqlx::Error> {
+        key.delete(self).await
+    }
+
+    async fn location(&self, org: i64, name: &str) -> Result<KeyLocation, sqlx::Error> {
+        KeyLocation::get(self, org, name).await
+    }
+
+    async fn slot(&self, org: i64, id: i64) -> Result<Slot, sqlx::Error> {
+        Slot::get(self, org, id).await
+    }
+
+    async fn store(&self, slot: &Slot, key_name: &str) -> Result<u64, sqlx::Error> {
+        slot.store_key(self, key_name).await
+    }
+}
+
+#[async_trait]
+impl UserRepository for Db {
+    async fn get(&self, org: i64, username: &str) -> Result<User, sqlx::Error> {
+        User::get(self, org, username).await
+    }
+
+    async fn find(&self, username: &str) -> Result<User, sqlx::Error> {
+        User::find(self, username).await
+    }
+
+    async fn get_all(&self, org: i64) -> Result<Vec<User>, sqlx::Error> {
+        User::get_all(self, org).await
+    }
+
+    async fn create(&self, org: i64, user: &User) -> Result<u64, sqlx::Error> {
+        user.create(self, org).await
+    }
+
+    async fn update(&self, org: i64, user: &User) -> Result<u64, sqlx::Error> {
+        user.update(self, org).await
+    }
+
+    async fn delete(&self, org: i64, user: &User) -> Result<(), sqlx::Error> {
+        user.delete(self, org).await
+    }
+
+    async fn set_password(&self, user: &mut User, password: &str) -> Result<u64, sqlx::Error> {
+        user.set_password(self, password).await
+    }
+
+    async fn count_admins(&self, org: i64) -> Result<i64, sqlx::Error> {
+        User::count_admins(self, org).await
+    }
+
+    async fn memberships(&self, username: &str) -> Result<Vec<Membership>, sqlx::Error> {
+        Membership::get_by_user(self, username).await
+    }
+
+    async fn add_member(&self, org: i64, username: &str, admin: bool) -> Result<u64, sqlx::Error> {
+        Org::add_member(self, org, username, admin).await
+    }
+
+    async fn is_site_admin(&self, username: &str) -> Result<bool, sqlx::Error> {
+        let org = Org::first(self).await?;
+        match User::get(self, org.id, username).await {
+            Ok(u) => Ok(u.admin),
+            Err(sqlx::Error::RowNotFound) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn notification_settings(
+        &self,
+        username: &str,
+    ) -> Result<NotificationSettings, sqlx::Error> {
+        NotificationSettings::get(self, username).await
+    }
+
+    async fn set_notification_settings(
+        &self,
+        username: &str,
+        settings: &NotificationSettings,
+    ) -> Result<(), sqlx::Error> {
+        settings.set(self, username).await
+    }
+}
+
+#[async_trait]
+impl AssignmentRepository for Db {
+    async fn get(&self, org: i64, id: i64) -> Result<Assignment, sqlx::Error> {
+        Assignment::get(self, org, id).await
+    }
+
+    async fn get_all(
+        &self,
+        org: i64,
+        filter: AssignmentQuery,
+    ) -> Result<Vec<Assignment>, sqlx::Error> {
+        Assignment::get_all(self, org, filter).await
+    }
+
+    async fn get_by_user(&self, org: i64, username: &str) -> Result<Vec<Assignment>, sqlx::Error> {
+        Assignment::get_assignments_by_user(self, org, username).await
+    }
+
+    async fn get_by_key(&self, org: i64, key_name: &str) -> Result<Vec<Assignment>, sqlx::Error> {
+        Assignment::get_assignments_by_key(self, org, key_name).await
+    }
+
+    async fn history(&self, id: i64) -> Result<Vec<AssignmentChange>, sqlx::Error> {
+        AssignmentChange::get_by_assignment(self, id).await
+    }
+
+    async fn create(&self, org: i64, assignment: &mut Assignment) -> Result<(), sqlx::Error> {
+        assignment.create(self, org).await
+    }
+
+    async fn update(
+        &self,
+        org: i64,
+        assignment: &mut Assignment,
+        changed_by: &str,
+    ) -> Result<(), sqlx::Error> {
+        assignment.update(self, org, changed_by).await
+    }
+
+    async fn confirm(
+        &self,
+        assignment: &mut Assignment,
+        confirmed_by: &str,
+    ) -> Result<bool, sqlx::Error> {
+        assignment.confirm(self, confirmed_by).await
+    }
+
+    async fn report_lost(
+        &self,
+        assignment: &mut Assignment,
+        date: NaiveDate,
+        reported_by: &str,
+    ) -> Result<bool, sqlx::Error> {
+        assignment.report_lost(self, date, reported_by).await
+    }
+
+    async fn delete(&self, assignment: &Assignment) -> Result<u64, sqlx::Error> {
+        assignment.delete(self).await
+    }
+}
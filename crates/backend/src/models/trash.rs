@@ -0,0 +1,124 @@
+use std::fmt;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use super::Db;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum TrashKind {
+    Key,
+    User,
+    Assignment,
+}
+
+impl fmt::Display for TrashKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            TrashKind::Key => "key",
+            TrashKind::User => "user",
+            TrashKind::Assignment => "assignment",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+/// The id and deletion time of a key or user being restored. Their assignments deleted at the
+/// same time are restored with them.
+#[derive(FromRow)]
+pub(super) struct Deleted {
+    pub id: i64,
+    pub deleted_at: DateTime<Utc>,
+}
+
+/// A deleted key, user or assignment which can still be restored or purged. `id` is the key
+/// name, username or assignment id.
+#[derive(Debug, PartialEq, Eq, Clone, FromRow, Serialize, ToSchema)]
+pub struct TrashItem {
+    pub kind: TrashKind,
+    pub id: String,
+    pub description: String,
+    pub deleted_at: DateTime<Utc>,
+}
+
+impl TrashItem {
+    /// Returns everything in the org's trash, most recently deleted first. Assignments deleted
+    /// along with their user or key are left out, as they come back or go with it.
+    pub async fn get_all(pool: &Db, org: i64) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT
+                CAST('key' AS VARCHAR) as "kind!: TrashKind",
+                name as "id!",
+                COALESCE(description, '') as "description!",
+                deleted_at as "deleted_at!"
+            FROM keys
+            WHERE org_id = $1 AND deleted_at IS NOT NULL
+            UNION ALL
+            SELECT
+                'user',
+                u.username,
+                COALESCE(u.display_name, ''),
+                m.deleted_at
+            FROM users u
+            JOIN org_members m ON m.user_id = u.id
+            WHERE m.org_id = $1 AND m.deleted_at IS NOT NULL
+            UNION ALL
+            SELECT
+                'assignment',
+                CAST(a.id AS VARCHAR),
+                COALESCE(u.username, g.name) || ' / ' || k.name || ' (out ' || a.date_out || ')',
+                a.deleted_at
+            FROM assignments a
+            LEFT JOIN users u ON u.id = a.user_id
+            LEFT JOIN user_groups g ON g.id = a.group_id
+            JOIN keys k ON k.id = a.key_id
+            LEFT JOIN org_members m ON m.user_id = u.id AND m.org_id = k.org_id
+            WHERE k.org_id = $1 AND a.deleted_at IS NOT NULL
+            AND a.deleted_at IS DISTINCT FROM m.deleted_at
+            AND a.deleted_at IS DISTINCT FROM k.deleted_at
+            ORDER BY 4 DESC"#,
+            org
+        )
+    }
+}
+
+#[cfg(test)]
+mod trash_tests {
+    use crate::models::{Assignment, Key, TrashItem, TrashKind, User, DEFAULT_ORG};
+    use anyhow::Result;
+    use chrono::NaiveDate;
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys", "assignments"))]
+        async fn list_trash(pool: Db) -> Result<()> {
+            let mut a = Assignment::new("userCanLogin", "key3", NaiveDate::from_ymd(1988, 10, 3));
+            a.create(&pool, DEFAULT_ORG).await?;
+            a.delete(&pool).await?;
+
+            // The fixture assignment goes with its key
+            Key::get(&pool, DEFAULT_ORG, "key1").await?.delete(&pool).await?;
+            User::get(&pool, DEFAULT_ORG, "userNoPass").await?.delete(&pool, DEFAULT_ORG).await?;
+
+            let trash = TrashItem::get_all(&pool, DEFAULT_ORG).await?;
+            let kinds: Vec<(TrashKind, &str)> = trash.iter().map(|t| (t.kind, t.id.as_str())).collect();
+
+            assert_eq!(3, kinds.len());
+            assert_eq!((TrashKind::User, "userNoPass"), kinds[0]);
+            assert_eq!((TrashKind::Key, "key1"), kinds[1]);
+            assert_eq!(
+                (TrashKind::Assignment, a.id().to_string().as_str()),
+                kinds[2]
+            );
+
+            Ok(())
+        }
+    }
+}
@@ -1,24 +1,30 @@
-use std::env;
-
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
 use orion::pwhash::{self, hash_password_verify, Password, PasswordHash};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgQueryResult, query, query_as, FromRow, PgPool};
+use sqlx::FromRow;
+use tracing::{debug, info, warn};
+use utoipa::ToSchema;
 use validator::Validate;
 
+use super::{trash::Deleted, Db, Org};
+use crate::config::AdminConfig;
+
 lazy_static! {
     static ref USERNAME: Regex = Regex::new(r#"[\w\d]{3,}"#).expect("failed creating regex");
 }
 
-#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, ToSchema)]
 pub struct Credentials {
     pub username: String,
     pub password: String,
 }
 
-#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Validate)]
+#[derive(
+    Debug, Default, PartialEq, Eq, Clone, FromRow, Serialize, Deserialize, Validate, ToSchema,
+)]
 pub struct User {
     #[serde(skip_deserializing)]
     pub id: i64,
@@ -28,11 +34,13 @@ pub struct User {
     #[validate(email)]
     pub email: Option<String>,
     #[serde(skip)]
-    password_hash: Option<String>,
+    pub(super) password_hash: Option<String>,
     #[serde(default = "_default_false")]
     pub can_login: bool,
     #[serde(default = "_default_false")]
     pub admin: bool,
+    #[serde(default = "_default_false")]
+    pub departed: bool,
 }
 
 fn _default_false() -> bool {
@@ -40,64 +48,85 @@ fn _default_false() -> bool {
 }
 
 impl User {
-    pub async fn get(pool: &PgPool, username: &str) -> Result<Self, sqlx::Error> {
-        query_as!(Self, r#"SELECT id, username, display_name, email, password_hash, can_login, admin FROM users WHERE username = $1"#, username)
-            .fetch_one(pool)
-            .await
+    /// Gets a member of the org. `admin` is their role in that org.
+    pub async fn get(pool: &Db, org: i64, username: &str) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            r#"SELECT u.id, username, display_name, email, password_hash, can_login, m.admin, departed
+            FROM users u
+            JOIN org_members m ON m.user_id = u.id
+            WHERE m.org_id = $1 AND username = $2 AND m.deleted_at IS NULL"#,
+            org,
+            username
+        )
     }
 
-    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>, sqlx::Error> {
-        query_as!(
+    /// Gets a user whichever orgs they belong to, for logging in. `admin` is always false.
+    pub async fn find(pool: &Db, username: &str) -> Result<Self, sqlx::Error> {
+        db_query_as!(
             Self,
+            fetch_one,
+            pool,
+            r#"SELECT id, username, display_name, email, password_hash, can_login, false as "admin!", departed
+            FROM users WHERE username = $1"#,
+            username
+        )
+    }
+
+    pub async fn get_all(pool: &Db, org: i64) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
             r#"SELECT
-                id,
+                u.id,
                 username,
                 display_name,
                 email,
                 password_hash,
                 can_login,
-                admin
-                FROM users
-                ORDER BY username"#
+                m.admin,
+                departed
+                FROM users u
+                JOIN org_members m ON m.user_id = u.id
+                WHERE m.org_id = $1 AND m.deleted_at IS NULL
+                ORDER BY username"#,
+            org
         )
-        .fetch_all(pool)
-        .await
     }
 
-    pub async fn authenticate(pool: &PgPool, creds: Credentials) -> Result<Self, actix_web::Error> {
-        let user = match Self::get(pool, &creds.username).await {
-            Ok(u) => u,
-            Err(_) => {
+    /// Checks a login attempt against the user it names, if there is one.
+    pub fn check_login(user: Option<Self>, password: &str) -> Result<Self, actix_web::Error> {
+        match user {
+            Some(u) if u.can_login && u.validate_password(password) => Ok(u),
+            _ => {
                 // Attempt to validate the password on a fake account to prevent a timing attack
                 fake_validate();
-                return Err(actix_web::error::ErrorUnauthorized("Authentication failed"));
+                Err(actix_web::error::ErrorUnauthorized("Authentication failed"))
             }
-        };
-        if user.can_login && user.validate_password(&creds.password) {
-            Ok(user)
-        } else {
-            fake_validate();
-            Err(actix_web::error::ErrorUnauthorized("Authentication failed"))
         }
     }
 
-    pub async fn set_password(
-        &mut self,
-        pool: &PgPool,
-        password: &str,
-    ) -> Result<PgQueryResult, sqlx::Error> {
-        let pw = Password::from_slice(password.as_bytes())
-            .map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
-        let hash = pwhash::hash_password(&pw, 3, 1 << 16).unwrap();
-        self.password_hash = Some(hash.unprotected_as_encoded().to_string());
+    pub async fn set_password(&mut self, pool: &Db, password: &str) -> Result<u64, sqlx::Error> {
+        self.hash_password(password)?;
 
-        query!(
+        db_execute!(
+            pool,
             "Update users SET password_hash = $1 WHERE username = $2",
             self.password_hash,
             self.username
         )
-        .execute(pool)
-        .await
+    }
+
+    /// Replaces the user's password hash without saving it.
+    pub fn hash_password(&mut self, password: &str) -> Result<(), sqlx::Error> {
+        let pw = Password::from_slice(password.as_bytes())
+            .map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
+        let hash = pwhash::hash_password(&pw, 3, 1 << 16).unwrap();
+        self.password_hash = Some(hash.unprotected_as_encoded().to_string());
+        Ok(())
     }
 
     pub fn validate_password(&self, input_password: &str) -> bool {
@@ -115,64 +144,203 @@ impl User {
         }
     }
 
-    pub async fn create(&self, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
-        query!(
-            r#"INSERT INTO users (username, display_name, email, password_hash, can_login, admin)
-            VALUES ($1, $2, $3, $4, $5, $6)"#,
+    /// Creates the user as a member of the org. Usernames are unique across orgs, as one login
+    /// can be used in several of them.
+    pub async fn create(&self, pool: &Db, org: i64) -> Result<u64, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let id = db_query_scalar!(
+            i64,
+            fetch_one,
+            &mut tx,
+            r#"INSERT INTO users (username, display_name, email, password_hash, can_login, departed)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id"#,
             self.username,
             self.display_name,
             self.email,
             self.password_hash,
             self.can_login,
+            self.departed
+        )?;
+
+        db_execute!(
+            &mut tx,
+            "INSERT INTO org_members (org_id, user_id, admin) VALUES ($1, $2, $3)",
+            org,
+            id,
             self.admin
-        )
-        .execute(pool)
-        .await
+        )?;
+
+        tx.commit().await?;
+        Ok(1)
     }
 
-    pub async fn update(&self, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
-        query!(
-            "UPDATE users SET display_name = $1, email = $2, can_login = $3, admin = $4 WHERE username = $5",
+    /// Saves the user and their role in the org. The user is found by id, so their username can
+    /// be changed.
+    pub async fn update(&self, pool: &Db, org: i64) -> Result<u64, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let updated = db_execute!(
+            &mut tx,
+            "UPDATE users SET username = $1, display_name = $2, email = $3, can_login = $4, departed = $5 WHERE id = $6",
+            self.username,
             self.display_name,
             self.email,
             self.can_login,
+            self.departed,
+            self.id
+        )?;
+
+        db_execute!(
+            &mut tx,
+            "UPDATE org_members SET admin = $1 WHERE org_id = $2 AND user_id = $3",
             self.admin,
-            self.username
-        )
-        .execute(pool)
-        .await
+            org,
+            self.id
+        )?;
+
+        tx.commit().await?;
+        Ok(updated)
     }
 
-    pub async fn delete(&self, pool: &PgPool) -> Result<PgQueryResult, sqlx::Error> {
-        query!("DELETE FROM users WHERE username = $1", self.username)
-            .execute(pool)
-            .await
+    /// Moves the user to the org's trash along with their assignments of the org's keys. They
+    /// stay in any other orgs they belong to.
+    pub async fn delete(&self, pool: &Db, org: i64) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let deleted_at = db_query_scalar!(
+            DateTime<Utc>,
+            fetch_one,
+            &mut tx,
+            r#"UPDATE org_members SET deleted_at = now()
+            WHERE org_id = $1 AND user_id = $2 AND deleted_at IS NULL
+            RETURNING deleted_at as "deleted_at!""#,
+            org,
+            self.id
+        )?;
+
+        db_execute!(
+            &mut tx,
+            r#"UPDATE assignments SET deleted_at = $1
+            WHERE user_id = $2 AND deleted_at IS NULL
+            AND key_id IN (SELECT id FROM keys WHERE org_id = $3)"#,
+            deleted_at,
+            self.id,
+            org
+        )?;
+
+        tx.commit().await
     }
 
-    pub async fn count_admins(pool: &PgPool) -> Result<i64, sqlx::Error> {
-        let count =
-            sqlx::query_scalar!(r#"SELECT count(*) as "count!" FROM users WHERE admin = 't'"#)
-                .fetch_one(pool)
-                .await?;
+    /// Takes a user out of the org's trash along with the assignments which were deleted with
+    /// them. Fails with `RowNotFound` if the user isn't in the trash.
+    pub async fn restore(pool: &Db, org: i64, username: &str) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let Deleted { id, deleted_at } = db_query_as!(
+            Deleted,
+            fetch_one,
+            &mut tx,
+            r#"SELECT u.id, m.deleted_at as "deleted_at!"
+            FROM users u
+            JOIN org_members m ON m.user_id = u.id
+            WHERE m.org_id = $1 AND username = $2 AND m.deleted_at IS NOT NULL
+            FOR UPDATE OF m"#,
+            org,
+            username
+        )?;
+
+        db_execute!(
+            &mut tx,
+            "UPDATE org_members SET deleted_at = NULL WHERE org_id = $1 AND user_id = $2",
+            org,
+            id
+        )?;
+
+        db_execute!(
+            &mut tx,
+            r#"UPDATE assignments AS a SET deleted_at = NULL
+            FROM keys k
+            WHERE k.id = a.key_id AND k.deleted_at IS NULL AND k.org_id = $3
+            AND a.user_id = $1 AND a.deleted_at = $2"#,
+            id,
+            deleted_at,
+            org
+        )?;
+
+        tx.commit().await
+    }
+
+    /// Permanently takes a user in the trash out of the org, along with all of their
+    /// assignments, charges and key requests there. The user is deleted once they belong to no
+    /// org. Fails with `RowNotFound` if the user isn't in the trash.
+    pub async fn purge(pool: &Db, org: i64, username: &str) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let id = db_query_scalar!(
+            i64,
+            fetch_one,
+            &mut tx,
+            r#"DELETE FROM org_members
+            WHERE org_id = $1 AND deleted_at IS NOT NULL
+            AND user_id = (SELECT id FROM users WHERE username = $2)
+            RETURNING user_id"#,
+            org,
+            username
+        )?;
+
+        db_execute!(
+            &mut tx,
+            "DELETE FROM assignments WHERE user_id = $1 AND key_id IN (SELECT id FROM keys WHERE org_id = $2)",
+            id,
+            org
+        )?;
+        db_execute!(
+            &mut tx,
+            r#"DELETE FROM charges WHERE "user" = $1 AND org_id = $2"#,
+            username,
+            org
+        )?;
+        db_execute!(
+            &mut tx,
+            r#"DELETE FROM key_requests WHERE "user" = $1 AND org_id = $2"#,
+            username,
+            org
+        )?;
+        db_execute!(
+            &mut tx,
+            "DELETE FROM users WHERE id = $1 AND NOT EXISTS (SELECT 1 FROM org_members WHERE user_id = $1)",
+            id
+        )?;
+
+        tx.commit().await
+    }
+
+    pub async fn count_admins(pool: &Db, org: i64) -> Result<i64, sqlx::Error> {
+        let count = db_query_scalar!(
+            i64,
+            fetch_one,
+            pool,
+            r#"SELECT count(*) as "count!" FROM org_members WHERE org_id = $1 AND admin AND deleted_at IS NULL"#,
+            org
+        )?;
         Ok(count)
     }
 }
 
-pub async fn initialize_admin(pool: &PgPool) -> Result<(), sqlx::Error> {
-    if User::count_admins(pool).await? >= 1 {
-        println!("Admin user exists.");
+/// Creates the configured admin in the first org if that org has no admins.
+pub async fn initialize_admin(pool: &Db, config: &AdminConfig) -> Result<(), sqlx::Error> {
+    let org = Org::first(pool).await?.id;
+    if User::count_admins(pool, org).await? >= 1 {
+        debug!("Admin user exists");
         return Ok(());
     }
 
-    println!("Creating admin user");
-    let mut pw_from_env = false;
-    let admin_username = env::var("KEYMASTER_ADMIN_USER").unwrap_or_else(|_| "admin".into());
-    let admin_pass = match env::var("KEYMASTER_ADMIN_PASS") {
-        Ok(v) => {
-            pw_from_env = true;
-            v
-        }
-        Err(_) => pwgen::generate("full", 14),
+    let admin_username = config.username.clone();
+    let (admin_pass, pw_from_config) = match &config.password {
+        Some(p) => (p.clone(), true),
+        None => (pwgen::generate("full", 14), false),
     };
 
     let mut admin = User {
@@ -182,17 +350,18 @@ pub async fn initialize_admin(pool: &PgPool) -> Result<(), sqlx::Error> {
         admin: true,
         ..Default::default()
     };
-    admin.create(pool).await?;
+    admin.create(pool, org).await?;
     admin.set_password(pool, &admin_pass).await?;
-    println!(
-        "Admin user created. username: '{}', password: '{}'",
-        &admin_username,
-        if pw_from_env {
-            "<FROM ENVIRONMENT>"
-        } else {
-            &admin_pass
-        }
-    );
+    if pw_from_config {
+        info!(username = %admin_username, "Created admin user");
+    } else {
+        // The generated password is only ever shown here
+        warn!(
+            username = %admin_username,
+            password = %admin_pass,
+            "Created admin user with a generated password. Change it after logging in"
+        );
+    }
 
     Ok(())
 }
@@ -207,123 +376,163 @@ fn fake_validate() {
 
 #[cfg(test)]
 mod user_tests {
-    use crate::models::{Credentials, User};
+    use crate::models::{Assignment, Credentials, User, UserRepository, DEFAULT_ORG};
     use anyhow::Result;
-    use sqlx::{query, PgPool};
-
-    #[sqlx::test()]
-    async fn test_create_user(pool: PgPool) -> Result<()> {
-        let username = "user1";
-        let display_name = "User 1";
-        let email = "user1@email.com";
-        let mut user = User {
-            username: username.to_string(),
-            display_name: Some(display_name.to_string()),
-            email: Some(email.to_string()),
-            can_login: true,
-            admin: true,
-            password_hash: Some("123".to_string()),
-            ..Default::default()
-        };
-        user.email = Some(email.to_string());
-        user.display_name = Some(display_name.to_string());
-        user.create(&pool).await?;
-        let got_user = User::get(&pool, &user.username).await?;
-
-        assert_eq!(user.username, got_user.username);
-        assert_eq!(user.display_name, got_user.display_name);
-        assert_eq!(user.email, got_user.email);
-        assert_eq!(user.can_login, got_user.can_login);
-        assert_eq!(user.admin, got_user.admin);
-        assert_eq!(user.password_hash, got_user.password_hash);
 
-        Ok(())
+    db_test! {
+        #[sqlx::test]
+        async fn test_create_user(pool: Db) -> Result<()> {
+            let username = "user1";
+            let display_name = "User 1";
+            let email = "user1@email.com";
+            let mut user = User {
+                username: username.to_string(),
+                display_name: Some(display_name.to_string()),
+                email: Some(email.to_string()),
+                can_login: true,
+                admin: true,
+                password_hash: Some("123".to_string()),
+                ..Default::default()
+            };
+            user.email = Some(email.to_string());
+            user.display_name = Some(display_name.to_string());
+            user.create(&pool, DEFAULT_ORG).await?;
+            let got_user = User::get(&pool, DEFAULT_ORG, &user.username).await?;
+
+            assert_eq!(user.username, got_user.username);
+            assert_eq!(user.display_name, got_user.display_name);
+            assert_eq!(user.email, got_user.email);
+            assert_eq!(user.can_login, got_user.can_login);
+            assert_eq!(user.admin, got_user.admin);
+            assert_eq!(user.password_hash, got_user.password_hash);
+
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("users"))]
-    async fn test_get_user(pool: PgPool) -> Result<()> {
-        let user = User::get(&pool, "user1").await?;
+    db_test! {
+        #[sqlx::test(fixtures("users"))]
+        async fn test_get_user(pool: Db) -> Result<()> {
+            let user = User::get(&pool, DEFAULT_ORG, "user1").await?;
+
+            assert_eq!("user1", user.username);
+            assert_eq!("User Juan", user.display_name.unwrap());
+            assert_eq!("user@email.com", user.email.unwrap());
+            assert_eq!(
+                "46a9d5bde718bf366178313019f04a753bad00685d38e3ec81c8628f35dfcb1b",
+                user.password_hash.unwrap()
+            );
+            assert!(!user.can_login);
+
+            Ok(())
+        }
+    }
 
-        assert_eq!("user1", user.username);
-        assert_eq!("User Juan", user.display_name.unwrap());
-        assert_eq!("user@email.com", user.email.unwrap());
-        assert_eq!(
-            "46a9d5bde718bf366178313019f04a753bad00685d38e3ec81c8628f35dfcb1b",
-            user.password_hash.unwrap()
-        );
-        assert!(!user.can_login);
+    db_test! {
+        #[sqlx::test(fixtures("users"))]
+        async fn test_update_user(pool: Db) -> Result<()> {
+            let new_display_name = "User Too";
+            let mut user = User::get(&pool, DEFAULT_ORG, "user1").await?;
+            user.display_name = Some(new_display_name.to_string());
+            user.update(&pool, DEFAULT_ORG).await?;
 
-        Ok(())
+            let updated_user = User::get(&pool, DEFAULT_ORG, "user1").await?;
+
+            assert_eq!(new_display_name, updated_user.display_name.unwrap());
+
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("users"))]
-    async fn test_update_user(pool: PgPool) -> Result<()> {
-        let new_display_name = "User Too";
-        let mut user = User::get(&pool, "user1").await?;
-        user.display_name = Some(new_display_name.to_string());
-        user.update(&pool).await?;
+    db_test! {
+        #[sqlx::test(fixtures("users", "keys", "assignments"))]
+        async fn test_rename_user(pool: Db) -> Result<()> {
+            let mut user = User::get(&pool, DEFAULT_ORG, "user1").await?;
+            user.username = "juan".into();
+            user.update(&pool, DEFAULT_ORG).await?;
 
-        let updated_user = User::get(&pool, "user1").await?;
+            assert!(User::get(&pool, DEFAULT_ORG, "user1").await.is_err());
+            assert_eq!(user, User::get(&pool, DEFAULT_ORG, "juan").await?);
 
-        assert_eq!(new_display_name, updated_user.display_name.unwrap());
+            let assignments = Assignment::get_assignments_by_user(&pool, DEFAULT_ORG, "juan").await?;
+            assert_eq!(1, assignments.len());
+            assert_eq!(Some("juan".to_string()), assignments[0].user);
 
-        Ok(())
+            user.username = "userNoPass".into();
+            assert!(user.update(&pool, DEFAULT_ORG).await.is_err());
+
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("users"))]
-    async fn test_delete_user(pool: PgPool) -> Result<()> {
-        let user = User::get(&pool, "user1").await?;
-        user.delete(&pool).await?;
+    db_test! {
+        #[sqlx::test(fixtures("users"))]
+        async fn test_delete_user(pool: Db) -> Result<()> {
+            let user = User::get(&pool, DEFAULT_ORG, "user1").await?;
+            user.delete(&pool, DEFAULT_ORG).await?;
+            assert!(User::get(&pool, DEFAULT_ORG, "user1").await.is_err());
 
-        let res = query("SELECT * FROM users WHERE username = $1")
-            .bind(user.username)
-            .execute(&pool)
-            .await?;
+            User::restore(&pool, DEFAULT_ORG, "user1").await?;
+            assert_eq!(user, User::get(&pool, DEFAULT_ORG, "user1").await?);
 
-        assert_eq!(res.rows_affected(), 0);
+            user.delete(&pool, DEFAULT_ORG).await?;
+            User::purge(&pool, DEFAULT_ORG, "user1").await?;
+            let count = format!(
+                "SELECT count(*) FROM users WHERE username = '{}'",
+                user.username
+            );
 
-        Ok(())
+            assert_eq!(pool.count(&count).await?, 0);
+
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("users"))]
-    async fn test_set_password(pool: PgPool) -> Result<()> {
-        let mut user = User::get(&pool, "userNoPass").await?;
-        let password = "itsagoodpass2";
-        user.set_password(&pool, password).await?;
+    db_test! {
+        #[sqlx::test(fixtures("users"))]
+        async fn test_set_password(pool: Db) -> Result<()> {
+            let mut user = User::get(&pool, DEFAULT_ORG, "userNoPass").await?;
+            let password = "itsagoodpass2";
+            user.set_password(&pool, password).await?;
 
-        user = User::get(&pool, "userNoPass").await?;
+            user = User::get(&pool, DEFAULT_ORG, "userNoPass").await?;
 
-        assert!(user.password_hash.is_some());
+            assert!(user.password_hash.is_some());
 
-        Ok(())
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("users"))]
-    async fn test_validate_password(pool: PgPool) -> Result<()> {
-        let mut user = User::get(&pool, "userNoPass").await?;
-        let password = "itsagoodpass2";
-        user.set_password(&pool, password).await?;
+    db_test! {
+        #[sqlx::test(fixtures("users"))]
+        async fn test_validate_password(pool: Db) -> Result<()> {
+            let mut user = User::get(&pool, DEFAULT_ORG, "userNoPass").await?;
+            let password = "itsagoodpass2";
+            user.set_password(&pool, password).await?;
 
-        user = User::get(&pool, "userNoPass").await?;
+            user = User::get(&pool, DEFAULT_ORG, "userNoPass").await?;
 
-        assert!(user.validate_password(password));
-        assert!(!user.validate_password("itsabadpass3"));
+            assert!(user.validate_password(password));
+            assert!(!user.validate_password("itsabadpass3"));
 
-        Ok(())
+            Ok(())
+        }
     }
 
-    #[sqlx::test(fixtures("users"))]
-    async fn test_authenticate(pool: PgPool) -> Result<()> {
-        let username = "userCanLogin".to_string();
-        let password = "abc123".to_string();
-        let creds = Credentials {
-            username: username.clone(),
-            password,
-        };
-        let auth_user = User::authenticate(&pool, creds).await.unwrap();
+    db_test! {
+        #[sqlx::test(fixtures("users"))]
+        async fn test_authenticate(pool: Db) -> Result<()> {
+            let username = "userCanLogin".to_string();
+            let password = "abc123".to_string();
+            let creds = Credentials {
+                username: username.clone(),
+                password,
+            };
+            let auth_user = pool.authenticate(creds).await.unwrap();
 
-        assert_eq!(User::get(&pool, &username).await?, auth_user);
+            assert_eq!(User::get(&pool, DEFAULT_ORG, &username).await?, auth_user);
 
-        Ok(())
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,449 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_scalar, sqlite::SqliteRow, types::Json, FromRow, Row};
+use utoipa::ToSchema;
+
+use super::Db;
+
+/// Maximum number of attempts made to deliver a webhook before it is given up on.
+pub static MAX_DELIVERY_ATTEMPTS: i32 = 8;
+
+/// The events webhooks can subscribe to.
+pub static WEBHOOK_EVENTS: [&str; 11] = [
+    "key.created",
+    "key.updated",
+    "key.deleted",
+    "user.created",
+    "user.updated",
+    "user.deleted",
+    "assignment.created",
+    "assignment.updated",
+    "assignment.deleted",
+    "assignment.returned",
+    "assignment.lost",
+];
+
+/// An admin configured subscription which receives signed event payloads. A webhook with no
+/// events receives every event. The secret is never sent back to clients.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Webhook {
+    #[serde(skip_deserializing)]
+    pub id: i64,
+    pub url: String,
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(default, skip_serializing)]
+    pub secret: String,
+    #[serde(default)]
+    pub active: bool,
+}
+
+/// SQLite has no arrays, so the events are stored as a JSON array.
+impl FromRow<'_, SqliteRow> for Webhook {
+    fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
+        let Json(events) = row.try_get("events")?;
+        Ok(Webhook {
+            id: row.try_get("id")?,
+            url: row.try_get("url")?,
+            events,
+            secret: row.try_get("secret")?,
+            active: row.try_get("active")?,
+        })
+    }
+}
+
+impl Webhook {
+    pub async fn get(pool: &Db, org: i64, id: i64) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            "SELECT id, url, events, secret, active FROM webhooks WHERE id = $1 AND org_id = $2",
+            id,
+            org
+        )
+    }
+
+    /// Gets a webhook whichever org it belongs to, for delivering to it.
+    pub async fn find(pool: &Db, id: i64) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            "SELECT id, url, events, secret, active FROM webhooks WHERE id = $1",
+            id
+        )
+    }
+
+    pub async fn get_all(pool: &Db, org: i64) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            "SELECT id, url, events, secret, active FROM webhooks WHERE org_id = $1 ORDER BY id",
+            org
+        )
+    }
+
+    /// Returns the org's active webhooks subscribed to `event`.
+    pub async fn get_subscribed(
+        pool: &Db,
+        org: i64,
+        event: &str,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT id, url, events, secret, active
+            FROM webhooks
+            WHERE org_id = $1 AND active AND (events = '{}' OR $2 = ANY(events))
+            ORDER BY id"#,
+            sqlite = r#"SELECT id, url, events, secret, active
+            FROM webhooks
+            WHERE org_id = $1 AND active AND (
+                events = '[]' OR EXISTS (SELECT 1 FROM json_each(events) WHERE value = $2)
+            )
+            ORDER BY id"#,
+            org,
+            event
+        )
+    }
+
+    pub async fn create(&mut self, pool: &Db, org: i64) -> Result<(), sqlx::Error> {
+        self.id = match pool {
+            Db::Postgres(p) => {
+                query_scalar!(
+                    r#"INSERT INTO webhooks (org_id, url, events, secret, active)
+                    VALUES ($1, $2, $3, $4, $5)
+                    RETURNING id"#,
+                    org,
+                    self.url,
+                    &self.events,
+                    self.secret,
+                    self.active
+                )
+                .fetch_one(p)
+                .await?
+            }
+            Db::Sqlite(p) => sqlite_fetch!(
+                fetch_one,
+                query_scalar(
+                    r#"INSERT INTO webhooks (org_id, url, events, secret, active)
+                    VALUES ($1, $2, $3, $4, $5)
+                    RETURNING id"#,
+                )
+                .bind(org)
+                .bind(&self.url)
+                .bind(Json(&self.events))
+                .bind(&self.secret)
+                .bind(self.active),
+                p
+            )?,
+        };
+        Ok(())
+    }
+
+    pub async fn update(&self, pool: &Db) -> Result<u64, sqlx::Error> {
+        let res = match pool {
+            Db::Postgres(p) => query!(
+                "UPDATE webhooks SET url = $1, events = $2, secret = $3, active = $4 WHERE id = $5",
+                self.url,
+                &self.events,
+                self.secret,
+                self.active,
+                self.id
+            )
+            .execute(p)
+            .await?
+            .rows_affected(),
+            Db::Sqlite(p) => sqlx::query(
+                "UPDATE webhooks SET url = $1, events = $2, secret = $3, active = $4 WHERE id = $5",
+            )
+            .bind(&self.url)
+            .bind(Json(&self.events))
+            .bind(&self.secret)
+            .bind(self.active)
+            .bind(self.id)
+            .execute(p)
+            .await?
+            .rows_affected(),
+        };
+        Ok(res)
+    }
+
+    pub async fn delete(&self, pool: &Db) -> Result<u64, sqlx::Error> {
+        db_execute!(pool, "DELETE FROM webhooks WHERE id = $1", self.id)
+    }
+}
+
+/// A single event sent, or waiting to be sent, to a webhook.
+#[derive(Debug, PartialEq, Eq, Clone, FromRow, Serialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub next_attempt: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl WebhookDelivery {
+    /// Queues `payload` for delivery to the webhook.
+    pub async fn create(
+        pool: &Db,
+        webhook_id: i64,
+        event: &str,
+        payload: &str,
+    ) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            r#"INSERT INTO webhook_deliveries (webhook_id, event, payload)
+            VALUES ($1, $2, $3)
+            RETURNING id, webhook_id, event, payload, attempts, status_code, last_error,
+                next_attempt, delivered_at, created_at"#,
+            webhook_id,
+            event,
+            payload
+        )
+    }
+
+    pub async fn get(pool: &Db, id: i64) -> Result<Self, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_one,
+            pool,
+            r#"SELECT id, webhook_id, event, payload, attempts, status_code, last_error,
+                next_attempt, delivered_at, created_at
+            FROM webhook_deliveries
+            WHERE id = $1"#,
+            id
+        )
+    }
+
+    /// Returns the most recent deliveries to a webhook, newest first.
+    pub async fn get_by_webhook(
+        pool: &Db,
+        webhook_id: i64,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT id, webhook_id, event, payload, attempts, status_code, last_error,
+                next_attempt, delivered_at, created_at
+            FROM webhook_deliveries
+            WHERE webhook_id = $1
+            ORDER BY id DESC
+            LIMIT $2"#,
+            webhook_id,
+            limit
+        )
+    }
+
+    /// Returns undelivered deliveries which are due for another attempt, oldest first.
+    pub async fn get_due(pool: &Db, limit: i64) -> Result<Vec<Self>, sqlx::Error> {
+        db_query_as!(
+            Self,
+            fetch_all,
+            pool,
+            r#"SELECT id, webhook_id, event, payload, attempts, status_code, last_error,
+                next_attempt, delivered_at, created_at
+            FROM webhook_deliveries
+            WHERE delivered_at IS NULL AND attempts < $1 AND next_attempt <= now()
+            ORDER BY id
+            LIMIT $2"#,
+            MAX_DELIVERY_ATTEMPTS,
+            limit
+        )
+    }
+
+    /// Pushes the next attempt back while this attempt is in flight so that the retry job
+    /// doesn't send the delivery at the same time. Returns false if the delivery has already
+    /// been delivered or another attempt holds it.
+    pub async fn lease(&mut self, pool: &Db) -> Result<bool, sqlx::Error> {
+        let next_attempt = db_query_scalar!(
+            DateTime<Utc>,
+            fetch_optional,
+            pool,
+            r#"UPDATE webhook_deliveries
+            SET next_attempt = now() + interval '5 minutes'
+            WHERE id = $1 AND delivered_at IS NULL AND next_attempt <= now()
+            RETURNING next_attempt"#,
+            sqlite = r#"UPDATE webhook_deliveries
+            SET next_attempt = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now', '+5 minutes')
+            WHERE id = $1 AND delivered_at IS NULL AND next_attempt <= now()
+            RETURNING next_attempt"#,
+            self.id
+        )?;
+
+        match next_attempt {
+            Some(n) => {
+                self.next_attempt = n;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub async fn mark_delivered(&mut self, pool: &Db, status_code: i32) -> Result<(), sqlx::Error> {
+        self.delivered_at = db_query_scalar!(
+            Option<DateTime<Utc>>,
+            fetch_one,
+            pool,
+            r#"UPDATE webhook_deliveries
+            SET attempts = attempts + 1, status_code = $1, last_error = NULL, delivered_at = now()
+            WHERE id = $2
+            RETURNING delivered_at"#,
+            status_code,
+            self.id
+        )?;
+
+        self.attempts += 1;
+        self.status_code = Some(status_code);
+        self.last_error = None;
+        Ok(())
+    }
+
+    /// Records a failed attempt. The next attempt is delayed exponentially, starting at two
+    /// minutes and doubling with every failure.
+    pub async fn mark_failed(
+        &mut self,
+        pool: &Db,
+        status_code: Option<i32>,
+        error: &str,
+    ) -> Result<(), sqlx::Error> {
+        self.next_attempt = db_query_scalar!(
+            DateTime<Utc>,
+            fetch_one,
+            pool,
+            r#"UPDATE webhook_deliveries
+            SET
+                attempts = attempts + 1,
+                status_code = $1,
+                last_error = $2,
+                next_attempt = now() + make_interval(mins => (2 ^ (attempts + 1))::INT)
+            WHERE id = $3
+            RETURNING next_attempt"#,
+            sqlite = r#"UPDATE webhook_deliveries
+            SET
+                attempts = attempts + 1,
+                status_code = $1,
+                last_error = $2,
+                next_attempt = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now', '+' || (1 << (attempts + 1)) || ' minutes')
+            WHERE id = $3
+            RETURNING next_attempt"#,
+            status_code,
+            error,
+            self.id
+        )?;
+
+        self.attempts += 1;
+        self.status_code = status_code;
+        self.last_error = Some(error.into());
+        Ok(())
+    }
+
+    /// Deletes deliveries which were delivered more than `days` days ago.
+    pub async fn delete_delivered(pool: &Db, days: i64) -> Result<u64, sqlx::Error> {
+        db_execute!(
+            pool,
+            "DELETE FROM webhook_deliveries WHERE delivered_at < $1",
+            Utc::now() - Duration::days(days)
+        )
+    }
+}
+
+#[cfg(test)]
+mod webhook_tests {
+    use crate::models::{Db, Webhook, WebhookDelivery, DEFAULT_ORG};
+    use anyhow::Result;
+
+    async fn create_webhook(pool: &Db, events: Vec<String>) -> Result<Webhook> {
+        let mut webhook = Webhook {
+            url: "http://localhost/hook".into(),
+            events,
+            secret: "secret".into(),
+            active: true,
+            ..Default::default()
+        };
+        webhook.create(pool, DEFAULT_ORG).await?;
+        Ok(webhook)
+    }
+
+    #[test]
+    fn secret_is_write_only() {
+        let webhook: Webhook =
+            serde_json::from_str(r#"{"url": "http://localhost/hook", "secret": "secret"}"#)
+                .unwrap();
+        assert_eq!("secret", webhook.secret);
+        assert!(serde_json::to_value(&webhook)
+            .unwrap()
+            .get("secret")
+            .is_none());
+    }
+
+    db_test! {
+        #[sqlx::test]
+        async fn subscribed_webhooks(pool: Db) -> Result<()> {
+            let all = create_webhook(&pool, vec![]).await?;
+            let keys = create_webhook(&pool, vec!["key.created".into()]).await?;
+            let mut inactive = create_webhook(&pool, vec![]).await?;
+            inactive.active = false;
+            inactive.update(&pool).await?;
+
+            assert_eq!(
+                vec![all.clone(), keys],
+                Webhook::get_subscribed(&pool, DEFAULT_ORG, "key.created").await?
+            );
+            assert_eq!(
+                vec![all],
+                Webhook::get_subscribed(&pool, DEFAULT_ORG, "user.created").await?
+            );
+
+            Ok(())
+        }
+    }
+
+    db_test! {
+        #[sqlx::test]
+        async fn retry_delivery(pool: Db) -> Result<()> {
+            let webhook = create_webhook(&pool, vec![]).await?;
+            let mut delivery = WebhookDelivery::create(&pool, webhook.id, "key.created", "{}").await?;
+            assert_eq!(1, WebhookDelivery::get_due(&pool, 10).await?.len());
+
+            // Only one attempt can hold the delivery at a time
+            assert!(delivery.lease(&pool).await?);
+            assert!(!delivery.lease(&pool).await?);
+
+            // Failed deliveries are retried later
+            delivery
+                .mark_failed(&pool, Some(500), "Server error")
+                .await?;
+            assert!(WebhookDelivery::get_due(&pool, 10).await?.is_empty());
+            assert!(delivery.next_attempt > delivery.created_at);
+
+            delivery.mark_delivered(&pool, 200).await?;
+            let stored = WebhookDelivery::get(&pool, delivery.id).await?;
+            assert_eq!(2, stored.attempts);
+            assert_eq!(Some(200), stored.status_code);
+            assert!(stored.delivered_at.is_some());
+            assert_eq!(
+                1,
+                WebhookDelivery::get_by_webhook(&pool, webhook.id, 10)
+                    .await?
+                    .len()
+            );
+
+            Ok(())
+        }
+    }
+}
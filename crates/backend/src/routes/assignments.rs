@@ -1,29 +1,102 @@
 use actix_session::Session;
 use actix_web::{
     delete,
-    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    error::{ErrorConflict, ErrorForbidden, ErrorInternalServerError, ErrorNotFound},
     get, post, web, HttpResponse, Responder,
 };
-use log::{error, info};
-use sqlx::PgPool;
-use std::fmt::Write;
+use chrono::{Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{debug, error};
+use utoipa::ToSchema;
 
 use crate::{
-    models::{Assignment, AssignmentQuery},
-    routes::{unpack, validate_admin, validate_session},
+    config::{AssignmentsConfig, FinanceConfig},
+    events::Events,
+    models::{
+        currency_for, is_foreign_key_violation, is_unique_violation, Assignment, AssignmentError,
+        AssignmentQuery, AssignmentRepository, KeyRepository, UserRepository,
+    },
+    routes::{current_org, unpack, validate_admin, validate_session},
 };
 
+/// An assignment update. When the assignment is closed, `slot_id` is the slot the key is
+/// returned to.
+#[derive(Deserialize, Clone, ToSchema)]
+struct UpdatePayload {
+    #[serde(flatten)]
+    assignment: Assignment,
+    slot_id: Option<i64>,
+}
+
+/// Why an assignment was refused. Creating assignments responds with one for each refused
+/// assignment, where `index` is its position in the request. Updates respond with just one,
+/// with an `index` of 0.
+#[derive(Serialize, ToSchema)]
+struct Problem {
+    index: usize,
+    code: &'static str,
+    message: String,
+}
+
+impl Problem {
+    fn new(index: usize, code: &'static str, message: impl ToString) -> Self {
+        Self {
+            index,
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn holder_not_found(index: usize, a: &Assignment) -> Self {
+        match &a.group {
+            Some(g) => Self::new(
+                index,
+                "group_not_found",
+                format!("Group '{}' does not exist", g),
+            ),
+            None => Self::new(
+                index,
+                "user_not_found",
+                format!("User '{}' does not exist", a.holder()),
+            ),
+        }
+    }
+
+    /// For errors which are logged rather than shown.
+    fn failed(index: usize, a: &Assignment) -> Self {
+        let msg = format!("Failed to assign key '{}' to '{}'", a.key, a.holder());
+        Self::new(index, "database_error", msg)
+    }
+
+    /// A 400 response listing just this problem.
+    fn response(self) -> HttpResponse {
+        HttpResponse::BadRequest().json([self])
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "assignments",
+    operation_id = "get_assignment",
+    responses(
+        (status = 200, body = Assignment),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
 #[get("/assignments/{assignment_id}")]
 async fn get(
     assignment_id: web::Path<i64>,
-    pool: web::Data<PgPool>,
+    assignments: web::Data<dyn AssignmentRepository>,
     session: Session,
 ) -> Result<impl Responder, actix_web::Error> {
     validate_session(&session)?;
+    let org = current_org(&session)?;
 
     let assignment_id = assignment_id.into_inner();
 
-    match Assignment::get(&pool, assignment_id).await {
+    match assignments.get(org, assignment_id).await {
         Ok(a) => Ok(HttpResponse::Ok().json(a)),
         Err(e) => match e.to_string() {
             x if x.contains("no rows returned") => Err(ErrorNotFound("Assignment not found.")),
@@ -35,15 +108,64 @@ async fn get(
     }
 }
 
+/// The assignment's change history, oldest first.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "assignments",
+    operation_id = "get_assignment_history",
+    responses(
+        (status = 200, body = [AssignmentChange]),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/assignments/{assignment_id}/history")]
+async fn get_history(
+    assignment_id: web::Path<i64>,
+    assignments: web::Data<dyn AssignmentRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    let assignment_id = assignment_id.into_inner();
+    if assignments.get(org, assignment_id).await.is_err() {
+        return Err(ErrorNotFound("Assignment not found."));
+    }
+    match assignments.history(assignment_id).await {
+        Ok(h) => Ok(HttpResponse::Ok().json(h)),
+        Err(e) => {
+            error!(
+                "Failed to get history of assignment '{}'. {}",
+                assignment_id, e
+            );
+            Err(ErrorInternalServerError(
+                "Failed to get assignment history.",
+            ))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "assignments",
+    operation_id = "get_assignments",
+    params(AssignmentQuery),
+    responses(
+        (status = 200, body = [Assignment]),
+        (status = 401, description = "Not logged in", body = String),
+    )
+)]
 #[get("/assignments")]
 async fn get_all(
-    pool: web::Data<PgPool>,
+    assignments: web::Data<dyn AssignmentRepository>,
     session: Session,
     query: web::Query<AssignmentQuery>,
 ) -> Result<impl Responder, actix_web::Error> {
     validate_session(&session)?;
+    let org = current_org(&session)?;
 
-    match Assignment::get_all(&pool, query.into_inner()).await {
+    match assignments.get_all(org, query.into_inner()).await {
         Ok(a) => Ok(HttpResponse::Ok().json(a)),
         Err(e) => {
             error!("Failed to get assignments. {}", e);
@@ -52,111 +174,380 @@ async fn get_all(
     }
 }
 
-/// Accepts an array of Assignment objects as either a form or json body
+/// Accepts an array of Assignment objects as either a form or json body. Assignments which
+/// don't specify a collected deposit are given the key's standard deposit. Assignments of
+/// restricted keys are pending until confirmed by a different admin.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "assignments",
+    operation_id = "create_assignments",
+    request_body = [Assignment],
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Refused assignments", body = inline(Vec<Problem>)),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+    )
+)]
+#[allow(clippy::too_many_arguments)]
 #[post("/assignments")]
 async fn create(
     assignment: web::Either<web::Json<Vec<Assignment>>, web::Form<Vec<Assignment>>>,
-    pool: web::Data<PgPool>,
+    assignments: web::Data<dyn AssignmentRepository>,
+    keys: web::Data<dyn KeyRepository>,
+    users: web::Data<dyn UserRepository>,
+    events: web::Data<dyn Events>,
+    finance: web::Data<FinanceConfig>,
+    limits: web::Data<AssignmentsConfig>,
     session: Session,
 ) -> Result<impl Responder, actix_web::Error> {
-    info!("{:?}", assignment);
-    validate_admin(&session, &pool).await?;
+    debug!(?assignment, "Creating assignments");
+    let username = validate_session(&session)?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
 
     let assignment = unpack(assignment);
 
-    let mut error_msg = String::new();
+    let mut problems = Vec::new();
 
-    for a in &assignment {
-        match a.create(&pool).await {
+    for (index, a) in assignment.iter().enumerate() {
+        let mut a = a.clone();
+        a.created_by = Some(username.clone());
+        if let Ok(k) = keys.get(org, &a.key).await {
+            a.pending = k.restricted;
+            if a.deposit_collected.is_none() {
+                a.deposit_collected = k.deposit_amount;
+                a.deposit_currency = k.deposit_currency;
+            }
+        }
+        match currency_for(
+            a.deposit_collected.or(a.deposit_refunded),
+            a.deposit_currency.clone(),
+            &finance.currency,
+        ) {
+            Ok(c) => a.deposit_currency = c,
+            Err(e) => {
+                problems.push(Problem::new(index, "invalid_currency", e));
+                continue;
+            }
+        }
+
+        match a
+            .validate(&**keys, &**users, org, None, limits.max_days_ahead)
+            .await
+        {
             Ok(_) => (),
-            Err(e) => match e.to_string() {
-                x if x.contains("duplicate key") => {
-                    writeln!(error_msg, "Key '{}' already assigned to {}", a.key, a.user).unwrap();
-                }
-                x if x.contains("violates foreign key") => match x {
-                    y if y.contains("assignments_key_fkey") => {
-                        writeln!(error_msg, "Key '{}' does not exist", a.key).unwrap();
+            Err(AssignmentError::Database(e)) => {
+                error!("Failed to validate assignment. {}", e);
+                problems.push(Problem::failed(index, &a));
+                continue;
+            }
+            Err(e) => {
+                problems.push(Problem::new(index, e.code(), e));
+                continue;
+            }
+        }
+
+        match assignments.create(org, &mut a).await {
+            Ok(_) => {
+                // Restricted keys are announced once they're confirmed
+                if !a.pending {
+                    if let Some(user) = &a.user {
+                        events.key_assigned(org, user, &a.key).await;
                     }
+                }
+                events.emit(org, "assignment.created", json!(a)).await;
+            }
+            Err(e) => problems.push(match e.to_string() {
+                _ if is_unique_violation(&e) => Problem::new(
+                    index,
+                    "already_assigned",
+                    format!("Key '{}' already assigned to {}", a.key, a.holder()),
+                ),
+                x if x.contains("assignments_holder_check") => Problem::holder_not_found(index, &a),
+                x if is_foreign_key_violation(&e) => match x {
+                    y if y.contains("assignments_key_fkey") => Problem::new(
+                        index,
+                        "key_not_found",
+                        format!("Key '{}' does not exist", a.key),
+                    ),
                     y if y.contains("assignments_user_fkey") => {
-                        writeln!(error_msg, "User '{}' does not exist", a.user).unwrap();
+                        Problem::holder_not_found(index, &a)
                     }
                     _ => {
                         error!("Foreign key error. {}", e);
-                        writeln!(
-                            error_msg,
-                            "Failed to assign key '{}' to user '{}'",
-                            a.key, a.user
-                        )
-                        .unwrap();
+                        Problem::failed(index, &a)
                     }
                 },
                 _ => {
                     error!("Failed to create assignment. {}", e);
-                    writeln!(
-                        error_msg,
-                        "Failed to assign key '{}' to user '{}'",
-                        a.key, a.user
-                    )
-                    .unwrap();
+                    Problem::failed(index, &a)
                 }
-            },
+            }),
         }
     }
-    if error_msg.is_empty() {
+    if problems.is_empty() {
         Ok(HttpResponse::Ok().json(format!("Created {} assignments.", &assignment.len())))
     } else {
-        Err(ErrorBadRequest(format!(
-            "Error creating assignments: {}",
-            error_msg
-        )))
+        Ok(HttpResponse::BadRequest().json(problems))
     }
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "assignments",
+    operation_id = "update_assignment",
+    request_body = inline(UpdatePayload),
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Refused assignments", body = inline(Vec<Problem>)),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[allow(clippy::too_many_arguments)]
 #[post("/assignments/{assignment_id}")]
 async fn update(
     assignment_id: web::Path<i64>,
-    body: web::Either<web::Json<Assignment>, web::Form<Assignment>>,
-    pool: web::Data<PgPool>,
+    body: web::Either<web::Json<UpdatePayload>, web::Form<UpdatePayload>>,
+    assignments: web::Data<dyn AssignmentRepository>,
+    keys: web::Data<dyn KeyRepository>,
+    users: web::Data<dyn UserRepository>,
+    events: web::Data<dyn Events>,
+    finance: web::Data<FinanceConfig>,
+    limits: web::Data<AssignmentsConfig>,
     session: Session,
 ) -> Result<impl Responder, actix_web::Error> {
-    validate_admin(&session, &pool).await?;
+    let username = validate_session(&session)?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
 
-    let body = unpack(body);
+    let UpdatePayload {
+        assignment: body,
+        slot_id,
+    } = unpack(body);
 
     let assignment_id = assignment_id.into_inner();
 
-    let mut assignment = match Assignment::get(&pool, assignment_id).await {
+    let original = match assignments.get(org, assignment_id).await {
         Ok(k) => k,
         Err(e) => {
             error!("Assignment '{}' not found. {}", assignment_id, e);
             return Err(ErrorNotFound("Assignment not found."));
         }
     };
+    if original.pending {
+        return Ok(Problem::new(
+            0,
+            "assignment_pending",
+            "Assignment can't be changed until it is confirmed.",
+        )
+        .response());
+    }
+    // Restricted keys can't change hands without a second admin confirming it
+    let mut moved = Vec::new();
+    if body.key != original.key {
+        moved.push(&body.key);
+    }
+    if body.user != original.user || body.group != original.group {
+        moved.push(&original.key);
+    }
+    for key in moved {
+        if let Ok(k) = keys.get(org, key).await {
+            if k.restricted {
+                return Ok(Problem::new(
+                    0,
+                    "restricted_key",
+                    "Restricted keys must be given a new assignment.",
+                )
+                .response());
+            }
+        }
+    }
+
+    let mut assignment = original.clone();
     assignment.user = body.user;
+    assignment.group = body.group;
     assignment.key = body.key;
     assignment.date_out = body.date_out;
     assignment.date_in = body.date_in;
+    assignment.date_due = body.date_due;
+    assignment.deposit_collected = body.deposit_collected;
+    assignment.deposit_refunded = body.deposit_refunded;
+    assignment.deposit_currency = match currency_for(
+        body.deposit_collected.or(body.deposit_refunded),
+        body.deposit_currency,
+        &finance.currency,
+    ) {
+        Ok(c) => c,
+        Err(e) => return Ok(Problem::new(0, "invalid_currency", e).response()),
+    };
+
+    match assignment
+        .validate(
+            &**keys,
+            &**users,
+            org,
+            Some(&original),
+            limits.max_days_ahead,
+        )
+        .await
+    {
+        Ok(_) => (),
+        Err(AssignmentError::Database(e)) => {
+            error!("Failed to validate assignment. {}", e);
+            return Err(ErrorInternalServerError("Failed to update assignment."));
+        }
+        Err(e) => return Ok(Problem::new(0, e.code(), e).response()),
+    }
+
+    let slot = match (assignment.date_in, slot_id) {
+        (Some(_), Some(id)) => match keys.slot(org, id).await {
+            Ok(s) if s.key.is_none() || s.key.as_ref() == Some(&assignment.key) => Some(s),
+            Ok(s) => {
+                let msg = format!(
+                    "Cabinet {}, hook {} already holds key '{}'.",
+                    s.cabinet,
+                    s.number,
+                    s.key.unwrap_or_default()
+                );
+                return Ok(Problem::new(0, "slot_occupied", msg).response());
+            }
+            Err(_) => return Err(ErrorNotFound("Slot not found.")),
+        },
+        _ => None,
+    };
+
+    if let Err(e) = assignments.update(org, &mut assignment, &username).await {
+        if e.to_string().contains("assignments_holder_check") {
+            return Ok(Problem::holder_not_found(0, &assignment).response());
+        }
+        if is_unique_violation(&e) {
+            let msg = format!(
+                "Key '{}' already assigned to {}",
+                assignment.key,
+                assignment.holder()
+            );
+            return Ok(Problem::new(0, "already_assigned", msg).response());
+        }
+        error!("Failed to update assignment. {}", e);
+        return Err(ErrorInternalServerError("Failed to update assignment."));
+    }
+
+    if let Some(s) = slot {
+        if let Err(e) = keys.store(&s, &assignment.key).await {
+            error!("Failed to return key to slot. {}", e);
+            return Err(ErrorInternalServerError(
+                "Updated assignment but failed to return key to its slot.",
+            ));
+        }
+    }
+
+    let event = match (original.date_in, assignment.date_in) {
+        (None, Some(_)) => "assignment.returned",
+        _ => "assignment.updated",
+    };
+    events.emit(org, event, json!(assignment)).await;
+
+    Ok(HttpResponse::Ok().json(format!("Updated assignment {}.", assignment.id())))
+}
+
+/// Confirms a pending assignment of a restricted key. The confirming admin must be different
+/// from the admin who created the assignment.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "assignments",
+    operation_id = "confirm_assignment",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin, or created the assignment", body = String),
+        (status = 404, description = "Not found", body = String),
+        (status = 409, description = "Not pending confirmation", body = String),
+    )
+)]
+#[post("/assignments/{assignment_id}/confirm")]
+async fn confirm(
+    assignment_id: web::Path<i64>,
+    assignments: web::Data<dyn AssignmentRepository>,
+    users: web::Data<dyn UserRepository>,
+    events: web::Data<dyn Events>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let assignment_id = assignment_id.into_inner();
+    let mut assignment = match assignments.get(org, assignment_id).await {
+        Ok(a) => a,
+        Err(e) => {
+            error!("Assignment '{}' not found. {}", assignment_id, e);
+            return Err(ErrorNotFound("Assignment not found."));
+        }
+    };
+
+    if !assignment.pending {
+        return Err(ErrorConflict("Assignment is not pending confirmation."));
+    }
+    if assignment.created_by.as_deref() == Some(username.as_str()) {
+        return Err(ErrorForbidden(
+            "Assignment must be confirmed by a different admin.",
+        ));
+    }
 
-    match assignment.update(&pool).await {
-        Ok(_) => Ok(HttpResponse::Ok().json(format!("Updated assignment {}.", assignment.id()))),
+    match assignments.confirm(&mut assignment, &username).await {
+        // Confirmed by someone else since it was read
+        Ok(false) => Err(ErrorConflict("Assignment is not pending confirmation.")),
+        Ok(true) => {
+            if let Some(user) = &assignment.user {
+                events.key_assigned(org, user, &assignment.key).await;
+            }
+            events
+                .emit(org, "assignment.updated", json!(assignment))
+                .await;
+            Ok(HttpResponse::Ok().json(format!(
+                "Confirmed assignment of key '{}' to '{}'",
+                assignment.key,
+                assignment.holder()
+            )))
+        }
         Err(e) => {
-            error!("Failed to update assignment. {}", e);
-            Err(ErrorInternalServerError("Failed to update assignment."))
+            error!("Failed to confirm assignment. {}", e);
+            Err(ErrorInternalServerError("Failed to confirm assignment."))
         }
     }
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "assignments",
+    operation_id = "delete_assignment",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
 #[delete("/assignments/{assignment_id}")]
 async fn delete(
     assignment_id: web::Path<i64>,
-    pool: web::Data<PgPool>,
+    assignments: web::Data<dyn AssignmentRepository>,
+    users: web::Data<dyn UserRepository>,
+    events: web::Data<dyn Events>,
     session: Session,
 ) -> Result<impl Responder, actix_web::Error> {
-    validate_admin(&session, &pool).await?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
 
-    match Assignment::get(&pool, assignment_id.into_inner()).await {
-        Ok(a) => match a.delete(&pool).await {
-            Ok(_) => Ok(HttpResponse::Ok().json(format!("Deleted assignment '{}'", a.id()))),
+    match assignments.get(org, assignment_id.into_inner()).await {
+        Ok(a) => match assignments.delete(&a).await {
+            Ok(_) => {
+                events.emit(org, "assignment.deleted", json!(a)).await;
+                Ok(HttpResponse::Ok().json(format!("Moved assignment '{}' to the trash", a.id())))
+            }
             Err(e) => {
                 error!("Failed to delete assignment. {}", e);
                 Err(ErrorInternalServerError("Failed to delete assignment."))
@@ -165,3 +556,424 @@ async fn delete(
         Err(_) => Err(ErrorNotFound("Assignment not found.")),
     }
 }
+
+#[derive(Deserialize, Clone, ToSchema)]
+struct LostPayload {
+    date: Option<NaiveDate>,
+}
+
+/// Reports the key of an open assignment as lost. The assignment is closed on `date`, or today
+/// if no date is given, and the key is deactivated.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "assignments",
+    operation_id = "report_assignment_lost",
+    request_body = inline(LostPayload),
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+        (status = 409, description = "Already closed", body = String),
+    )
+)]
+#[post("/assignments/{assignment_id}/lost")]
+async fn report_lost(
+    assignment_id: web::Path<i64>,
+    body: Option<web::Either<web::Json<LostPayload>, web::Form<LostPayload>>>,
+    assignments: web::Data<dyn AssignmentRepository>,
+    users: web::Data<dyn UserRepository>,
+    events: web::Data<dyn Events>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let assignment_id = assignment_id.into_inner();
+    let mut assignment = match assignments.get(org, assignment_id).await {
+        Ok(a) => a,
+        Err(e) => {
+            error!("Assignment '{}' not found. {}", assignment_id, e);
+            return Err(ErrorNotFound("Assignment not found."));
+        }
+    };
+    if assignment.date_in.is_some() {
+        return Err(ErrorConflict("Assignment is already closed."));
+    }
+
+    let date = body
+        .and_then(|b| unpack(b).date)
+        .unwrap_or_else(|| Local::today().naive_local());
+
+    match assignments
+        .report_lost(&mut assignment, date, &username)
+        .await
+    {
+        // Returned or reported lost since it was read
+        Ok(false) => Err(ErrorConflict("Assignment is already closed.")),
+        Ok(true) => {
+            events.emit(org, "assignment.lost", json!(assignment)).await;
+            Ok(HttpResponse::Ok().json(format!(
+                "Reported key '{}' lost by '{}'",
+                assignment.key,
+                assignment.holder()
+            )))
+        }
+        Err(e) => {
+            error!("Failed to report key lost. {}", e);
+            Err(ErrorInternalServerError("Failed to report key lost."))
+        }
+    }
+}
+
+#[cfg(test)]
+mod assignment_routes_tests {
+    use std::sync::Arc;
+
+    use actix_web::test::{call_and_read_body_json, call_service, read_body_json, TestRequest};
+    use chrono::{Duration, Local};
+    use serde_json::{json, Value};
+
+    use crate::models::{AssignmentRepository, KeyRepository, MemoryRepository, DEFAULT_ORG};
+    use crate::routes::{
+        assignments,
+        testing::{app, login, status},
+    };
+
+    fn routes(cfg: &mut actix_web::web::ServiceConfig) {
+        cfg.service(assignments::get)
+            .service(assignments::get_history)
+            .service(assignments::get_all)
+            .service(assignments::create)
+            .service(assignments::update)
+            .service(assignments::confirm)
+            .service(assignments::delete)
+            .service(assignments::report_lost);
+    }
+
+    #[actix_web::test]
+    async fn require_login_and_admin() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("user1", false);
+        repo.add_key("key1");
+        let today = Local::today().naive_local();
+        let id = repo.add_assignment("user1", "key1", today).id();
+        let app = app(&repo, routes).await;
+
+        let reads = [
+            "/assignments".to_string(),
+            format!("/assignments/{}", id),
+            format!("/assignments/{}/history", id),
+        ];
+        for uri in &reads {
+            assert_eq!(
+                401,
+                status(&app, TestRequest::get().uri(uri)).await,
+                "{}",
+                uri
+            );
+        }
+
+        let user = login(&app, "user1").await;
+        for uri in &reads {
+            let req = TestRequest::get().uri(uri).cookie(user.clone());
+            assert_eq!(200, status(&app, req).await, "{}", uri);
+        }
+        let body = json!({ "user": "user1", "key": "key1", "date_out": today });
+        for req in [
+            TestRequest::post().uri("/assignments").set_json([&body]),
+            TestRequest::post()
+                .uri(&format!("/assignments/{}", id))
+                .set_json(&body),
+            TestRequest::post().uri(&format!("/assignments/{}/confirm", id)),
+            TestRequest::post().uri(&format!("/assignments/{}/lost", id)),
+            TestRequest::delete().uri(&format!("/assignments/{}", id)),
+        ] {
+            assert_eq!(401, status(&app, req.cookie(user.clone())).await);
+        }
+        assert!(repo.events().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn create_and_get() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("admin", true);
+        repo.add_user("user1", false);
+        repo.add_key("key1");
+        let app = app(&repo, routes).await;
+        let admin = login(&app, "admin").await;
+        let today = Local::today().naive_local();
+
+        let body = json!([{ "user": "user1", "key": "key1", "date_out": today }]);
+        let req = TestRequest::post().uri("/assignments").set_json(&body);
+        assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::post().uri("/assignments").set_json(&body);
+        assert_eq!(400, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::post()
+            .uri("/assignments")
+            .set_json(json!([{ "user": "user1", "key": "missing", "date_out": today }]));
+        assert_eq!(400, status(&app, req.cookie(admin.clone())).await);
+
+        let req = TestRequest::get().uri("/assignments").cookie(admin.clone());
+        let all: Vec<Value> = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!(1, all.len());
+        let id = all[0]["id"].as_i64().unwrap();
+
+        let req = TestRequest::get()
+            .uri(&format!("/assignments/{}", id))
+            .cookie(admin.clone());
+        let assignment: Value = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!("admin", assignment["created_by"]);
+        assert_eq!(false, assignment["pending"]);
+        let req = TestRequest::get()
+            .uri(&format!("/assignments/{}/history", id))
+            .cookie(admin.clone());
+        let history: Vec<Value> = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!("created", history[0]["action"]);
+
+        let req = TestRequest::get().uri("/assignments/0");
+        assert_eq!(404, status(&app, req.cookie(admin)).await);
+        assert_eq!(vec!["key_assigned", "assignment.created"], repo.events());
+    }
+
+    #[actix_web::test]
+    async fn refusals_are_listed() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("admin", true);
+        repo.add_user("user1", false);
+        repo.add_key("key1");
+        repo.add_key("key2");
+        let app = app(&repo, routes).await;
+        let admin = login(&app, "admin").await;
+        let today = Local::today().naive_local();
+        let yesterday = today - Duration::days(1);
+
+        let req = TestRequest::post().uri("/assignments").set_json(json!([
+            { "user": "user1", "key": "key1", "date_out": today },
+            { "user": "user1", "key": "missing", "date_out": today },
+            { "user": "user1", "key": "key2", "date_out": today, "date_in": yesterday },
+        ]));
+        let resp = call_service(&app, req.cookie(admin.clone()).to_request()).await;
+        assert_eq!(400, resp.status().as_u16());
+        let problems: Vec<Value> = read_body_json(resp).await;
+        assert_eq!(
+            vec![
+                (1, "key_not_found", "Key 'missing' does not exist"),
+                (
+                    2,
+                    "date_in_before_date_out",
+                    "Date in cannot be before date out"
+                ),
+            ],
+            problems
+                .iter()
+                .map(|p| (
+                    p["index"].as_i64().unwrap(),
+                    p["code"].as_str().unwrap(),
+                    p["message"].as_str().unwrap()
+                ))
+                .collect::<Vec<_>>()
+        );
+
+        // Updates respond the same way
+        let id = AssignmentRepository::get_all(&*repo, DEFAULT_ORG, Default::default())
+            .await
+            .unwrap()[0]
+            .id();
+        let req = TestRequest::post()
+            .uri(&format!("/assignments/{}", id))
+            .set_json(
+                json!({ "user": "user1", "key": "key1", "date_out": today, "date_in": yesterday }),
+            );
+        let problems: Vec<Value> =
+            call_and_read_body_json(&app, req.cookie(admin).to_request()).await;
+        assert_eq!(
+            json!([{
+                "index": 0,
+                "code": "date_in_before_date_out",
+                "message": "Date in cannot be before date out"
+            }]),
+            json!(problems)
+        );
+    }
+
+    #[actix_web::test]
+    async fn restricted_keys_need_a_second_admin() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("admin1", true);
+        repo.add_user("admin2", true);
+        repo.add_user("user1", false);
+        repo.add_user("user2", false);
+        let mut key = repo.add_key("key1");
+        key.restricted = true;
+        KeyRepository::update(&*repo, &key).await.unwrap();
+        let app = app(&repo, routes).await;
+        let admin1 = login(&app, "admin1").await;
+        let today = Local::today().naive_local();
+
+        let req = TestRequest::post()
+            .uri("/assignments")
+            .set_json(json!([{ "user": "user1", "key": "key1", "date_out": today }]));
+        assert_eq!(200, status(&app, req.cookie(admin1.clone())).await);
+        let assignment = &AssignmentRepository::get_all(&*repo, DEFAULT_ORG, Default::default())
+            .await
+            .unwrap()[0];
+        assert!(assignment.pending);
+
+        // Pending assignments can't be edited
+        let update_uri = format!("/assignments/{}", assignment.id());
+        let body = |user: &str| json!({ "user": user, "key": "key1", "date_out": today });
+        let req = TestRequest::post().uri(&update_uri).set_json(body("user1"));
+        assert_eq!(400, status(&app, req.cookie(admin1.clone())).await);
+
+        let uri = format!("/assignments/{}/confirm", assignment.id());
+        let req = TestRequest::post().uri(&uri);
+        assert_eq!(403, status(&app, req.cookie(admin1.clone())).await);
+        let admin2 = login(&app, "admin2").await;
+        let req = TestRequest::post().uri(&uri);
+        assert_eq!(200, status(&app, req.cookie(admin2.clone())).await);
+        let req = TestRequest::post().uri(&uri);
+        assert_eq!(409, status(&app, req.cookie(admin2)).await);
+
+        // Nor can a confirmed assignment be handed to someone else
+        let req = TestRequest::post().uri(&update_uri).set_json(body("user2"));
+        assert_eq!(400, status(&app, req.cookie(admin1)).await);
+
+        let assignment = AssignmentRepository::get(&*repo, DEFAULT_ORG, assignment.id())
+            .await
+            .unwrap();
+        assert!(!assignment.pending);
+        assert_eq!(Some("admin2".to_string()), assignment.confirmed_by);
+        assert_eq!(
+            vec!["assignment.created", "key_assigned", "assignment.updated"],
+            repo.events()
+        );
+    }
+
+    #[actix_web::test]
+    async fn return_to_slot() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("admin", true);
+        repo.add_user("user1", false);
+        repo.add_key("key1");
+        repo.add_key("key2");
+        let empty = repo.add_slot("Office", 1);
+        let full = repo.add_slot("Office", 2);
+        KeyRepository::store(&*repo, &full, "key2").await.unwrap();
+        let today = Local::today().naive_local();
+        let id = repo
+            .add_assignment("user1", "key1", today - Duration::days(7))
+            .id();
+        let app = app(&repo, routes).await;
+        let admin = login(&app, "admin").await;
+
+        let body = |slot_id: i64| {
+            json!({
+                "user": "user1",
+                "key": "key1",
+                "date_out": today - Duration::days(7),
+                "date_in": today,
+                "slot_id": slot_id,
+            })
+        };
+        let req = TestRequest::post()
+            .uri("/assignments/0")
+            .set_json(body(empty.id));
+        assert_eq!(404, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::post()
+            .uri(&format!("/assignments/{}", id))
+            .set_json(body(full.id));
+        assert_eq!(400, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::post()
+            .uri(&format!("/assignments/{}", id))
+            .set_json(body(empty.id));
+        assert_eq!(200, status(&app, req.cookie(admin)).await);
+
+        let slot = KeyRepository::slot(&*repo, DEFAULT_ORG, empty.id)
+            .await
+            .unwrap();
+        assert_eq!(Some("key1".to_string()), slot.key);
+        let assignment = AssignmentRepository::get(&*repo, DEFAULT_ORG, id)
+            .await
+            .unwrap();
+        assert_eq!(Some(today), assignment.date_in);
+        assert_eq!(vec!["assignment.returned"], repo.events());
+    }
+
+    #[actix_web::test]
+    async fn report_lost_and_delete() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("admin", true);
+        repo.add_user("user1", false);
+        repo.add_key("key1");
+        let today = Local::today().naive_local();
+        let id = repo.add_assignment("user1", "key1", today).id();
+        let app = app(&repo, routes).await;
+        let admin = login(&app, "admin").await;
+
+        let uri = format!("/assignments/{}/lost", id);
+        let req = TestRequest::post().uri(&uri);
+        assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::post().uri(&uri);
+        assert_eq!(409, status(&app, req.cookie(admin.clone())).await);
+        let assignment = AssignmentRepository::get(&*repo, DEFAULT_ORG, id)
+            .await
+            .unwrap();
+        assert!(assignment.lost);
+        assert!(
+            !KeyRepository::get(&*repo, DEFAULT_ORG, "key1")
+                .await
+                .unwrap()
+                .active
+        );
+
+        let uri = format!("/assignments/{}", id);
+        let req = TestRequest::delete().uri(&uri);
+        assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::delete().uri(&uri);
+        assert_eq!(404, status(&app, req.cookie(admin)).await);
+        assert_eq!(vec!["assignment.lost", "assignment.deleted"], repo.events());
+    }
+
+    #[actix_web::test]
+    async fn assign_to_group() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("admin", true);
+        repo.add_user("user1", false);
+        repo.add_key("key1");
+        repo.add_group("Janitorial", &["user1"]);
+        let app = app(&repo, routes).await;
+        let admin = login(&app, "admin").await;
+        let today = Local::today().naive_local();
+
+        for body in [
+            json!([{ "group": "Missing", "key": "key1", "date_out": today }]),
+            json!([{ "user": "user1", "group": "Janitorial", "key": "key1", "date_out": today }]),
+            json!([{ "key": "key1", "date_out": today }]),
+        ] {
+            let req = TestRequest::post().uri("/assignments").set_json(&body);
+            assert_eq!(400, status(&app, req.cookie(admin.clone())).await);
+        }
+        let body = json!([{ "group": "Janitorial", "key": "key1", "date_out": today }]);
+        let req = TestRequest::post().uri("/assignments").set_json(&body);
+        assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+
+        let req = TestRequest::get()
+            .uri("/assignments?member=user1")
+            .cookie(admin.clone());
+        let held: Vec<Value> = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!(1, held.len());
+        assert_eq!("Janitorial", held[0]["group"]);
+        assert_eq!(Value::Null, held[0]["user"]);
+        let req = TestRequest::get()
+            .uri("/assignments?user=user1")
+            .cookie(admin);
+        let own: Vec<Value> = call_and_read_body_json(&app, req.to_request()).await;
+        assert!(own.is_empty());
+
+        // Nobody is emailed about keys assigned to a group
+        assert_eq!(vec!["assignment.created"], repo.events());
+    }
+}
@@ -0,0 +1,458 @@
+use actix_session::Session;
+use actix_web::{
+    delete,
+    error::{ErrorBadRequest, ErrorConflict, ErrorInternalServerError, ErrorNotFound},
+    get, post, web, HttpResponse, Responder,
+};
+use chrono::{Local, NaiveDate};
+use serde::Deserialize;
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::{
+    models::{
+        is_foreign_key_violation, Assignment, Audit, AuditItem, AuditResult, Db, UserRepository,
+    },
+    routes::{csv_field, current_org, unpack, validate_admin, validate_session},
+    webhooks,
+};
+
+#[derive(Deserialize, Clone, ToSchema)]
+struct CheckPayload {
+    result: AuditResult,
+    actual_holder: Option<String>,
+    note: Option<String>,
+}
+
+/// Closes an audit. With `report_missing`, keys which were found missing are reported lost on
+/// `date`, or today if no date is given.
+#[derive(Deserialize, Clone, ToSchema)]
+struct ClosePayload {
+    #[serde(default)]
+    report_missing: bool,
+    date: Option<NaiveDate>,
+}
+
+async fn get_audit(pool: &Db, org: i64, audit_id: i64) -> Result<Audit, actix_web::Error> {
+    match Audit::get(pool, org, audit_id).await {
+        Ok(a) => Ok(a),
+        Err(e) => match e.to_string() {
+            x if x.contains("no rows returned") => Err(ErrorNotFound("Audit not found.")),
+            _ => {
+                error!("Failed to get audit '{}'. {}", audit_id, e);
+                Err(ErrorInternalServerError("Failed to get audit."))
+            }
+        },
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "audits",
+    operation_id = "get_audit",
+    responses(
+        (status = 200, body = Audit),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/audits/{audit_id}")]
+async fn get(
+    audit_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let audit = get_audit(&pool, org, audit_id.into_inner()).await?;
+    Ok(HttpResponse::Ok().json(audit))
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "audits",
+    operation_id = "get_audits",
+    responses(
+        (status = 200, body = [Audit]),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+    )
+)]
+#[get("/audits")]
+async fn get_all(
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    match Audit::get_all(&pool, org).await {
+        Ok(a) => Ok(HttpResponse::Ok().json(a)),
+        Err(e) => {
+            error!("Failed to get audits. {}", e);
+            Err(ErrorInternalServerError("Failed to get audits."))
+        }
+    }
+}
+
+/// Starts an audit of every open assignment.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "audits",
+    operation_id = "create_audit",
+    request_body = Audit,
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+    )
+)]
+#[post("/audits")]
+async fn create(
+    audit: web::Either<web::Json<Audit>, web::Form<Audit>>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let mut audit = unpack(audit);
+    if audit.name.trim().is_empty() {
+        return Err(ErrorBadRequest("Audit name is required."));
+    }
+    audit.started_by = Some(username);
+
+    match audit.create(&pool, org).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Started audit '{}'", audit.name))),
+        Err(e) => {
+            error!("Failed to create audit. {}", e);
+            Err(ErrorInternalServerError("Failed to start audit."))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "audits",
+    operation_id = "delete_audit",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[delete("/audits/{audit_id}")]
+async fn delete(
+    audit_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let audit = get_audit(&pool, org, audit_id.into_inner()).await?;
+    match audit.delete(&pool).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Deleted audit '{}'", audit.name))),
+        Err(e) => {
+            error!("Failed to delete audit. {}", e);
+            Err(ErrorInternalServerError("Failed to delete audit."))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "audits",
+    operation_id = "get_audit_items",
+    responses(
+        (status = 200, body = [AuditItem]),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/audits/{audit_id}/items")]
+async fn get_items(
+    audit_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let audit_id = audit_id.into_inner();
+    get_audit(&pool, org, audit_id).await?;
+    match AuditItem::get_by_audit(&pool, audit_id, false).await {
+        Ok(i) => Ok(HttpResponse::Ok().json(i)),
+        Err(e) => {
+            error!("Failed to get items of audit '{}'. {}", audit_id, e);
+            Err(ErrorInternalServerError("Failed to get audit items."))
+        }
+    }
+}
+
+/// Records the outcome of checking an item. Items of closed audits can't be changed.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "audits",
+    operation_id = "check_audit_item",
+    request_body = inline(CheckPayload),
+    responses(
+        (status = 200, body = AuditItem),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+        (status = 409, description = "Audit is closed", body = String),
+    )
+)]
+#[post("/audit-items/{item_id}")]
+async fn check_item(
+    item_id: web::Path<i64>,
+    body: web::Either<web::Json<CheckPayload>, web::Form<CheckPayload>>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let body = unpack(body);
+    let item_id = item_id.into_inner();
+
+    let mut item = match AuditItem::get(&pool, item_id).await {
+        Ok(i) => i,
+        Err(e) => {
+            error!("Audit item '{}' not found. {}", item_id, e);
+            return Err(ErrorNotFound("Audit item not found."));
+        }
+    };
+    if get_audit(&pool, org, item.audit_id)
+        .await?
+        .closed_at
+        .is_some()
+    {
+        return Err(ErrorConflict("Audit is closed."));
+    }
+
+    let actual_holder = body.actual_holder.filter(|h| !h.is_empty());
+    if body.result == AuditResult::WrongHolder && actual_holder.is_none() {
+        return Err(ErrorBadRequest("Enter who actually holds the key."));
+    }
+
+    match item
+        .check(
+            &pool,
+            body.result,
+            actual_holder,
+            body.note.filter(|n| !n.is_empty()),
+            &username,
+        )
+        .await
+    {
+        Ok(_) => Ok(HttpResponse::Ok().json(item)),
+        Err(e) => match e.to_string() {
+            _ if is_foreign_key_violation(&e) => Err(ErrorBadRequest("User does not exist.")),
+            _ => {
+                error!("Failed to check audit item. {}", e);
+                Err(ErrorInternalServerError("Failed to check audit item."))
+            }
+        },
+    }
+}
+
+/// Ends the audit, optionally reporting the keys found missing as lost.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "audits",
+    operation_id = "close_audit",
+    request_body = inline(ClosePayload),
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+        (status = 409, description = "Audit is closed", body = String),
+    )
+)]
+#[post("/audits/{audit_id}/close")]
+async fn close(
+    audit_id: web::Path<i64>,
+    body: web::Either<web::Json<ClosePayload>, web::Form<ClosePayload>>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let body = unpack(body);
+    let mut audit = get_audit(&pool, org, audit_id.into_inner()).await?;
+    if audit.closed_at.is_some() {
+        return Err(ErrorConflict("Audit is already closed."));
+    }
+
+    let mut reported = 0;
+    if body.report_missing {
+        let date = body.date.unwrap_or_else(|| Local::today().naive_local());
+        let items = match AuditItem::get_by_audit(&pool, audit.id, true).await {
+            Ok(i) => i,
+            Err(e) => {
+                error!("Failed to get discrepancies of audit '{}'. {}", audit.id, e);
+                return Err(ErrorInternalServerError("Failed to close audit."));
+            }
+        };
+
+        for mut item in items
+            .into_iter()
+            .filter(|i| i.result == Some(AuditResult::Missing))
+        {
+            let mut assignment = match item.assignment_id {
+                Some(id) => match Assignment::get(&pool, org, id).await {
+                    Ok(a) if a.date_in.is_none() => a,
+                    _ => continue,
+                },
+                None => continue,
+            };
+            match assignment.report_lost(&pool, date, &username).await {
+                Ok(true) => (),
+                // Returned since it was read
+                Ok(false) => continue,
+                Err(e) => {
+                    error!("Failed to report key '{}' lost. {}", assignment.key, e);
+                    return Err(ErrorInternalServerError(
+                        "Failed to report missing keys lost.",
+                    ));
+                }
+            }
+            webhooks::emit(&pool, org, "assignment.lost", &assignment).await;
+            if let Err(e) = item.mark_lost_reported(&pool).await {
+                error!("Failed to update audit item. {}", e);
+            }
+            reported += 1;
+        }
+    }
+
+    match audit.close(&pool, &username).await {
+        Ok(false) => Err(ErrorConflict("Audit is already closed.")),
+        Ok(true) => Ok(HttpResponse::Ok().json(format!(
+            "Closed audit '{}'. {} keys reported lost.",
+            audit.name, reported
+        ))),
+        Err(e) => {
+            error!("Failed to close audit. {}", e);
+            Err(ErrorInternalServerError("Failed to close audit."))
+        }
+    }
+}
+
+/// Lists the items which weren't verified, including those which were never checked.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "audits",
+    operation_id = "get_audit_report",
+    responses(
+        (status = 200, body = [AuditItem]),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/audits/{audit_id}/report")]
+async fn report(
+    audit_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let audit_id = audit_id.into_inner();
+    get_audit(&pool, org, audit_id).await?;
+    match AuditItem::get_by_audit(&pool, audit_id, true).await {
+        Ok(i) => Ok(HttpResponse::Ok().json(i)),
+        Err(e) => {
+            error!("Failed to get discrepancies of audit '{}'. {}", audit_id, e);
+            Err(ErrorInternalServerError("Failed to get audit report."))
+        }
+    }
+}
+
+/// The discrepancy report as a CSV file.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "audits",
+    operation_id = "get_audit_report_csv",
+    responses(
+        (status = 200, description = "CSV file", content_type = "text/csv", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/audits/{audit_id}/report.csv")]
+async fn report_csv(
+    audit_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let audit_id = audit_id.into_inner();
+    get_audit(&pool, org, audit_id).await?;
+    let items = match AuditItem::get_by_audit(&pool, audit_id, true).await {
+        Ok(i) => i,
+        Err(e) => {
+            error!("Failed to get discrepancies of audit '{}'. {}", audit_id, e);
+            return Err(ErrorInternalServerError("Failed to export audit report."));
+        }
+    };
+
+    let mut csv =
+        String::from("user,key,result,actual_holder,note,checked_by,checked_at,lost_reported\n");
+    for i in items {
+        let result = match i.result {
+            Some(AuditResult::Verified) => "verified",
+            Some(AuditResult::Missing) => "missing",
+            Some(AuditResult::WrongHolder) => "wrong_holder",
+            None => "unchecked",
+        };
+        let row = [
+            i.user,
+            i.key,
+            result.to_string(),
+            i.actual_holder.unwrap_or_default(),
+            i.note.unwrap_or_default(),
+            i.checked_by.unwrap_or_default(),
+            i.checked_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+            i.lost_reported.to_string(),
+        ];
+        let row: Vec<String> = row.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv")
+        .insert_header((
+            "Content-Disposition",
+            format!(r#"attachment; filename="keymaster-audit-{}.csv""#, audit_id),
+        ))
+        .body(csv))
+}
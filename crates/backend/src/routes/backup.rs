@@ -0,0 +1,62 @@
+use actix_session::Session;
+use actix_web::{error::ErrorInternalServerError, get, web, HttpResponse, Responder};
+use serde::Deserialize;
+use tracing::{error, info};
+use utoipa::IntoParams;
+
+use crate::{
+    models::{Backup, Db, UserRepository},
+    routes::{validate_session, validate_site_admin},
+};
+
+#[derive(Deserialize, Clone, IntoParams)]
+struct BackupQuery {
+    /// Include password hashes so that users can log in after a restore.
+    passwords: Option<bool>,
+}
+
+/// Downloads every org, key, user, assignment and the data related to them as JSON. Restore it
+/// with `backend restore <path>` on an empty database. Only admins of the first org can download
+/// it, since it holds every org's data.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "admin",
+    operation_id = "get_backup",
+    params(BackupQuery),
+    responses(
+        (status = 200, body = Backup),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin of the first org", body = String),
+    )
+)]
+#[get("/admin/backup")]
+async fn get(
+    query: web::Query<BackupQuery>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+    validate_site_admin(&session, &users).await?;
+
+    let include_passwords = query.passwords.unwrap_or(false);
+    match Backup::create(&pool, include_passwords).await {
+        Ok(b) => {
+            info!(%username, include_passwords, "Backup downloaded");
+            let filename = format!(
+                "keymaster-backup-{}.json",
+                b.created_at.format("%Y%m%d-%H%M%S")
+            );
+            Ok(HttpResponse::Ok()
+                .insert_header((
+                    "Content-Disposition",
+                    format!(r#"attachment; filename="{}""#, filename),
+                ))
+                .json(b))
+        }
+        Err(e) => {
+            error!("Failed to create backup. {}", e);
+            Err(ErrorInternalServerError("Failed to create backup."))
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use actix_session::Session;
+use actix_web::{
+    delete,
+    error::{ErrorBadRequest, ErrorConflict, ErrorInternalServerError, ErrorNotFound},
+    get, post, web, HttpResponse, Responder,
+};
+use serde::Deserialize;
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::{
+    models::{is_unique_violation, Cabinet, Db, Slot, UserRepository},
+    routes::{current_org, unpack, validate_admin, validate_session},
+};
+
+#[derive(Deserialize, Clone, ToSchema)]
+struct CabinetPayload {
+    name: String,
+    description: Option<String>,
+    slots: Option<i32>,
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "cabinets",
+    operation_id = "get_cabinet",
+    responses(
+        (status = 200, body = Cabinet),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/cabinets/{cabinet_id}")]
+async fn get(
+    cabinet_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    let cabinet_id = cabinet_id.into_inner();
+    match Cabinet::get(&pool, org, cabinet_id).await {
+        Ok(c) => Ok(HttpResponse::Ok().json(c)),
+        Err(e) => match e.to_string() {
+            x if x.contains("no rows returned") => Err(ErrorNotFound("Cabinet not found.")),
+            _ => {
+                error!("Failed to get cabinet '{}'. {}", cabinet_id, e);
+                Err(ErrorInternalServerError("Failed to get cabinet."))
+            }
+        },
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "cabinets",
+    operation_id = "get_cabinets",
+    responses(
+        (status = 200, body = [Cabinet]),
+        (status = 401, description = "Not logged in", body = String),
+    )
+)]
+#[get("/cabinets")]
+async fn get_all(
+    pool: web::Data<Db>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    match Cabinet::get_all(&pool, org).await {
+        Ok(c) => Ok(HttpResponse::Ok().json(c)),
+        Err(e) => {
+            error!("Failed to get cabinets. {}", e);
+            Err(ErrorInternalServerError("Failed to get cabinets."))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "cabinets",
+    operation_id = "create_cabinet",
+    request_body = inline(CabinetPayload),
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+    )
+)]
+#[post("/cabinets")]
+async fn create(
+    body: web::Either<web::Json<CabinetPayload>, web::Form<CabinetPayload>>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let body = unpack(body);
+    let slots = body.slots.unwrap_or_default();
+    if slots < 0 {
+        return Err(ErrorBadRequest("Number of slots cannot be negative."));
+    }
+
+    let mut cabinet = Cabinet {
+        name: body.name,
+        description: body.description,
+        ..Default::default()
+    };
+
+    match cabinet.create(&pool, org, slots).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Created cabinet '{}'", cabinet.name))),
+        Err(e) => match e.to_string() {
+            _ if is_unique_violation(&e) => Err(ErrorBadRequest("Cabinet already exists.")),
+            _ => {
+                error!("Failed to create cabinet. {}", e);
+                Err(ErrorInternalServerError("Failed to create cabinet."))
+            }
+        },
+    }
+}
+
+/// Updates the cabinet's details. If `slots` is given, slots are added to or removed from the
+/// end of the cabinet. Slots which still hold a key cannot be removed.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "cabinets",
+    operation_id = "update_cabinet",
+    request_body = inline(CabinetPayload),
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+        (status = 409, description = "Slots to remove still hold keys", body = String),
+    )
+)]
+#[post("/cabinets/{cabinet_id}")]
+async fn update(
+    cabinet_id: web::Path<i64>,
+    body: web::Either<web::Json<CabinetPayload>, web::Form<CabinetPayload>>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let body = unpack(body);
+    let cabinet_id = cabinet_id.into_inner();
+
+    let mut cabinet = match Cabinet::get(&pool, org, cabinet_id).await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Cabinet '{}' not found. {}", cabinet_id, e);
+            return Err(ErrorNotFound("Cabinet not found."));
+        }
+    };
+
+    cabinet.name = body.name;
+    cabinet.description = body.description;
+
+    if let Err(e) = cabinet.update(&pool).await {
+        return match e.to_string() {
+            _ if is_unique_violation(&e) => Err(ErrorBadRequest("Cabinet already exists.")),
+            _ => {
+                error!("Failed to update cabinet. {}", e);
+                Err(ErrorInternalServerError("Failed to update cabinet."))
+            }
+        };
+    }
+
+    if let Some(slots) = body.slots {
+        if slots < 0 {
+            return Err(ErrorBadRequest("Number of slots cannot be negative."));
+        }
+        match cabinet.set_slot_count(&pool, slots).await {
+            Ok(true) => (),
+            Ok(false) => return Err(ErrorConflict("Cannot remove slots which still hold a key.")),
+            Err(e) => {
+                error!("Failed to update cabinet slots. {}", e);
+                return Err(ErrorInternalServerError("Failed to update cabinet."));
+            }
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(format!("Updated cabinet '{}'", cabinet.name)))
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "cabinets",
+    operation_id = "delete_cabinet",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[delete("/cabinets/{cabinet_id}")]
+async fn delete(
+    cabinet_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    match Cabinet::get(&pool, org, cabinet_id.into_inner()).await {
+        Ok(c) => match c.delete(&pool).await {
+            Ok(_) => Ok(HttpResponse::Ok().json(format!("Deleted cabinet '{}'", c.name))),
+            Err(e) => {
+                error!("Failed to delete cabinet. {}", e);
+                Err(ErrorInternalServerError("Failed to delete cabinet."))
+            }
+        },
+        Err(_) => Err(ErrorNotFound("Cabinet not found.")),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "cabinets",
+    operation_id = "get_cabinet_slots",
+    responses(
+        (status = 200, body = [Slot]),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/cabinets/{cabinet_id}/slots")]
+async fn get_slots(
+    cabinet_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    let cabinet_id = cabinet_id.into_inner();
+    if Cabinet::get(&pool, org, cabinet_id).await.is_err() {
+        return Err(ErrorNotFound("Cabinet not found."));
+    }
+    match Slot::get_by_cabinet(&pool, cabinet_id).await {
+        Ok(s) => Ok(HttpResponse::Ok().json(s)),
+        Err(e) => {
+            error!("Failed to get slots for cabinet '{}'. {}", cabinet_id, e);
+            Err(ErrorInternalServerError("Failed to get slots."))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "cabinets",
+    operation_id = "get_slots",
+    responses(
+        (status = 200, body = [Slot]),
+        (status = 401, description = "Not logged in", body = String),
+    )
+)]
+#[get("/slots")]
+async fn get_all_slots(
+    pool: web::Data<Db>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    match Slot::get_all(&pool, org).await {
+        Ok(s) => Ok(HttpResponse::Ok().json(s)),
+        Err(e) => {
+            error!("Failed to get slots. {}", e);
+            Err(ErrorInternalServerError("Failed to get slots."))
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use actix_web::{get, HttpResponse, Responder};
+use utoipa::OpenApi;
+
+use crate::models::{
+    Assignment, AssignmentChange, Audit, AuditItem, AuditResult, Backup, Balance, Cabinet, Charge,
+    Credentials, Group, GroupBalance, JobStatus, Key, KeyLocation, KeyRequest, Membership,
+    NotificationSettings, Org, RequestStatus, Slot, TrashItem, TrashKind, User, Webhook,
+    WebhookDelivery,
+};
+
+static REDOC: &str = include_str!("../../templates/docs/redoc.html");
+
+/// The OpenAPI document for every route. A route has to be listed under `paths` to show up.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "KeyMaster API",
+        description = "Most routes require a session cookie, which `POST /api/login` sets. \
+            Request bodies can be sent as JSON or as `application/x-www-form-urlencoded` forms, \
+            except for setting a password which only accepts JSON. Updates are `POST`s to the \
+            resource's path."
+    ),
+    paths(
+        super::health::healthz,
+        super::health::readyz,
+        super::health::get_metrics,
+        super::keys::get,
+        super::keys::get_all,
+        super::keys::update,
+        super::keys::create,
+        super::keys::delete,
+        super::keys::get_assignments,
+        super::keys::get_location,
+        super::keys::get_label,
+        super::keys::get_labels,
+        super::users::get,
+        super::users::get_all,
+        super::users::update,
+        super::users::create,
+        super::users::delete,
+        super::users::set_password,
+        super::users::get_assignments,
+        super::users::get_notifications,
+        super::users::set_notifications,
+        super::assignments::get,
+        super::assignments::get_all,
+        super::assignments::get_history,
+        super::assignments::update,
+        super::assignments::create,
+        super::assignments::delete,
+        super::assignments::confirm,
+        super::assignments::report_lost,
+        super::audits::get,
+        super::audits::get_all,
+        super::audits::create,
+        super::audits::delete,
+        super::audits::get_items,
+        super::audits::check_item,
+        super::audits::close,
+        super::audits::report,
+        super::audits::report_csv,
+        super::cabinets::get,
+        super::cabinets::get_all,
+        super::cabinets::update,
+        super::cabinets::create,
+        super::cabinets::delete,
+        super::cabinets::get_slots,
+        super::cabinets::get_all_slots,
+        super::finance::get,
+        super::finance::get_all,
+        super::finance::update,
+        super::finance::create,
+        super::finance::delete,
+        super::finance::balances,
+        super::finance::group_balances,
+        super::finance::finance_csv,
+        super::groups::get,
+        super::groups::get_all,
+        super::groups::update,
+        super::groups::create,
+        super::groups::delete,
+        super::groups::add_member,
+        super::groups::remove_member,
+        super::requests::get,
+        super::requests::get_all,
+        super::requests::create,
+        super::requests::approve,
+        super::requests::deny,
+        super::requests::fulfill,
+        super::requests::delete,
+        super::jobs::get_all,
+        super::orgs::get_all,
+        super::orgs::create,
+        super::orgs::switch,
+        super::orgs::add_member,
+        super::trash::get_all,
+        super::trash::restore,
+        super::trash::purge,
+        super::webhooks::get,
+        super::webhooks::get_all,
+        super::webhooks::update,
+        super::webhooks::create,
+        super::webhooks::delete,
+        super::webhooks::get_deliveries,
+        super::webhooks::resend,
+        super::backup::get,
+        super::login,
+        super::logout,
+        super::session_info,
+        super::docs::get_openapi,
+        super::docs::get_docs,
+    ),
+    components(schemas(
+        Assignment,
+        AssignmentChange,
+        Audit,
+        AuditItem,
+        AuditResult,
+        Backup,
+        Balance,
+        Cabinet,
+        Charge,
+        Credentials,
+        Group,
+        GroupBalance,
+        JobStatus,
+        Key,
+        KeyLocation,
+        KeyRequest,
+        Membership,
+        NotificationSettings,
+        Org,
+        RequestStatus,
+        Slot,
+        TrashItem,
+        TrashKind,
+        User,
+        Webhook,
+        WebhookDelivery,
+    ))
+)]
+pub struct ApiDoc;
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "docs",
+    operation_id = "get_openapi",
+    responses((status = 200, description = "This OpenAPI document")),
+)]
+#[get("/openapi.json")]
+async fn get_openapi() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+/// Interactive documentation rendered from the OpenAPI document.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "docs",
+    operation_id = "get_docs",
+    responses((status = 200, description = "HTML page", content_type = "text/html", body = String)),
+)]
+#[get("/docs")]
+async fn get_docs() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(REDOC)
+}
+
+#[cfg(test)]
+mod docs_tests {
+    use std::fs;
+
+    use regex::Regex;
+    use utoipa::OpenApi;
+
+    use super::ApiDoc;
+
+    static SRC: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+
+    /// Finds the method and path of every handler registered in lib.rs and checks that the
+    /// spec documents it.
+    #[test]
+    fn spec_covers_routes() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let main = fs::read_to_string(format!("{}/lib.rs", SRC)).unwrap();
+        let scope_start = main.find(r#"scope("/api")"#).unwrap();
+        let scope_end = main.find("spa()").unwrap();
+
+        let service = Regex::new(r"\.service\(routes::(?:(\w+)::)?(\w+)\)").unwrap();
+        let mut checked = 0;
+        for c in service.captures_iter(&main) {
+            let module = c.get(1).map(|m| m.as_str()).unwrap_or("mod");
+            let handler = &c[2];
+            let source = fs::read_to_string(format!("{}/routes/{}.rs", SRC, module)).unwrap();
+            let route = Regex::new(&format!(
+                r#"#\[(get|post|put|delete)\("([^"]+)"\)\]\s*(?:pub )?async fn {}\b"#,
+                handler
+            ))
+            .unwrap();
+            let route = route
+                .captures(&source)
+                .unwrap_or_else(|| panic!("No route attribute on {}::{}", module, handler));
+
+            let position = c.get(0).unwrap().start();
+            let prefix = if position > scope_start && position < scope_end {
+                "/api"
+            } else {
+                ""
+            };
+            let path = format!("{}{}", prefix, &route[2]);
+            assert!(
+                spec["paths"][&path][&route[1]].is_object(),
+                "{} {} ({}::{}) is missing from the OpenAPI spec",
+                route[1].to_uppercase(),
+                path,
+                module,
+                handler
+            );
+            checked += 1;
+        }
+
+        assert!(checked > 70);
+    }
+}
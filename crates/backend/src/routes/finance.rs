@@ -0,0 +1,337 @@
+use actix_session::Session;
+use actix_web::{
+    delete,
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    get, post, web, HttpResponse, Responder,
+};
+use serde::Deserialize;
+use tracing::error;
+use utoipa::IntoParams;
+
+use crate::{
+    config::FinanceConfig,
+    models::{
+        currency_for, is_foreign_key_violation, Balance, Charge, Db, FinanceEntry, GroupBalance,
+        User, UserRepository,
+    },
+    routes::{csv_field, current_org, unpack, validate_admin, validate_session},
+};
+
+#[derive(Deserialize, Clone, IntoParams)]
+struct ChargeQuery {
+    user: Option<String>,
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "finance",
+    operation_id = "get_charge",
+    responses(
+        (status = 200, body = Charge),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/charges/{charge_id}")]
+async fn get(
+    charge_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    let charge_id = charge_id.into_inner();
+    match Charge::get(&pool, org, charge_id).await {
+        Ok(c) => Ok(HttpResponse::Ok().json(c)),
+        Err(e) => match e.to_string() {
+            x if x.contains("no rows returned") => Err(ErrorNotFound("Charge not found.")),
+            _ => {
+                error!("Failed to get charge '{}'. {}", charge_id, e);
+                Err(ErrorInternalServerError("Failed to get charge."))
+            }
+        },
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "finance",
+    operation_id = "get_charges",
+    params(ChargeQuery),
+    responses(
+        (status = 200, body = [Charge]),
+        (status = 401, description = "Not logged in", body = String),
+    )
+)]
+#[get("/charges")]
+async fn get_all(
+    pool: web::Data<Db>,
+    session: Session,
+    query: web::Query<ChargeQuery>,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    match Charge::get_all(&pool, org, query.user.as_deref()).await {
+        Ok(c) => Ok(HttpResponse::Ok().json(c)),
+        Err(e) => {
+            error!("Failed to get charges. {}", e);
+            Err(ErrorInternalServerError("Failed to get charges."))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "finance",
+    operation_id = "create_charge",
+    request_body = Charge,
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+    )
+)]
+#[post("/charges")]
+async fn create(
+    charge: web::Either<web::Json<Charge>, web::Form<Charge>>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    finance: web::Data<FinanceConfig>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let mut charge = unpack(charge);
+    charge.currency = currency_for(
+        Some(charge.amount),
+        Some(charge.currency),
+        &finance.currency,
+    )
+    .map_err(ErrorBadRequest)?
+    .unwrap_or_default();
+    // SQLite doesn't name the foreign key that failed, so the user is checked up front
+    if User::get(&pool, org, &charge.user).await.is_err() {
+        return Err(ErrorBadRequest(format!(
+            "User '{}' does not exist",
+            charge.user
+        )));
+    }
+
+    match charge.create(&pool, org).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Charged user '{}'", charge.user))),
+        Err(e) => match e.to_string() {
+            _ if is_foreign_key_violation(&e) => Err(ErrorBadRequest(format!(
+                "Key '{}' does not exist",
+                charge.key.unwrap_or_default()
+            ))),
+            _ => {
+                error!("Failed to create charge. {}", e);
+                Err(ErrorInternalServerError("Failed to create charge."))
+            }
+        },
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "finance",
+    operation_id = "update_charge",
+    request_body = Charge,
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[post("/charges/{charge_id}")]
+async fn update(
+    charge_id: web::Path<i64>,
+    body: web::Either<web::Json<Charge>, web::Form<Charge>>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    finance: web::Data<FinanceConfig>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let body = unpack(body);
+    let charge_id = charge_id.into_inner();
+
+    let mut charge = match Charge::get(&pool, org, charge_id).await {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Charge '{}' not found. {}", charge_id, e);
+            return Err(ErrorNotFound("Charge not found."));
+        }
+    };
+
+    charge.key = body.key;
+    charge.amount = body.amount;
+    charge.currency = currency_for(Some(body.amount), Some(body.currency), &finance.currency)
+        .map_err(ErrorBadRequest)?
+        .unwrap_or_default();
+    charge.date = body.date;
+    charge.description = body.description;
+    charge.paid = body.paid;
+
+    match charge.update(&pool).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Updated charge {}.", charge.id))),
+        Err(e) => {
+            error!("Failed to update charge. {}", e);
+            Err(ErrorInternalServerError("Failed to update charge."))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "finance",
+    operation_id = "delete_charge",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[delete("/charges/{charge_id}")]
+async fn delete(
+    charge_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    match Charge::get(&pool, org, charge_id.into_inner()).await {
+        Ok(c) => match c.delete(&pool).await {
+            Ok(_) => Ok(HttpResponse::Ok().json(format!("Deleted charge {}.", c.id))),
+            Err(e) => {
+                error!("Failed to delete charge. {}", e);
+                Err(ErrorInternalServerError("Failed to delete charge."))
+            }
+        },
+        Err(_) => Err(ErrorNotFound("Charge not found.")),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "finance",
+    operation_id = "get_balances",
+    responses(
+        (status = 200, body = [Balance]),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+    )
+)]
+#[get("/reports/balances")]
+async fn balances(
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    match Balance::get_all(&pool, org).await {
+        Ok(b) => Ok(HttpResponse::Ok().json(b)),
+        Err(e) => {
+            error!("Failed to get balances. {}", e);
+            Err(ErrorInternalServerError("Failed to get balances."))
+        }
+    }
+}
+
+/// Rolls the balances up by group. A group's balance is its members' balances plus the
+/// deposits held on keys assigned to the group itself.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "finance",
+    operation_id = "get_group_balances",
+    responses(
+        (status = 200, body = [GroupBalance]),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+    )
+)]
+#[get("/reports/balances/groups")]
+async fn group_balances(
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    match GroupBalance::get_all(&pool, org).await {
+        Ok(b) => Ok(HttpResponse::Ok().json(b)),
+        Err(e) => {
+            error!("Failed to get group balances. {}", e);
+            Err(ErrorInternalServerError("Failed to get balances."))
+        }
+    }
+}
+
+/// Exports every deposit collected, deposit refunded and charge as CSV. Amounts are in minor
+/// units of the row's currency.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "finance",
+    operation_id = "get_finance_csv",
+    responses(
+        (status = 200, description = "CSV file", content_type = "text/csv", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+    )
+)]
+#[get("/reports/finance.csv")]
+async fn finance_csv(
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let entries = match FinanceEntry::get_all(&pool, org).await {
+        Ok(e) => e,
+        Err(e) => {
+            error!("Failed to get finance entries. {}", e);
+            return Err(ErrorInternalServerError("Failed to export finances."));
+        }
+    };
+
+    let mut csv = String::from("date,user,group,key,type,amount,currency,paid\n");
+    for e in entries {
+        let row = [
+            e.date.map(|d| d.to_string()).unwrap_or_default(),
+            e.user.unwrap_or_default(),
+            e.group.unwrap_or_default(),
+            e.key.unwrap_or_default(),
+            e.entry_type,
+            e.amount.to_string(),
+            e.currency,
+            e.paid.map(|p| p.to_string()).unwrap_or_default(),
+        ];
+        let row: Vec<String> = row.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv")
+        .insert_header((
+            "Content-Disposition",
+            r#"attachment; filename="keymaster-finance.csv""#,
+        ))
+        .body(csv))
+}
@@ -0,0 +1,7 @@
+INSERT INTO orgs (name) VALUES ('Acme');
+
+INSERT INTO users (username, display_name, email, password_hash, can_login)
+VALUES ('outsider', 'Out Sider', 'outsider@email.com', 'pass4', false);
+
+INSERT INTO org_members (org_id, user_id, admin)
+SELECT o.id, u.id, true FROM orgs o, users u WHERE o.name = 'Acme' AND u.username = 'outsider';
@@ -1,26 +1,26 @@
-INSERT INTO users (username, display_name, email, password_hash, can_login, admin)
+INSERT INTO users (username, display_name, email, password_hash, can_login)
 VALUES (
     'user1',
     'User One',
     'user1@email.com',
     'pass1',
-    'f',
-    'f'
+    false
 ),
 (
     'user2',
     'User Two',
     'user2@email.com',
     '$argon2i$v=19$m=65536,t=3,p=1$6JGByse/9Ous9DCnkgfFnA$lrixZa334c0rLb0k8SWK67q6TtSWoYjwXje67aKK0cU',
-    't',
-    'f'
+    true
 ),
 (
     'user3',
     'User Three',
     'user3@email.com',
     'pass3',
-    't',
-    't'
+    true
 )
 ;
+
+INSERT INTO org_members (org_id, user_id, admin)
+SELECT 1, id, username = 'user3' FROM users;
@@ -0,0 +1,425 @@
+use actix_session::Session;
+use actix_web::{
+    delete,
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    get, post, web, HttpResponse, Responder,
+};
+use serde::Deserialize;
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::{
+    models::{is_foreign_key_violation, is_unique_violation, Db, Group, UserRepository},
+    routes::{current_org, unpack, validate_admin, validate_session},
+};
+
+#[derive(Deserialize, Clone, ToSchema)]
+struct GroupPayload {
+    name: String,
+    description: Option<String>,
+}
+
+#[derive(Deserialize, Clone, ToSchema)]
+struct MemberPayload {
+    username: String,
+}
+
+/// Returns the group or a 404 if the current org has no group called `name`.
+async fn find(pool: &Db, org: i64, name: &str) -> Result<Group, actix_web::Error> {
+    match Group::get(pool, org, name).await {
+        Ok(g) => Ok(g),
+        Err(sqlx::Error::RowNotFound) => Err(ErrorNotFound("Group not found.")),
+        Err(e) => {
+            error!("Failed to get group '{}'. {}", name, e);
+            Err(ErrorInternalServerError("Failed to get group."))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "groups",
+    operation_id = "get_group",
+    responses(
+        (status = 200, body = Group),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/groups/{group_name}")]
+async fn get(
+    group_name: web::Path<String>,
+    pool: web::Data<Db>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    let group = find(&pool, org, &group_name).await?;
+    Ok(HttpResponse::Ok().json(group))
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "groups",
+    operation_id = "get_groups",
+    responses(
+        (status = 200, body = [Group]),
+        (status = 401, description = "Not logged in", body = String),
+    )
+)]
+#[get("/groups")]
+async fn get_all(
+    pool: web::Data<Db>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    match Group::get_all(&pool, org).await {
+        Ok(g) => Ok(HttpResponse::Ok().json(g)),
+        Err(e) => {
+            error!("Failed to get groups. {}", e);
+            Err(ErrorInternalServerError("Failed to get groups."))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "groups",
+    operation_id = "create_group",
+    request_body = inline(GroupPayload),
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+    )
+)]
+#[post("/groups")]
+async fn create(
+    body: web::Either<web::Json<GroupPayload>, web::Form<GroupPayload>>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let body = unpack(body);
+    if body.name.trim().is_empty() {
+        return Err(ErrorBadRequest("Group name cannot be empty."));
+    }
+
+    let mut group = Group {
+        name: body.name,
+        description: body.description,
+        ..Default::default()
+    };
+
+    match group.create(&pool, org).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Created group '{}'", group.name))),
+        Err(e) => match e.to_string() {
+            _ if is_unique_violation(&e) => Err(ErrorBadRequest("Group already exists.")),
+            _ => {
+                error!("Failed to create group. {}", e);
+                Err(ErrorInternalServerError("Failed to create group."))
+            }
+        },
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "groups",
+    operation_id = "update_group",
+    request_body = inline(GroupPayload),
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[post("/groups/{group_name}")]
+async fn update(
+    group_name: web::Path<String>,
+    body: web::Either<web::Json<GroupPayload>, web::Form<GroupPayload>>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let body = unpack(body);
+    if body.name.trim().is_empty() {
+        return Err(ErrorBadRequest("Group name cannot be empty."));
+    }
+
+    let mut group = find(&pool, org, &group_name).await?;
+    group.name = body.name;
+    group.description = body.description;
+
+    match group.update(&pool).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Updated group '{}'", group.name))),
+        Err(e) => match e.to_string() {
+            _ if is_unique_violation(&e) => Err(ErrorBadRequest("Group already exists.")),
+            _ => {
+                error!("Failed to update group. {}", e);
+                Err(ErrorInternalServerError("Failed to update group."))
+            }
+        },
+    }
+}
+
+/// Deletes the group. Groups which still hold keys, including assignments in the trash, can't
+/// be deleted.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "groups",
+    operation_id = "delete_group",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Group has assignments", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[delete("/groups/{group_name}")]
+async fn delete(
+    group_name: web::Path<String>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let group = find(&pool, org, &group_name).await?;
+    match group.delete(&pool).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Deleted group '{}'", group.name))),
+        Err(e) => match e.to_string() {
+            _ if is_foreign_key_violation(&e) => Err(ErrorBadRequest(
+                "Group still has assignments. Delete or purge them first.",
+            )),
+            _ => {
+                error!("Failed to delete group. {}", e);
+                Err(ErrorInternalServerError("Failed to delete group."))
+            }
+        },
+    }
+}
+
+/// Adds a member of the current org to the group. Adding an existing member does nothing.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "groups",
+    operation_id = "add_group_member",
+    request_body = inline(MemberPayload),
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Group or user not found", body = String),
+    )
+)]
+#[post("/groups/{group_name}/members")]
+async fn add_member(
+    group_name: web::Path<String>,
+    body: web::Either<web::Json<MemberPayload>, web::Form<MemberPayload>>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let MemberPayload { username } = unpack(body);
+    let group = find(&pool, org, &group_name).await?;
+    if group.members.contains(&username) {
+        return Ok(HttpResponse::Ok().json(format!(
+            "'{}' is already in group '{}'",
+            username, group.name
+        )));
+    }
+
+    match group.add_member(&pool, &username).await {
+        Ok(0) => Err(ErrorNotFound("User not found.")),
+        Ok(_) => {
+            Ok(HttpResponse::Ok().json(format!("Added '{}' to group '{}'", username, group.name)))
+        }
+        Err(e) => {
+            error!(
+                "Failed to add '{}' to group '{}'. {}",
+                username, group.name, e
+            );
+            Err(ErrorInternalServerError("Failed to add group member."))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "groups",
+    operation_id = "remove_group_member",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Group or member not found", body = String),
+    )
+)]
+#[delete("/groups/{group_name}/members/{username}")]
+async fn remove_member(
+    path: web::Path<(String, String)>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let (group_name, username) = path.into_inner();
+    let group = find(&pool, org, &group_name).await?;
+    match group.remove_member(&pool, &username).await {
+        Ok(0) => Err(ErrorNotFound("Member not found.")),
+        Ok(_) => Ok(HttpResponse::Ok().json(format!(
+            "Removed '{}' from group '{}'",
+            username, group.name
+        ))),
+        Err(e) => {
+            error!(
+                "Failed to remove '{}' from group '{}'. {}",
+                username, group.name, e
+            );
+            Err(ErrorInternalServerError("Failed to remove group member."))
+        }
+    }
+}
+
+#[cfg(test)]
+mod group_routes_tests {
+    use actix_web::test::{call_and_read_body_json, TestRequest};
+    use serde_json::{json, Value};
+
+    use crate::db_test;
+    use crate::routes::{
+        groups,
+        testing::{db_app, login, status},
+    };
+
+    fn routes(cfg: &mut actix_web::web::ServiceConfig) {
+        cfg.service(groups::get)
+            .service(groups::get_all)
+            .service(groups::create)
+            .service(groups::update)
+            .service(groups::delete)
+            .service(groups::add_member)
+            .service(groups::remove_member);
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users"))]
+        async fn require_admin(pool: Db) {
+            let app = db_app(&pool, routes).await;
+            let admin = login(&app, "user3").await;
+            let req = TestRequest::post()
+                .uri("/groups")
+                .set_json(json!({ "name": "Janitorial" }));
+            assert_eq!(200, status(&app, req.cookie(admin)).await);
+
+            let requests = || {
+                vec![
+                    TestRequest::get().uri("/groups"),
+                    TestRequest::get().uri("/groups/Janitorial"),
+                    TestRequest::post()
+                        .uri("/groups")
+                        .set_json(json!({ "name": "Cleaners" })),
+                    TestRequest::post()
+                        .uri("/groups/Janitorial")
+                        .set_json(json!({ "name": "Cleaners" })),
+                    TestRequest::delete().uri("/groups/Janitorial"),
+                    TestRequest::post()
+                        .uri("/groups/Janitorial/members")
+                        .set_json(json!({ "username": "user1" })),
+                    TestRequest::delete().uri("/groups/Janitorial/members/user1"),
+                ]
+            };
+            for req in requests() {
+                assert_eq!(401, status(&app, req).await);
+            }
+
+            // Users can see groups but not change them
+            let user = login(&app, "user1").await;
+            let statuses = [200, 200, 401, 401, 401, 401, 401];
+            for (req, expected) in requests().into_iter().zip(statuses) {
+                assert_eq!(expected, status(&app, req.cookie(user.clone())).await);
+            }
+        }
+    }
+
+    db_test! {
+        #[sqlx::test(fixtures("users", "orgs"))]
+        async fn group_members(pool: Db) {
+            let app = db_app(&pool, routes).await;
+            let admin = login(&app, "user3").await;
+            let req = TestRequest::post()
+                .uri("/groups")
+                .set_json(json!({ "name": "Janitorial", "description": "Cleaners" }));
+            assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+            let req = TestRequest::post()
+                .uri("/groups")
+                .set_json(json!({ "name": "Janitorial" }));
+            assert_eq!(400, status(&app, req.cookie(admin.clone())).await);
+
+            let add = |username: &str| {
+                TestRequest::post()
+                    .uri("/groups/Janitorial/members")
+                    .set_json(json!({ "username": username }))
+                    .cookie(admin.clone())
+            };
+            assert_eq!(200, status(&app, add("user1")).await);
+            assert_eq!(200, status(&app, add("user2")).await);
+            assert_eq!(200, status(&app, add("user1")).await);
+            assert_eq!(404, status(&app, add("nobody")).await);
+            // Only members of the group's org can join it
+            assert_eq!(404, status(&app, add("outsider")).await);
+            let req = TestRequest::post()
+                .uri("/groups/missing/members")
+                .set_json(json!({ "username": "user1" }));
+            assert_eq!(404, status(&app, req.cookie(admin.clone())).await);
+
+            let req = TestRequest::get()
+                .uri("/groups/Janitorial")
+                .cookie(admin.clone());
+            let group: Value = call_and_read_body_json(&app, req.to_request()).await;
+            assert_eq!(json!(["user1", "user2"]), group["members"]);
+            assert_eq!("Cleaners", group["description"]);
+
+            let req = TestRequest::delete().uri("/groups/Janitorial/members/user1");
+            assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+            let req = TestRequest::delete().uri("/groups/Janitorial/members/user1");
+            assert_eq!(404, status(&app, req.cookie(admin.clone())).await);
+            let req = TestRequest::delete().uri("/groups/Janitorial/members/outsider");
+            assert_eq!(404, status(&app, req.cookie(admin.clone())).await);
+
+            let req = TestRequest::get().uri("/groups").cookie(admin.clone());
+            let groups: Vec<Value> = call_and_read_body_json(&app, req.to_request()).await;
+            assert_eq!(1, groups.len());
+            assert_eq!(json!(["user2"]), groups[0]["members"]);
+
+            let req = TestRequest::post()
+                .uri("/groups/Janitorial")
+                .set_json(json!({ "name": "Cleaners" }));
+            assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+            let req = TestRequest::delete().uri("/groups/Cleaners");
+            assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+            let req = TestRequest::get().uri("/groups/Cleaners");
+            assert_eq!(404, status(&app, req.cookie(admin)).await);
+        }
+    }
+}
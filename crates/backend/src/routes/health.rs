@@ -0,0 +1,106 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use tracing::error;
+
+use crate::{
+    metrics,
+    models::{self, Db},
+};
+
+/// The process is up and serving requests.
+#[utoipa::path(
+    tag = "health",
+    operation_id = "healthz",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+    )
+)]
+#[get("/healthz")]
+async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json("OK")
+}
+
+/// The database is reachable and has every migration applied.
+#[utoipa::path(
+    tag = "health",
+    operation_id = "readyz",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 503, description = "The database is unreachable or migrations are pending", body = String),
+    )
+)]
+#[get("/readyz")]
+async fn readyz(pool: web::Data<Db>) -> impl Responder {
+    match models::pending_migrations(&pool).await {
+        Ok(0) => HttpResponse::Ok().json("Ready"),
+        Ok(n) => HttpResponse::ServiceUnavailable().json(format!("{} migrations pending", n)),
+        Err(e) => {
+            error!("Readiness check failed. {}", e);
+            HttpResponse::ServiceUnavailable().json("Database unavailable")
+        }
+    }
+}
+
+#[utoipa::path(
+    tag = "health",
+    operation_id = "get_metrics",
+    responses(
+        (status = 200, description = "Prometheus text format", content_type = "text/plain", body = String),
+    )
+)]
+#[get("/metrics")]
+async fn get_metrics(pool: web::Data<Db>) -> impl Responder {
+    match metrics::render(&pool).await {
+        Ok(m) => HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4")
+            .body(m),
+        Err(e) => {
+            error!("Failed to gather metrics. {}", e);
+            HttpResponse::InternalServerError().json("Failed to gather metrics")
+        }
+    }
+}
+
+#[cfg(test)]
+mod health_routes_tests {
+    use crate::db_test;
+    use crate::routes;
+
+    use actix_web::{
+        test::{call_and_read_body, call_service, init_service, TestRequest},
+        web::Data,
+        App,
+    };
+
+    db_test! {
+        #[sqlx::test]
+        async fn health_checks(pool: Db) {
+            let app = init_service(
+                App::new()
+                    .app_data(Data::new(pool.clone()))
+                    .service(routes::health::healthz)
+                    .service(routes::health::readyz)
+                    .service(routes::health::get_metrics),
+            )
+            .await;
+
+            for uri in ["/healthz", "/readyz", "/metrics"] {
+                let req = TestRequest::get().uri(uri).to_request();
+                let resp = call_service(&app, req).await;
+                assert!(resp.status().is_success(), "{}", uri);
+            }
+
+            let req = TestRequest::get().uri("/metrics").to_request();
+            let body = call_and_read_body(&app, req).await;
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            assert!(body.contains("keymaster_active_keys 0"));
+            assert!(body.contains(r#"keymaster_assignments{state="open"} 0"#));
+
+            pool.execute("DELETE FROM _sqlx_migrations WHERE version = (SELECT max(version) FROM _sqlx_migrations)")
+                .await
+                .unwrap();
+            let req = TestRequest::get().uri("/readyz").to_request();
+            let resp = call_service(&app, req).await;
+            assert_eq!(503, resp.status().as_u16());
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use actix_session::Session;
+use actix_web::{error::ErrorInternalServerError, get, web, HttpResponse, Responder};
+use tracing::error;
+
+use crate::{
+    models::{Db, JobStatus, UserRepository},
+    routes::validate_site_admin,
+};
+
+/// Lists the background jobs with their last run and last error.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "jobs",
+    operation_id = "get_jobs",
+    responses(
+        (status = 200, body = [JobStatus]),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin of the first org", body = String),
+    )
+)]
+#[get("/jobs")]
+async fn get_all(
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_site_admin(&session, &users).await?;
+
+    match JobStatus::get_all(&pool).await {
+        Ok(j) => Ok(HttpResponse::Ok().json(j)),
+        Err(e) => {
+            error!("Failed to get jobs. {}", e);
+            Err(ErrorInternalServerError("Failed to get jobs."))
+        }
+    }
+}
@@ -1,28 +1,86 @@
 use actix_session::Session;
 use actix_web::error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound};
 use actix_web::{delete, get, post, web, HttpResponse, Responder};
-use log::error;
-use serde::Deserialize;
-use sqlx::PgPool;
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+use tracing::{error, info};
+use utoipa::{IntoParams, ToSchema};
 
-use crate::models::{Assignment, Key};
-use crate::routes::{unpack, validate_admin, validate_session};
+use crate::config::FinanceConfig;
+use crate::events::Events;
+use crate::labels;
+use crate::models::{
+    currency_for, is_foreign_key_violation, is_unique_violation, AssignmentQuery,
+    AssignmentRepository, Key, KeyRepository, UserRepository,
+};
+use crate::routes::{current_org, unpack, validate_admin, validate_session, validate_site_admin};
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, IntoParams)]
 struct GetAllFilter {
     active: Option<bool>,
 }
 
+/// A key update. Leaving out `slot_id` keeps the key where it is, while a null `slot_id` takes
+/// it off its hook.
+#[derive(Deserialize, Clone, ToSchema)]
+struct UpdatePayload {
+    #[serde(flatten)]
+    key: Key,
+    #[serde(default, deserialize_with = "present")]
+    slot_id: Option<Option<i64>>,
+}
+
+/// Tells a field given as null apart from one which was left out.
+fn present<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Option<i64>>, D::Error> {
+    Option::deserialize(d).map(Some)
+}
+
+/// Checks that the slot, if any, belongs to the org.
+async fn check_slot(
+    keys: &web::Data<dyn KeyRepository>,
+    org: i64,
+    slot_id: Option<i64>,
+) -> Result<(), actix_web::Error> {
+    match slot_id {
+        Some(id) => match keys.slot(org, id).await {
+            Ok(_) => Ok(()),
+            Err(sqlx::Error::RowNotFound) => Err(ErrorNotFound("Slot not found.")),
+            Err(e) => {
+                error!("Failed to get slot '{}'. {}", id, e);
+                Err(ErrorInternalServerError("Failed to get slot."))
+            }
+        },
+        None => Ok(()),
+    }
+}
+
+/// A comma separated list of key names. All active keys are used when it is left out.
+#[derive(Deserialize, Clone, IntoParams)]
+struct LabelsQuery {
+    keys: Option<String>,
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "keys",
+    operation_id = "get_key",
+    responses(
+        (status = 200, body = Key),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
 #[get("/keys/{key_name}")]
 async fn get(
     key_name: web::Path<String>,
-    pool: web::Data<PgPool>,
+    keys: web::Data<dyn KeyRepository>,
     session: Session,
 ) -> Result<impl Responder, actix_web::Error> {
     validate_session(&session)?;
+    let org = current_org(&session)?;
 
     let key_name = key_name.into_inner();
-    match Key::get(&pool, &key_name).await {
+    match keys.get(org, &key_name).await {
         Ok(k) => Ok(HttpResponse::Ok().json(k)),
         Err(e) => match e.to_string() {
             x if x.contains("no rows returned") => Err(ErrorNotFound("Key not found")),
@@ -34,23 +92,34 @@ async fn get(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "keys",
+    operation_id = "get_keys",
+    params(GetAllFilter),
+    responses(
+        (status = 200, body = [Key]),
+        (status = 401, description = "Not logged in", body = String),
+    )
+)]
 #[get("/keys")]
 async fn get_all(
-    pool: web::Data<PgPool>,
+    keys: web::Data<dyn KeyRepository>,
     session: Session,
     filter: web::Query<GetAllFilter>,
 ) -> Result<impl Responder, actix_web::Error> {
     validate_session(&session)?;
+    let org = current_org(&session)?;
 
     match filter.into_inner().active {
-        None => match Key::get_all(&pool).await {
+        None => match keys.get_all(org).await {
             Ok(k) => Ok(HttpResponse::Ok().json(k)),
             Err(e) => {
                 error!("Failed to get keys. {}", e);
                 Err(ErrorInternalServerError("Failed to get keys."))
             }
         },
-        Some(a) => match Key::get_all_active(&pool, a).await {
+        Some(a) => match keys.get_all_active(org, a).await {
             Ok(k) => Ok(HttpResponse::Ok().json(k)),
             Err(e) => {
                 error!("Failed to get keys. {}", e);
@@ -60,20 +129,45 @@ async fn get_all(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "keys",
+    operation_id = "create_key",
+    request_body = Key,
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+    )
+)]
 #[post("/keys")]
 async fn create(
     key: web::Either<web::Json<Key>, web::Form<Key>>,
-    pool: web::Data<PgPool>,
+    keys: web::Data<dyn KeyRepository>,
+    users: web::Data<dyn UserRepository>,
+    events: web::Data<dyn Events>,
+    finance: web::Data<FinanceConfig>,
     session: Session,
 ) -> Result<impl Responder, actix_web::Error> {
-    validate_admin(&session, &pool).await?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
 
-    let key = unpack(key);
+    let mut key = unpack(key);
+    check_slot(&keys, org, key.slot_id).await?;
+    key.deposit_currency =
+        currency_for(key.deposit_amount, key.deposit_currency, &finance.currency)
+            .map_err(ErrorBadRequest)?;
 
-    match key.create(&pool).await {
-        Ok(_) => Ok(HttpResponse::Ok().json(format!("Created key '{}'", key.name))),
+    match keys.create(org, &mut key).await {
+        Ok(_) => {
+            events.emit(org, "key.created", json!(key)).await;
+            Ok(HttpResponse::Ok().json(format!("Created key '{}'", key.name)))
+        }
         Err(e) => match e.to_string() {
-            x if x.contains("duplicate key") => Err(ErrorBadRequest("Key already exists.")),
+            _ if is_unique_violation(&e) => Err(ErrorBadRequest(
+                "Key already exists. It may be in the trash.",
+            )),
             _ => {
                 error!("Failed to create key. {}", e);
                 Err(ErrorInternalServerError("Failed to create key."))
@@ -82,19 +176,39 @@ async fn create(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "keys",
+    operation_id = "update_key",
+    request_body = inline(UpdatePayload),
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[allow(clippy::too_many_arguments)]
 #[post("/keys/{key_name}")]
 async fn update(
     key_name: web::Path<String>,
-    body: web::Either<web::Json<Key>, web::Form<Key>>,
-    pool: web::Data<PgPool>,
+    body: web::Either<web::Json<UpdatePayload>, web::Form<UpdatePayload>>,
+    keys: web::Data<dyn KeyRepository>,
+    assignments: web::Data<dyn AssignmentRepository>,
+    users: web::Data<dyn UserRepository>,
+    events: web::Data<dyn Events>,
+    finance: web::Data<FinanceConfig>,
     session: Session,
 ) -> Result<impl Responder, actix_web::Error> {
-    validate_admin(&session, &pool).await?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
 
-    let body = unpack(body);
+    let username = validate_session(&session)?;
+    let UpdatePayload { key: body, slot_id } = unpack(body);
     let key_name = &key_name.into_inner();
 
-    let mut key = match Key::get(&pool, key_name).await {
+    let mut key = match keys.get(org, key_name).await {
         Ok(k) => k,
         Err(e) => {
             error!("Key '{}' not found. {}", key_name, e);
@@ -102,29 +216,93 @@ async fn update(
         }
     };
 
+    // Renaming is optional, so a missing or blank name keeps the current one
+    if !body.name.trim().is_empty() {
+        key.name = body.name.trim().to_string();
+    }
     key.description = body.description;
     key.active = body.active;
+    if let Some(slot_id) = slot_id {
+        check_slot(&keys, org, slot_id).await?;
+        key.slot_id = slot_id;
+    }
+    // Lifting a restriction would let one admin hand out the key alone, so only site admins
+    // may do it and only while nobody holds the key
+    if key.restricted && !body.restricted {
+        validate_site_admin(&session, &users).await?;
+        let query = AssignmentQuery {
+            key: Some(key.name.clone()),
+            ..Default::default()
+        };
+        match assignments.get_all(org, query).await {
+            Ok(a) if a.iter().any(|a| a.date_in.is_none()) => {
+                return Err(ErrorBadRequest(
+                    "Key is assigned. Return it before lifting its restriction.",
+                ))
+            }
+            Ok(_) => info!(%username, key = %key.name, "Key restriction lifted"),
+            Err(e) => {
+                error!("Failed to get assignments of key '{}'. {}", key.name, e);
+                return Err(ErrorInternalServerError("Failed to update key."));
+            }
+        }
+    }
+    key.restricted = body.restricted;
+    key.deposit_amount = body.deposit_amount;
+    key.deposit_currency = currency_for(
+        body.deposit_amount,
+        body.deposit_currency,
+        &finance.currency,
+    )
+    .map_err(ErrorBadRequest)?;
 
-    match key.update(&pool).await {
-        Ok(_) => Ok(HttpResponse::Ok().json(format!("Updated key '{}'", key.name))),
-        Err(e) => {
-            error!("Failed to update key. {}", e);
-            Err(ErrorInternalServerError("Failed to update key."))
+    match keys.update(&key).await {
+        Ok(_) => {
+            events.emit(org, "key.updated", json!(key)).await;
+            Ok(HttpResponse::Ok().json(format!("Updated key '{}'", key.name)))
         }
+        Err(e) => match e.to_string() {
+            x if x.contains("keys_name_key") || x.contains("keys.name") => Err(ErrorBadRequest(
+                format!("Key '{}' already exists. It may be in the trash.", key.name),
+            )),
+            _ if is_unique_violation(&e) => Err(ErrorBadRequest("Slot already holds a key.")),
+            _ if is_foreign_key_violation(&e) => Err(ErrorBadRequest("Slot does not exist.")),
+            _ => {
+                error!("Failed to update key. {}", e);
+                Err(ErrorInternalServerError("Failed to update key."))
+            }
+        },
     }
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "keys",
+    operation_id = "delete_key",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
 #[delete("/keys/{key_name}")]
 async fn delete(
     key_name: web::Path<String>,
-    pool: web::Data<PgPool>,
+    keys: web::Data<dyn KeyRepository>,
+    users: web::Data<dyn UserRepository>,
+    events: web::Data<dyn Events>,
     session: Session,
 ) -> Result<impl Responder, actix_web::Error> {
-    validate_admin(&session, &pool).await?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
 
-    match Key::get(&pool, &key_name.into_inner()).await {
-        Ok(k) => match k.delete(&pool).await {
-            Ok(_) => Ok(HttpResponse::Ok().json(format!("Deleted key '{}'", k.name))),
+    match keys.get(org, &key_name.into_inner()).await {
+        Ok(k) => match keys.delete(&k).await {
+            Ok(_) => {
+                events.emit(org, "key.deleted", json!(k)).await;
+                Ok(HttpResponse::Ok().json(format!("Moved key '{}' to the trash", k.name)))
+            }
             Err(e) => {
                 error!("Failed to delete key. {}", e);
                 Err(ErrorInternalServerError("Failed to delete key."))
@@ -134,16 +312,27 @@ async fn delete(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "keys",
+    operation_id = "get_key_assignments",
+    responses(
+        (status = 200, body = [Assignment]),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
 #[get("/keys/{key_name}/assignments")]
 async fn get_assignments(
     key_name: web::Path<String>,
-    pool: web::Data<PgPool>,
+    assignments: web::Data<dyn AssignmentRepository>,
     session: Session,
 ) -> Result<impl Responder, actix_web::Error> {
     validate_session(&session)?;
+    let org = current_org(&session)?;
 
     let key_name = key_name.into_inner();
-    match Assignment::get_assignments_by_key(&pool, &key_name).await {
+    match assignments.get_by_key(org, &key_name).await {
         Ok(k) => Ok(HttpResponse::Ok().json(k)),
         Err(e) => match e.to_string() {
             x if x.contains("no rows returned") => Err(ErrorNotFound("Key not found")),
@@ -154,3 +343,374 @@ async fn get_assignments(
         },
     }
 }
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "keys",
+    operation_id = "get_key_location",
+    responses(
+        (status = 200, body = KeyLocation),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/keys/{key_name}/location")]
+async fn get_location(
+    key_name: web::Path<String>,
+    keys: web::Data<dyn KeyRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    let key_name = key_name.into_inner();
+    match keys.location(org, &key_name).await {
+        Ok(l) => Ok(HttpResponse::Ok().json(l)),
+        Err(e) => {
+            error!("Failed to get location of key '{}'. {}", key_name, e);
+            Err(ErrorInternalServerError("Failed to get key location."))
+        }
+    }
+}
+
+/// Renders a label for the key with a QR code of its name.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "keys",
+    operation_id = "get_key_label",
+    responses(
+        (status = 200, description = "SVG image", content_type = "image/svg+xml", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/keys/{key_name}/label.svg")]
+async fn get_label(
+    key_name: web::Path<String>,
+    keys: web::Data<dyn KeyRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    let key_name = key_name.into_inner();
+    if let Err(e) = keys.get(org, &key_name).await {
+        return match e.to_string() {
+            x if x.contains("no rows returned") => Err(ErrorNotFound("Key not found")),
+            _ => {
+                error!("Failed to get key '{}'. {}", key_name, e);
+                Err(ErrorInternalServerError("Failed to get key."))
+            }
+        };
+    }
+
+    match labels::key_label_svg(&key_name) {
+        Ok(svg) => Ok(HttpResponse::Ok().content_type("image/svg+xml").body(svg)),
+        Err(e) => {
+            error!("Failed to render label for key '{}'. {}", key_name, e);
+            Err(ErrorInternalServerError("Failed to render label."))
+        }
+    }
+}
+
+/// Renders a printable sheet of labels for a set of keys.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "keys",
+    operation_id = "get_key_labels",
+    params(LabelsQuery),
+    responses(
+        (status = 200, description = "PDF document", content_type = "application/pdf", body = String),
+        (status = 401, description = "Not logged in", body = String),
+    )
+)]
+#[get("/labels.pdf")]
+async fn get_labels(
+    keys: web::Data<dyn KeyRepository>,
+    session: Session,
+    query: web::Query<LabelsQuery>,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    let names = match query.into_inner().keys {
+        Some(k) => {
+            let all = match keys.get_all(org).await {
+                Ok(k) => k,
+                Err(e) => {
+                    error!("Failed to get keys. {}", e);
+                    return Err(ErrorInternalServerError("Failed to get keys."));
+                }
+            };
+            let mut names = Vec::new();
+            for name in k.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                if !all.iter().any(|key| key.name == name) {
+                    return Err(ErrorNotFound(format!("Key '{}' not found.", name)));
+                }
+                names.push(name.to_string());
+            }
+            names
+        }
+        None => match keys.get_all_active(org, true).await {
+            Ok(k) => k.into_iter().map(|k| k.name).collect(),
+            Err(e) => {
+                error!("Failed to get keys. {}", e);
+                return Err(ErrorInternalServerError("Failed to get keys."));
+            }
+        },
+    };
+
+    match labels::label_sheet_pdf(&names) {
+        Ok(pdf) => Ok(HttpResponse::Ok()
+            .content_type("application/pdf")
+            .insert_header((
+                "Content-Disposition",
+                r#"inline; filename="keymaster-labels.pdf""#,
+            ))
+            .body(pdf)),
+        Err(e) => {
+            error!("Failed to render label sheet. {}", e);
+            Err(ErrorInternalServerError("Failed to render labels."))
+        }
+    }
+}
+
+#[cfg(test)]
+mod key_routes_tests {
+    use std::sync::Arc;
+
+    use actix_web::test::{call_and_read_body_json, call_service, TestRequest};
+    use chrono::Local;
+    use serde_json::{json, Value};
+
+    use crate::models::{AssignmentRepository, KeyRepository, MemoryRepository, DEFAULT_ORG};
+    use crate::routes::{
+        assignments, keys, orgs,
+        testing::{app, login, status},
+    };
+
+    fn routes(cfg: &mut actix_web::web::ServiceConfig) {
+        cfg.service(keys::get_labels)
+            .service(keys::get)
+            .service(keys::get_all)
+            .service(keys::create)
+            .service(keys::update)
+            .service(keys::delete)
+            .service(keys::get_assignments)
+            .service(keys::get_location)
+            .service(keys::get_label)
+            .service(orgs::switch)
+            .service(assignments::create);
+    }
+
+    #[actix_web::test]
+    async fn require_login() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_key("key1");
+        let app = app(&repo, routes).await;
+
+        for uri in [
+            "/keys",
+            "/keys/key1",
+            "/keys/key1/assignments",
+            "/keys/key1/location",
+            "/keys/key1/label.svg",
+            "/labels.pdf",
+        ] {
+            assert_eq!(
+                401,
+                status(&app, TestRequest::get().uri(uri)).await,
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn changes_require_admin() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("user1", false);
+        repo.add_key("key1");
+        let app = app(&repo, routes).await;
+        let user = login(&app, "user1").await;
+
+        let key = json!({ "name": "key2" });
+        for req in [
+            TestRequest::post().uri("/keys").set_json(&key),
+            TestRequest::post().uri("/keys/key1").set_json(&key),
+            TestRequest::delete().uri("/keys/key1"),
+        ] {
+            assert_eq!(401, status(&app, req.cookie(user.clone())).await);
+        }
+        assert_eq!(vec!["key1"], names(&repo).await);
+        assert!(repo.events().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn create_update_and_delete() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("admin", true);
+        let app = app(&repo, routes).await;
+        let admin = login(&app, "admin").await;
+
+        let req = TestRequest::post()
+            .uri("/keys")
+            .set_json(json!({ "name": "key1", "deposit_amount": 500, "deposit_currency": "eur" }));
+        assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::post()
+            .uri("/keys")
+            .set_json(json!({ "name": "key1" }));
+        assert_eq!(400, status(&app, req.cookie(admin.clone())).await);
+
+        let req = TestRequest::get().uri("/keys/key1").cookie(admin.clone());
+        let key: Value = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!("EUR", key["deposit_currency"]);
+        let req = TestRequest::get().uri("/keys/missing");
+        assert_eq!(404, status(&app, req.cookie(admin.clone())).await);
+
+        let req = TestRequest::post()
+            .uri("/keys/key1")
+            .set_json(json!({ "name": "key2", "active": false }));
+        assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::get()
+            .uri("/keys?active=false")
+            .cookie(admin.clone());
+        let inactive: Vec<Value> = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!("key2", inactive[0]["name"]);
+        let req = TestRequest::post()
+            .uri("/keys/missing")
+            .set_json(json!({ "name": "key3" }));
+        assert_eq!(404, status(&app, req.cookie(admin.clone())).await);
+
+        repo.add_key("key3");
+        let req = TestRequest::post()
+            .uri("/keys/key3")
+            .set_json(json!({ "name": "key2" }));
+        assert_eq!(400, status(&app, req.cookie(admin.clone())).await);
+
+        let req = TestRequest::delete().uri("/keys/key2");
+        assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::delete().uri("/keys/key2");
+        assert_eq!(404, status(&app, req.cookie(admin.clone())).await);
+        assert_eq!(vec!["key3"], names(&repo).await);
+
+        // Names of keys in the trash stay taken
+        let req = TestRequest::post()
+            .uri("/keys")
+            .set_json(json!({ "name": "key2" }));
+        assert_eq!(400, status(&app, req.cookie(admin)).await);
+
+        assert_eq!(
+            vec!["key.created", "key.updated", "key.deleted"],
+            repo.events()
+        );
+    }
+
+    #[actix_web::test]
+    async fn holders_location_and_labels() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("user1", false);
+        repo.add_key("key1");
+        let mut key2 = repo.add_key("key2");
+        let slot = repo.add_slot("Office", 3);
+        key2.slot_id = Some(slot.id);
+        KeyRepository::update(&*repo, &key2).await.unwrap();
+        repo.add_assignment("user1", "key1", Local::today().naive_local());
+        let app = app(&repo, routes).await;
+        let user = login(&app, "user1").await;
+
+        let req = TestRequest::get()
+            .uri("/keys/key1/assignments")
+            .cookie(user.clone());
+        let assignments: Vec<Value> = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!("user1", assignments[0]["user"]);
+
+        let req = TestRequest::get()
+            .uri("/keys/key1/location")
+            .cookie(user.clone());
+        let location: Value = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!(json!({ "type": "user", "username": "user1" }), location);
+        let req = TestRequest::get()
+            .uri("/keys/key2/location")
+            .cookie(user.clone());
+        let location: Value = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!(3, location["number"]);
+
+        let req = TestRequest::get().uri("/keys/key1/label.svg");
+        assert_eq!(200, status(&app, req.cookie(user.clone())).await);
+        let req = TestRequest::get().uri("/keys/missing/label.svg");
+        assert_eq!(404, status(&app, req.cookie(user.clone())).await);
+        let req = TestRequest::get().uri("/labels.pdf?keys=key1,key2");
+        assert_eq!(200, status(&app, req.cookie(user.clone())).await);
+        let req = TestRequest::get().uri("/labels.pdf?keys=key1,missing");
+        assert_eq!(404, status(&app, req.cookie(user)).await);
+    }
+
+    async fn names(repo: &MemoryRepository) -> Vec<String> {
+        let keys = KeyRepository::get_all(repo, DEFAULT_ORG).await.unwrap();
+        keys.into_iter().map(|k| k.name).collect()
+    }
+
+    #[actix_web::test]
+    async fn only_site_admins_lift_restrictions() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("admin", true);
+        repo.add_user("admin1", false);
+        repo.add_user("user1", false);
+        let acme = repo.add_org("Acme");
+        repo.add_member(acme.id, "admin", true);
+        repo.add_member(acme.id, "admin1", true);
+        repo.add_member(acme.id, "user1", false);
+        for name in ["master", "spare"] {
+            let mut key = repo.add_key_to(acme.id, name);
+            key.restricted = true;
+            KeyRepository::update(&*repo, &key).await.unwrap();
+        }
+        let app = app(&repo, routes).await;
+        let switch = |user: &'static str| {
+            let app = &app;
+            async move {
+                let req = TestRequest::post()
+                    .uri(&format!("/orgs/{}/switch", acme.id))
+                    .cookie(login(app, user).await);
+                let resp = call_service(app, req.to_request()).await;
+                resp.response().cookies().next().unwrap().into_owned()
+            }
+        };
+        let lift = |name: &str| {
+            TestRequest::post()
+                .uri(&format!("/keys/{}", name))
+                .set_json(json!({ "name": name, "restricted": false }))
+        };
+
+        // An org admin can't lift the restriction to hand out the key alone
+        let admin1 = switch("admin1").await;
+        assert_eq!(
+            401,
+            status(&app, lift("master").cookie(admin1.clone())).await
+        );
+        let today = Local::today().naive_local();
+        let req = TestRequest::post()
+            .uri("/assignments")
+            .set_json(json!([{ "user": "user1", "key": "master", "date_out": today }]));
+        assert_eq!(200, status(&app, req.cookie(admin1)).await);
+        let assignments = AssignmentRepository::get_all(&*repo, acme.id, Default::default())
+            .await
+            .unwrap();
+        assert!(assignments[0].pending);
+
+        // Site admins can, but not while the key is out
+        let admin = switch("admin").await;
+        assert_eq!(
+            400,
+            status(&app, lift("master").cookie(admin.clone())).await
+        );
+        assert_eq!(200, status(&app, lift("spare").cookie(admin)).await);
+        assert!(
+            !KeyRepository::get(&*repo, acme.id, "spare")
+                .await
+                .unwrap()
+                .restricted
+        );
+    }
+}
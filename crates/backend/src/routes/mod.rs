@@ -4,41 +4,92 @@ use actix_web::{
     get, post, web, Either, HttpResponse, Responder,
 };
 use serde::Serialize;
-use sqlx::PgPool;
+use std::sync::Arc;
+use utoipa::ToSchema;
 
 pub mod assignments;
+pub mod audits;
+pub mod backup;
+pub mod cabinets;
+pub mod docs;
+pub mod finance;
+pub mod groups;
+pub mod health;
+pub mod jobs;
 pub mod keys;
+pub mod orgs;
+pub mod requests;
+pub mod trash;
 pub mod users;
+pub mod webhooks;
 
-use crate::models::{Credentials, User};
+use crate::{
+    events::Events,
+    metrics,
+    models::{AssignmentRepository, Credentials, KeyRepository, Membership, UserRepository},
+};
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 struct SessionInfo {
     username: Option<String>,
     is_auth: bool,
     is_admin: bool,
+    org_id: Option<i64>,   // The org the session is working in
+    orgs: Vec<Membership>, // Every org the user can switch to
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "session",
+    operation_id = "login",
+    request_body = Credentials,
+    responses(
+        (status = 200, body = inline(SessionInfo)),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Authentication failed or the user belongs to no org", body = String),
+    )
+)]
 #[post("/login")]
 async fn login(
-    pool: web::Data<PgPool>,
+    users: web::Data<dyn UserRepository>,
     session: Session,
     creds: web::Either<web::Json<Credentials>, web::Form<Credentials>>,
 ) -> impl Responder {
     let creds = unpack(creds);
 
-    match User::authenticate(&pool, creds).await {
-        Ok(user) => {
-            session
-                .insert("username", user.username)
-                .expect("Unable to insert new session");
-            let si = get_session_info(session, pool).await;
-            HttpResponse::Ok().json(si)
-        }
-        Err(_) => HttpResponse::Unauthorized().json("Authentication failed"),
-    }
+    let result = users.authenticate(creds).await;
+    metrics::record_login(result.is_ok());
+
+    let user = match result {
+        Ok(user) => user,
+        Err(_) => return HttpResponse::Unauthorized().json("Authentication failed"),
+    };
+
+    // The session starts in the user's oldest org
+    let org = match users.memberships(&user.username).await.as_deref() {
+        Ok([first, ..]) => first.org_id,
+        _ => return HttpResponse::Unauthorized().json("Authentication failed"),
+    };
+
+    session
+        .insert("username", user.username)
+        .expect("Unable to insert new session");
+    session
+        .insert("org_id", org)
+        .expect("Unable to insert new session");
+    let si = get_session_info(session, users).await;
+    HttpResponse::Ok().json(si)
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "session",
+    operation_id = "logout",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+    )
+)]
 #[post("/logout")]
 async fn logout(session: Session) -> Result<impl Responder, actix_web::Error> {
     if validate_session(&session).is_err() {
@@ -50,9 +101,17 @@ async fn logout(session: Session) -> Result<impl Responder, actix_web::Error> {
     Ok(HttpResponse::Ok().json("Logged out"))
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "session",
+    operation_id = "get_session",
+    responses(
+        (status = 200, body = inline(SessionInfo)),
+    )
+)]
 #[get("/session")]
-async fn session_info(session: Session, pool: web::Data<PgPool>) -> impl Responder {
-    let si = get_session_info(session, pool).await;
+async fn session_info(session: Session, users: web::Data<dyn UserRepository>) -> impl Responder {
+    let si = get_session_info(session, users).await;
 
     HttpResponse::Ok().json(si)
 }
@@ -69,12 +128,21 @@ pub fn validate_session(session: &Session) -> Result<String, actix_web::Error> {
     }
 }
 
+/// Returns the org the session is working in. Every route only sees that org's rows.
+pub fn current_org(session: &Session) -> Result<i64, actix_web::Error> {
+    let org: Option<i64> = session.get("org_id").unwrap_or_default();
+    org.ok_or_else(|| ErrorUnauthorized("Unauthorized"))
+}
+
+/// Checks that the user is an admin of the session's current org.
 pub async fn validate_admin(
     session: &Session,
-    pool: &web::Data<PgPool>,
+    users: &web::Data<dyn UserRepository>,
 ) -> Result<(), actix_web::Error> {
     let username = validate_session(session)?;
-    let user = User::get(pool, &username)
+    let org = current_org(session)?;
+    let user = users
+        .get(org, &username)
         .await
         .map_err(actix_web::error::ErrorUnauthorized)?;
 
@@ -85,16 +153,57 @@ pub async fn validate_admin(
     }
 }
 
-async fn get_session_info(session: Session, pool: web::Data<PgPool>) -> SessionInfo {
+/// Checks that the user is an admin of the first org, whose admins look after the deployment
+/// as a whole: its jobs, backups and the other orgs.
+pub async fn validate_site_admin(
+    session: &Session,
+    users: &web::Data<dyn UserRepository>,
+) -> Result<(), actix_web::Error> {
+    let username = validate_session(session)?;
+
+    match users.is_site_admin(&username).await {
+        Ok(true) => Ok(()),
+        _ => Err(ErrorUnauthorized("Unauthorized")),
+    }
+}
+
+/// Checks that the session may change the login details of `username`: their username, email,
+/// password and whether they can log in. Users share these across every org they belong to,
+/// so an org's admins may only change them for users who are in no other org. Site admins
+/// and the user themselves may always change them.
+pub async fn validate_login_change(
+    session: &Session,
+    users: &web::Data<dyn UserRepository>,
+    username: &str,
+) -> Result<(), actix_web::Error> {
+    let session_user = validate_session(session)?;
+    let org = current_org(session)?;
+    if session_user == username {
+        return Ok(());
+    }
+
+    let memberships = users
+        .memberships(username)
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    if memberships.iter().all(|m| m.org_id == org) {
+        return Ok(());
+    }
+    validate_site_admin(session, users).await
+}
+
+async fn get_session_info(session: Session, users: web::Data<dyn UserRepository>) -> SessionInfo {
     let mut username = None;
     let mut is_auth = false;
     let mut is_admin = false;
+    let mut orgs = Vec::new();
 
     if let Ok(u) = validate_session(&session) {
+        orgs = users.memberships(&u).await.unwrap_or_default();
         username = Some(u);
         is_auth = true;
     }
-    if validate_admin(&session, &pool).await.is_ok() {
+    if validate_admin(&session, &users).await.is_ok() {
         is_admin = true;
     }
 
@@ -102,6 +211,25 @@ async fn get_session_info(session: Session, pool: web::Data<PgPool>) -> SessionI
         username,
         is_auth,
         is_admin,
+        org_id: current_org(&session).ok(),
+        orgs,
+    }
+}
+
+/// Registers `repo` as the store of keys, users and assignments and `events` as the sink for
+/// events. The routes get each as a `web::Data<dyn Trait>`.
+pub fn repositories<R>(
+    repo: Arc<R>,
+    events: Arc<dyn Events>,
+) -> impl FnOnce(&mut web::ServiceConfig)
+where
+    R: KeyRepository + UserRepository + AssignmentRepository + 'static,
+{
+    move |cfg| {
+        cfg.app_data(web::Data::<dyn KeyRepository>::from(repo.clone() as Arc<_>))
+            .app_data(web::Data::<dyn UserRepository>::from(repo.clone() as Arc<_>))
+            .app_data(web::Data::<dyn AssignmentRepository>::from(repo as Arc<_>))
+            .app_data(web::Data::<dyn Events>::from(events));
     }
 }
 
@@ -113,66 +241,223 @@ pub fn unpack<T: Clone>(e: web::Either<web::Json<T>, web::Form<T>>) -> T {
     }
 }
 
+/// Quotes a CSV field if it contains a delimiter, quote or newline
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Helpers for testing routes against a `MemoryRepository` instead of a database.
 #[cfg(test)]
-mod routes_tests {
-    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
-    use actix_web::test as actix_test;
+pub(crate) mod testing {
+    use std::sync::Arc;
+
+    use actix_http::Request;
+    use actix_session::{storage::CookieSessionStore, Session, SessionMiddleware};
     use actix_web::{
-        cookie::{Key, SameSite},
-        web::Data,
-        App,
+        body::MessageBody,
+        cookie::{Cookie, Key},
+        dev::{Service, ServiceResponse},
+        post, test, web, App, HttpResponse,
     };
-    use sqlx::PgPool;
 
-    use crate::routes;
+    use crate::{
+        config::{AssignmentsConfig, FinanceConfig},
+        events::DbEvents,
+        models::{Db, MemoryRepository, DEFAULT_ORG},
+    };
+
+    /// Starts a session for the user in the default org without checking their password, which
+    /// is slow to hash.
+    #[post("/test/login/{username}")]
+    async fn login_as(session: Session, username: web::Path<String>) -> HttpResponse {
+        session.insert("username", username.into_inner()).unwrap();
+        session.insert("org_id", DEFAULT_ORG).unwrap();
+        HttpResponse::Ok().finish()
+    }
 
-    #[sqlx::test(fixtures("users"))]
-    async fn test_login(pool: PgPool) {
-        let secret_key = Key::generate();
+    /// Builds an app serving `routes` with `repo` behind every repository.
+    pub async fn app(
+        repo: &Arc<MemoryRepository>,
+        routes: impl FnOnce(&mut web::ServiceConfig),
+    ) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>
+    {
+        test::init_service(
+            App::new()
+                .wrap(
+                    SessionMiddleware::builder(CookieSessionStore::default(), Key::generate())
+                        .cookie_secure(false)
+                        .build(),
+                )
+                .app_data(web::Data::new(AssignmentsConfig::default()))
+                .app_data(web::Data::new(FinanceConfig::default()))
+                .configure(super::repositories(repo.clone(), repo.clone()))
+                .service(login_as)
+                .configure(routes),
+        )
+        .await
+    }
 
-        let app = actix_test::init_service(
+    /// Builds an app serving `routes` against the database, for routes which use the pool
+    /// directly.
+    pub async fn db_app(
+        pool: &Db,
+        routes: impl FnOnce(&mut web::ServiceConfig),
+    ) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>
+    {
+        test::init_service(
             App::new()
                 .wrap(
-                    SessionMiddleware::builder(CookieSessionStore::default(), secret_key.clone())
+                    SessionMiddleware::builder(CookieSessionStore::default(), Key::generate())
                         .cookie_secure(false)
-                        .cookie_http_only(false)
-                        .cookie_same_site(SameSite::Strict)
                         .build(),
                 )
-                .app_data(Data::new(pool.clone()))
-                .service(routes::login),
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(AssignmentsConfig::default()))
+                .app_data(web::Data::new(FinanceConfig::default()))
+                .configure(super::repositories(
+                    Arc::new(pool.clone()),
+                    Arc::new(DbEvents::new(pool.clone(), false)),
+                ))
+                .service(login_as)
+                .configure(routes),
         )
+        .await
+    }
+
+    /// Returns the session cookie of `username` logged in to `app`.
+    pub async fn login<S, B>(app: &S, username: &str) -> Cookie<'static>
+    where
+        S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    {
+        let req = test::TestRequest::post()
+            .uri(&format!("/test/login/{}", username))
+            .to_request();
+        let resp = test::call_service(app, req).await;
+        let cookie = resp.response().cookies().next().expect("No session cookie");
+        cookie.into_owned()
+    }
+
+    /// Sends the request and returns the response's status code.
+    pub async fn status<S, B>(app: &S, req: test::TestRequest) -> u16
+    where
+        S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    {
+        test::call_service(app, req.to_request())
+            .await
+            .status()
+            .as_u16()
+    }
+}
+
+#[cfg(test)]
+mod routes_tests {
+    use std::sync::Arc;
+
+    use crate::db_test;
+    use actix_web::test as actix_test;
+
+    use super::testing::{app, login, status};
+    use crate::config::Config;
+    use crate::models::{Credentials, MemoryRepository, UserRepository};
+    use crate::routes;
+    use crate::{build_app, AppState};
+
+    db_test! {
+        #[sqlx::test(fixtures("users"))]
+        async fn test_login(pool: Db) {
+            let config = Config::default();
+            let app = actix_test::init_service(build_app(&config, AppState::new(&config, pool))).await;
+
+            // No request body
+            let req = actix_test::TestRequest::post().uri("/api/login").to_request();
+            let resp = actix_test::call_service(&app, req).await;
+
+            assert!(resp.status().is_client_error());
+
+            // User not allowed to log in
+            let creds = crate::models::Credentials {
+                username: "user1".to_string(),
+                password: "pass1".to_string(),
+            };
+            let req = actix_test::TestRequest::post()
+                .uri("/api/login")
+                .set_json(creds)
+                .to_request();
+            let resp = actix_test::call_service(&app, req).await;
+
+            assert!(resp.status().is_client_error());
+
+            // User can log in
+            let creds = crate::models::Credentials {
+                username: "user2".to_string(),
+                password: "abc123".to_string(),
+            };
+            let req = actix_test::TestRequest::post()
+                .uri("/api/login")
+                .set_json(creds)
+                .to_request();
+            let resp = actix_test::call_service(&app, req).await;
+
+            assert!(resp.status().is_success());
+
+            // The session cookie works on the rest of the API, trailing slash or not
+            let cookie = resp.response().cookies().next().unwrap().into_owned();
+            let req = actix_test::TestRequest::get()
+                .uri("/api/session/")
+                .cookie(cookie)
+                .to_request();
+            let body: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+
+            assert_eq!("user2", body["username"]);
+        }
+    }
+
+    #[actix_web::test]
+    async fn session_without_database() {
+        let repo = Arc::new(MemoryRepository::new());
+        let mut admin = repo.add_user("admin", true);
+        repo.set_password(&mut admin, "password1").await.unwrap();
+        let app = app(&repo, |cfg| {
+            cfg.service(routes::login)
+                .service(routes::logout)
+                .service(routes::session_info);
+        })
         .await;
-        // No request body
-        let req = actix_test::TestRequest::post().uri("/login").to_request();
-        let resp = actix_test::call_service(&app, req).await;
 
-        assert!(resp.status().is_client_error());
+        let req = actix_test::TestRequest::get().uri("/session");
+        let body: serde_json::Value =
+            actix_test::call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!(false, body["is_auth"]);
 
-        // User not allowed to log in
-        let creds = crate::models::Credentials {
-            username: "user1".to_string(),
-            password: "pass1".to_string(),
+        let mut creds = Credentials {
+            username: "admin".into(),
+            password: "wrong".into(),
         };
-        let req = actix_test::TestRequest::post()
-            .uri("/login")
-            .set_json(creds)
-            .to_request();
-        let resp = actix_test::call_service(&app, req).await;
+        let req = actix_test::TestRequest::post().uri("/login");
+        assert_eq!(401, status(&app, req.set_json(&creds)).await);
 
-        assert!(resp.status().is_client_error());
+        creds.password = "password1".into();
+        let req = actix_test::TestRequest::post().uri("/login");
+        let body: serde_json::Value =
+            actix_test::call_and_read_body_json(&app, req.set_json(&creds).to_request()).await;
+        assert_eq!(true, body["is_admin"]);
 
-        // User can log in
-        let creds = crate::models::Credentials {
-            username: "user2".to_string(),
-            password: "abc123".to_string(),
-        };
-        let req = actix_test::TestRequest::post()
-            .uri("/login")
-            .set_json(creds)
-            .to_request();
-        let resp = actix_test::call_service(&app, req).await;
+        let user = login(&app, "admin").await;
+        let req = actix_test::TestRequest::get()
+            .uri("/session")
+            .cookie(user.clone());
+        let body: serde_json::Value =
+            actix_test::call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!("admin", body["username"]);
+        assert_eq!(true, body["is_admin"]);
 
-        assert!(resp.status().is_success());
+        let req = actix_test::TestRequest::post().uri("/logout");
+        assert_eq!(401, status(&app, req).await);
+        let req = actix_test::TestRequest::post().uri("/logout").cookie(user);
+        assert_eq!(200, status(&app, req).await);
     }
 }
@@ -0,0 +1,341 @@
+use actix_session::Session;
+use actix_web::{
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized},
+    get, post, web, HttpResponse, Responder,
+};
+use serde::Deserialize;
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::{
+    models::{is_unique_violation, Db, Org, UserRepository},
+    routes::{unpack, validate_session, validate_site_admin},
+};
+
+#[derive(Deserialize, Clone, ToSchema)]
+struct MemberPayload {
+    username: String,
+    #[serde(default)]
+    admin: bool,
+}
+
+/// Lists the orgs the user belongs to along with their role in each.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "orgs",
+    operation_id = "get_orgs",
+    responses(
+        (status = 200, body = [Membership]),
+        (status = 401, description = "Not logged in", body = String),
+    )
+)]
+#[get("/orgs")]
+async fn get_all(
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+
+    match users.memberships(&username).await {
+        Ok(m) => Ok(HttpResponse::Ok().json(m)),
+        Err(e) => {
+            error!("Failed to get orgs of '{}'. {}", username, e);
+            Err(ErrorInternalServerError("Failed to get orgs."))
+        }
+    }
+}
+
+/// Creates an org with the user who created it as its first admin. Only admins of the first
+/// org can create orgs.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "orgs",
+    operation_id = "create_org",
+    request_body = Org,
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin of the first org", body = String),
+    )
+)]
+#[post("/orgs")]
+async fn create(
+    body: web::Either<web::Json<Org>, web::Form<Org>>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+    validate_site_admin(&session, &users).await?;
+
+    let mut org = unpack(body);
+    org.name = org.name.trim().to_string();
+    if org.name.is_empty() {
+        return Err(ErrorBadRequest("Org name is required."));
+    }
+
+    match org.create(&pool).await {
+        Ok(_) => {
+            if let Err(e) = Org::add_member(&pool, org.id, &username, true).await {
+                error!("Failed to add '{}' to org '{}'. {}", username, org.name, e);
+            }
+            Ok(HttpResponse::Ok().json(format!("Created org '{}'", org.name)))
+        }
+        Err(e) => match e.to_string() {
+            _ if is_unique_violation(&e) => Err(ErrorBadRequest("Org already exists.")),
+            _ => {
+                error!("Failed to create org. {}", e);
+                Err(ErrorInternalServerError("Failed to create org."))
+            }
+        },
+    }
+}
+
+/// Makes the org the session's current org. Every other route only sees the current org.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "orgs",
+    operation_id = "switch_org",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not a member of the org", body = String),
+    )
+)]
+#[post("/orgs/{org_id}/switch")]
+async fn switch(
+    org_id: web::Path<i64>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+    let org_id = org_id.into_inner();
+
+    let memberships = match users.memberships(&username).await {
+        Ok(m) => m,
+        Err(e) => {
+            error!("Failed to get orgs of '{}'. {}", username, e);
+            return Err(ErrorInternalServerError("Failed to switch org."));
+        }
+    };
+    let membership = match memberships.into_iter().find(|m| m.org_id == org_id) {
+        Some(m) => m,
+        None => return Err(ErrorNotFound("Org not found.")),
+    };
+
+    session
+        .insert("org_id", org_id)
+        .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(format!("Switched to org '{}'", membership.org)))
+}
+
+/// Adds an existing user to the org or changes their role in it. Only the org's admins can
+/// manage its members, and only site admins can bring in users from outside the org, since
+/// users share their login across orgs.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "orgs",
+    operation_id = "add_org_member",
+    request_body = inline(MemberPayload),
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin of the org", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[post("/orgs/{org_id}/members")]
+async fn add_member(
+    org_id: web::Path<i64>,
+    body: web::Either<web::Json<MemberPayload>, web::Form<MemberPayload>>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+    let org_id = org_id.into_inner();
+    match users.get(org_id, &username).await {
+        Ok(u) if u.admin => (),
+        _ => return Err(ErrorUnauthorized("Unauthorized")),
+    }
+
+    let body = unpack(body);
+    if users.get(org_id, &body.username).await.is_err() {
+        validate_site_admin(&session, &users).await?;
+    }
+
+    match users.add_member(org_id, &body.username, body.admin).await {
+        Ok(0) => Err(ErrorNotFound("User not found.")),
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Added user '{}' to the org", body.username))),
+        Err(e) => {
+            error!("Failed to add member to org '{}'. {}", org_id, e);
+            Err(ErrorInternalServerError("Failed to add member."))
+        }
+    }
+}
+
+#[cfg(test)]
+mod org_routes_tests {
+    use std::sync::Arc;
+
+    use actix_web::test::{call_and_read_body_json, call_service, TestRequest};
+    use serde_json::{json, Value};
+
+    use crate::models::{KeyRepository, MemoryRepository};
+    use crate::routes::{
+        keys, orgs,
+        testing::{app, login, status},
+        users,
+    };
+
+    fn routes(cfg: &mut actix_web::web::ServiceConfig) {
+        cfg.service(orgs::get_all)
+            .service(orgs::switch)
+            .service(orgs::add_member)
+            .service(keys::get)
+            .service(keys::get_all)
+            .service(keys::create)
+            .service(keys::update)
+            .service(users::update)
+            .service(users::set_password);
+    }
+
+    #[actix_web::test]
+    async fn switching_orgs() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("user1", true);
+        repo.add_user("user2", false);
+        let acme = repo.add_org("Acme");
+        repo.add_member(acme.id, "user1", false);
+        repo.add_key("key1");
+        repo.add_key_to(acme.id, "key1");
+        repo.add_key_to(acme.id, "key2");
+        let app = app(&repo, routes).await;
+        let user = login(&app, "user1").await;
+
+        let req = TestRequest::get().uri("/orgs").cookie(user.clone());
+        let orgs: Vec<Value> = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!(vec!["Default", "Acme"], names(&orgs, "org"));
+
+        let req = TestRequest::get().uri("/keys").cookie(user.clone());
+        let keys: Vec<Value> = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!(vec!["key1"], names(&keys, "name"));
+
+        // Users can only switch to orgs they belong to
+        let req = TestRequest::post().uri(&format!("/orgs/{}/switch", acme.id));
+        assert_eq!(
+            404,
+            status(&app, req.cookie(login(&app, "user2").await)).await
+        );
+
+        let req = TestRequest::post()
+            .uri(&format!("/orgs/{}/switch", acme.id))
+            .cookie(user);
+        let resp = call_service(&app, req.to_request()).await;
+        assert_eq!(200, resp.status().as_u16());
+        let user = resp.response().cookies().next().unwrap().into_owned();
+
+        let req = TestRequest::get().uri("/keys").cookie(user.clone());
+        let keys: Vec<Value> = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!(vec!["key1", "key2"], names(&keys, "name"));
+
+        // Roles are per org
+        let req = TestRequest::post()
+            .uri("/keys")
+            .set_json(json!({ "name": "key3" }));
+        assert_eq!(401, status(&app, req.cookie(user)).await);
+    }
+
+    #[actix_web::test]
+    async fn org_admins_cannot_take_over_other_users() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("admin", true);
+        repo.add_user("mallory", false);
+        repo.add_user("victim", false);
+        let acme = repo.add_org("Acme");
+        repo.add_member(acme.id, "admin", true);
+        repo.add_member(acme.id, "mallory", true);
+        let app = app(&repo, routes).await;
+
+        let req = TestRequest::post()
+            .uri(&format!("/orgs/{}/switch", acme.id))
+            .cookie(login(&app, "mallory").await);
+        let resp = call_service(&app, req.to_request()).await;
+        let mallory = resp.response().cookies().next().unwrap().into_owned();
+
+        // Org admins can't pull in users from other orgs
+        let req = TestRequest::post()
+            .uri(&format!("/orgs/{}/members", acme.id))
+            .set_json(json!({ "username": "victim", "admin": true }));
+        assert_eq!(401, status(&app, req.cookie(mallory.clone())).await);
+
+        // Nor change the login of users who also belong to another org
+        repo.add_member(acme.id, "victim", false);
+        let req = TestRequest::post()
+            .uri("/users/victim/set-password")
+            .set_json(json!({ "new_password": "password1" }));
+        assert_eq!(401, status(&app, req.cookie(mallory.clone())).await);
+        let req = TestRequest::post().uri("/users/victim").set_json(json!({
+            "username": "victim",
+            "email": "mallory@example.com",
+        }));
+        assert_eq!(401, status(&app, req.cookie(mallory.clone())).await);
+
+        // But they can still change the user's role in their org
+        let req = TestRequest::post()
+            .uri(&format!("/orgs/{}/members", acme.id))
+            .set_json(json!({ "username": "victim", "admin": true }));
+        assert_eq!(200, status(&app, req.cookie(mallory)).await);
+
+        // Site admins can bring in anyone
+        repo.add_user("user1", false);
+        let req = TestRequest::post()
+            .uri(&format!("/orgs/{}/members", acme.id))
+            .set_json(json!({ "username": "user1", "admin": false }));
+        assert_eq!(
+            200,
+            status(&app, req.cookie(login(&app, "admin").await)).await
+        );
+    }
+
+    #[actix_web::test]
+    async fn keys_only_go_on_hooks_in_their_org() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("admin", true);
+        let acme = repo.add_org("Acme");
+        let vault = repo.add_slot_to(acme.id, "Vault", 1);
+        let office = repo.add_slot("Office", 1);
+        repo.add_key("key1");
+        KeyRepository::store(&*repo, &office, "key1").await.unwrap();
+        let app = app(&repo, routes).await;
+        let admin = login(&app, "admin").await;
+
+        let req = TestRequest::post()
+            .uri("/keys/key1")
+            .set_json(json!({ "name": "key1", "slot_id": vault.id }));
+        assert_eq!(404, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::post()
+            .uri("/keys")
+            .set_json(json!({ "name": "key2", "slot_id": vault.id }));
+        assert_eq!(404, status(&app, req.cookie(admin.clone())).await);
+
+        // Leaving out the slot keeps the key on its hook, and null takes it off
+        let get = || TestRequest::get().uri("/keys/key1").cookie(admin.clone());
+        let req = TestRequest::post()
+            .uri("/keys/key1")
+            .set_json(json!({ "name": "key1" }));
+        assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+        let key: Value = call_and_read_body_json(&app, get().to_request()).await;
+        assert_eq!(office.id, key["slot_id"]);
+        let req = TestRequest::post()
+            .uri("/keys/key1")
+            .set_json(json!({ "name": "key1", "slot_id": null }));
+        assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+        let key: Value = call_and_read_body_json(&app, get().to_request()).await;
+        assert_eq!(Value::Null, key["slot_id"]);
+    }
+
+    fn names<'a>(values: &'a [Value], field: &str) -> Vec<&'a str> {
+        values.iter().map(|v| v[field].as_str().unwrap()).collect()
+    }
+}
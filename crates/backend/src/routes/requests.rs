@@ -0,0 +1,421 @@
+use actix_session::Session;
+use actix_web::{
+    delete,
+    error::{
+        ErrorBadRequest, ErrorConflict, ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized,
+    },
+    get, post, web, HttpResponse, Responder,
+};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::{
+    config::AssignmentsConfig,
+    events::Events,
+    models::{
+        is_foreign_key_violation, is_unique_violation, Assignment, AssignmentError,
+        AssignmentQuery, Db, Key, KeyRequest, RequestStatus, User, UserRepository,
+    },
+    routes::{current_org, unpack, validate_admin, validate_session},
+    webhooks,
+};
+
+#[derive(Deserialize, Clone, ToSchema)]
+struct DecisionPayload {
+    note: Option<String>,
+}
+
+#[derive(Deserialize, Clone, ToSchema)]
+struct FulfillPayload {
+    date_out: NaiveDate,
+}
+
+/// Returns true if the user is an admin of the org.
+async fn is_admin(pool: &Db, org: i64, username: &str) -> Result<bool, actix_web::Error> {
+    User::get(pool, org, username)
+        .await
+        .map(|u| u.admin)
+        .map_err(ErrorUnauthorized)
+}
+
+/// Gets the request if the user is allowed to see it. Admins can see every request, other
+/// users can only see requests they made or were asked to approve.
+async fn get_visible(
+    pool: &Db,
+    org: i64,
+    request_id: i64,
+    username: &str,
+) -> Result<KeyRequest, actix_web::Error> {
+    let request = match KeyRequest::get(pool, org, request_id).await {
+        Ok(r) => r,
+        Err(e) => {
+            return match e.to_string() {
+                x if x.contains("no rows returned") => Err(ErrorNotFound("Request not found.")),
+                _ => {
+                    error!("Failed to get request '{}'. {}", request_id, e);
+                    Err(ErrorInternalServerError("Failed to get request."))
+                }
+            }
+        }
+    };
+
+    if request.user == username
+        || request.approver.as_deref() == Some(username)
+        || is_admin(pool, org, username).await?
+    {
+        Ok(request)
+    } else {
+        Err(ErrorNotFound("Request not found."))
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "requests",
+    operation_id = "get_request",
+    responses(
+        (status = 200, body = KeyRequest),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/requests/{request_id}")]
+async fn get(
+    request_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    let request = get_visible(&pool, org, request_id.into_inner(), &username).await?;
+    Ok(HttpResponse::Ok().json(request))
+}
+
+/// Admins get every request. Other users get the requests they made or were asked to approve.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "requests",
+    operation_id = "get_requests",
+    responses(
+        (status = 200, body = [KeyRequest]),
+        (status = 401, description = "Not logged in", body = String),
+    )
+)]
+#[get("/requests")]
+async fn get_all(
+    pool: web::Data<Db>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    let filter = match is_admin(&pool, org, &username).await? {
+        true => None,
+        false => Some(username.as_str()),
+    };
+
+    match KeyRequest::get_all(&pool, org, filter).await {
+        Ok(r) => Ok(HttpResponse::Ok().json(r)),
+        Err(e) => {
+            error!("Failed to get requests. {}", e);
+            Err(ErrorInternalServerError("Failed to get requests."))
+        }
+    }
+}
+
+/// Requests keys for the logged in user.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "requests",
+    operation_id = "create_request",
+    request_body = KeyRequest,
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+    )
+)]
+#[post("/requests")]
+async fn create(
+    body: web::Either<web::Json<KeyRequest>, web::Form<KeyRequest>>,
+    pool: web::Data<Db>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+    let org = current_org(&session)?;
+
+    let mut request = unpack(body);
+    request.user = username;
+
+    if request.keys.is_empty() {
+        return Err(ErrorBadRequest("At least one key must be requested."));
+    }
+    if request.justification.trim().is_empty() {
+        return Err(ErrorBadRequest("A justification is required."));
+    }
+    if request
+        .date_to
+        .map(|d| d < request.date_from)
+        .unwrap_or(false)
+    {
+        return Err(ErrorBadRequest("Date to cannot be before date from."));
+    }
+    if request.approver.as_deref() == Some(request.user.as_str()) {
+        return Err(ErrorBadRequest("Users cannot approve their own requests."));
+    }
+    // SQLite doesn't name the foreign key that failed, so the approver is checked up front.
+    // The approver and keys must belong to the current org.
+    if let Some(approver) = &request.approver {
+        if User::get(&pool, org, approver).await.is_err() {
+            return Err(ErrorBadRequest(format!(
+                "User '{}' does not exist",
+                approver
+            )));
+        }
+    }
+    for key in &request.keys {
+        if Key::get(&pool, org, key).await.is_err() {
+            return Err(ErrorBadRequest("Requested key does not exist."));
+        }
+    }
+
+    match request.create(&pool, org).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Created request {}.", request.id))),
+        Err(e) => match e.to_string() {
+            _ if is_foreign_key_violation(&e) => {
+                Err(ErrorBadRequest("Requested key does not exist."))
+            }
+            _ => {
+                error!("Failed to create request. {}", e);
+                Err(ErrorInternalServerError("Failed to create request."))
+            }
+        },
+    }
+}
+
+/// Approves or denies a request. Only admins and the request's approver can decide, and
+/// users can never decide their own requests.
+async fn decide(
+    request_id: i64,
+    status: RequestStatus,
+    body: DecisionPayload,
+    pool: &Db,
+    session: &Session,
+) -> Result<HttpResponse, actix_web::Error> {
+    let username = validate_session(session)?;
+    let org = current_org(session)?;
+    let mut request = get_visible(pool, org, request_id, &username).await?;
+
+    let is_approver = request.approver.as_deref() == Some(username.as_str());
+    if request.user == username || !(is_approver || is_admin(pool, org, &username).await?) {
+        return Err(ErrorUnauthorized("Unauthorized"));
+    }
+
+    match request.decide(pool, status, &username, body.note).await {
+        Ok(false) => Err(ErrorConflict("Request has already been decided.")),
+        Ok(true) => Ok(HttpResponse::Ok().json(format!(
+            "Request {} {}.",
+            request.id,
+            match status {
+                RequestStatus::Approved => "approved",
+                _ => "denied",
+            }
+        ))),
+        Err(e) => {
+            error!("Failed to decide request. {}", e);
+            Err(ErrorInternalServerError("Failed to update request."))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "requests",
+    operation_id = "approve_request",
+    request_body = inline(DecisionPayload),
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+        (status = 409, description = "Already decided", body = String),
+    )
+)]
+#[post("/requests/{request_id}/approve")]
+async fn approve(
+    request_id: web::Path<i64>,
+    body: web::Either<web::Json<DecisionPayload>, web::Form<DecisionPayload>>,
+    pool: web::Data<Db>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    decide(
+        request_id.into_inner(),
+        RequestStatus::Approved,
+        unpack(body),
+        &pool,
+        &session,
+    )
+    .await
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "requests",
+    operation_id = "deny_request",
+    request_body = inline(DecisionPayload),
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+        (status = 409, description = "Already decided", body = String),
+    )
+)]
+#[post("/requests/{request_id}/deny")]
+async fn deny(
+    request_id: web::Path<i64>,
+    body: web::Either<web::Json<DecisionPayload>, web::Form<DecisionPayload>>,
+    pool: web::Data<Db>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    decide(
+        request_id.into_inner(),
+        RequestStatus::Denied,
+        unpack(body),
+        &pool,
+        &session,
+    )
+    .await
+}
+
+/// Hands over the keys of an approved request, assigning each of them to the requester.
+/// Restricted keys are assigned pending confirmation by a second admin.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "requests",
+    operation_id = "fulfill_request",
+    request_body = inline(FulfillPayload),
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+        (status = 409, description = "Not approved", body = String),
+    )
+)]
+#[post("/requests/{request_id}/fulfill")]
+async fn fulfill(
+    request_id: web::Path<i64>,
+    body: web::Either<web::Json<FulfillPayload>, web::Form<FulfillPayload>>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    events: web::Data<dyn Events>,
+    limits: web::Data<AssignmentsConfig>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let body = unpack(body);
+    let request_id = request_id.into_inner();
+
+    let mut request = match KeyRequest::get(&pool, org, request_id).await {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Request '{}' not found. {}", request_id, e);
+            return Err(ErrorNotFound("Request not found."));
+        }
+    };
+    if request.status != RequestStatus::Approved {
+        return Err(ErrorConflict("Only approved requests can be fulfilled."));
+    }
+
+    for key in &request.keys {
+        let assignment = Assignment::new(&request.user, key, body.date_out);
+        match assignment
+            .validate(pool.get_ref(), &**users, org, None, limits.max_days_ahead)
+            .await
+        {
+            Ok(_) => (),
+            Err(AssignmentError::Database(e)) => {
+                error!("Failed to validate assignment. {}", e);
+                return Err(ErrorInternalServerError("Failed to fulfill request."));
+            }
+            Err(e) => return Err(ErrorBadRequest(e.to_string())),
+        }
+    }
+
+    match request.fulfill(&pool, body.date_out, &username).await {
+        // Fulfilled by someone else since it was read
+        Ok(false) => Err(ErrorConflict("Only approved requests can be fulfilled.")),
+        Ok(true) => {
+            for key in &request.keys {
+                events.key_assigned(org, &request.user, key).await;
+            }
+            let query = AssignmentQuery {
+                user: Some(request.user.clone()),
+                ..Default::default()
+            };
+            if let Ok(assignments) = Assignment::get_all(&pool, org, query).await {
+                for a in assignments
+                    .iter()
+                    .filter(|a| a.date_in.is_none() && request.keys.contains(&a.key))
+                {
+                    webhooks::emit(&pool, org, "assignment.created", a).await;
+                }
+            }
+            Ok(HttpResponse::Ok().json(format!("Request {} fulfilled.", request.id)))
+        }
+        Err(e) => match e.to_string() {
+            _ if is_unique_violation(&e) => Err(ErrorBadRequest(format!(
+                "A requested key is already assigned to {}",
+                request.user
+            ))),
+            _ => {
+                error!("Failed to fulfill request. {}", e);
+                Err(ErrorInternalServerError("Failed to fulfill request."))
+            }
+        },
+    }
+}
+
+/// Withdraws a request. Users can withdraw their own pending requests, admins can delete any
+/// request.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "requests",
+    operation_id = "delete_request",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[delete("/requests/{request_id}")]
+async fn delete(
+    request_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = validate_session(&session)?;
+    let org = current_org(&session)?;
+    let request = get_visible(&pool, org, request_id.into_inner(), &username).await?;
+
+    let is_own_pending = request.user == username && request.status == RequestStatus::Pending;
+    if !(is_own_pending || is_admin(&pool, org, &username).await?) {
+        return Err(ErrorUnauthorized("Unauthorized"));
+    }
+
+    match request.delete(&pool).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Deleted request {}.", request.id))),
+        Err(e) => {
+            error!("Failed to delete request. {}", e);
+            Err(ErrorInternalServerError("Failed to delete request."))
+        }
+    }
+}
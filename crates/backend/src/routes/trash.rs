@@ -0,0 +1,137 @@
+use actix_session::Session;
+use actix_web::{
+    delete,
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    get, post, web, HttpResponse, Responder,
+};
+use tracing::error;
+
+use crate::models::{
+    is_unique_violation, Assignment, Db, Key, TrashItem, TrashKind, User, UserRepository,
+};
+use crate::routes::{current_org, validate_admin};
+
+fn assignment_id(id: &str) -> Result<i64, actix_web::Error> {
+    id.parse()
+        .map_err(|_| ErrorBadRequest("Invalid assignment id."))
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "trash",
+    operation_id = "get_trash",
+    responses(
+        (status = 200, body = [TrashItem]),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+    )
+)]
+#[get("/trash")]
+async fn get_all(
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    match TrashItem::get_all(&pool, org).await {
+        Ok(t) => Ok(HttpResponse::Ok().json(t)),
+        Err(e) => {
+            error!("Failed to get trash. {}", e);
+            Err(ErrorInternalServerError("Failed to get trash."))
+        }
+    }
+}
+
+/// Takes an item out of the trash. Keys and users come back with the assignments which were
+/// deleted along with them.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "trash",
+    operation_id = "restore_from_trash",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[post("/trash/{kind}/{id}/restore")]
+async fn restore(
+    path: web::Path<(TrashKind, String)>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let (kind, id) = path.into_inner();
+    let res = match kind {
+        TrashKind::Key => Key::restore(&pool, org, &id).await,
+        TrashKind::User => User::restore(&pool, org, &id).await,
+        TrashKind::Assignment => Assignment::restore(&pool, org, assignment_id(&id)?).await,
+    };
+
+    match res {
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Restored {} '{}'", kind, id))),
+        Err(e) => match e.to_string() {
+            x if x.contains("no rows returned") => match kind {
+                TrashKind::Assignment => Err(ErrorNotFound(
+                    "Assignment not found in the trash, or its user or key is still in the trash.",
+                )),
+                _ => Err(ErrorNotFound("Not found in the trash.")),
+            },
+            _ if is_unique_violation(&e) => {
+                Err(ErrorBadRequest("The key is already assigned to this user."))
+            }
+            _ => {
+                error!("Failed to restore {} '{}'. {}", kind, id, e);
+                Err(ErrorInternalServerError("Failed to restore."))
+            }
+        },
+    }
+}
+
+/// Permanently deletes an item in the trash. Purging a key or user also purges all of its
+/// assignments.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "trash",
+    operation_id = "purge_from_trash",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[delete("/trash/{kind}/{id}")]
+async fn purge(
+    path: web::Path<(TrashKind, String)>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let (kind, id) = path.into_inner();
+    let res = match kind {
+        TrashKind::Key => Key::purge(&pool, org, &id).await,
+        TrashKind::User => User::purge(&pool, org, &id).await,
+        TrashKind::Assignment => Assignment::purge(&pool, org, assignment_id(&id)?).await,
+    };
+
+    match res {
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Permanently deleted {} '{}'", kind, id))),
+        Err(e) => match e.to_string() {
+            x if x.contains("no rows returned") => Err(ErrorNotFound("Not found in the trash.")),
+            _ => {
+                error!("Failed to purge {} '{}'. {}", kind, id, e);
+                Err(ErrorInternalServerError("Failed to permanently delete."))
+            }
+        },
+    }
+}
@@ -1,31 +1,47 @@
 use actix_session::Session;
 use actix_web::{delete, error, get, post, web, HttpResponse, Responder};
-use log::error;
 use serde::Deserialize;
-use sqlx::PgPool;
+use serde_json::json;
+use tracing::error;
+use utoipa::ToSchema;
 use validator::Validate;
 
 use crate::{
-    models::{Assignment, User},
-    routes::{unpack, validate_admin, validate_session},
+    events::Events,
+    models::{
+        is_foreign_key_violation, is_unique_violation, AssignmentRepository, NotificationSettings,
+        User, UserRepository,
+    },
+    routes::{current_org, unpack, validate_admin, validate_login_change, validate_session},
 };
 
-#[derive(Deserialize, Validate)]
+#[derive(Deserialize, Validate, ToSchema)]
 struct SetPasswdPayload {
     #[validate(length(min = 8))]
     new_password: String,
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "users",
+    operation_id = "get_user",
+    responses(
+        (status = 200, body = User),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
 #[get("/users/{username}")]
 async fn get(
     session: Session,
     username: web::Path<String>,
-    pool: web::Data<PgPool>,
+    users: web::Data<dyn UserRepository>,
 ) -> Result<impl Responder, actix_web::Error> {
     validate_session(&session)?;
+    let org = current_org(&session)?;
 
     let username = &username.into_inner();
-    match User::get(&pool, username).await {
+    match users.get(org, username).await {
         Ok(k) => Ok(HttpResponse::Ok().json(k)),
         Err(e) => match e.to_string() {
             x if x.contains("no rows returned") => {
@@ -40,14 +56,24 @@ async fn get(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "users",
+    operation_id = "get_users",
+    responses(
+        (status = 200, body = [User]),
+        (status = 401, description = "Not logged in", body = String),
+    )
+)]
 #[get("/users")]
 async fn get_all(
     session: Session,
-    pool: web::Data<PgPool>,
+    users: web::Data<dyn UserRepository>,
 ) -> Result<impl Responder, actix_web::Error> {
     validate_session(&session)?;
+    let org = current_org(&session)?;
 
-    match User::get_all(&pool).await {
+    match users.get_all(org).await {
         Ok(u) => Ok(HttpResponse::Ok().json(u)),
         Err(e) => {
             error!("Failed to get users. {}", e);
@@ -56,24 +82,43 @@ async fn get_all(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "users",
+    operation_id = "create_user",
+    request_body = User,
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+    )
+)]
 #[post("/users")]
 async fn create(
     session: Session,
     user: web::Either<web::Json<User>, web::Form<User>>,
-    pool: web::Data<PgPool>,
+    users: web::Data<dyn UserRepository>,
+    events: web::Data<dyn Events>,
 ) -> Result<impl Responder, actix_web::Error> {
-    validate_admin(&session, &pool).await?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
 
     let user = unpack(user);
     if let Err(e) = user.validate() {
         return Err(error::ErrorBadRequest(e));
     }
 
-    match user.create(&pool).await {
-        Ok(_) => Ok(HttpResponse::Ok().json(format!("Created user '{}'", user.username))),
+    match users.create(org, &user).await {
+        Ok(_) => {
+            if let Ok(u) = users.get(org, &user.username).await {
+                events.emit(org, "user.created", json!(u)).await;
+            }
+            Ok(HttpResponse::Ok().json(format!("Created user '{}'", user.username)))
+        }
         Err(e) => match e.to_string() {
-            x if x.contains("duplicate key") => Err(error::ErrorBadRequest(format!(
-                "User '{}' already exists.",
+            _ if is_unique_violation(&e) => Err(error::ErrorBadRequest(format!(
+                "User '{}' already exists. They may be in the trash or belong to another org.",
                 user.username
             ))),
             _ => {
@@ -84,19 +129,35 @@ async fn create(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "users",
+    operation_id = "update_user",
+    request_body = User,
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
 #[post("/users/{username}")]
 async fn update(
     session: Session,
     username: web::Path<String>,
     body: web::Either<web::Json<User>, web::Form<User>>,
-    pool: web::Data<PgPool>,
+    users: web::Data<dyn UserRepository>,
+    events: web::Data<dyn Events>,
 ) -> Result<impl Responder, actix_web::Error> {
-    validate_admin(&session, &pool).await?;
+    let session_user = validate_session(&session)?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
 
     let body = unpack(body);
     let username = &username.into_inner();
 
-    let mut user = match User::get(&pool, username).await {
+    let mut user = match users.get(org, username).await {
         Ok(k) => k,
         Err(e) => {
             error!("User '{}' not found. {}", username, e);
@@ -104,34 +165,82 @@ async fn update(
         }
     };
 
+    let original = user.clone();
+    // Renaming is optional, so a missing or blank username keeps the current one
+    if !body.username.trim().is_empty() {
+        user.username = body.username.trim().to_string();
+    }
     user.display_name = body.display_name;
     user.email = body.email;
     user.can_login = body.can_login;
+    if user.username != original.username
+        || user.email != original.email
+        || user.can_login != original.can_login
+    {
+        validate_login_change(&session, &users, username).await?;
+    }
     user.admin = body.admin;
+    user.departed = body.departed;
+    if let Err(e) = user.validate() {
+        return Err(error::ErrorBadRequest(e));
+    }
 
-    match user.update(&pool).await {
-        Ok(_) => Ok(HttpResponse::Ok().json(format!("Updated user '{}'", user.username))),
-        Err(e) => {
-            error!("Failed to update user. {}", e);
-            Err(error::ErrorInternalServerError("Failed to update user."))
+    match users.update(org, &user).await {
+        Ok(_) => {
+            // Keep admins who rename themselves logged in
+            if &session_user == username && &user.username != username {
+                session
+                    .insert("username", &user.username)
+                    .map_err(error::ErrorInternalServerError)?;
+            }
+            events.emit(org, "user.updated", json!(user)).await;
+            Ok(HttpResponse::Ok().json(format!("Updated user '{}'", user.username)))
         }
+        Err(e) => match e.to_string() {
+            x if x.contains("users_username_key") || x.contains("users.username") => {
+                Err(error::ErrorBadRequest(format!(
+                    "User '{}' already exists. They may be in the trash.",
+                    user.username
+                )))
+            }
+            _ if is_unique_violation(&e) => Err(error::ErrorBadRequest(
+                "Display name or email is already in use.",
+            )),
+            _ => {
+                error!("Failed to update user. {}", e);
+                Err(error::ErrorInternalServerError("Failed to update user."))
+            }
+        },
     }
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "users",
+    operation_id = "delete_user",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
 #[delete("/users/{username}")]
 async fn delete(
     session: Session,
     username: web::Path<String>,
-    pool: web::Data<PgPool>,
+    users: web::Data<dyn UserRepository>,
+    events: web::Data<dyn Events>,
 ) -> Result<impl Responder, actix_web::Error> {
-    validate_admin(&session, &pool).await?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
 
-    match User::get(&pool, &username.into_inner()).await {
+    match users.get(org, &username.into_inner()).await {
         Ok(u) => {
             // Check if the user being deleted is an administrator. If so, check that we're
             // not about to delete the last admin in the database.
             if u.admin {
-                match User::count_admins(&pool).await {
+                match users.count_admins(org).await {
                     Ok(count) => {
                         if count <= 1 {
                             return Err(error::ErrorBadRequest(
@@ -145,8 +254,14 @@ async fn delete(
                     }
                 }
             };
-            match u.delete(&pool).await {
-                Ok(_) => Ok(HttpResponse::Ok().json(format!("Deleted user '{}'", u.username))),
+            match users.delete(org, &u).await {
+                Ok(_) => {
+                    events.emit(org, "user.deleted", json!(u)).await;
+                    Ok(
+                        HttpResponse::Ok()
+                            .json(format!("Moved user '{}' to the trash", u.username)),
+                    )
+                }
                 Err(e) => {
                     error!("Failed to delete user. {}", e);
                     Err(error::ErrorInternalServerError("Failed to delete user"))
@@ -160,14 +275,27 @@ async fn delete(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "users",
+    operation_id = "set_user_password",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
 #[post("/users/{username}/set-password")]
 async fn set_password(
     session: Session,
     username: web::Path<String>,
     payload: web::Json<SetPasswdPayload>,
-    pool: web::Data<PgPool>,
+    users: web::Data<dyn UserRepository>,
 ) -> Result<impl Responder, actix_web::Error> {
-    validate_admin(&session, &pool).await?;
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
 
     if payload.validate().is_err() {
         return Err(error::ErrorBadRequest(
@@ -175,8 +303,11 @@ async fn set_password(
         ));
     }
 
-    match User::get(&pool, &username.into_inner()).await {
-        Ok(mut u) => match u.set_password(&pool, &payload.new_password).await {
+    let username = username.into_inner();
+    validate_login_change(&session, &users, &username).await?;
+
+    match users.get(org, &username).await {
+        Ok(mut u) => match users.set_password(&mut u, &payload.new_password).await {
             Ok(_) => {
                 Ok(HttpResponse::Ok().json(format!("Password updated for user '{}'", u.username)))
             }
@@ -191,16 +322,27 @@ async fn set_password(
     }
 }
 
+#[utoipa::path(
+    context_path = "/api",
+    tag = "users",
+    operation_id = "get_user_assignments",
+    responses(
+        (status = 200, body = [Assignment]),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
 #[get("/users/{username}/assignments")]
 async fn get_assignments(
     session: Session,
     username: web::Path<String>,
-    pool: web::Data<PgPool>,
+    assignments: web::Data<dyn AssignmentRepository>,
 ) -> Result<impl Responder, actix_web::Error> {
     validate_session(&session)?;
+    let org = current_org(&session)?;
 
     let username = &username.into_inner();
-    match Assignment::get_assignments_by_user(&pool, username).await {
+    match assignments.get_by_user(org, username).await {
         Ok(k) => Ok(HttpResponse::Ok().json(k)),
         Err(e) => match e.to_string() {
             x if x.contains("no rows returned") => {
@@ -215,46 +357,317 @@ async fn get_assignments(
     }
 }
 
+/// Only the user themselves and admins may view or change a user's notification settings.
+async fn validate_self_or_admin(
+    session: &Session,
+    users: &web::Data<dyn UserRepository>,
+    username: &str,
+) -> Result<(), actix_web::Error> {
+    if validate_session(session)? == username {
+        Ok(())
+    } else {
+        validate_admin(session, users).await
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "users",
+    operation_id = "get_user_notifications",
+    responses(
+        (status = 200, body = NotificationSettings),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/users/{username}/notifications")]
+async fn get_notifications(
+    session: Session,
+    username: web::Path<String>,
+    users: web::Data<dyn UserRepository>,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = &username.into_inner();
+    validate_self_or_admin(&session, &users, username).await?;
+
+    match users.notification_settings(username).await {
+        Ok(n) => Ok(HttpResponse::Ok().json(n)),
+        Err(e) => {
+            error!(
+                "Failed to get notification settings for '{}'. {}",
+                username, e
+            );
+            Err(error::ErrorInternalServerError(
+                "Failed to get notification settings.",
+            ))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "users",
+    operation_id = "set_user_notifications",
+    request_body = NotificationSettings,
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[post("/users/{username}/notifications")]
+async fn set_notifications(
+    session: Session,
+    username: web::Path<String>,
+    body: web::Either<web::Json<NotificationSettings>, web::Form<NotificationSettings>>,
+    users: web::Data<dyn UserRepository>,
+) -> Result<impl Responder, actix_web::Error> {
+    let username = &username.into_inner();
+    validate_self_or_admin(&session, &users, username).await?;
+
+    match users
+        .set_notification_settings(username, &unpack(body))
+        .await
+    {
+        Ok(_) => Ok(HttpResponse::Ok().json(format!(
+            "Updated notification settings for user '{}'",
+            username
+        ))),
+        Err(e) => match e.to_string() {
+            _ if is_foreign_key_violation(&e) => Err(error::ErrorNotFound("User not found")),
+            _ => {
+                error!("Failed to update notification settings. {}", e);
+                Err(error::ErrorInternalServerError(
+                    "Failed to update notification settings.",
+                ))
+            }
+        },
+    }
+}
+
 #[cfg(test)]
 mod user_routes_tests {
-    use crate::{models, routes};
-
-    use actix_session::{storage::CookieSessionStore, SessionMiddleware};
-    use actix_web::{
-        cookie::{Key, SameSite},
-        test,
-        web::Data,
-        App,
+    use std::sync::Arc;
+
+    use actix_web::test::{call_and_read_body_json, TestRequest};
+    use chrono::Local;
+    use serde_json::{json, Value};
+
+    use crate::models::{MemoryRepository, UserRepository, DEFAULT_ORG};
+    use crate::routes::{
+        testing::{app, login, status},
+        users,
     };
 
+    fn routes(cfg: &mut actix_web::web::ServiceConfig) {
+        cfg.service(users::get)
+            .service(users::get_all)
+            .service(users::create)
+            .service(users::update)
+            .service(users::delete)
+            .service(users::set_password)
+            .service(users::get_assignments)
+            .service(users::get_notifications)
+            .service(users::set_notifications);
+    }
+
     #[actix_web::test]
-    async fn test_get_users() {
-        let secret_key = Key::generate();
+    async fn require_login() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("user1", false);
+        let app = app(&repo, routes).await;
 
-        let pool = match models::db().await {
-            Ok(p) => p,
-            Err(e) => {
-                eprintln!("{}", e);
-                std::process::exit(1);
-            }
-        };
-
-        let app = test::init_service(
-            App::new()
-                .wrap(
-                    SessionMiddleware::builder(CookieSessionStore::default(), secret_key.clone())
-                        .cookie_secure(false)
-                        .cookie_http_only(false)
-                        .cookie_same_site(SameSite::Strict)
-                        .build(),
-                )
-                .app_data(Data::new(pool.clone()))
-                .service(routes::users::get_all),
-        )
-        .await;
-        let req = test::TestRequest::get().uri("/users").to_request();
-        let resp = test::call_service(&app, req).await;
-
-        assert!(resp.status().is_client_error());
+        for uri in [
+            "/users",
+            "/users/user1",
+            "/users/user1/assignments",
+            "/users/user1/notifications",
+        ] {
+            assert_eq!(
+                401,
+                status(&app, TestRequest::get().uri(uri)).await,
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn changes_require_admin() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("user1", false);
+        repo.add_user("user2", false);
+        let app = app(&repo, routes).await;
+        let user = login(&app, "user1").await;
+
+        let body = json!({ "username": "user3" });
+        let settings = json!({
+            "key_assigned": false,
+            "key_due": true,
+            "key_overdue": true,
+            "admin_summary": true,
+        });
+        for req in [
+            TestRequest::post().uri("/users").set_json(&body),
+            TestRequest::post().uri("/users/user2").set_json(&body),
+            TestRequest::delete().uri("/users/user2"),
+            TestRequest::post()
+                .uri("/users/user2/set-password")
+                .set_json(json!({ "new_password": "password1" })),
+            TestRequest::get().uri("/users/user2/notifications"),
+            TestRequest::post()
+                .uri("/users/user2/notifications")
+                .set_json(&settings),
+        ] {
+            assert_eq!(401, status(&app, req.cookie(user.clone())).await);
+        }
+
+        // Users manage their own notifications
+        let req = TestRequest::post()
+            .uri("/users/user1/notifications")
+            .set_json(&settings);
+        assert_eq!(200, status(&app, req.cookie(user.clone())).await);
+        let req = TestRequest::get()
+            .uri("/users/user1/notifications")
+            .cookie(user);
+        let stored: Value = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!(settings, stored);
+        assert!(repo.events().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn create_update_and_delete() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("admin", true);
+        let app = app(&repo, routes).await;
+        let admin = login(&app, "admin").await;
+
+        let req = TestRequest::post()
+            .uri("/users")
+            .set_json(json!({ "username": "user1", "email": "user1@example.com" }));
+        assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::post()
+            .uri("/users")
+            .set_json(json!({ "username": "user1" }));
+        assert_eq!(400, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::post()
+            .uri("/users")
+            .set_json(json!({ "username": "u" }));
+        assert_eq!(400, status(&app, req.cookie(admin.clone())).await);
+
+        let req = TestRequest::get().uri("/users/user1").cookie(admin.clone());
+        let user: Value = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!("user1@example.com", user["email"]);
+        let req = TestRequest::get().uri("/users/missing");
+        assert_eq!(404, status(&app, req.cookie(admin.clone())).await);
+
+        let req = TestRequest::post()
+            .uri("/users/user1")
+            .set_json(json!({ "username": "user2", "email": "user2@example.com" }));
+        assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::post()
+            .uri("/users/missing")
+            .set_json(json!({ "username": "user3" }));
+        assert_eq!(404, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::post()
+            .uri("/users/user2")
+            .set_json(json!({ "username": "admin" }));
+        assert_eq!(400, status(&app, req.cookie(admin.clone())).await);
+
+        // Admins who rename themselves stay logged in
+        let req = TestRequest::post()
+            .uri("/users/admin")
+            .set_json(json!({ "username": "root", "admin": true }));
+        let resp = actix_web::test::call_service(&app, req.cookie(admin).to_request()).await;
+        assert_eq!(200, resp.status().as_u16());
+        let admin = resp.response().cookies().next().unwrap().into_owned();
+
+        let req = TestRequest::delete().uri("/users/root");
+        assert_eq!(400, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::delete().uri("/users/user2");
+        assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::delete().uri("/users/user2");
+        assert_eq!(404, status(&app, req.cookie(admin.clone())).await);
+
+        let req = TestRequest::get().uri("/users").cookie(admin);
+        let all: Vec<Value> = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!(1, all.len());
+        assert_eq!(
+            vec![
+                "user.created",
+                "user.updated",
+                "user.updated",
+                "user.deleted"
+            ],
+            repo.events()
+        );
+    }
+
+    #[actix_web::test]
+    async fn set_password() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("admin", true);
+        repo.add_user("user1", false);
+        let app = app(&repo, routes).await;
+        let admin = login(&app, "admin").await;
+
+        let req = TestRequest::post()
+            .uri("/users/user1/set-password")
+            .set_json(json!({ "new_password": "short" }));
+        assert_eq!(400, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::post()
+            .uri("/users/missing/set-password")
+            .set_json(json!({ "new_password": "password1" }));
+        assert_eq!(404, status(&app, req.cookie(admin.clone())).await);
+        let req = TestRequest::post()
+            .uri("/users/user1/set-password")
+            .set_json(json!({ "new_password": "password1" }));
+        assert_eq!(200, status(&app, req.cookie(admin)).await);
+
+        let user = repo.get(DEFAULT_ORG, "user1").await.unwrap();
+        assert!(user.validate_password("password1"));
+    }
+
+    #[actix_web::test]
+    async fn assignments_and_notifications() {
+        let repo = Arc::new(MemoryRepository::new());
+        repo.add_user("admin", true);
+        repo.add_user("user1", false);
+        repo.add_key("key1");
+        repo.add_key("key2");
+        let today = Local::today().naive_local();
+        repo.add_assignment("user1", "key2", today);
+        repo.add_assignment("user1", "key1", today);
+        let app = app(&repo, routes).await;
+        let admin = login(&app, "admin").await;
+
+        let req = TestRequest::get()
+            .uri("/users/user1/assignments")
+            .cookie(admin.clone());
+        let assignments: Vec<Value> = call_and_read_body_json(&app, req.to_request()).await;
+        let keys: Vec<&Value> = assignments.iter().map(|a| &a["key"]).collect();
+        assert_eq!(vec!["key1", "key2"], keys);
+
+        let req = TestRequest::get()
+            .uri("/users/user1/notifications")
+            .cookie(admin.clone());
+        let settings: Value = call_and_read_body_json(&app, req.to_request()).await;
+        assert_eq!(true, settings["key_due"]);
+
+        let settings = json!({
+            "key_assigned": true,
+            "key_due": false,
+            "key_overdue": true,
+            "admin_summary": true,
+        });
+        let req = TestRequest::post()
+            .uri("/users/user1/notifications")
+            .set_json(&settings);
+        assert_eq!(200, status(&app, req.cookie(admin.clone())).await);
+        assert!(!repo.notification_settings("user1").await.unwrap().key_due);
+        let req = TestRequest::post()
+            .uri("/users/missing/notifications")
+            .set_json(&settings);
+        assert_eq!(404, status(&app, req.cookie(admin)).await);
     }
 }
@@ -0,0 +1,303 @@
+use actix_session::Session;
+use actix_web::{
+    delete,
+    error::{ErrorBadRequest, ErrorInternalServerError, ErrorNotFound},
+    get, post, web, HttpResponse, Responder,
+};
+use tracing::error;
+
+use crate::{
+    models::{Db, UserRepository, Webhook, WebhookDelivery, WEBHOOK_EVENTS},
+    routes::{current_org, unpack, validate_admin},
+    webhooks,
+};
+
+/// Number of deliveries shown in a webhook's delivery log.
+static DELIVERY_LOG_LENGTH: i64 = 100;
+
+/// Checks the webhook's URL and events and generates a secret if one wasn't given.
+fn prepare(webhook: &mut Webhook) -> Result<(), actix_web::Error> {
+    if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+        return Err(ErrorBadRequest(
+            "Webhook URL must start with http:// or https://",
+        ));
+    }
+    if let Some(e) = webhook
+        .events
+        .iter()
+        .find(|e| !WEBHOOK_EVENTS.contains(&e.as_str()))
+    {
+        return Err(ErrorBadRequest(format!("Unknown event '{}'.", e)));
+    }
+    if webhook.secret.is_empty() {
+        webhook.secret = pwgen::generate("full", 32);
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "webhooks",
+    operation_id = "get_webhook",
+    responses(
+        (status = 200, body = Webhook),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/webhooks/{webhook_id}")]
+async fn get(
+    webhook_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let webhook_id = webhook_id.into_inner();
+    match Webhook::get(&pool, org, webhook_id).await {
+        Ok(w) => Ok(HttpResponse::Ok().json(w)),
+        Err(e) => match e.to_string() {
+            x if x.contains("no rows returned") => Err(ErrorNotFound("Webhook not found.")),
+            _ => {
+                error!("Failed to get webhook '{}'. {}", webhook_id, e);
+                Err(ErrorInternalServerError("Failed to get webhook."))
+            }
+        },
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "webhooks",
+    operation_id = "get_webhooks",
+    responses(
+        (status = 200, body = [Webhook]),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+    )
+)]
+#[get("/webhooks")]
+async fn get_all(
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    match Webhook::get_all(&pool, org).await {
+        Ok(w) => Ok(HttpResponse::Ok().json(w)),
+        Err(e) => {
+            error!("Failed to get webhooks. {}", e);
+            Err(ErrorInternalServerError("Failed to get webhooks."))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "webhooks",
+    operation_id = "create_webhook",
+    request_body = Webhook,
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+    )
+)]
+#[post("/webhooks")]
+async fn create(
+    webhook: web::Either<web::Json<Webhook>, web::Form<Webhook>>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let mut webhook = unpack(webhook);
+    let generated = webhook.secret.is_empty();
+    prepare(&mut webhook)?;
+
+    match webhook.create(&pool, org).await {
+        Ok(_) if generated => Ok(HttpResponse::Ok().json(format!(
+            "Created webhook {} with secret '{}'. It won't be shown again.",
+            webhook.id, webhook.secret
+        ))),
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Created webhook {}", webhook.id))),
+        Err(e) => {
+            error!("Failed to create webhook. {}", e);
+            Err(ErrorInternalServerError("Failed to create webhook."))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "webhooks",
+    operation_id = "update_webhook",
+    request_body = Webhook,
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 400, description = "Invalid request", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[post("/webhooks/{webhook_id}")]
+async fn update(
+    webhook_id: web::Path<i64>,
+    body: web::Either<web::Json<Webhook>, web::Form<Webhook>>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let body = unpack(body);
+    let webhook_id = webhook_id.into_inner();
+
+    let mut webhook = match Webhook::get(&pool, org, webhook_id).await {
+        Ok(w) => w,
+        Err(e) => {
+            error!("Webhook '{}' not found. {}", webhook_id, e);
+            return Err(ErrorNotFound("Webhook not found."));
+        }
+    };
+
+    webhook.url = body.url;
+    webhook.events = body.events;
+    webhook.active = body.active;
+    if !body.secret.is_empty() {
+        webhook.secret = body.secret;
+    }
+    prepare(&mut webhook)?;
+
+    match webhook.update(&pool).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(format!("Updated webhook {}", webhook.id))),
+        Err(e) => {
+            error!("Failed to update webhook. {}", e);
+            Err(ErrorInternalServerError("Failed to update webhook."))
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api",
+    tag = "webhooks",
+    operation_id = "delete_webhook",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[delete("/webhooks/{webhook_id}")]
+async fn delete(
+    webhook_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    match Webhook::get(&pool, org, webhook_id.into_inner()).await {
+        Ok(w) => match w.delete(&pool).await {
+            Ok(_) => Ok(HttpResponse::Ok().json(format!("Deleted webhook {}", w.id))),
+            Err(e) => {
+                error!("Failed to delete webhook. {}", e);
+                Err(ErrorInternalServerError("Failed to delete webhook."))
+            }
+        },
+        Err(_) => Err(ErrorNotFound("Webhook not found.")),
+    }
+}
+
+/// Lists the most recent deliveries to a webhook, newest first.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "webhooks",
+    operation_id = "get_webhook_deliveries",
+    responses(
+        (status = 200, body = [WebhookDelivery]),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[get("/webhooks/{webhook_id}/deliveries")]
+async fn get_deliveries(
+    webhook_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let webhook_id = webhook_id.into_inner();
+    if Webhook::get(&pool, org, webhook_id).await.is_err() {
+        return Err(ErrorNotFound("Webhook not found."));
+    }
+    match WebhookDelivery::get_by_webhook(&pool, webhook_id, DELIVERY_LOG_LENGTH).await {
+        Ok(d) => Ok(HttpResponse::Ok().json(d)),
+        Err(e) => {
+            error!(
+                "Failed to get deliveries for webhook '{}'. {}",
+                webhook_id, e
+            );
+            Err(ErrorInternalServerError("Failed to get deliveries."))
+        }
+    }
+}
+
+/// Sends a delivery's payload again as a new delivery.
+#[utoipa::path(
+    context_path = "/api",
+    tag = "webhooks",
+    operation_id = "resend_webhook_delivery",
+    responses(
+        (status = 200, description = "Confirmation message", body = String),
+        (status = 401, description = "Not logged in", body = String),
+        (status = 403, description = "Not an admin", body = String),
+        (status = 404, description = "Not found", body = String),
+    )
+)]
+#[post("/webhook-deliveries/{delivery_id}/resend")]
+async fn resend(
+    delivery_id: web::Path<i64>,
+    pool: web::Data<Db>,
+    users: web::Data<dyn UserRepository>,
+    session: Session,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_admin(&session, &users).await?;
+    let org = current_org(&session)?;
+
+    let delivery_id = delivery_id.into_inner();
+    let delivery = match WebhookDelivery::get(&pool, delivery_id).await {
+        Ok(d) => d,
+        Err(e) => {
+            error!("Delivery '{}' not found. {}", delivery_id, e);
+            return Err(ErrorNotFound("Delivery not found."));
+        }
+    };
+    if Webhook::get(&pool, org, delivery.webhook_id).await.is_err() {
+        return Err(ErrorNotFound("Delivery not found."));
+    }
+
+    match webhooks::resend(&pool, &delivery).await {
+        Ok(d) => Ok(HttpResponse::Ok().json(format!("Queued delivery {}", d.id))),
+        Err(e) => {
+            error!("Failed to resend delivery. {}", e);
+            Err(ErrorInternalServerError("Failed to resend delivery."))
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    sync::{Arc, RwLock},
+};
+
+use actix_web::{dev::Server, http::header, web, App, HttpRequest, HttpResponse, HttpServer};
+use anyhow::{anyhow, Result};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{any_supported_type, CertifiedKey},
+    Certificate, PrivateKey, ServerConfig,
+};
+use rustls_pemfile::Item;
+use tracing::{error, info};
+
+/// Serves the certificate loaded from the configured files, which can be swapped out while the
+/// server is running.
+pub struct CertResolver {
+    cert_path: String,
+    key_path: String,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    pub fn load(cert_path: &str, key_path: &str) -> Result<Self> {
+        Ok(CertResolver {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            current: RwLock::new(Arc::new(load_certified_key(cert_path, key_path)?)),
+        })
+    }
+
+    /// Reads the certificate and key again. The old certificate stays in use if they can't be
+    /// loaded.
+    pub fn reload(&self) -> Result<()> {
+        let certified_key = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap() = Arc::new(certified_key);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+fn load_certified_key(cert_path: &str, key_path: &str) -> Result<CertifiedKey> {
+    let mut reader = BufReader::new(
+        File::open(cert_path)
+            .map_err(|e| anyhow!("Failed to open certificate '{}'. {}", cert_path, e))?,
+    );
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut reader)
+        .map_err(|e| anyhow!("Failed to read certificate '{}'. {}", cert_path, e))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in '{}'", cert_path));
+    }
+
+    let mut reader = BufReader::new(
+        File::open(key_path).map_err(|e| anyhow!("Failed to open key '{}'. {}", key_path, e))?,
+    );
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .map_err(|e| anyhow!("Failed to read key '{}'. {}", key_path, e))?
+        {
+            Some(Item::PKCS8Key(k) | Item::RSAKey(k) | Item::ECKey(k)) => break PrivateKey(k),
+            Some(_) => continue,
+            None => return Err(anyhow!("No private key found in '{}'", key_path)),
+        }
+    };
+    let signing_key = any_supported_type(&key)
+        .map_err(|_| anyhow!("Unsupported private key type in '{}'", key_path))?;
+
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+/// Builds the rustls config for the HTTPS listener.
+pub fn server_config(resolver: Arc<CertResolver>) -> ServerConfig {
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver)
+}
+
+/// Reloads the certificate whenever the process receives SIGHUP, so that renewed certificates
+/// are picked up without a restart.
+#[cfg(unix)]
+pub async fn reload_on_hangup(resolver: Arc<CertResolver>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!(
+                "Failed to listen for SIGHUP. Certificates won't be reloaded. {}",
+                e
+            );
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        match resolver.reload() {
+            Ok(_) => info!("Reloaded TLS certificate"),
+            Err(e) => error!("Failed to reload TLS certificate. {}", e),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn reload_on_hangup(_resolver: Arc<CertResolver>) {}
+
+/// Serves plain HTTP on `redirect_port`, permanently redirecting every request to the same
+/// path over HTTPS on `public_host` and `https_port`. The request's own Host header is ignored,
+/// so the redirect can't be pointed at another site.
+pub fn redirect_server(
+    bind: &str,
+    redirect_port: u16,
+    public_host: &str,
+    https_port: u16,
+) -> io::Result<Server> {
+    let base = https_url(public_host, https_port, "");
+    Ok(HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(base.clone()))
+            .default_service(web::to(redirect_to_https))
+    })
+    .bind((bind, redirect_port))?
+    .run())
+}
+
+async fn redirect_to_https(req: HttpRequest, base: web::Data<String>) -> HttpResponse {
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, format!("{}{}", base.as_str(), path)))
+        .finish()
+}
+
+/// Returns the HTTPS URL for `path` on `host`, replacing any port the host came with.
+fn https_url(host: &str, port: u16, path: &str) -> String {
+    let hostname = match host.rsplit_once(':') {
+        Some((h, p)) if !h.is_empty() && p.chars().all(|c| c.is_ascii_digit()) => h,
+        _ => host,
+    };
+    if port == 443 {
+        format!("https://{}{}", hostname, path)
+    } else {
+        format!("https://{}:{}{}", hostname, port, path)
+    }
+}
+
+#[cfg(test)]
+mod tls_tests {
+    use actix_web::{
+        http::header,
+        test::{call_service, init_service, TestRequest},
+        web, App,
+    };
+
+    use super::{https_url, redirect_to_https, CertResolver};
+
+    static CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/fixtures/tls/cert.pem");
+    static KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/fixtures/tls/key.pem");
+
+    #[test]
+    fn load_certificate() {
+        let resolver = CertResolver::load(CERT, KEY).unwrap();
+        assert!(resolver.reload().is_ok());
+
+        // Keys aren't certificates
+        assert!(CertResolver::load(KEY, KEY).is_err());
+        assert!(CertResolver::load(CERT, CERT).is_err());
+        assert!(CertResolver::load(CERT, "missing.pem").is_err());
+    }
+
+    #[test]
+    fn redirect_url() {
+        assert_eq!(
+            "https://keys.example.com/keys?q=1",
+            https_url("keys.example.com", 443, "/keys?q=1")
+        );
+        assert_eq!(
+            "https://keys.example.com:8443/",
+            https_url("keys.example.com:8080", 8443, "/")
+        );
+        assert_eq!("https://[::1]/", https_url("[::1]:80", 443, "/"));
+        assert_eq!("https://[::1]/", https_url("[::1]", 443, "/"));
+    }
+
+    #[actix_web::test]
+    async fn redirect_ignores_host_header() {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(https_url("keys.example.com", 8443, "")))
+                .default_service(web::to(redirect_to_https)),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/keys?q=1")
+            .insert_header((header::HOST, "evil.example.com"))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(308, resp.status().as_u16());
+        assert_eq!(
+            "https://keys.example.com:8443/keys?q=1",
+            resp.headers().get(header::LOCATION).unwrap()
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use tracing::error;
+
+use crate::models::{Db, Webhook, WebhookDelivery};
+
+/// Number of due deliveries attempted per pass over the queue.
+static BATCH_SIZE: i64 = 50;
+
+/// How long a webhook has to respond before the attempt counts as failed.
+static TIMEOUT_SECONDS: u64 = 10;
+
+/// The JSON body posted to webhooks.
+#[derive(Serialize)]
+struct Payload<'a, T: Serialize> {
+    event: &'a str,
+    timestamp: DateTime<Utc>,
+    data: &'a T,
+}
+
+/// Returns the signature of a payload, sent in the `X-KeyMaster-Signature` header. It is the
+/// hex encoded HMAC-SHA256 of the raw body using the webhook's secret, prefixed with `sha256=`.
+pub fn sign(secret: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Queues `event` for every webhook of the org subscribed to it and tries to deliver it
+/// straight away. Failures are logged rather than returned so they never fail the request
+/// which caused the event.
+pub async fn emit<T: Serialize>(pool: &Db, org: i64, event: &str, data: &T) {
+    let payload = Payload {
+        event,
+        timestamp: Utc::now(),
+        data,
+    };
+    let payload = match serde_json::to_string(&payload) {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Failed to serialize '{}' event. {}", event, e);
+            return;
+        }
+    };
+
+    let webhooks = match Webhook::get_subscribed(pool, org, event).await {
+        Ok(w) => w,
+        Err(e) => {
+            error!("Failed to get webhooks for '{}' event. {}", event, e);
+            return;
+        }
+    };
+
+    for webhook in webhooks {
+        match WebhookDelivery::create(pool, webhook.id, event, &payload).await {
+            Ok(d) => spawn_delivery(pool, d),
+            Err(e) => error!(
+                "Failed to queue '{}' event for webhook {}. {}",
+                event, webhook.id, e
+            ),
+        }
+    }
+}
+
+/// Sends a delivery's payload again as a new delivery. The original delivery is left in the log.
+pub async fn resend(pool: &Db, original: &WebhookDelivery) -> Result<WebhookDelivery, sqlx::Error> {
+    let delivery = WebhookDelivery::create(
+        pool,
+        original.webhook_id,
+        &original.event,
+        &original.payload,
+    )
+    .await?;
+    spawn_delivery(pool, delivery.clone());
+    Ok(delivery)
+}
+
+fn spawn_delivery(pool: &Db, delivery: WebhookDelivery) {
+    let pool = pool.clone();
+    actix_web::rt::spawn(async move {
+        if let Err(e) = deliver(&pool, delivery).await {
+            error!("Failed to deliver webhook. {}", e);
+        }
+    });
+}
+
+/// Posts a delivery to its webhook, recording the outcome. Deliveries which another attempt is
+/// already working on are skipped.
+async fn deliver(pool: &Db, mut delivery: WebhookDelivery) -> Result<(), sqlx::Error> {
+    if !delivery.lease(pool).await? {
+        return Ok(());
+    }
+    let webhook = Webhook::find(pool, delivery.webhook_id).await?;
+
+    let client = awc::Client::builder()
+        .timeout(Duration::from_secs(TIMEOUT_SECONDS))
+        .finish();
+    let res = client
+        .post(&webhook.url)
+        .content_type("application/json")
+        .insert_header(("X-KeyMaster-Event", delivery.event.as_str()))
+        .insert_header(("X-KeyMaster-Delivery", delivery.id.to_string()))
+        .insert_header((
+            "X-KeyMaster-Signature",
+            sign(&webhook.secret, &delivery.payload),
+        ))
+        .send_body(delivery.payload.clone())
+        .await;
+
+    match res {
+        Ok(r) if r.status().is_success() => {
+            delivery
+                .mark_delivered(pool, r.status().as_u16().into())
+                .await
+        }
+        Ok(r) => {
+            delivery
+                .mark_failed(
+                    pool,
+                    Some(r.status().as_u16().into()),
+                    &format!("Webhook responded with {}", r.status()),
+                )
+                .await
+        }
+        Err(e) => delivery.mark_failed(pool, None, &e.to_string()).await,
+    }
+}
+
+/// Attempts every delivery which is due for a retry. Returns the number of deliveries attempted.
+pub async fn process_queue(pool: &Db) -> Result<usize, sqlx::Error> {
+    let due = WebhookDelivery::get_due(pool, BATCH_SIZE).await?;
+    let count = due.len();
+    for delivery in due {
+        deliver(pool, delivery).await?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod webhooks_tests {
+    use crate::webhooks::sign;
+
+    #[test]
+    fn sign_payload() {
+        // Known value from RFC 4231 test case 2
+        assert_eq!(
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            sign("Jefe", "what do ya want for nothing?")
+        );
+    }
+}
@@ -350,6 +350,7 @@ pub fn checkbox_field(props: &CheckboxProps) -> Html {
 pub struct MultiSelectFieldProps {
     pub label: String,
     pub state: UseStateHandle<Vec<String>>,
+    pub required: Option<bool>,
     pub children: ChildrenWithProps<MultiSelectOption>,
 }
 
@@ -395,7 +396,7 @@ pub fn multi_select_field(props: &MultiSelectFieldProps) -> Html {
                 id={ label_sn.clone() }
                 multiple=true
                 name={ label_sn.clone() }
-                required=true
+                required={props.required.unwrap_or(true)}
                 {onchange}
             >
                 {
@@ -411,12 +412,85 @@ pub struct MultiSelectOptionProps {
     pub value: String,
     pub label: Option<String>,
     pub onclick: Option<Callback<MouseEvent>>,
+    pub selected: Option<bool>,
 }
 
 #[function_component(MultiSelectOption)]
 pub fn multi_select_option(props: &MultiSelectOptionProps) -> Html {
     html! {
-        <option value={ props.value.clone() } onclick={props.onclick.clone()}>{
+        <option
+            value={ props.value.clone() }
+            onclick={props.onclick.clone()}
+            selected={props.selected.unwrap_or_default()}
+        >{
+            props.label.clone().unwrap_or_else(|| props.value.clone())
+        }</option>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct SelectFieldProps {
+    pub label: String,
+    pub state: UseStateHandle<String>,
+    pub children: ChildrenWithProps<SelectOption>,
+}
+
+#[function_component(SelectField)]
+pub fn select_field(props: &SelectFieldProps) -> Html {
+    let label_sn = snake_case(props.label.clone());
+
+    let onchange = {
+        let state = props.state.clone();
+        Callback::from(move |e: Event| {
+            if let Some(input) = e.target_dyn_into::<HtmlSelectElement>() {
+                state.set(input.value());
+            }
+        })
+    };
+
+    let cl_input = classes!(
+        "border",
+        "text-sm",
+        "rounded-lg",
+        "block",
+        "w-full",
+        "p-2.5",
+        "bg-gray-700",
+        "border-gray-600",
+        "placeholder-gray-400",
+        "text-white",
+        "focus:ring-blue-500",
+        "focus:border-blue-500"
+    );
+
+    html! {
+        <div class="form-group">
+            <Label for_input={label_sn.clone()} value={ props.label.clone() } />
+            <select
+                class={cl_input}
+                id={ label_sn.clone() }
+                name={ label_sn.clone() }
+                {onchange}
+            >
+                {
+                    for props.children.iter()
+                }
+            </select>
+        </div>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct SelectOptionProps {
+    pub value: String,
+    pub label: Option<String>,
+    pub selected: Option<bool>,
+}
+
+#[function_component(SelectOption)]
+pub fn select_option(props: &SelectOptionProps) -> Html {
+    html! {
+        <option value={ props.value.clone() } selected={props.selected.unwrap_or_default()}>{
             props.label.clone().unwrap_or_else(|| props.value.clone())
         }</option>
     }
@@ -3,5 +3,6 @@ pub mod form;
 pub mod modal;
 pub mod nav;
 pub mod notifier;
+pub mod scan;
 pub mod session_provider;
 pub mod table;
@@ -1,6 +1,7 @@
 use crate::{
+    components::notifier::notify_error,
     routes::Route,
-    services::requests::get,
+    services::requests::{get, post},
     types::{SessionInfo, User},
 };
 use yew::prelude::*;
@@ -34,13 +35,24 @@ pub fn navbar() -> Html {
                         {
                             if user.is_admin {
                                 html!{
-                                    <NavLink label="Assign Key" route={Route::AssignKey}/>
+                                    <>
+                                        <NavLink label="Assign Key" route={Route::AssignKey}/>
+                                        <NavLink label="Audits" route={Route::Audits}/>
+                                        <NavLink label="Balances" route={Route::Balances}/>
+                                        <NavLink label="Jobs" route={Route::Jobs}/>
+                                        <NavLink label="Trash" route={Route::Trash}/>
+                                        <NavLink label="Webhooks" route={Route::Webhooks}/>
+                                    </>
                                 }
                             } else {html!{}}
                         }
                             <NavLink label="Assignments" route={Route::Assignments}/>
                             <NavLink label="Keys" route={Route::Keys}/>
+                            <NavLink label="Cabinets" route={Route::Cabinets}/>
                             <NavLink label="Users" route={Route::Users}/>
+                            <NavLink label="Groups" route={Route::Groups}/>
+                            <NavLink label="Request Keys" route={Route::NewRequest}/>
+                            <NavLink label="Requests" route={Route::Requests}/>
                         </>
                     }
                 } else {
@@ -48,6 +60,7 @@ pub fn navbar() -> Html {
                 }
             }
             <div class="grow"></div>
+            <OrgSwitcher user={(*user).clone()} />
             <UserMenu user={(*user).clone()} />
         </NavLinks>
     </Nav>
@@ -317,6 +330,66 @@ pub fn nav_dropdown_link(props: &NavLinkProps) -> Html {
     }
 }
 
+#[derive(Properties, PartialEq, Eq)]
+pub struct OrgSwitcherProps {
+    pub user: SessionInfo,
+}
+
+/// Lets users who belong to several orgs pick the one every page works in. The page is
+/// reloaded after switching since everything on it belongs to the previous org.
+#[function_component(OrgSwitcher)]
+pub fn org_switcher(props: &OrgSwitcherProps) -> Html {
+    if !props.user.is_auth || props.user.orgs.len() < 2 {
+        return html! {};
+    }
+
+    let classes = classes!(
+        "block",
+        "py-2",
+        "px-4",
+        "w-full",
+        "text-left",
+        "hover:bg-gray-600",
+        "hover:text-white"
+    );
+
+    let current = props
+        .user
+        .orgs
+        .iter()
+        .find(|m| Some(m.org_id) == props.user.org_id)
+        .map(|m| m.org.clone())
+        .unwrap_or_default();
+
+    html! {
+        <NavDropdown label={current}>
+        {
+            for props.user.orgs.iter().map(|m| {
+                let url = format!("/api/orgs/{}/switch", m.org_id);
+                let onclick = Callback::from(move |_: MouseEvent| {
+                    let url = url.clone();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        match post::<(), String>(url, ()).await {
+                            Ok(_) => {
+                                if let Some(window) = web_sys::window() {
+                                    let _ = window.location().reload();
+                                }
+                            }
+                            Err(e) => notify_error(&e.to_string()),
+                        }
+                    });
+                });
+                html! {
+                    <li>
+                        <button class={classes.clone()} {onclick}>{m.org.clone()}</button>
+                    </li>
+                }
+            })
+        }
+        </NavDropdown>
+    }
+}
+
 #[derive(Properties, PartialEq, Eq)]
 pub struct UserMenuProps {
     pub user: SessionInfo,
@@ -358,6 +431,14 @@ pub fn user_menu(props: &UserMenuProps) -> Html {
                         }
                     }
             />
+                <NavDropdownLink
+                    label="Notifications"
+                    route={
+                        Route::Notifications {
+                            username: props.user.username.clone().unwrap_or_default()
+                        }
+                    }
+                />
                 <NavDropdownLink label="Logout" route={Route::Logout} />
             </NavDropdown>
         }
@@ -0,0 +1,55 @@
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+
+#[derive(Properties, PartialEq)]
+pub struct ScanInputProps {
+    pub placeholder: String,
+    pub onscan: Callback<String>,
+}
+
+/// A text input for barcode scanners. Keyboard wedge scanners type the code and press Enter,
+/// which passes the value to `onscan` and clears the input for the next scan.
+#[function_component(ScanInput)]
+pub fn scan_input(props: &ScanInputProps) -> Html {
+    let cl_input = classes!(
+        "border",
+        "text-sm",
+        "rounded-lg",
+        "focus:ring-blue-500",
+        "focus:border-blue-500",
+        "block",
+        "w-full",
+        "p-2.5",
+        "bg-gray-600",
+        "border-gray-500",
+        "placeholder-gray-400",
+        "text-white"
+    );
+
+    let onkeydown = {
+        let onscan = props.onscan.clone();
+        Callback::from(move |e: KeyboardEvent| {
+            if e.key() != "Enter" {
+                return;
+            }
+            // Don't submit a surrounding form
+            e.prevent_default();
+            let input: HtmlInputElement = e.target_unchecked_into();
+            let value = input.value().trim().to_string();
+            input.set_value("");
+            if !value.is_empty() {
+                onscan.emit(value);
+            }
+        })
+    };
+
+    html! {
+        <input
+            class={cl_input}
+            type="text"
+            placeholder={props.placeholder.clone()}
+            autocomplete="off"
+            {onkeydown}
+        />
+    }
+}
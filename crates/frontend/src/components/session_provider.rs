@@ -20,6 +20,8 @@ pub fn session_provider(props: &SessionProviderProps) -> Html {
                     s.username = ui.username;
                     s.is_auth = ui.is_auth;
                     s.is_admin = ui.is_admin;
+                    s.org_id = ui.org_id;
+                    s.orgs = ui.orgs;
                     s.fetched = true;
                 });
             });
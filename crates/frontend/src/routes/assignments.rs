@@ -3,13 +3,18 @@ use std::vec::Vec;
 use crate::components::details_card::*;
 use crate::components::form::*;
 use crate::components::modal::Modal;
-use crate::components::notifier::notify_error;
+use crate::components::notifier::{notify_error, notify_info};
+use crate::components::scan::ScanInput;
 use crate::components::table::*;
+use crate::services::auth::current_user;
 use crate::services::form_actions::{get_options, ondelete, onload, submit_form};
-use crate::services::requests::get;
-use crate::services::{format_date, get_display_name, parse_date};
-use crate::theme::FORM_SUBTITLE;
-use crate::types::{Assignment, User};
+use crate::services::requests::{get, post};
+use crate::services::{
+    format_date, format_money, format_timestamp, get_display_name, holder_name, money_field,
+    parse_date, parse_money, slot_label, to_option, CURRENCY_PATTERN, MONEY_PATTERN,
+};
+use crate::theme::{BTN, BTN_DANGER, FORM_SUBTITLE, TEXT_DARK};
+use crate::types::{Assignment, AssignmentChange, AssignmentUpdate, Group, Slot, User};
 
 use yew::prelude::*;
 use yew_router::prelude::*;
@@ -22,18 +27,23 @@ pub fn new_assignment() -> Html {
     let available_users = use_state(Vec::new);
     let available_keys = use_state(Vec::new);
     let date_out = use_state(String::new);
+    let date_due = use_state(String::new);
     let selected_users = use_state(Vec::<String>::new);
     let selected_keys = use_state(Vec::<String>::new);
+    let selected_groups = use_state(Vec::<String>::new);
     let all_users = use_state(Vec::<User>::new);
+    let groups = use_state(Vec::<Group>::new);
 
     {
         let users = available_users.clone();
         let keys = available_keys.clone();
         let all_users = all_users.clone();
+        let groups = groups.clone();
         use_effect_with_deps(
             move |_| {
                 get_options(users, keys);
                 onload("/api/users".into(), all_users);
+                onload("/api/groups".into(), groups);
                 || ()
             },
             (),
@@ -43,13 +53,27 @@ pub fn new_assignment() -> Html {
     let onsubmit = {
         let mut assignments: Vec<Assignment> = Vec::new();
         let date_out = parse_date((*date_out).clone());
+        let date_due = to_option((*date_due).clone()).map(parse_date);
 
         for user in &*selected_users {
             for key in &*selected_keys {
                 let a = Assignment {
-                    user: user.into(),
+                    user: Some(user.into()),
                     key: key.into(),
                     date_out,
+                    date_due,
+                    ..Default::default()
+                };
+                assignments.push(a);
+            }
+        }
+        for group in &*selected_groups {
+            for key in &*selected_keys {
+                let a = Assignment {
+                    group: Some(group.into()),
+                    key: key.into(),
+                    date_out,
+                    date_due,
                     ..Default::default()
                 };
                 assignments.push(a);
@@ -70,9 +94,37 @@ pub fn new_assignment() -> Html {
         }
     });
 
-    let key_options = available_keys.iter().map(|key| {
+    let group_options = groups.iter().map(|group| {
+        html_nested! {
+            <MultiSelectOption value={ group.name.clone() } />
+        }
+    });
+
+    // Add scanned keys to the selection
+    let onscan = {
+        let available_keys = available_keys.clone();
+        let selected_keys = selected_keys.clone();
+        Callback::from(move |code: String| {
+            match available_keys
+                .iter()
+                .find(|k: &&String| k.eq_ignore_ascii_case(&code))
+            {
+                Some(key) if selected_keys.contains(key) => {
+                    notify_info(&format!("Key '{}' is already selected.", key))
+                }
+                Some(key) => {
+                    let mut keys = (*selected_keys).clone();
+                    keys.push(key.clone());
+                    selected_keys.set(keys);
+                }
+                None => notify_error(&format!("Key '{}' is not available.", code)),
+            }
+        })
+    };
+
+    let key_options = available_keys.iter().map(|key: &String| {
         html_nested! {
-            <MultiSelectOption value={key.to_string()} />
+            <MultiSelectOption value={key.to_string()} selected={selected_keys.contains(key)} />
         }
     });
 
@@ -80,13 +132,28 @@ pub fn new_assignment() -> Html {
         <CheckAuth admin=true>
             <div class="container my-5 mx-auto">
                 <Form title="Assign Key" {onsubmit}>
-                    <MultiSelectField label="User" state={selected_users}>
+                    <MultiSelectField
+                        label="User"
+                        required={selected_groups.is_empty()}
+                        state={selected_users.clone()}
+                    >
                         { for user_options.clone() }
                     </MultiSelectField>
-                    <MultiSelectField label="Key" state={selected_keys}>
+                    <MultiSelectField
+                        label="Group"
+                        required={selected_users.is_empty()}
+                        state={selected_groups.clone()}
+                    >
+                        { for group_options }
+                    </MultiSelectField>
+                    <div class="form-group">
+                        <ScanInput placeholder="Scan a key to add it" {onscan} />
+                    </div>
+                    <MultiSelectField label="Key" state={selected_keys.clone()}>
                         { for key_options }
                     </MultiSelectField>
                     <DateField label="Date Out" required=true state={date_out} />
+                    <DateField label="Date Due" state={date_due} />
                     <Button value="Assign Key" button_type={ButtonType::Primary} />
                     {" "}
                     <RouteButton value="Cancel" route={Route::Assignments} />
@@ -103,20 +170,33 @@ pub struct AssignmentProps {
 
 #[function_component(EditAssignment)]
 pub fn edit_assignment(props: &AssignmentProps) -> Html {
-    let user = use_state(String::new);
+    let user = use_state(|| None::<String>);
+    let group = use_state(|| None::<String>);
     let key = use_state(String::new);
     let date_out = use_state(String::new);
     let date_in = use_state(String::new);
+    let date_due = use_state(String::new);
+    let slot_id = use_state(String::new);
+    let deposit_collected = use_state(String::new);
+    let deposit_refunded = use_state(String::new);
+    let currency = use_state(String::new);
 
     let show_modal = use_state(|| false);
     let users = use_state(Vec::<User>::new);
+    let slots = use_state(Vec::<Slot>::new);
 
     {
         let user = user.clone();
+        let group = group.clone();
         let key = key.clone();
         let date_out = date_out.clone();
         let date_in = date_in.clone();
+        let date_due = date_due.clone();
+        let deposit_collected = deposit_collected.clone();
+        let deposit_refunded = deposit_refunded.clone();
+        let currency = currency.clone();
         let users = users.clone();
+        let slots = slots.clone();
         let url = format!("/api/assignments/{}", &props.id.clone());
         use_effect_with_deps(
             move |_| {
@@ -124,17 +204,23 @@ pub fn edit_assignment(props: &AssignmentProps) -> Html {
                     match get::<Assignment>(url).await {
                         Ok(a) => {
                             user.set(a.user);
+                            group.set(a.group);
                             key.set(a.key);
                             date_out.set(format_date(a.date_out));
                             date_in.set(match a.date_in {
                                 Some(d) => format_date(d),
                                 None => "".into(),
                             });
+                            date_due.set(a.date_due.map(format_date).unwrap_or_default());
+                            deposit_collected.set(money_field(a.deposit_collected));
+                            deposit_refunded.set(money_field(a.deposit_refunded));
+                            currency.set(a.deposit_currency.unwrap_or_default());
                         }
                         Err(e) => notify_error(&e.to_string()),
                     }
                 });
                 onload("/api/users".into(), users);
+                onload("/api/slots".into(), slots);
                 || ()
             },
             (),
@@ -144,6 +230,7 @@ pub fn edit_assignment(props: &AssignmentProps) -> Html {
     let onsubmit = {
         let assignment = Assignment {
             user: (*user).clone(),
+            group: (*group).clone(),
             key: (*key).clone(),
             date_out: parse_date((*date_out).clone()),
             date_in: if (*date_in).clone().is_empty() {
@@ -151,13 +238,27 @@ pub fn edit_assignment(props: &AssignmentProps) -> Html {
             } else {
                 Some(parse_date((*date_in).clone()))
             },
+            date_due: to_option((*date_due).clone()).map(parse_date),
+            deposit_collected: parse_money(&deposit_collected),
+            deposit_refunded: parse_money(&deposit_refunded),
+            deposit_currency: to_option((*currency).clone()),
             ..Default::default()
         };
+        let update = AssignmentUpdate {
+            assignment,
+            slot_id: (*slot_id).parse().ok(),
+        };
         let history = use_history().unwrap();
         let path = format!("/api/assignments/{}", props.id);
-        submit_form(path, assignment, history, Route::Assignments)
+        submit_form(path, update, history, Route::Assignments)
     };
 
+    let slot_options = slots.iter().filter(|s| s.key.is_none()).map(|s| {
+        html_nested! {
+            <SelectOption value={s.id.to_string()} label={slot_label(s)} />
+        }
+    });
+
     let delete_action = {
         let history = use_history().unwrap();
         let path = format!("/api/assignments/{}", props.id);
@@ -169,13 +270,34 @@ pub fn edit_assignment(props: &AssignmentProps) -> Html {
             <div class="container my-5 mx-auto">
                 <Form title="Edit Assignment" {onsubmit}>
                     <h6 class={FORM_SUBTITLE}>
-                        { format!("User: {}", get_display_name(&(*users), (*user).clone())) }
+                        {
+                            match (*user).clone() {
+                                Some(u) => format!("User: {}", get_display_name(&(*users), u)),
+                                None => format!("Group: {}", (*group).clone().unwrap_or_default()),
+                            }
+                        }
                     </h6>
                     <h6 class={FORM_SUBTITLE}>
                         { format!("Key: {}", (*key).clone()) }
                     </h6>
                     <DateField label="Date Out" state={date_out}/>
-                    <DateField label="Date In" state={date_in}/>
+                    <DateField label="Date In" state={date_in.clone()}/>
+                    <DateField label="Date Due" state={date_due}/>
+                    <TextField label="Deposit Collected" pattern={MONEY_PATTERN} state={deposit_collected} />
+                    <TextField label="Deposit Refunded" pattern={MONEY_PATTERN} state={deposit_refunded} />
+                    <TextField label="Currency" pattern={CURRENCY_PATTERN} state={currency} />
+                    {
+                        if date_in.is_empty() {
+                            html! {}
+                        } else {
+                            html! {
+                                <SelectField label="Return To" state={slot_id}>
+                                    <SelectOption value="" label="-" />
+                                    { for slot_options }
+                                </SelectField>
+                            }
+                        }
+                    }
                     <Button
                         value="Update Assignment"
                         button_type={ButtonType::Primary}
@@ -191,7 +313,7 @@ pub fn edit_assignment(props: &AssignmentProps) -> Html {
                 </Form>
                 <Modal
                     title="Delete Assignment"
-                    msg="Are you sure you want to delete this assignment?"
+                    msg="Are you sure you want to delete this assignment? It will be moved to the trash."
                     confirm_action={delete_action}
                     {show_modal}
                 />
@@ -222,7 +344,7 @@ pub fn assignments() -> Html {
         html_nested! {
             <Row>
                 <CellLink
-                    value={get_display_name(&all_users, a.user.clone())}
+                    value={holder_name(&all_users, a)}
                     route={Route::AssignmentDetails { id: a.id }}
                 />
                 <Cell value={a.key.clone()} />
@@ -242,7 +364,7 @@ pub fn assignments() -> Html {
             <div class="container my-5 mx-auto max-w-4xl">
                 <TableCard
                     title="Assignments"
-                    headings={vec!["User", "Key", "Date Out", "Date In"]}
+                    headings={vec!["Holder", "Key", "Date Out", "Date In"]}
                     button_label="Assign Key"
                     button_route={Route::AssignKey}
                 >
@@ -253,26 +375,114 @@ pub fn assignments() -> Html {
     }
 }
 
+/// Describes an entry in an assignment's history, e.g. "Date due: - → 2022-10-03".
+fn describe_change(change: &AssignmentChange, currency: &str) -> String {
+    let field = change.field.clone().unwrap_or_default();
+    let value = |v: &Option<String>| match (field.as_str(), v) {
+        ("deposit_collected" | "deposit_refunded", Some(v)) => v
+            .parse()
+            .map(|a| format_money(a, currency))
+            .unwrap_or_else(|_| v.clone()),
+        (_, Some(v)) => v.clone(),
+        (_, None) => "-".into(),
+    };
+    match change.action.as_str() {
+        "created" => "Created".into(),
+        "confirmed" => "Confirmed".into(),
+        "lost" => format!("Reported lost on {}", value(&change.new_value)),
+        _ => {
+            let label = match field.as_str() {
+                "user" => "User",
+                "group" => "Group",
+                "key" => "Key",
+                "date_out" => "Date assigned",
+                "date_in" => "Date returned",
+                "date_due" => "Date due",
+                "deposit_collected" => "Deposit collected",
+                "deposit_refunded" => "Deposit refunded",
+                "deposit_currency" => "Deposit currency",
+                f => f,
+            };
+            format!(
+                "{}: {} → {}",
+                label,
+                value(&change.old_value),
+                value(&change.new_value)
+            )
+        }
+    }
+}
+
 #[function_component(AssignmentDetails)]
 pub fn assignment_details(props: &AssignmentProps) -> Html {
     let assignment = use_state(Assignment::default);
     let users = use_state(Vec::<User>::new);
+    let history = use_state(Vec::<AssignmentChange>::new);
 
     {
         let assignment = assignment.clone();
         let users = users.clone();
+        let history = history.clone();
         let assignment_url = format!("/api/assignments/{}", &props.id);
+        let history_url = format!("/api/assignments/{}/history", &props.id);
         use_effect_with_deps(
             move |_| {
                 onload(assignment_url, assignment);
                 onload("/api/users".into(), users);
+                onload(history_url, history);
                 || ()
             },
             (),
         );
     }
 
+    let show_modal = use_state(|| false);
+    let report_lost = {
+        let assignment = assignment.clone();
+        let history = history.clone();
+        let show_modal = show_modal.clone();
+        let path = format!("/api/assignments/{}/lost", props.id);
+        let assignment_url = format!("/api/assignments/{}", props.id);
+        let history_url = format!("/api/assignments/{}/history", props.id);
+        Callback::from(move |_: MouseEvent| {
+            let assignment = assignment.clone();
+            let history = history.clone();
+            let path = path.clone();
+            let assignment_url = assignment_url.clone();
+            let history_url = history_url.clone();
+            show_modal.set(false);
+            wasm_bindgen_futures::spawn_local(async move {
+                match post::<(), String>(path, ()).await {
+                    Ok(data) => {
+                        notify_info(&data);
+                        onload(assignment_url, assignment);
+                        onload(history_url, history);
+                    }
+                    Err(e) => notify_error(&e.to_string()),
+                }
+            })
+        })
+    };
+    let onclick_lost = {
+        let show_modal = show_modal.clone();
+        Callback::from(move |_: MouseEvent| show_modal.set(true))
+    };
+
     let assignment = (*assignment).clone();
+    let currency = assignment.deposit_currency.clone().unwrap_or_default();
+    let deposit = |amount: Option<i64>| match amount {
+        Some(a) => format_money(a, &currency),
+        None => "-".into(),
+    };
+    let history_rows = history.iter().map(|c| {
+        html_nested! {
+            <Row>
+                <Cell value={format_timestamp(c.changed_at)} />
+                <Cell value={c.changed_by.clone().map(|u| get_display_name(&users, u)).unwrap_or_else(|| "-".into())} />
+                <Cell value={describe_change(c, &currency)} />
+            </Row>
+        }
+    });
     html! {
         <CheckAuth>
             <DetailsCard
@@ -280,9 +490,20 @@ pub fn assignment_details(props: &AssignmentProps) -> Html {
                 edit_route={Route::EditAssignment { id: assignment.id }}
             >
                 <DetailsHeader>
-                    <DetailsHeaderItem content={format!("Assigned to: {}", get_display_name(&(*users), assignment.user))} />
+                    <DetailsHeaderItem content={format!("Assigned to: {}", holder_name(&users, &assignment))} />
                     <DetailsHeaderItem content={format!("Key: {}", assignment.key)} />
+                    <DetailsHeaderItem content={format!("Pending confirmation: {}", assignment.pending)} />
+                    <DetailsHeaderItem content={format!("Issued by: {}", assignment.created_by.clone().map(|u| get_display_name(&users, u)).unwrap_or_else(|| "-".into()))} />
+                    <DetailsHeaderItem content={format!("Confirmed by: {}", assignment.confirmed_by.clone().map(|u| get_display_name(&users, u)).unwrap_or_else(|| "-".into()))} />
                     <DetailsHeaderItem content={format!("Date assigned: {}", assignment.date_out)} />
+                    <DetailsHeaderItem
+                        content={
+                            match assignment.date_due {
+                                Some(d) => format!("Date due: {}", d),
+                                None => "Date due: -".into(),
+                            }
+                        }
+                    />
                     <DetailsHeaderItem
                         content={
                             match assignment.date_in {
@@ -291,9 +512,35 @@ pub fn assignment_details(props: &AssignmentProps) -> Html {
                             }
                         }
                     />
+                    <DetailsHeaderItem content={format!("Reported lost: {}", assignment.lost)} />
+                    <DetailsHeaderItem content={format!("Deposit collected: {}", deposit(assignment.deposit_collected))} />
+                    <DetailsHeaderItem content={format!("Deposit refunded: {}", deposit(assignment.deposit_refunded))} />
                 </DetailsHeader>
+                {
+                    if current_user().is_admin && assignment.date_in.is_none() {
+                        html! {
+                            <div class="text-right">
+                                <button class={classes!(BTN, BTN_DANGER)} onclick={onclick_lost}>
+                                    {"Report Lost"}
+                                </button>
+                            </div>
+                        }
+                    } else {
+                        html! {}
+                    }
+                }
+                <h3 class={classes!(TEXT_DARK, "text-lg", "font-semibold", "mt-6", "ml-4")}>{"History"}</h3>
+                <Table headings={vec!["When", "By", "Change"]}>
+                    { for history_rows }
+                </Table>
                 <DetailsFooter/>
             </DetailsCard>
+            <Modal
+                title="Report Key Lost"
+                msg="Are you sure this key is lost? The assignment will be closed and the key deactivated."
+                confirm_action={report_lost}
+                {show_modal}
+            />
         </CheckAuth>
     }
 }
@@ -0,0 +1,505 @@
+use std::vec::Vec;
+
+use crate::components::form::*;
+use crate::components::modal::Modal;
+use crate::components::notifier::{notify_error, notify_info};
+use crate::components::table::*;
+use crate::services::form_actions::{ondelete, onload, submit_form};
+use crate::services::requests::post;
+use crate::services::{format_timestamp, get_display_name, to_option};
+use crate::theme::*;
+use crate::types::{Audit, AuditCheck, AuditClose, AuditItem, AuditResult, User};
+
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use super::auth::CheckAuth;
+use super::Route;
+
+fn format_result(result: Option<AuditResult>) -> String {
+    result
+        .map(|r| r.to_string())
+        .unwrap_or_else(|| "Not checked".into())
+}
+
+#[function_component(NewAudit)]
+pub fn new_audit() -> Html {
+    let name = use_state(String::new);
+
+    let onsubmit = {
+        let audit = Audit {
+            name: (*name).clone(),
+            ..Default::default()
+        };
+        let history = use_history().unwrap();
+        submit_form("/api/audits".to_string(), audit, history, Route::Audits)
+    };
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto">
+                <Form
+                    title="Start Audit"
+                    subtitle="Every key which is currently assigned will be added to the checklist."
+                    {onsubmit}
+                >
+                    <TextField label="Audit Name" required=true state={name} />
+                    <Button value="Start Audit" button_type={ButtonType::Primary} />
+                    {" "}
+                    <RouteButton value="Cancel" route={Route::Audits} />
+                </Form>
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[function_component(AuditTable)]
+pub fn audit_table() -> Html {
+    let audits = use_state(Vec::<Audit>::new);
+
+    {
+        let audits = audits.clone();
+        use_effect_with_deps(
+            move |_| {
+                onload("/api/audits".into(), audits);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let rows = audits.iter().map(|a| {
+        let (status, route) = match a.closed_at {
+            Some(t) => (
+                format!("Closed {}", format_timestamp(t)),
+                Route::AuditReport { id: a.id },
+            ),
+            None => ("In progress".into(), Route::AuditChecklist { id: a.id }),
+        };
+        html_nested! {
+            <Row>
+                <CellLink value={a.name.clone()} {route} />
+                <Cell value={a.started_at.map(format_timestamp).unwrap_or_else(|| "-".into())} />
+                <Cell value={format!("{} / {}", a.items - a.unchecked, a.items)} />
+                <Cell value={a.discrepancies.to_string()} />
+                <Cell value={status} />
+            </Row>
+        }
+    });
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto max-w-4xl">
+                <TableCard
+                    title="Audits"
+                    headings={vec!["Audit", "Started", "Checked", "Discrepancies", "Status"]}
+                    button_label="Start Audit"
+                    button_route={Route::StartAudit}
+                >
+                    { for rows }
+                </TableCard>
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[derive(PartialEq, Eq, Properties)]
+pub struct AuditProps {
+    pub id: i64,
+}
+
+#[derive(PartialEq, Properties)]
+struct AuditItemCardProps {
+    item: AuditItem,
+    users: Vec<User>,
+    closed: bool,
+    on_checked: Callback<AuditItem>,
+}
+
+/// One line of the checklist. Sized for tapping on a phone.
+#[function_component(AuditItemCard)]
+fn audit_item_card(props: &AuditItemCardProps) -> Html {
+    let holder = use_state(|| props.item.actual_holder.clone().unwrap_or_default());
+    let note = use_state(|| props.item.note.clone().unwrap_or_default());
+    let show_holder = use_state(|| false);
+
+    let check = |result: AuditResult| {
+        let holder = holder.clone();
+        let note = note.clone();
+        let show_holder = show_holder.clone();
+        let on_checked = props.on_checked.clone();
+        let path = format!("/api/audit-items/{}", props.item.id);
+        Callback::from(move |_: MouseEvent| {
+            let body = AuditCheck {
+                result,
+                actual_holder: to_option((*holder).clone()),
+                note: to_option((*note).clone()),
+            };
+            let path = path.clone();
+            let on_checked = on_checked.clone();
+            let show_holder = show_holder.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match post::<AuditCheck, AuditItem>(path, body).await {
+                    Ok(item) => {
+                        show_holder.set(false);
+                        on_checked.emit(item);
+                    }
+                    Err(e) => notify_error(&e.to_string()),
+                }
+            })
+        })
+    };
+
+    let onclick_wrong_holder = {
+        let show_holder = show_holder.clone();
+        Callback::from(move |_: MouseEvent| show_holder.set(!*show_holder))
+    };
+
+    let item = &props.item;
+    let cl_result = match item.result {
+        Some(AuditResult::Verified) => "text-green-500",
+        Some(_) => "text-red-500",
+        None => TEXT_GRAY,
+    };
+    let cl_big_btn = classes!(
+        "text-white",
+        "font-medium",
+        "rounded-lg",
+        "w-full",
+        "py-3",
+        "cursor-pointer"
+    );
+    let holder_name = match &item.actual_holder {
+        Some(h) => format!(" ({})", get_display_name(&props.users, h.clone())),
+        None => String::new(),
+    };
+
+    html! {
+        <div class={classes!(BG_SEC_DARK, TEXT_DARK, "rounded-lg", "shadow-md", "p-4", "mb-3")}>
+            <div class="flex justify-between items-baseline">
+                <span class="text-xl font-semibold">{&item.key}</span>
+                <span class={cl_result}>{format_result(item.result)}{holder_name}</span>
+            </div>
+            <div class={TEXT_GRAY}>{get_display_name(&props.users, item.user.clone())}</div>
+            {
+                match &item.note {
+                    Some(n) => html! { <div class="text-sm italic">{n}</div> },
+                    None => html! {},
+                }
+            }
+            {
+                if props.closed {
+                    html! {}
+                } else {
+                    html! {
+                        <>
+                            <div class="grid grid-cols-3 gap-2 mt-3">
+                                <button class={classes!(cl_big_btn.clone(), BTN_PRIMARY)} onclick={check(AuditResult::Verified)}>
+                                    {"Verified"}
+                                </button>
+                                <button class={classes!(cl_big_btn.clone(), BTN_DANGER)} onclick={check(AuditResult::Missing)}>
+                                    {"Missing"}
+                                </button>
+                                <button class={classes!(cl_big_btn.clone(), BTN_SECONDARY)} onclick={onclick_wrong_holder}>
+                                    {"Wrong Holder"}
+                                </button>
+                            </div>
+                            {
+                                if *show_holder {
+                                    let options = props.users.iter().map(|u| html_nested! {
+                                        <SelectOption
+                                            value={u.username.clone()}
+                                            label={u.display_name.clone().unwrap_or_else(|| u.username.clone())}
+                                            selected={*holder == u.username}
+                                        />
+                                    });
+                                    html! {
+                                        <div class="space-y-3 mt-3">
+                                            <SelectField label="Actual Holder" state={holder.clone()}>
+                                                <SelectOption value="" label="Select a user" />
+                                                { for options }
+                                            </SelectField>
+                                            <TextField label="Note" state={note.clone()} />
+                                            <button
+                                                class={classes!(cl_big_btn, BTN_PRIMARY)}
+                                                onclick={check(AuditResult::WrongHolder)}
+                                            >
+                                                {"Save"}
+                                            </button>
+                                        </div>
+                                    }
+                                } else {
+                                    html! {}
+                                }
+                            }
+                        </>
+                    }
+                }
+            }
+        </div>
+    }
+}
+
+/// The checklist admins work through while walking the building.
+#[function_component(AuditChecklist)]
+pub fn audit_checklist(props: &AuditProps) -> Html {
+    let audit = use_state(Audit::default);
+    let items = use_state(Vec::<AuditItem>::new);
+    let users = use_state(Vec::<User>::new);
+    let search = use_state(String::new);
+    let unchecked_only = use_state(|| false);
+    let report_missing = use_state(|| true);
+    let show_modal = use_state(|| false);
+
+    {
+        let audit = audit.clone();
+        let items = items.clone();
+        let users = users.clone();
+        let audit_url = format!("/api/audits/{}", props.id);
+        let items_url = format!("/api/audits/{}/items", props.id);
+        use_effect_with_deps(
+            move |_| {
+                onload(audit_url, audit);
+                onload(items_url, items);
+                onload("/api/users".into(), users);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let on_checked = {
+        let items = items.clone();
+        Callback::from(move |checked: AuditItem| {
+            let updated = items
+                .iter()
+                .map(|i| {
+                    if i.id == checked.id {
+                        checked.clone()
+                    } else {
+                        i.clone()
+                    }
+                })
+                .collect();
+            items.set(updated);
+        })
+    };
+
+    let oninput_search = {
+        let search = search.clone();
+        Callback::from(move |e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            search.set(input.value());
+        })
+    };
+
+    let close_action = {
+        let history = use_history().unwrap();
+        let report_missing = report_missing.clone();
+        let show_modal = show_modal.clone();
+        let path = format!("/api/audits/{}/close", props.id);
+        let id = props.id;
+        Callback::from(move |_: MouseEvent| {
+            let history = history.clone();
+            let path = path.clone();
+            let body = AuditClose {
+                report_missing: *report_missing,
+            };
+            show_modal.set(false);
+            wasm_bindgen_futures::spawn_local(async move {
+                match post::<AuditClose, String>(path, body).await {
+                    Ok(data) => {
+                        notify_info(&data);
+                        history.push(Route::AuditReport { id });
+                    }
+                    Err(e) => notify_error(&e.to_string()),
+                }
+            })
+        })
+    };
+
+    let onclick_close = {
+        let show_modal = show_modal.clone();
+        Callback::from(move |_: MouseEvent| show_modal.set(true))
+    };
+
+    let search_term = search.to_lowercase();
+    let closed = audit.closed_at.is_some();
+    let checked = items.iter().filter(|i| i.result.is_some()).count();
+    let cards = items
+        .iter()
+        .filter(|i| !*unchecked_only || i.result.is_none())
+        .filter(|i| {
+            search_term.is_empty()
+                || i.key.to_lowercase().contains(&search_term)
+                || get_display_name(&users, i.user.clone())
+                    .to_lowercase()
+                    .contains(&search_term)
+        })
+        .map(|i| {
+            html! {
+                <AuditItemCard
+                    key={i.id}
+                    item={i.clone()}
+                    users={(*users).clone()}
+                    {closed}
+                    on_checked={on_checked.clone()}
+                />
+            }
+        });
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container mx-auto max-w-lg px-2 my-5">
+                <div class={classes!(DETAIL_CARD)}>{audit.name.clone()}</div>
+                <div class={classes!(TEXT_DARK, "mb-3")}>
+                    {format!("{} of {} checked", checked, items.len())}
+                    {
+                        if closed {
+                            html! {
+                                <>
+                                    {" · Closed · "}
+                                    <Link<Route> classes={TEXT_BLUE} to={Route::AuditReport { id: props.id }}>
+                                        {"View report"}
+                                    </Link<Route>>
+                                </>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                </div>
+                <input
+                    class="border text-base rounded-lg block w-full p-3 mb-3 bg-gray-600 border-gray-500 placeholder-gray-400 text-white"
+                    type="search"
+                    placeholder="Search keys or holders"
+                    value={(*search).clone()}
+                    oninput={oninput_search}
+                />
+                <div class={classes!(TEXT_DARK, "mb-3")}>
+                    <CheckboxField label="Only show unchecked keys" state={unchecked_only.clone()} />
+                </div>
+                { for cards }
+                {
+                    if closed {
+                        html! {}
+                    } else {
+                        html! {
+                            <div class={classes!(BG_SEC_DARK, TEXT_DARK, "rounded-lg", "p-4", "mt-5")}>
+                                <CheckboxField label="Report missing keys as lost" state={report_missing} />
+                                <button class={classes!(BTN, BTN_DANGER, "w-full", "py-3", "mt-3")} onclick={onclick_close}>
+                                    {"Close Audit"}
+                                </button>
+                            </div>
+                        }
+                    }
+                }
+                <Modal
+                    title="Close Audit"
+                    msg="Are you sure you want to close this audit? Items can't be checked once it is closed."
+                    confirm_action={close_action}
+                    {show_modal}
+                />
+            </div>
+        </CheckAuth>
+    }
+}
+
+/// The items of an audit which weren't verified.
+#[function_component(AuditReport)]
+pub fn audit_report(props: &AuditProps) -> Html {
+    let audit = use_state(Audit::default);
+    let items = use_state(Vec::<AuditItem>::new);
+    let users = use_state(Vec::<User>::new);
+    let show_modal = use_state(|| false);
+
+    {
+        let audit = audit.clone();
+        let items = items.clone();
+        let users = users.clone();
+        let audit_url = format!("/api/audits/{}", props.id);
+        let report_url = format!("/api/audits/{}/report", props.id);
+        use_effect_with_deps(
+            move |_| {
+                onload(audit_url, audit);
+                onload(report_url, items);
+                onload("/api/users".into(), users);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let delete_action = {
+        let history = use_history().unwrap();
+        let path = format!("/api/audits/{}", props.id);
+        ondelete(path, history, Route::Audits)
+    };
+
+    let onclick_delete = {
+        let show_modal = show_modal.clone();
+        Callback::from(move |_: MouseEvent| show_modal.set(true))
+    };
+
+    let rows = items.iter().map(|i| {
+        html_nested! {
+            <Row>
+                <Cell value={get_display_name(&users, i.user.clone())} />
+                <Cell value={i.key.clone()} />
+                <Cell value={format_result(i.result)} />
+                <Cell value={i.actual_holder.clone().map(|h| get_display_name(&users, h)).unwrap_or_else(|| "-".into())} />
+                <Cell value={i.note.clone().unwrap_or_else(|| "-".into())} />
+                <Cell value={if i.lost_reported { "Yes" } else { "No" }} />
+            </Row>
+        }
+    });
+
+    let status = match audit.closed_at {
+        Some(t) => format!(
+            "Closed {} by {}",
+            format_timestamp(t),
+            get_display_name(&users, audit.closed_by.clone().unwrap_or_default())
+        ),
+        None => "In progress".into(),
+    };
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto max-w-4xl">
+                <TableCard
+                    title={format!("{}: Discrepancies", audit.name)}
+                    headings={vec!["Holder", "Key", "Result", "Actual Holder", "Note", "Reported Lost"]}
+                >
+                    { for rows }
+                </TableCard>
+                <div class={classes!(TEXT_DARK, "my-3")}>
+                    {format!("{} of {} keys verified. {}", audit.items - audit.discrepancies - audit.unchecked, audit.items, status)}
+                </div>
+                <a class={classes!(BTN, BTN_PRIMARY)} href={format!("/api/audits/{}/report.csv", props.id)}>
+                    {"Download CSV"}
+                </a>
+                {
+                    if audit.closed_at.is_none() {
+                        html! {
+                            <Link<Route> classes={classes!(BTN, BTN_SECONDARY)} to={Route::AuditChecklist { id: props.id }}>
+                                {"Back to Checklist"}
+                            </Link<Route>>
+                        }
+                    } else {
+                        html! {}
+                    }
+                }
+                <button class={classes!(BTN, BTN_DANGER)} onclick={onclick_delete}>
+                    {"Delete Audit"}
+                </button>
+                <Modal
+                    title="Delete Audit"
+                    msg="Are you sure you want to delete this audit and its results?"
+                    confirm_action={delete_action}
+                    {show_modal}
+                />
+            </div>
+        </CheckAuth>
+    }
+}
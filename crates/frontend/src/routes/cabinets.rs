@@ -0,0 +1,250 @@
+use std::vec::Vec;
+
+use crate::components::details_card::*;
+use crate::components::form::*;
+use crate::components::modal::Modal;
+use crate::components::notifier::notify_error;
+use crate::components::table::*;
+use crate::services::form_actions::{ondelete, onload, submit_form};
+use crate::services::requests::get;
+use crate::services::to_option;
+use crate::types::{Cabinet, CabinetPayload, Slot};
+
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use super::auth::CheckAuth;
+use super::Route;
+
+#[function_component(NewCabinet)]
+pub fn new_cabinet() -> Html {
+    let name = use_state(String::new);
+    let description = use_state(String::new);
+    let slots = use_state(String::new);
+
+    let onsubmit = {
+        let cabinet = CabinetPayload {
+            name: (*name).clone(),
+            description: to_option((*description).clone()),
+            slots: (*slots).parse().ok(),
+        };
+        let history = use_history().unwrap();
+        submit_form(
+            "/api/cabinets".to_string(),
+            cabinet,
+            history,
+            Route::Cabinets,
+        )
+    };
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto">
+                <Form title="New Cabinet" {onsubmit}>
+                    <TextField label="Cabinet Name" required=true state={name} />
+                    <TextField label="Description" state={description} />
+                    <TextField label="Hooks" required=true pattern="[0-9]+" state={slots} />
+                    <Button value="Add Cabinet" button_type={ButtonType::Primary} />
+                    {" "}
+                    <RouteButton value="Cancel" route={Route::Cabinets} />
+                </Form>
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[derive(PartialEq, Eq, Properties)]
+pub struct CabinetProps {
+    pub id: i64,
+}
+
+#[function_component(EditCabinet)]
+pub fn edit_cabinet(props: &CabinetProps) -> Html {
+    let name = use_state(String::new);
+    let description = use_state(String::new);
+    let slots = use_state(String::new);
+
+    let show_modal = use_state(|| false);
+
+    {
+        let name = name.clone();
+        let description = description.clone();
+        let slots = slots.clone();
+        let url = format!("/api/cabinets/{}", props.id);
+        let slots_url = format!("/api/cabinets/{}/slots", props.id);
+        use_effect_with_deps(
+            move |_| {
+                wasm_bindgen_futures::spawn_local(async move {
+                    match get::<Cabinet>(url).await {
+                        Ok(c) => {
+                            name.set(c.name);
+                            description.set(c.description.unwrap_or_default());
+                        }
+                        Err(e) => notify_error(&e.to_string()),
+                    }
+                    match get::<Vec<Slot>>(slots_url).await {
+                        Ok(s) => slots.set(s.len().to_string()),
+                        Err(e) => notify_error(&e.to_string()),
+                    }
+                });
+                || ()
+            },
+            (),
+        );
+    }
+
+    let onsubmit = {
+        let cabinet = CabinetPayload {
+            name: (*name).clone(),
+            description: to_option((*description).clone()),
+            slots: (*slots).parse().ok(),
+        };
+        let history = use_history().unwrap();
+        let path = format!("/api/cabinets/{}", props.id);
+        submit_form(
+            path,
+            cabinet,
+            history,
+            Route::CabinetDetails { id: props.id },
+        )
+    };
+
+    let delete_action = {
+        let history = use_history().unwrap();
+        let path = format!("/api/cabinets/{}", props.id);
+        ondelete(path, history, Route::Cabinets)
+    };
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto">
+                <Form title="Edit Cabinet" {onsubmit}>
+                    <TextField label="Cabinet Name" required=true state={name} />
+                    <TextField label="Description" state={description} />
+                    <TextField label="Hooks" required=true pattern="[0-9]+" state={slots} />
+                    <Button value="Update Cabinet" button_type={ButtonType::Primary} />
+                    {" "}
+                    <DeleteButton
+                        value="Delete Cabinet"
+                        route={Route::Cabinets}
+                        show_modal={show_modal.clone()}
+                    />
+                    {" "}
+                    <RouteButton value="Cancel" route={Route::CabinetDetails { id: props.id }} />
+                </Form>
+                <Modal
+                    title="Delete Cabinet"
+                    msg="Are you sure you want to delete this cabinet? Keys stored in it will no longer have a location."
+                    confirm_action={delete_action}
+                    {show_modal}
+                />
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[function_component(CabinetTable)]
+pub fn cabinet_table() -> Html {
+    let cabinets = use_state(Vec::<Cabinet>::new);
+    let slots = use_state(Vec::<Slot>::new);
+
+    {
+        let cabinets = cabinets.clone();
+        let slots = slots.clone();
+        use_effect_with_deps(
+            move |_| {
+                onload("/api/cabinets".into(), cabinets);
+                onload("/api/slots".into(), slots);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let rows = cabinets.iter().map(|cabinet| {
+        let cabinet_slots = slots.iter().filter(|s| s.cabinet_id == cabinet.id);
+        let total = cabinet_slots.clone().count();
+        let occupied = cabinet_slots.filter(|s| s.key.is_some()).count();
+        html_nested! {
+            <Row>
+                <CellLink value={cabinet.name.clone()} route={Route::CabinetDetails { id: cabinet.id }} />
+                <Cell value={cabinet.description.clone().unwrap_or_else(|| "-".into())} />
+                <Cell value={format!("{} / {}", occupied, total)} />
+            </Row>
+        }
+    });
+
+    html! {
+        <CheckAuth>
+            <div class="container my-5 mx-auto max-w-4xl">
+                <TableCard
+                    title="Cabinets"
+                    headings={vec!["Cabinet", "Description", "Hooks Occupied"]}
+                    button_label="Add Cabinet"
+                    button_route={Route::AddCabinet}
+                >
+                    { for rows }
+                </TableCard>
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[function_component(CabinetDetails)]
+pub fn cabinet_details(props: &CabinetProps) -> Html {
+    let cabinet = use_state(Cabinet::default);
+    let slots = use_state(Vec::<Slot>::new);
+
+    {
+        let cabinet = cabinet.clone();
+        let slots = slots.clone();
+        let cabinet_url = format!("/api/cabinets/{}", props.id);
+        let slots_url = format!("/api/cabinets/{}/slots", props.id);
+        use_effect_with_deps(
+            move |_| {
+                onload(cabinet_url, cabinet);
+                onload(slots_url, slots);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let occupied = slots.iter().filter(|s| s.key.is_some()).count();
+
+    let rows = slots.iter().map(|s| match s.key.clone() {
+        Some(key) => html_nested! {
+            <Row>
+                <Cell value={s.number.to_string()} />
+                <CellLink value={key.clone()} route={Route::KeyDetails { key_name: key.clone() }} />
+                <Cell value="Occupied" />
+            </Row>
+        },
+        None => html_nested! {
+            <Row>
+                <Cell value={s.number.to_string()} />
+                <Cell value="-" />
+                <Cell value="Empty" />
+            </Row>
+        },
+    });
+
+    let cabinet = (*cabinet).clone();
+    html! {
+        <CheckAuth>
+            <DetailsCard
+                title={format!("Cabinet {}", cabinet.name)}
+                edit_route={Route::EditCabinet { id: props.id }}
+            >
+                <DetailsHeader>
+                    <DetailsHeaderItem content={format!("Description: {}", cabinet.description.unwrap_or_else(|| "-".into()))} />
+                    <DetailsHeaderItem content={format!("Hooks occupied: {} / {}", occupied, slots.len())} />
+                </DetailsHeader>
+                <Table headings={vec!["Hook", "Key", "Status"]}>
+                    { for rows }
+                </Table>
+                <DetailsFooter/>
+            </DetailsCard>
+        </CheckAuth>
+    }
+}
@@ -0,0 +1,289 @@
+use std::vec::Vec;
+
+use crate::components::form::*;
+use crate::components::modal::Modal;
+use crate::components::notifier::notify_error;
+use crate::components::table::*;
+use crate::services::form_actions::{ondelete, onload, submit_form};
+use crate::services::requests::get;
+use crate::services::{
+    format_date, format_money, get_display_name, money_field, parse_date, parse_money, to_option,
+    CURRENCY_PATTERN, MONEY_PATTERN,
+};
+use crate::theme::FORM_SUBTITLE;
+use crate::types::{Balance, Charge, GroupBalance, Key, User};
+
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use super::auth::CheckAuth;
+use super::Route;
+
+#[function_component(NewCharge)]
+pub fn new_charge() -> Html {
+    let user = use_state(String::new);
+    let key = use_state(String::new);
+    let amount = use_state(String::new);
+    let currency = use_state(String::new);
+    let date = use_state(String::new);
+    let description = use_state(String::new);
+    let users = use_state(Vec::<User>::new);
+    let keys = use_state(Vec::<Key>::new);
+
+    {
+        let users = users.clone();
+        let keys = keys.clone();
+        use_effect_with_deps(
+            move |_| {
+                onload("/api/users".into(), users);
+                onload("/api/keys".into(), keys);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let onsubmit = {
+        let charge = Charge {
+            user: (*user).clone(),
+            key: to_option((*key).clone()),
+            amount: parse_money(&amount).unwrap_or_default(),
+            currency: (*currency).clone(),
+            date: parse_date((*date).clone()),
+            description: to_option((*description).clone()),
+            ..Default::default()
+        };
+        let history = use_history().unwrap();
+        submit_form("/api/charges".to_string(), charge, history, Route::Balances)
+    };
+
+    let user_options = users.iter().map(|u| {
+        html_nested! {
+            <SelectOption
+                value={u.username.clone()}
+                label={u.display_name.clone().unwrap_or_else(|| u.username.clone())}
+            />
+        }
+    });
+
+    let key_options = keys.iter().map(|k| {
+        html_nested! {
+            <SelectOption value={k.name.clone()} />
+        }
+    });
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto">
+                <Form title="New Charge" {onsubmit}>
+                    <SelectField label="User" state={user}>
+                        <SelectOption value="" label="-" />
+                        { for user_options }
+                    </SelectField>
+                    <SelectField label="Key" state={key}>
+                        <SelectOption value="" label="-" />
+                        { for key_options }
+                    </SelectField>
+                    <TextField label="Amount" required=true pattern={MONEY_PATTERN} state={amount} />
+                    <TextField label="Currency" pattern={CURRENCY_PATTERN} state={currency} />
+                    <DateField label="Date" required=true state={date} />
+                    <TextField label="Description" state={description} />
+                    <Button value="Add Charge" button_type={ButtonType::Primary} />
+                    {" "}
+                    <RouteButton value="Cancel" route={Route::Balances} />
+                </Form>
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[derive(PartialEq, Eq, Properties)]
+pub struct ChargeProps {
+    pub id: i64,
+}
+
+#[function_component(EditCharge)]
+pub fn edit_charge(props: &ChargeProps) -> Html {
+    let user = use_state(String::new);
+    let key = use_state(String::new);
+    let amount = use_state(String::new);
+    let currency = use_state(String::new);
+    let date = use_state(String::new);
+    let description = use_state(String::new);
+    let paid = use_state(|| false);
+    let users = use_state(Vec::<User>::new);
+
+    let show_modal = use_state(|| false);
+
+    {
+        let user = user.clone();
+        let key = key.clone();
+        let amount = amount.clone();
+        let currency = currency.clone();
+        let date = date.clone();
+        let description = description.clone();
+        let paid = paid.clone();
+        let users = users.clone();
+        let url = format!("/api/charges/{}", props.id);
+        use_effect_with_deps(
+            move |_| {
+                wasm_bindgen_futures::spawn_local(async move {
+                    match get::<Charge>(url).await {
+                        Ok(c) => {
+                            user.set(c.user);
+                            key.set(c.key.unwrap_or_default());
+                            amount.set(money_field(Some(c.amount)));
+                            currency.set(c.currency);
+                            date.set(format_date(c.date));
+                            description.set(c.description.unwrap_or_default());
+                            paid.set(c.paid);
+                        }
+                        Err(e) => notify_error(&e.to_string()),
+                    }
+                });
+                onload("/api/users".into(), users);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let user_route = Route::UserDetails {
+        username: (*user).clone(),
+    };
+
+    let onsubmit = {
+        let charge = Charge {
+            user: (*user).clone(),
+            key: to_option((*key).clone()),
+            amount: parse_money(&amount).unwrap_or_default(),
+            currency: (*currency).clone(),
+            date: parse_date((*date).clone()),
+            description: to_option((*description).clone()),
+            paid: *paid,
+            ..Default::default()
+        };
+        let history = use_history().unwrap();
+        let path = format!("/api/charges/{}", props.id);
+        submit_form(path, charge, history, user_route.clone())
+    };
+
+    let delete_action = {
+        let history = use_history().unwrap();
+        let path = format!("/api/charges/{}", props.id);
+        ondelete(path, history, user_route.clone())
+    };
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto">
+                <Form title="Edit Charge" {onsubmit}>
+                    <h6 class={FORM_SUBTITLE}>
+                        { format!("User: {}", get_display_name(&users, (*user).clone())) }
+                    </h6>
+                    <h6 class={FORM_SUBTITLE}>
+                        { format!("Key: {}", to_option((*key).clone()).unwrap_or_else(|| "-".into())) }
+                    </h6>
+                    <TextField label="Amount" required=true pattern={MONEY_PATTERN} state={amount} />
+                    <TextField label="Currency" pattern={CURRENCY_PATTERN} state={currency} />
+                    <DateField label="Date" required=true state={date} />
+                    <TextField label="Description" state={description} />
+                    <CheckboxField label="Paid" state={paid} />
+                    <Button value="Update Charge" button_type={ButtonType::Primary} />
+                    {" "}
+                    <DeleteButton
+                        value="Delete Charge"
+                        route={user_route.clone()}
+                        show_modal={show_modal.clone()}
+                    />
+                    {" "}
+                    <RouteButton value="Cancel" route={user_route} />
+                </Form>
+                <Modal
+                    title="Delete Charge"
+                    msg="Are you sure you want to delete this charge?"
+                    confirm_action={delete_action}
+                    {show_modal}
+                />
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[function_component(Balances)]
+pub fn balances() -> Html {
+    let balances = use_state(Vec::<Balance>::new);
+    let group_balances = use_state(Vec::<GroupBalance>::new);
+    let users = use_state(Vec::<User>::new);
+
+    {
+        let balances = balances.clone();
+        let group_balances = group_balances.clone();
+        let users = users.clone();
+        use_effect_with_deps(
+            move |_| {
+                onload("/api/reports/balances".into(), balances);
+                onload("/api/reports/balances/groups".into(), group_balances);
+                onload("/api/users".into(), users);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let rows = balances.iter().map(|b| {
+        html_nested! {
+            <Row>
+                <CellLink
+                    value={get_display_name(&users, b.user.clone())}
+                    route={Route::UserDetails { username: b.user.clone() }}
+                />
+                <Cell value={format_money(b.deposits_held, &b.currency)} />
+                <Cell value={format_money(b.charges_outstanding, &b.currency)} />
+                <Cell value={format_money(b.charges_paid, &b.currency)} />
+            </Row>
+        }
+    });
+
+    let group_rows = group_balances.iter().map(|b| {
+        html_nested! {
+            <Row>
+                <CellLink
+                    value={b.group.clone()}
+                    route={Route::GroupDetails { group_name: b.group.clone() }}
+                />
+                <Cell value={format_money(b.deposits_held, &b.currency)} />
+                <Cell value={format_money(b.charges_outstanding, &b.currency)} />
+                <Cell value={format_money(b.charges_paid, &b.currency)} />
+            </Row>
+        }
+    });
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto max-w-4xl">
+                <TableCard
+                    title="Balances"
+                    headings={vec!["User", "Deposits Held", "Charges Outstanding", "Charges Paid"]}
+                    button_label="Add Charge"
+                    button_route={Route::AddCharge}
+                >
+                    { for rows }
+                </TableCard>
+                <div class="my-5">
+                    <TableCard
+                        title="Balances by Group"
+                        headings={vec!["Group", "Deposits Held", "Charges Outstanding", "Charges Paid"]}
+                    >
+                        { for group_rows }
+                    </TableCard>
+                </div>
+                <div class="text-right my-3">
+                    <a class="font-medium text-blue-600 hover:underline" href="/api/reports/finance.csv">
+                        { "Export CSV" }
+                    </a>
+                </div>
+            </div>
+        </CheckAuth>
+    }
+}
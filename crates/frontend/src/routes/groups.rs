@@ -0,0 +1,338 @@
+use std::vec::Vec;
+
+use crate::components::details_card::*;
+use crate::components::form::*;
+use crate::components::modal::Modal;
+use crate::components::notifier::{notify_error, notify_info};
+use crate::components::table::*;
+use crate::services::form_actions::{ondelete, onload, submit_form};
+use crate::services::requests::{delete, get, post};
+use crate::services::{get_display_name, to_option};
+use crate::theme::*;
+use crate::types::{Assignment, Group, GroupPayload, MemberPayload, User};
+
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use super::auth::CheckAuth;
+use super::Route;
+
+#[function_component(NewGroup)]
+pub fn new_group() -> Html {
+    let name = use_state(String::new);
+    let description = use_state(String::new);
+
+    let onsubmit = {
+        let group = GroupPayload {
+            name: (*name).clone(),
+            description: to_option((*description).clone()),
+        };
+        let history = use_history().unwrap();
+        submit_form(
+            "/api/groups".to_string(),
+            group,
+            history,
+            Route::GroupDetails {
+                group_name: (*name).clone(),
+            },
+        )
+    };
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto">
+                <Form title="New Group" {onsubmit}>
+                    <TextField label="Group Name" required=true state={name} />
+                    <TextField label="Description" state={description} />
+                    <Button value="Add Group" button_type={ButtonType::Primary} />
+                    {" "}
+                    <RouteButton value="Cancel" route={Route::Groups} />
+                </Form>
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[derive(PartialEq, Eq, Properties)]
+pub struct GroupProps {
+    pub group_name: String,
+}
+
+/// Edits the group's details and adds or removes its members.
+#[function_component(EditGroup)]
+pub fn edit_group(props: &GroupProps) -> Html {
+    let name = use_state(String::new);
+    let description = use_state(String::new);
+    let members = use_state(Vec::<String>::new);
+    let new_member = use_state(String::new);
+    let users = use_state(Vec::<User>::new);
+
+    let show_modal = use_state(|| false);
+    let url = format!("/api/groups/{}", props.group_name);
+
+    // Loads the group, and again after its members change
+    let load = {
+        let name = name.clone();
+        let description = description.clone();
+        let members = members.clone();
+        let url = url.clone();
+        Callback::from(move |_: ()| {
+            let name = name.clone();
+            let description = description.clone();
+            let members = members.clone();
+            let url = url.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match get::<Group>(url).await {
+                    Ok(g) => {
+                        name.set(g.name);
+                        description.set(g.description.unwrap_or_default());
+                        members.set(g.members);
+                    }
+                    Err(e) => notify_error(&e.to_string()),
+                }
+            });
+        })
+    };
+
+    {
+        let load = load.clone();
+        let users = users.clone();
+        use_effect_with_deps(
+            move |_| {
+                load.emit(());
+                onload("/api/users".into(), users);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let onsubmit = {
+        let group = GroupPayload {
+            name: (*name).clone(),
+            description: to_option((*description).clone()),
+        };
+        let history = use_history().unwrap();
+        submit_form(
+            url.clone(),
+            group,
+            history,
+            Route::GroupDetails {
+                group_name: (*name).clone(),
+            },
+        )
+    };
+
+    let onadd = {
+        let load = load.clone();
+        let new_member = new_member.clone();
+        let path = format!("{}/members", url);
+        Callback::from(move |e: FocusEvent| {
+            e.prevent_default();
+            let load = load.clone();
+            let path = path.clone();
+            let body = MemberPayload {
+                username: (*new_member).clone(),
+            };
+            wasm_bindgen_futures::spawn_local(async move {
+                match post::<MemberPayload, String>(path, body).await {
+                    Ok(data) => {
+                        notify_info(&data);
+                        load.emit(());
+                    }
+                    Err(e) => notify_error(&e.to_string()),
+                }
+            })
+        })
+    };
+
+    let delete_action = {
+        let history = use_history().unwrap();
+        ondelete(url.clone(), history, Route::Groups)
+    };
+
+    let user_options = users
+        .iter()
+        .filter(|u| !u.departed && !members.contains(&u.username))
+        .map(|u| {
+            html_nested! {
+                <SelectOption value={u.username.clone()} label={get_display_name(&users, u.username.clone())} />
+            }
+        });
+
+    let member_rows = members.iter().map(|m| {
+        let onremove = {
+            let load = load.clone();
+            let path = format!("{}/members/{}", url, m);
+            Callback::from(move |_: MouseEvent| {
+                let load = load.clone();
+                let path = path.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    match delete::<String>(path).await {
+                        Ok(data) => {
+                            notify_info(&data);
+                            load.emit(());
+                        }
+                        Err(e) => notify_error(&e.to_string()),
+                    }
+                })
+            })
+        };
+        html_nested! {
+            <Row>
+                <Cell value={get_display_name(&users, m.clone())} />
+                <td class="py-4 px-6">
+                    <button class={classes!(BTN, BTN_DANGER)} onclick={onremove}>
+                        {"Remove"}
+                    </button>
+                </td>
+            </Row>
+        }
+    });
+
+    let details_route = Route::GroupDetails {
+        group_name: props.group_name.clone(),
+    };
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto">
+                <Form title="Edit Group" {onsubmit}>
+                    <TextField label="Group Name" required=true state={name} />
+                    <TextField label="Description" state={description} />
+                    <Button value="Update Group" button_type={ButtonType::Primary} />
+                    {" "}
+                    <DeleteButton
+                        value="Delete Group"
+                        route={Route::Groups}
+                        show_modal={show_modal.clone()}
+                    />
+                    {" "}
+                    <RouteButton value="Cancel" route={details_route} />
+                </Form>
+                <div class="my-5">
+                    <Form title="Members" onsubmit={onadd}>
+                        <SelectField label="User" state={new_member}>
+                            <SelectOption value="" label="-" />
+                            { for user_options }
+                        </SelectField>
+                        <Button value="Add Member" button_type={ButtonType::Primary} />
+                    </Form>
+                </div>
+                <div class="mx-auto max-w-md">
+                    <Table headings={vec!["Member", ""]}>
+                        { for member_rows }
+                    </Table>
+                </div>
+                <Modal
+                    title="Delete Group"
+                    msg="Are you sure you want to delete this group? Groups still holding keys can't be deleted."
+                    confirm_action={delete_action}
+                    {show_modal}
+                />
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[function_component(GroupTable)]
+pub fn group_table() -> Html {
+    let groups = use_state(Vec::<Group>::new);
+
+    {
+        let groups = groups.clone();
+        use_effect_with_deps(
+            move |_| {
+                onload("/api/groups".into(), groups);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let rows = groups.iter().map(|group| {
+        html_nested! {
+            <Row>
+                <CellLink value={group.name.clone()} route={Route::GroupDetails { group_name: group.name.clone() }} />
+                <Cell value={group.description.clone().unwrap_or_else(|| "-".into())} />
+                <Cell value={group.members.len().to_string()} />
+            </Row>
+        }
+    });
+
+    html! {
+        <CheckAuth>
+            <div class="container my-5 mx-auto max-w-4xl">
+                <TableCard
+                    title="Groups"
+                    headings={vec!["Group", "Description", "Members"]}
+                    button_label="Add Group"
+                    button_route={Route::AddGroup}
+                >
+                    { for rows }
+                </TableCard>
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[function_component(GroupDetails)]
+pub fn group_details(props: &GroupProps) -> Html {
+    let group = use_state(Group::default);
+    let assignments = use_state(Vec::<Assignment>::new);
+    let users = use_state(Vec::<User>::new);
+
+    {
+        let group = group.clone();
+        let assignments = assignments.clone();
+        let users = users.clone();
+        let group_url = format!("/api/groups/{}", props.group_name);
+        let group_keys_url = format!("/api/assignments?group={}", props.group_name);
+        use_effect_with_deps(
+            move |_| {
+                onload(group_url, group);
+                onload(group_keys_url, assignments);
+                onload("/api/users".into(), users);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let group = (*group).clone();
+    html! {
+        <CheckAuth>
+            <DetailsCard
+                title={format!("Group {}", group.name)}
+                edit_route={Route::EditGroup { group_name: props.group_name.clone() }}
+            >
+                <DetailsHeader>
+                    <DetailsHeaderItem content={format!("Description: {}", group.description.unwrap_or_else(|| "-".into()))} />
+                    <DetailsHeaderItem content={format!("Members: {}", group.members.len())} />
+                </DetailsHeader>
+                <DetailsList label="Members">
+                    {
+                        for group.members.iter().map(|m|
+                            html_nested!{
+                                <DetailsListItem
+                                    label={get_display_name(&users, m.clone())}
+                                    route={Route::UserDetails { username: m.clone() }}
+                                />
+                            })
+                    }
+                </DetailsList>
+                <DetailsList label="Keys Held">
+                    {
+                        for assignments.iter().filter(|a| a.date_in.is_none()).map(|a|
+                            html_nested!{
+                                <DetailsListItem
+                                    label={a.key.clone()}
+                                    route={Route::AssignmentDetails { id: a.id }}
+                                />
+                            })
+                    }
+                </DetailsList>
+                <DetailsFooter/>
+            </DetailsCard>
+        </CheckAuth>
+    }
+}
@@ -1,7 +1,10 @@
-use crate::components::table::{Cell, Row, Table, TableHeader};
-use crate::services::get_display_name;
+use crate::components::notifier::{notify_error, notify_info};
+use crate::components::table::{Cell, Row, Table, TableCard, TableHeader};
+use crate::services::auth::current_user;
+use crate::services::requests::post;
+use crate::services::{format_date, get_display_name, holder_name};
 use crate::theme::*;
-use crate::types::{Assignment, User};
+use crate::types::{Assignment, Group, User};
 use crate::{routes::auth::CheckAuth, services::form_actions::onload};
 use std::collections::HashMap;
 
@@ -42,8 +45,10 @@ pub fn home() -> Html {
     let sorted_assignments = use_state(Vec::<SortedAssignment>::new);
     let headers = use_state(|| ("User", "Keys Assigned"));
     let all_users = use_state(Vec::<User>::new);
+    let groups = use_state(Vec::<Group>::new);
     let by_user_btn_class = use_state(|| cl_button_selected.clone());
     let by_key_btn_class = use_state(|| cl_button_unselected.clone());
+    let by_group_btn_class = use_state(|| cl_button_unselected.clone());
 
     let cl_table_container = classes!(
         BG_PRIME_DARK,
@@ -60,10 +65,12 @@ pub fn home() -> Html {
     {
         let assignments = assignments.clone();
         let all_users = all_users.clone();
+        let groups = groups.clone();
         use_effect_with_deps(
             move |_| {
                 onload("/api/assignments".into(), assignments.clone());
                 onload("/api/users".into(), all_users);
+                onload("/api/groups".into(), groups);
                 || ()
             },
             (),
@@ -91,6 +98,7 @@ pub fn home() -> Html {
         let all_users = all_users.clone();
         let by_user_btn_class = by_user_btn_class.clone();
         let by_key_btn_class = by_key_btn_class.clone();
+        let by_group_btn_class = by_group_btn_class.clone();
         let cl_button_selected = cl_button_selected.clone();
         let cl_button_unselected = cl_button_unselected.clone();
         Callback::from(move |_: MouseEvent| {
@@ -98,23 +106,46 @@ pub fn home() -> Html {
             headers.set(("User", "Keys Assigned"));
             by_user_btn_class.set(cl_button_selected.clone());
             by_key_btn_class.set(cl_button_unselected.clone());
+            by_group_btn_class.set(cl_button_unselected.clone());
         })
     };
 
     let on_sort_by_key = {
+        let headers = headers.clone();
+        let assignments = assignments.clone();
+        let sorted_assignments = sorted_assignments.clone();
+        let all_users = all_users.clone();
+        let by_user_btn_class = by_user_btn_class.clone();
+        let by_key_btn_class = by_key_btn_class.clone();
+        let by_group_btn_class = by_group_btn_class.clone();
+        let cl_button_selected = cl_button_selected.clone();
+        let cl_button_unselected = cl_button_unselected.clone();
+        Callback::from(move |_: MouseEvent| {
+            sorted_assignments.set(agg_by_key(&*assignments, &*all_users));
+            headers.set(("Key", "Users Assigned"));
+            by_user_btn_class.set(cl_button_unselected.clone());
+            by_key_btn_class.set(cl_button_selected.clone());
+            by_group_btn_class.set(cl_button_unselected.clone());
+        })
+    };
+
+    let on_sort_by_group = {
         let headers = headers.clone();
         let assignments = assignments;
         let sorted_assignments = sorted_assignments.clone();
         let all_users = all_users;
+        let groups = groups;
         let by_user_btn_class = by_user_btn_class.clone();
         let by_key_btn_class = by_key_btn_class.clone();
+        let by_group_btn_class = by_group_btn_class.clone();
         let cl_button_selected = cl_button_selected;
         let cl_button_unselected = cl_button_unselected;
         Callback::from(move |_: MouseEvent| {
-            sorted_assignments.set(agg_by_key(&*assignments, &*all_users));
-            headers.set(("Key", "Users Assigned"));
+            sorted_assignments.set(agg_by_group(&*assignments, &*all_users, &*groups));
+            headers.set(("Group", "Keys Held"));
             by_user_btn_class.set(cl_button_unselected.clone());
-            by_key_btn_class.set(cl_button_selected.clone());
+            by_key_btn_class.set(cl_button_unselected.clone());
+            by_group_btn_class.set(cl_button_selected.clone());
         })
     };
 
@@ -132,6 +163,13 @@ pub fn home() -> Html {
     html! {
         <CheckAuth>
             <div class="container my-5 mx-auto max-w-4xl">
+                {
+                    if current_user().is_admin {
+                        html! { <PendingAssignments /> }
+                    } else {
+                        html! {}
+                    }
+                }
                 <div class={cl_table_container}>
                     <TableHeader title="Key Inventory Tracker">
 
@@ -145,11 +183,17 @@ pub fn home() -> Html {
                                     {"By Key"}
                                 </button>
                                 <button
-                                    class={classes!((*by_user_btn_class).clone(), "rounded-r-lg")}
+                                    class={(*by_user_btn_class).clone()}
                                     onclick={on_sort_by_user}
                                 >
                                     {"By User"}
                                 </button>
+                                <button
+                                    class={classes!((*by_group_btn_class).clone(), "rounded-r-lg")}
+                                    onclick={on_sort_by_group}
+                                >
+                                    {"By Group"}
+                                </button>
                             </div>
                         </div>
                     </TableHeader>
@@ -163,6 +207,84 @@ pub fn home() -> Html {
     }
 }
 
+/// Assignments of restricted keys which are waiting for a second admin to confirm them.
+#[function_component(PendingAssignments)]
+fn pending_assignments() -> Html {
+    let pending = use_state(Vec::<Assignment>::new);
+    let users = use_state(Vec::<User>::new);
+
+    {
+        let pending = pending.clone();
+        let users = users.clone();
+        use_effect_with_deps(
+            move |_| {
+                onload("/api/assignments?pending=true".into(), pending);
+                onload("/api/users".into(), users);
+                || ()
+            },
+            (),
+        );
+    }
+
+    if pending.is_empty() {
+        return html! {};
+    }
+
+    let username = current_user().username.unwrap_or_default();
+    let rows = pending.iter().map(|a| {
+        let created_by = a.created_by.clone().unwrap_or_default();
+        let onconfirm = {
+            let pending = pending.clone();
+            let path = format!("/api/assignments/{}/confirm", a.id);
+            Callback::from(move |_: MouseEvent| {
+                let pending = pending.clone();
+                let path = path.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    match post::<(), String>(path, ()).await {
+                        Ok(data) => {
+                            notify_info(&data);
+                            onload("/api/assignments?pending=true".into(), pending);
+                        }
+                        Err(e) => notify_error(&e.to_string()),
+                    }
+                })
+            })
+        };
+        html_nested! {
+            <Row>
+                <Cell value={holder_name(&users, a)} />
+                <Cell value={a.key.clone()} />
+                <Cell value={format_date(a.date_out)} />
+                <Cell value={get_display_name(&users, created_by.clone())} />
+                <td class="py-4 px-6">
+                    {
+                        if created_by == username {
+                            html! { "Awaiting second admin" }
+                        } else {
+                            html! {
+                                <button class={classes!(BTN, BTN_PRIMARY)} onclick={onconfirm}>
+                                    {"Confirm"}
+                                </button>
+                            }
+                        }
+                    }
+                </td>
+            </Row>
+        }
+    });
+
+    html! {
+        <div class="mb-5">
+            <TableCard
+                title="Pending Confirmation"
+                headings={vec!["Holder", "Key", "Date Out", "Issued By", ""]}
+            >
+                { for rows }
+            </TableCard>
+        </div>
+    }
+}
+
 #[derive(Ord, PartialEq, PartialOrd, Eq)]
 struct SortedAssignment {
     index: String,
@@ -172,9 +294,9 @@ struct SortedAssignment {
 /// Aggregates the assignment list by user
 fn agg_by_user(assignments: &[Assignment], users: &Vec<User>) -> Vec<SortedAssignment> {
     let mut map = HashMap::new();
-    let assignments = assignments.to_owned();
+    let assignments = assignments.iter().filter(|a| !a.pending).cloned();
     for a in assignments {
-        map.entry(get_display_name(users, a.user))
+        map.entry(holder_name(users, &a))
             .and_modify(|v| *v = format!("{}, {}", v, a.key))
             .or_insert(a.key);
     }
@@ -184,11 +306,47 @@ fn agg_by_user(assignments: &[Assignment], users: &Vec<User>) -> Vec<SortedAssig
 /// Aggregates the assignment list by key
 fn agg_by_key(assignments: &[Assignment], users: &Vec<User>) -> Vec<SortedAssignment> {
     let mut map = HashMap::new();
-    let assignments = assignments.to_owned();
+    let assignments = assignments.iter().filter(|a| !a.pending).cloned();
     for a in assignments {
+        let holder = holder_name(users, &a);
         map.entry(a.key)
-            .and_modify(|v| *v = format!("{}, {}", v, get_display_name(users, a.user.clone())))
-            .or_insert_with(|| get_display_name(users, a.user));
+            .and_modify(|v| *v = format!("{}, {}", v, holder))
+            .or_insert(holder);
+    }
+    map_to_sort(map)
+}
+
+/// Aggregates the assignment list by group. Keys held by a user are listed under each of the
+/// user's groups, with the user's name.
+fn agg_by_group(
+    assignments: &[Assignment],
+    users: &[User],
+    groups: &[Group],
+) -> Vec<SortedAssignment> {
+    let mut map = HashMap::new();
+    let assignments = assignments.iter().filter(|a| !a.pending);
+    for a in assignments {
+        let (held_by, key) = match &a.user {
+            Some(u) => (
+                groups
+                    .iter()
+                    .filter(|g| g.members.contains(u))
+                    .map(|g| g.name.clone())
+                    .collect(),
+                format!("{} ({})", a.key, get_display_name(users, u.clone())),
+            ),
+            None => (vec![a.holder()], a.key.clone()),
+        };
+        let held_by = if held_by.is_empty() {
+            vec!["No group".to_string()]
+        } else {
+            held_by
+        };
+        for g in held_by {
+            map.entry(g)
+                .and_modify(|v| *v = format!("{}, {}", v, key))
+                .or_insert_with(|| key.clone());
+        }
     }
     map_to_sort(map)
 }
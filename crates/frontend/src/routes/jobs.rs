@@ -0,0 +1,60 @@
+use std::vec::Vec;
+
+use crate::components::table::*;
+use crate::services::form_actions::onload;
+use crate::services::format_timestamp;
+use crate::types::JobStatus;
+
+use yew::prelude::*;
+
+use super::auth::CheckAuth;
+
+#[function_component(JobTable)]
+pub fn job_table() -> Html {
+    let jobs = use_state(Vec::<JobStatus>::new);
+
+    {
+        let jobs = jobs.clone();
+        use_effect_with_deps(
+            move |_| {
+                onload("/api/jobs".into(), jobs);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let rows = jobs.iter().map(|j| {
+        let last_run = match (j.last_started, j.last_finished) {
+            (Some(_), Some(f)) => format_timestamp(f),
+            (Some(s), None) => format!("Running since {}", format_timestamp(s)),
+            _ => "-".into(),
+        };
+        let last_error = match (&j.last_error, j.last_error_at) {
+            (Some(e), Some(t)) => format!("{}: {}", format_timestamp(t), e),
+            _ => "-".into(),
+        };
+        html_nested! {
+            <Row>
+                <Cell value={j.name.clone()} />
+                <Cell value={j.schedule.clone()} />
+                <Cell value={last_run} />
+                <Cell value={format_timestamp(j.next_run)} />
+                <Cell value={last_error} />
+            </Row>
+        }
+    });
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto max-w-4xl">
+                <TableCard
+                    title="Jobs"
+                    headings={vec!["Job", "Schedule", "Last Run", "Next Run", "Last Error"]}
+                >
+                    { for rows }
+                </TableCard>
+            </div>
+        </CheckAuth>
+    }
+}
@@ -4,18 +4,21 @@ use crate::components::details_card::*;
 use crate::components::form::*;
 use crate::components::modal::Modal;
 use crate::components::notifier::notify_error;
+use crate::components::scan::ScanInput;
 use crate::components::table::*;
 use crate::services::form_actions::{ondelete, onload, submit_form};
-use crate::services::get_display_name;
 use crate::services::requests::get;
-use crate::services::to_option;
-use crate::theme::FORM_SUBTITLE;
+use crate::services::{
+    format_money, money_field, parse_money, slot_label, to_option, CURRENCY_PATTERN, MONEY_PATTERN,
+};
+use crate::services::{get_display_name, holder_name};
 use crate::types::Assignment;
 use crate::types::Key;
 use crate::types::User;
+use crate::types::{KeyLocation, Slot};
 
 use yew::prelude::*;
-use yew_router::hooks::use_history;
+use yew_router::prelude::*;
 
 use super::auth::CheckAuth;
 use super::Route;
@@ -24,12 +27,19 @@ use super::Route;
 pub fn new_key() -> Html {
     let name = use_state(String::new);
     let description = use_state(String::new);
+    let deposit = use_state(String::new);
+    let currency = use_state(String::new);
+    let restricted = use_state(|| false);
 
     let onsubmit = {
         let key = Key {
             name: (*name).clone(),
             description: to_option((*description).clone()),
             active: true,
+            slot_id: None,
+            deposit_amount: parse_money(&deposit),
+            deposit_currency: to_option((*currency).clone()),
+            restricted: *restricted,
         };
         let history = use_history().unwrap();
         submit_form("/api/keys".to_string(), key, history, Route::Keys)
@@ -45,6 +55,9 @@ pub fn new_key() -> Html {
                         state={name}
                     />
                     <TextField label="Description" state={description} />
+                    <TextField label="Deposit" pattern={MONEY_PATTERN} state={deposit} />
+                    <TextField label="Currency" pattern={CURRENCY_PATTERN} state={currency} />
+                    <CheckboxField label="Restricted" state={restricted} />
                     <Button
                     value="Add Key"
                     button_type={ButtonType::Primary}
@@ -65,15 +78,27 @@ pub struct KeyProps {
 #[function_component(EditKey)]
 pub fn edit_key(props: &KeyProps) -> Html {
     let key_name = use_state(String::new);
+    let original_name = use_state(String::new);
     let description = use_state(String::new);
     let active = use_state(|| false);
+    let slot_id = use_state(String::new);
+    let slots = use_state(Vec::<Slot>::new);
+    let deposit = use_state(String::new);
+    let currency = use_state(String::new);
+    let restricted = use_state(|| false);
 
     let show_modal = use_state(|| false);
 
     {
         let key_name = key_name.clone();
+        let original_name = original_name.clone();
         let description = description.clone();
         let active = active.clone();
+        let slot_id = slot_id.clone();
+        let slots = slots.clone();
+        let deposit = deposit.clone();
+        let currency = currency.clone();
+        let restricted = restricted.clone();
         let url = format!("/api/keys/{}", props.key_name.clone());
         use_effect_with_deps(
             move |_| {
@@ -82,13 +107,19 @@ pub fn edit_key(props: &KeyProps) -> Html {
                         Ok(k) => {
                             // Setting the key name otherwise it will be url
                             // encoded
+                            original_name.set(k.name.clone());
                             key_name.set(k.name);
                             description.set(k.description.unwrap_or_default());
                             active.set(k.active);
+                            slot_id.set(k.slot_id.map(|s| s.to_string()).unwrap_or_default());
+                            deposit.set(money_field(k.deposit_amount));
+                            currency.set(k.deposit_currency.unwrap_or_default());
+                            restricted.set(k.restricted);
                         }
                         Err(e) => notify_error(&e.to_string()),
                     }
                 });
+                onload("/api/slots".into(), slots);
                 || ()
             },
             (),
@@ -100,6 +131,10 @@ pub fn edit_key(props: &KeyProps) -> Html {
             name: (*key_name).clone(),
             description: to_option((*description).clone()),
             active: *active,
+            slot_id: (*slot_id).parse().ok(),
+            deposit_amount: parse_money(&deposit),
+            deposit_currency: to_option((*currency).clone()),
+            restricted: *restricted,
         };
         let history = use_history().unwrap();
         let path = format!("/api/keys/{}", props.key_name.clone());
@@ -112,15 +147,34 @@ pub fn edit_key(props: &KeyProps) -> Html {
         ondelete(path, history, Route::Keys)
     };
 
+    // Only list slots which are empty or already hold this key
+    let slot_options = slots
+        .iter()
+        .filter(|s| s.key.is_none() || s.key == Some((*original_name).clone()))
+        .map(|s| {
+            html_nested! {
+                <SelectOption
+                    value={s.id.to_string()}
+                    label={slot_label(s)}
+                    selected={s.id.to_string() == *slot_id}
+                />
+            }
+        });
+
     html! {
         <CheckAuth admin=true>
             <div class="container my-5 mx-auto">
                 <Form title="Edit Key" {onsubmit}>
-                    <h6 class={FORM_SUBTITLE}>
-                        {format!("Key: {}", (*key_name).clone())}
-                    </h6>
+                    <TextField label="Name" required=true state={key_name} />
                     <TextField label="Description" state={description} />
                     <CheckboxField label="Active" state={active} />
+                    <CheckboxField label="Restricted" state={restricted} />
+                    <TextField label="Deposit" pattern={MONEY_PATTERN} state={deposit} />
+                    <TextField label="Currency" pattern={CURRENCY_PATTERN} state={currency} />
+                    <SelectField label="Stored In" state={slot_id.clone()}>
+                        <SelectOption value="" label="-" selected={slot_id.is_empty()} />
+                        { for slot_options }
+                    </SelectField>
                     <Button
                         value="Update Key"
                         button_type={ButtonType::Primary}
@@ -136,7 +190,7 @@ pub fn edit_key(props: &KeyProps) -> Html {
                 </Form>
                 <Modal
                     title="Delete Key"
-                    msg="Are you sure you want to delete this key? All assignments which use this key will also be deleted."
+                    msg="Are you sure you want to delete this key? It will be moved to the trash along with its assignments."
                     confirm_action={delete_action}
                     {show_modal}
                 />
@@ -186,9 +240,26 @@ pub fn key_table() -> Html {
         }
     });
 
+    // Jump straight to a scanned key
+    let onscan = {
+        let keys = keys.clone();
+        let history = use_history().unwrap();
+        Callback::from(move |code: String| {
+            match keys.iter().find(|k| k.name.eq_ignore_ascii_case(&code)) {
+                Some(key) => history.push(Route::KeyDetails {
+                    key_name: key.name.clone(),
+                }),
+                None => notify_error(&format!("Key '{}' not found.", code)),
+            }
+        })
+    };
+
     html! {
         <CheckAuth>
             <div class="container my-5 mx-auto max-w-4xl">
+                <div class="mb-3">
+                    <ScanInput placeholder="Scan or type a key name and press Enter" {onscan} />
+                </div>
                 <TableCard
                     title="Keys"
                     headings={vec!["Key", "Description", "Status"]}
@@ -197,6 +268,11 @@ pub fn key_table() -> Html {
                 >
                     { for rows }
                 </TableCard>
+                <div class="text-right my-3">
+                    <a class="font-medium text-blue-600 hover:underline" href="/api/labels.pdf" target="_blank">
+                        { "Print Labels" }
+                    </a>
+                </div>
             </div>
         </CheckAuth>
     }
@@ -207,26 +283,43 @@ pub fn key_details(props: &KeyProps) -> Html {
     let key = use_state(Key::default);
     let assignments = use_state(Vec::<Assignment>::new);
     let users = use_state(Vec::<User>::new);
+    let location = use_state(|| KeyLocation::Unknown);
 
     {
         let key = key.clone();
         let assignments = assignments.clone();
         let users = users.clone();
+        let location = location.clone();
 
         let key_url = format!("/api/keys/{}", &props.key_name);
         let key_users_url = format!("/api/assignments?key={}", &props.key_name);
+        let location_url = format!("/api/keys/{}/location", &props.key_name);
 
         use_effect_with_deps(
             move |_| {
                 onload(key_url, key);
                 onload(key_users_url, assignments);
                 onload("/api/users".into(), users);
+                onload(location_url, location);
                 || ()
             },
             (),
         );
     }
 
+    let location = match (*location).clone() {
+        KeyLocation::User { username } => {
+            format!("with user {}", get_display_name(&users, username))
+        }
+        KeyLocation::Group { name } => format!("with group {}", name),
+        KeyLocation::Slot {
+            cabinet, number, ..
+        } => {
+            format!("cabinet {}, hook {}", cabinet, number)
+        }
+        KeyLocation::Unknown => "unknown".into(),
+    };
+
     let key = (*key).clone();
     html! {
         <CheckAuth>
@@ -237,19 +330,39 @@ pub fn key_details(props: &KeyProps) -> Html {
                 <DetailsHeader>
                     <DetailsHeaderItem content={format!("Description: {}", key.description.unwrap_or("-".into()))} />
                     <DetailsHeaderItem content={format!("Active: {}", key.active)} />
+                    <DetailsHeaderItem content={format!("Restricted: {}", key.restricted)} />
+                    <DetailsHeaderItem content={format!("Location: {}", location)} />
+                    <DetailsHeaderItem
+                        content={
+                            match (key.deposit_amount, key.deposit_currency) {
+                                (Some(a), Some(c)) => format!("Deposit: {}", format_money(a, &c)),
+                                _ => "Deposit: -".into(),
+                            }
+                        }
+                    />
                 </DetailsHeader>
-                <DetailsList label="Assigned Users">
+                <DetailsList label="Assigned To">
                     { for (*assignments)
                         .iter()
                             .map(|a|
                                 html_nested!{
                                     <DetailsListItem
-                                        label={get_display_name(&(*users).clone(), a.clone().user)}
+                                        label={holder_name(&users, a)}
                                         route={Route::AssignmentDetails { id: a.clone().id } }
                                     />
                                 })
                     }
                 </DetailsList>
+                <div class="m-4 flex items-center">
+                    <img class="w-32 h-36 rounded" src={format!("/api/keys/{}/label.svg", key.name)} alt="Key label" />
+                    <a
+                        class="ml-4 font-medium text-blue-600 hover:underline"
+                        href={format!("/api/labels.pdf?keys={}", key.name)}
+                        target="_blank"
+                    >
+                        { "Print Label" }
+                    </a>
+                </div>
                 <DetailsFooter/>
             </DetailsCard>
         </CheckAuth>
@@ -2,14 +2,30 @@ use yew::prelude::*;
 use yew_router::prelude::*;
 
 mod assignments;
+mod audits;
 mod auth;
+mod cabinets;
+mod finance;
+mod groups;
 mod home;
+mod jobs;
 mod keys;
+mod requests;
+mod trash;
 mod users;
+mod webhooks;
 
 use assignments::*;
+use audits::*;
+use cabinets::*;
+use finance::*;
+use groups::*;
+use jobs::*;
 use keys::*;
+use requests::*;
+use trash::*;
 use users::*;
+use webhooks::*;
 
 #[derive(Clone, Routable, PartialEq, Eq)]
 pub enum Route {
@@ -37,6 +53,58 @@ pub enum Route {
     EditAssignment { id: i64 },
     #[at("/assignments/:id")]
     AssignmentDetails { id: i64 },
+    #[at("/audits")]
+    Audits,
+    #[at("/start-audit")]
+    StartAudit,
+    #[at("/audits/:id")]
+    AuditChecklist { id: i64 },
+    #[at("/audits/:id/report")]
+    AuditReport { id: i64 },
+    #[at("/cabinets")]
+    Cabinets,
+    #[at("/add-cabinet")]
+    AddCabinet,
+    #[at("/edit-cabinet/:id")]
+    EditCabinet { id: i64 },
+    #[at("/cabinets/:id")]
+    CabinetDetails { id: i64 },
+    #[at("/groups")]
+    Groups,
+    #[at("/add-group")]
+    AddGroup,
+    #[at("/edit-group/:group_name")]
+    EditGroup { group_name: String },
+    #[at("/groups/:group_name")]
+    GroupDetails { group_name: String },
+    #[at("/requests")]
+    Requests,
+    #[at("/request-keys")]
+    NewRequest,
+    #[at("/requests/:id")]
+    RequestDetails { id: i64 },
+    #[at("/review-request/:id")]
+    ReviewRequest { id: i64 },
+    #[at("/fulfill-request/:id")]
+    FulfillRequest { id: i64 },
+    #[at("/balances")]
+    Balances,
+    #[at("/add-charge")]
+    AddCharge,
+    #[at("/edit-charge/:id")]
+    EditCharge { id: i64 },
+    #[at("/jobs")]
+    Jobs,
+    #[at("/trash")]
+    Trash,
+    #[at("/webhooks")]
+    Webhooks,
+    #[at("/add-webhook")]
+    AddWebhook,
+    #[at("/edit-webhook/:id")]
+    EditWebhook { id: i64 },
+    #[at("/webhooks/:id")]
+    WebhookDetails { id: i64 },
     #[at("/users")]
     Users,
     #[at("/add-user")]
@@ -47,6 +115,8 @@ pub enum Route {
     UserDetails { username: String },
     #[at("/edit-user/:username/set-password")]
     SetPassword { username: String },
+    #[at("/edit-user/:username/notifications")]
+    Notifications { username: String },
     #[not_found]
     #[at("/not-found")]
     NotFound,
@@ -71,6 +141,42 @@ pub fn switch(routes: &Route) -> Html {
             html! {<AssignmentDetails id={ *id }/>}
         }
 
+        Route::Audits => html! { <AuditTable /> },
+        Route::StartAudit => html! { <NewAudit /> },
+        Route::AuditChecklist { id } => html! { <AuditChecklist id={ *id }/> },
+        Route::AuditReport { id } => html! { <AuditReport id={ *id }/> },
+
+        Route::Cabinets => html! { <CabinetTable /> },
+        Route::AddCabinet => html! { <NewCabinet /> },
+        Route::EditCabinet { id } => html! { <EditCabinet id={ *id }/> },
+        Route::CabinetDetails { id } => html! { <CabinetDetails id={ *id }/> },
+
+        Route::Groups => html! { <GroupTable /> },
+        Route::AddGroup => html! { <NewGroup /> },
+        Route::EditGroup { group_name } => html! { <EditGroup group_name={group_name.clone()}/> },
+        Route::GroupDetails { group_name } => {
+            html! { <GroupDetails group_name={group_name.clone()}/> }
+        }
+
+        Route::Requests => html! { <RequestTable /> },
+        Route::NewRequest => html! { <NewRequest /> },
+        Route::RequestDetails { id } => html! { <RequestDetails id={ *id }/> },
+        Route::ReviewRequest { id } => html! { <ReviewRequest id={ *id }/> },
+        Route::FulfillRequest { id } => html! { <FulfillRequest id={ *id }/> },
+
+        Route::Balances => html! { <Balances /> },
+        Route::AddCharge => html! { <NewCharge /> },
+        Route::EditCharge { id } => html! { <EditCharge id={ *id }/> },
+
+        Route::Jobs => html! { <JobTable /> },
+
+        Route::Trash => html! { <Trash /> },
+
+        Route::Webhooks => html! { <WebhookTable /> },
+        Route::AddWebhook => html! { <NewWebhook /> },
+        Route::EditWebhook { id } => html! { <EditWebhook id={ *id }/> },
+        Route::WebhookDetails { id } => html! { <WebhookDetails id={ *id }/> },
+
         Route::Users => html! { <UserTable /> },
         Route::AddUser => html! { <NewUser />},
         Route::EditUser { username } => html! { <EditUser username={username.clone()}/>},
@@ -80,6 +186,9 @@ pub fn switch(routes: &Route) -> Html {
         Route::SetPassword { username } => {
             html! { <SetPassword username={username.clone()}/>}
         }
+        Route::Notifications { username } => {
+            html! { <Notifications username={username.clone()}/>}
+        }
 
         Route::NotFound => html! { <h1>{ "404" }</h1> },
     }
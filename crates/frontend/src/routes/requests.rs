@@ -0,0 +1,314 @@
+use std::vec::Vec;
+
+use crate::components::details_card::*;
+use crate::components::form::*;
+use crate::components::notifier::{notify_error, notify_info};
+use crate::components::table::*;
+use crate::services::auth::current_user;
+use crate::services::form_actions::{onload, submit_form};
+use crate::services::requests::post;
+use crate::services::{format_date, get_display_name, parse_date, to_option};
+use crate::theme::FORM_SUBTITLE;
+use crate::types::{DecisionPayload, FulfillPayload, Key, KeyRequest, RequestStatus, User};
+
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use super::auth::CheckAuth;
+use super::Route;
+
+#[function_component(NewRequest)]
+pub fn new_request() -> Html {
+    let selected_keys = use_state(Vec::<String>::new);
+    let justification = use_state(String::new);
+    let date_from = use_state(String::new);
+    let date_to = use_state(String::new);
+    let approver = use_state(String::new);
+    let keys = use_state(Vec::<Key>::new);
+    let users = use_state(Vec::<User>::new);
+
+    {
+        let keys = keys.clone();
+        let users = users.clone();
+        use_effect_with_deps(
+            move |_| {
+                onload("/api/keys?active=true".into(), keys);
+                onload("/api/users".into(), users);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let onsubmit = {
+        let request = KeyRequest {
+            keys: (*selected_keys).clone(),
+            justification: (*justification).clone(),
+            date_from: parse_date((*date_from).clone()),
+            date_to: to_option((*date_to).clone()).map(parse_date),
+            approver: to_option((*approver).clone()),
+            ..Default::default()
+        };
+        let history = use_history().unwrap();
+        submit_form(
+            "/api/requests".to_string(),
+            request,
+            history,
+            Route::Requests,
+        )
+    };
+
+    let key_options = keys.iter().map(|k| {
+        html_nested! {
+            <MultiSelectOption value={k.name.clone()} />
+        }
+    });
+
+    let username = current_user().username.unwrap_or_default();
+    let approver_options = users
+        .iter()
+        .filter(|u| !u.departed && u.username != username)
+        .map(|u| {
+            html_nested! {
+                <SelectOption
+                    value={u.username.clone()}
+                    label={u.display_name.clone().unwrap_or_else(|| u.username.clone())}
+                />
+            }
+        });
+
+    html! {
+        <CheckAuth>
+            <div class="container my-5 mx-auto">
+                <Form title="Request Keys" {onsubmit}>
+                    <MultiSelectField label="Keys" state={selected_keys}>
+                        { for key_options }
+                    </MultiSelectField>
+                    <TextField label="Justification" required=true state={justification} />
+                    <DateField label="Date From" required=true state={date_from} />
+                    <DateField label="Date To" state={date_to} />
+                    <SelectField label="Approver" state={approver}>
+                        <SelectOption value="" label="Any admin" />
+                        { for approver_options }
+                    </SelectField>
+                    <Button value="Submit Request" button_type={ButtonType::Primary} />
+                    {" "}
+                    <RouteButton value="Cancel" route={Route::Requests} />
+                </Form>
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[function_component(RequestTable)]
+pub fn request_table() -> Html {
+    let requests = use_state(Vec::<KeyRequest>::new);
+    let users = use_state(Vec::<User>::new);
+
+    {
+        let requests = requests.clone();
+        let users = users.clone();
+        use_effect_with_deps(
+            move |_| {
+                onload("/api/requests".into(), requests);
+                onload("/api/users".into(), users);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let rows = requests.iter().map(|r| {
+        html_nested! {
+            <Row>
+                <CellLink
+                    value={get_display_name(&users, r.user.clone())}
+                    route={Route::RequestDetails { id: r.id }}
+                />
+                <Cell value={r.keys.join(", ")} />
+                <Cell value={format_date(r.date_from)} />
+                <Cell value={r.status.to_string()} />
+            </Row>
+        }
+    });
+
+    html! {
+        <CheckAuth>
+            <div class="container my-5 mx-auto max-w-4xl">
+                <TableCard
+                    title="Key Requests"
+                    headings={vec!["User", "Keys", "Date From", "Status"]}
+                >
+                    { for rows }
+                </TableCard>
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[derive(PartialEq, Eq, Properties)]
+pub struct RequestProps {
+    pub id: i64,
+}
+
+#[function_component(RequestDetails)]
+pub fn request_details(props: &RequestProps) -> Html {
+    let request = use_state(KeyRequest::default);
+    let users = use_state(Vec::<User>::new);
+
+    {
+        let request = request.clone();
+        let users = users.clone();
+        let url = format!("/api/requests/{}", props.id);
+        use_effect_with_deps(
+            move |_| {
+                onload(url, request);
+                onload("/api/users".into(), users);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let session = current_user();
+    let username = session.username.clone().unwrap_or_default();
+    let request = (*request).clone();
+    let can_review = request.status == RequestStatus::Pending
+        && request.user != username
+        && (session.is_admin || request.approver.as_deref() == Some(username.as_str()));
+    let can_fulfill = request.status == RequestStatus::Approved && session.is_admin;
+
+    html! {
+        <CheckAuth>
+            <DetailsCard
+                title={format!("Request {}", request.id)}
+                edit_route={Route::ReviewRequest { id: props.id }}
+            >
+                <DetailsHeader>
+                    <DetailsHeaderItem content={format!("Requested by: {}", get_display_name(&users, request.user.clone()))} />
+                    <DetailsHeaderItem content={format!("Keys: {}", request.keys.join(", "))} />
+                    <DetailsHeaderItem content={format!("Justification: {}", request.justification)} />
+                    <DetailsHeaderItem
+                        content={format!(
+                            "Needed: {} to {}",
+                            format_date(request.date_from),
+                            request.date_to.map(format_date).unwrap_or_else(|| "-".into())
+                        )}
+                    />
+                    <DetailsHeaderItem
+                        content={format!(
+                            "Approver: {}",
+                            request.approver.clone().map(|a| get_display_name(&users, a)).unwrap_or_else(|| "Any admin".into())
+                        )}
+                    />
+                    <DetailsHeaderItem content={format!("Status: {}", request.status)} />
+                    <DetailsHeaderItem
+                        content={format!(
+                            "Decided by: {}",
+                            request.decided_by.clone().map(|d| get_display_name(&users, d)).unwrap_or_else(|| "-".into())
+                        )}
+                    />
+                    <DetailsHeaderItem content={format!("Note: {}", request.decision_note.clone().unwrap_or_else(|| "-".into()))} />
+                </DetailsHeader>
+                {
+                    if can_review {
+                        html! { <ActionButton label="Review" route={Route::ReviewRequest { id: props.id }} /> }
+                    } else if can_fulfill {
+                        html! { <ActionButton label="Hand Over Keys" route={Route::FulfillRequest { id: props.id }} /> }
+                    } else {
+                        html! {}
+                    }
+                }
+                <DetailsFooter/>
+            </DetailsCard>
+        </CheckAuth>
+    }
+}
+
+#[function_component(ReviewRequest)]
+pub fn review_request(props: &RequestProps) -> Html {
+    let note = use_state(String::new);
+
+    let onsubmit = {
+        let decision = DecisionPayload {
+            note: to_option((*note).clone()),
+        };
+        let history = use_history().unwrap();
+        let path = format!("/api/requests/{}/approve", props.id);
+        submit_form(
+            path,
+            decision,
+            history,
+            Route::RequestDetails { id: props.id },
+        )
+    };
+
+    let ondeny = {
+        let decision = DecisionPayload {
+            note: to_option((*note).clone()),
+        };
+        let history = use_history().unwrap();
+        let path = format!("/api/requests/{}/deny", props.id);
+        let next_route = Route::RequestDetails { id: props.id };
+        Callback::from(move |e: MouseEvent| {
+            e.prevent_default();
+            let path = path.clone();
+            let decision = decision.clone();
+            let history = history.clone();
+            let next_route = next_route.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match post::<DecisionPayload, String>(path, decision).await {
+                    Ok(data) => {
+                        notify_info(&data);
+                        history.push(next_route)
+                    }
+                    Err(e) => notify_error(&e.to_string()),
+                }
+            })
+        })
+    };
+
+    html! {
+        <CheckAuth>
+            <div class="container my-5 mx-auto">
+                <Form title="Review Request" {onsubmit}>
+                    <h6 class={FORM_SUBTITLE}>{ format!("Request {}", props.id) }</h6>
+                    <TextField label="Note" state={note} />
+                    <Button value="Approve" button_type={ButtonType::Primary} />
+                    {" "}
+                    <Button value="Deny" button_type={ButtonType::Danger} onclick={ondeny} />
+                    {" "}
+                    <RouteButton value="Cancel" route={Route::RequestDetails { id: props.id }} />
+                </Form>
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[function_component(FulfillRequest)]
+pub fn fulfill_request(props: &RequestProps) -> Html {
+    let date_out = use_state(String::new);
+
+    let onsubmit = {
+        let payload = FulfillPayload {
+            date_out: parse_date((*date_out).clone()),
+        };
+        let history = use_history().unwrap();
+        let path = format!("/api/requests/{}/fulfill", props.id);
+        submit_form(path, payload, history, Route::Assignments)
+    };
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto">
+                <Form title="Hand Over Keys" {onsubmit}>
+                    <h6 class={FORM_SUBTITLE}>{ format!("Request {}", props.id) }</h6>
+                    <DateField label="Date Out" required=true state={date_out} />
+                    <Button value="Assign Keys" button_type={ButtonType::Primary} />
+                    {" "}
+                    <RouteButton value="Cancel" route={Route::RequestDetails { id: props.id }} />
+                </Form>
+            </div>
+        </CheckAuth>
+    }
+}
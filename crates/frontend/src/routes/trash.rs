@@ -0,0 +1,134 @@
+use std::vec::Vec;
+
+use crate::components::modal::Modal;
+use crate::components::notifier::{notify_error, notify_info};
+use crate::components::table::*;
+use crate::services::form_actions::onload;
+use crate::services::format_timestamp;
+use crate::services::requests::{delete, post};
+use crate::theme::*;
+use crate::types::TrashItem;
+
+use yew::prelude::*;
+
+use super::auth::CheckAuth;
+
+/// Deleted keys, users and assignments. Each can be restored or permanently deleted.
+#[function_component(Trash)]
+pub fn trash() -> Html {
+    let items = use_state(Vec::<TrashItem>::new);
+    let purge_target = use_state(|| None::<TrashItem>);
+    let show_modal = use_state(|| false);
+
+    {
+        let items = items.clone();
+        use_effect_with_deps(
+            move |_| {
+                onload("/api/trash".into(), items);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let rows = items.iter().map(|item| {
+        let path = format!("/api/trash/{}/{}", item.kind.as_path(), item.id);
+        let onrestore = {
+            let items = items.clone();
+            let path = format!("{}/restore", path);
+            Callback::from(move |_: MouseEvent| {
+                let items = items.clone();
+                let path = path.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    match post::<(), String>(path, ()).await {
+                        Ok(data) => {
+                            notify_info(&data);
+                            onload("/api/trash".into(), items);
+                        }
+                        Err(e) => notify_error(&e.to_string()),
+                    }
+                })
+            })
+        };
+        let onpurge = {
+            let purge_target = purge_target.clone();
+            let show_modal = show_modal.clone();
+            let item = item.clone();
+            Callback::from(move |_: MouseEvent| {
+                purge_target.set(Some(item.clone()));
+                show_modal.set(true);
+            })
+        };
+        html_nested! {
+            <Row>
+                <Cell value={item.kind.to_string()} />
+                <Cell value={item.id.clone()} />
+                <Cell value={if item.description.is_empty() { "-".into() } else { item.description.clone() }} />
+                <Cell value={format_timestamp(item.deleted_at)} />
+                <td class="py-4 px-6">
+                    <button class={classes!(BTN, BTN_PRIMARY)} onclick={onrestore}>
+                        {"Restore"}
+                    </button>
+                    <button class={classes!(BTN, BTN_DANGER)} onclick={onpurge}>
+                        {"Delete Forever"}
+                    </button>
+                </td>
+            </Row>
+        }
+    });
+
+    let purge_action = {
+        let items = items.clone();
+        let purge_target = purge_target.clone();
+        let show_modal = show_modal.clone();
+        Callback::from(move |_: MouseEvent| {
+            show_modal.set(false);
+            let item = match (*purge_target).clone() {
+                Some(i) => i,
+                None => return,
+            };
+            let items = items.clone();
+            let path = format!("/api/trash/{}/{}", item.kind.as_path(), item.id);
+            wasm_bindgen_futures::spawn_local(async move {
+                match delete::<String>(path).await {
+                    Ok(data) => {
+                        notify_info(&data);
+                        onload("/api/trash".into(), items);
+                    }
+                    Err(e) => notify_error(&e.to_string()),
+                }
+            })
+        })
+    };
+
+    let msg = match &*purge_target {
+        Some(item) => format!(
+            "Are you sure you want to permanently delete {} '{}'? This can't be undone.",
+            item.kind.as_path(),
+            item.id
+        ),
+        None => String::new(),
+    };
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto max-w-5xl">
+                <TableCard
+                    title="Trash"
+                    headings={vec!["Type", "Item", "Description", "Deleted", ""]}
+                >
+                    { for rows }
+                </TableCard>
+                <p class={classes!(TEXT_GRAY, "text-sm", "my-3")}>
+                    { "Assignments deleted along with their user or key are restored or deleted with it." }
+                </p>
+                <Modal
+                    title="Delete Forever"
+                    {msg}
+                    confirm_action={purge_action}
+                    {show_modal}
+                />
+            </div>
+        </CheckAuth>
+    }
+}
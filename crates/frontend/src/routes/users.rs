@@ -5,12 +5,12 @@ use crate::components::form::*;
 use crate::components::modal::Modal;
 use crate::components::notifier::notify_error;
 use crate::components::table::{Cell, CellLink, Row, TableCard};
+use crate::services::auth::current_user;
 use crate::services::form_actions::{ondelete, onload, submit_form};
 use crate::services::requests::get;
-use crate::services::to_option;
-use crate::theme::FORM_SUBTITLE;
-use crate::types::Assignment;
-use crate::types::{SetPasswdPayload, User};
+use crate::services::{format_money, to_option};
+use crate::types::{Assignment, Charge, Group};
+use crate::types::{NotificationSettings, SetPasswdPayload, User};
 
 use yew::prelude::*;
 use yew_router::prelude::*;
@@ -88,10 +88,12 @@ pub struct UserProps {
 pub fn edit_user(props: &UserProps) -> Html {
     let id = use_state(|| 0);
     let username = props.username.clone();
+    let new_username = use_state(|| props.username.clone());
     let email = use_state(String::new);
     let display_name = use_state(String::new);
     let can_login = use_state(|| false);
     let admin = use_state(|| false);
+    let departed = use_state(|| false);
 
     let show_modal = use_state(|| false);
 
@@ -103,6 +105,7 @@ pub fn edit_user(props: &UserProps) -> Html {
         let display_name = display_name.clone();
         let can_login = can_login.clone();
         let admin = admin.clone();
+        let departed = departed.clone();
         let url = format!("/api/users/{}", &username);
         use_effect_with_deps(
             move |_| {
@@ -114,6 +117,7 @@ pub fn edit_user(props: &UserProps) -> Html {
                             display_name.set(u.display_name.unwrap_or_default());
                             can_login.set(u.can_login);
                             admin.set(u.admin);
+                            departed.set(u.departed);
                         }
                         Err(e) => notify_error(&e.to_string()),
                     }
@@ -127,11 +131,12 @@ pub fn edit_user(props: &UserProps) -> Html {
     let onsubmit = {
         let user = User {
             id: *id,
-            username: username.clone(),
+            username: (*new_username).clone(),
             email: to_option((*email).clone()),
             display_name: to_option((*display_name).clone()),
             can_login: *can_login,
             admin: *admin,
+            departed: *departed,
         };
         let history = use_history().unwrap();
         let path = format!("/api/users/{}", username);
@@ -148,9 +153,12 @@ pub fn edit_user(props: &UserProps) -> Html {
         <CheckAuth admin=true>
             <div class="container my-5 mx-auto">
                 <Form title="Edit User" {onsubmit} >
-                    <h6 class={FORM_SUBTITLE}>
-                        { format!("Username: {}", props.username.clone())}
-                    </h6>
+                    <TextField
+                        label="Username"
+                        required=true
+                        state={new_username}
+                        pattern=r#"[\w\d]{3,}"#
+                    />
                     <TextField
                         label="Email"
                         state={email}
@@ -161,6 +169,7 @@ pub fn edit_user(props: &UserProps) -> Html {
                         state={display_name}
                     />
                     <CheckboxField label="Can Login?" state={can_login} />
+                    <CheckboxField label="Departed" state={departed} />
                     <Button
                         value="Update User"
                         button_type={ButtonType::Primary}
@@ -177,7 +186,7 @@ pub fn edit_user(props: &UserProps) -> Html {
                     <RouteButton value="Cancel" route={Route::Users} />
                 <Modal
                     title="Delete User"
-                    msg="Are you sure you want to delete this user? All assignments for which this user is assigned will also be deleted."
+                    msg="Are you sure you want to delete this user? They will be moved to the trash along with their assignments."
                     confirm_action={delete_action}
                     {show_modal}
                 />
@@ -282,16 +291,91 @@ pub fn set_password(props: &UserProps) -> Html {
     }
 }
 
+#[function_component(Notifications)]
+pub fn notifications(props: &UserProps) -> Html {
+    let key_assigned = use_state(|| true);
+    let key_due = use_state(|| true);
+    let key_overdue = use_state(|| true);
+    let admin_summary = use_state(|| true);
+
+    {
+        let key_assigned = key_assigned.clone();
+        let key_due = key_due.clone();
+        let key_overdue = key_overdue.clone();
+        let admin_summary = admin_summary.clone();
+        let url = format!("/api/users/{}/notifications", props.username);
+        use_effect_with_deps(
+            move |_| {
+                wasm_bindgen_futures::spawn_local(async move {
+                    match get::<NotificationSettings>(url).await {
+                        Ok(n) => {
+                            key_assigned.set(n.key_assigned);
+                            key_due.set(n.key_due);
+                            key_overdue.set(n.key_overdue);
+                            admin_summary.set(n.admin_summary);
+                        }
+                        Err(e) => notify_error(&e.to_string()),
+                    }
+                });
+                || ()
+            },
+            (),
+        );
+    }
+
+    let onsubmit = {
+        let settings = NotificationSettings {
+            key_assigned: *key_assigned,
+            key_due: *key_due,
+            key_overdue: *key_overdue,
+            admin_summary: *admin_summary,
+        };
+        let history = use_history().unwrap();
+        let path = format!("/api/users/{}/notifications", props.username);
+        submit_form(path, settings, history, Route::Home)
+    };
+
+    html! {
+        <CheckAuth>
+            <div class="container my-5 mx-auto">
+                <Form title="Email Notifications" subtitle={props.username.clone()} {onsubmit}>
+                    <CheckboxField label="Key assigned to me" state={key_assigned} />
+                    <CheckboxField label="Key due back soon" state={key_due} />
+                    <CheckboxField label="Key overdue" state={key_overdue} />
+                    {
+                        if current_user().is_admin {
+                            html! { <CheckboxField label="Daily admin summary" state={admin_summary} /> }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    <Button value="Save" button_type={ButtonType::Primary} />
+                    {" "}
+                    <RouteButton value="Cancel" route={Route::Home} />
+                </Form>
+            </div>
+        </CheckAuth>
+    }
+}
+
 #[function_component(UserDetails)]
 pub fn user_details(props: &UserProps) -> Html {
     let user = use_state(User::default);
     let assignments = use_state(Vec::new);
+    let group_assignments = use_state(Vec::<Assignment>::new);
+    let groups = use_state(Vec::<Group>::new);
+    let charges = use_state(Vec::<Charge>::new);
 
     {
         let user = user.clone();
         let assignments = assignments.clone();
+        let group_assignments = group_assignments.clone();
+        let groups = groups.clone();
+        let charges = charges.clone();
         let user_url = format!("/api/users/{}", &props.username);
         let user_keys_url = format!("/api/assignments?user={}", &props.username);
+        let group_keys_url = format!("/api/assignments?member={}", &props.username);
+        let charges_url = format!("/api/charges?user={}", &props.username);
         use_effect_with_deps(
             move |_| {
                 wasm_bindgen_futures::spawn_local(async move {
@@ -308,6 +392,9 @@ pub fn user_details(props: &UserProps) -> Html {
                         Err(e) => notify_error(&e.to_string()),
                     };
                 });
+                onload(group_keys_url, group_assignments);
+                onload("/api/groups".into(), groups);
+                onload(charges_url, charges);
                 || ()
             },
             (),
@@ -315,6 +402,11 @@ pub fn user_details(props: &UserProps) -> Html {
     }
 
     let user = (*user).clone();
+    let user_groups = groups
+        .iter()
+        .filter(|g| g.members.contains(&props.username))
+        .map(|g| g.name.clone())
+        .collect::<Vec<String>>();
     html! {
         <CheckAuth>
             <DetailsCard
@@ -325,6 +417,8 @@ pub fn user_details(props: &UserProps) -> Html {
                     <DetailsHeaderItem content={format!("Email: {}", user.email.unwrap_or_else(|| "-".into()))} />
                     <DetailsHeaderItem content={format!("Can login: {}", user.can_login)} />
                     <DetailsHeaderItem content={format!("Admin: {}", user.admin)} />
+                    <DetailsHeaderItem content={format!("Departed: {}", user.departed)} />
+                    <DetailsHeaderItem content={format!("Groups: {}", if user_groups.is_empty() { "-".into() } else { user_groups.join(", ") })} />
                 </DetailsHeader>
                 <DetailsList label="Keys Assigned">
                     {
@@ -343,6 +437,33 @@ pub fn user_details(props: &UserProps) -> Html {
                             })
                     }
                 </DetailsList>
+                <DetailsList label="Group Keys">
+                    {
+                        for group_assignments.iter().filter(|a| a.date_in.is_none()).map(|a|
+                            html_nested!{
+                                <DetailsListItem
+                                    label={format!("{} (via {})", a.key, a.holder())}
+                                    route={Route::AssignmentDetails { id: a.id } }
+                                />
+                            })
+                    }
+                </DetailsList>
+                <DetailsList label="Charges">
+                    {
+                        for charges.iter().map(|c|
+                            html_nested!{
+                                <DetailsListItem
+                                    label={format!(
+                                        "{} {} ({})",
+                                        format_money(c.amount, &c.currency),
+                                        c.description.clone().unwrap_or_default(),
+                                        if c.paid { "Paid" } else { "Outstanding" }
+                                    )}
+                                    route={Route::EditCharge { id: c.id }}
+                                />
+                            })
+                    }
+                </DetailsList>
                 <DetailsFooter/>
             </DetailsCard>
         </CheckAuth>
@@ -0,0 +1,296 @@
+use std::vec::Vec;
+
+use crate::components::details_card::*;
+use crate::components::form::*;
+use crate::components::modal::Modal;
+use crate::components::notifier::{notify_error, notify_info};
+use crate::components::table::*;
+use crate::services::form_actions::{ondelete, onload, submit_form};
+use crate::services::format_timestamp;
+use crate::services::requests::{get, post};
+use crate::theme::*;
+use crate::types::{Webhook, WebhookDelivery, WEBHOOK_EVENTS};
+
+use yew::prelude::*;
+use yew_router::prelude::*;
+
+use super::auth::CheckAuth;
+use super::Route;
+
+fn format_events(events: &[String]) -> String {
+    if events.is_empty() {
+        "All events".into()
+    } else {
+        events.join(", ")
+    }
+}
+
+#[function_component(NewWebhook)]
+pub fn new_webhook() -> Html {
+    let url = use_state(String::new);
+    let events = use_state(Vec::<String>::new);
+    let secret = use_state(String::new);
+    let active = use_state(|| true);
+
+    let onsubmit = {
+        let webhook = Webhook {
+            url: (*url).clone(),
+            events: (*events).clone(),
+            secret: (*secret).clone(),
+            active: *active,
+            ..Default::default()
+        };
+        let history = use_history().unwrap();
+        submit_form(
+            "/api/webhooks".to_string(),
+            webhook,
+            history,
+            Route::Webhooks,
+        )
+    };
+
+    let event_options = WEBHOOK_EVENTS.iter().map(|e| {
+        html_nested! { <MultiSelectOption value={e.to_string()} /> }
+    });
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto">
+                <Form title="New Webhook" {onsubmit}>
+                    <TextField label="URL" required=true pattern="https?://.+" state={url} />
+                    <MultiSelectField label="Events (none for all events)" required=false state={events}>
+                        { for event_options }
+                    </MultiSelectField>
+                    <TextField label="Secret (leave blank to generate)" state={secret} />
+                    <CheckboxField label="Active" state={active} />
+                    <Button value="Add Webhook" button_type={ButtonType::Primary} />
+                    {" "}
+                    <RouteButton value="Cancel" route={Route::Webhooks} />
+                </Form>
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[derive(PartialEq, Eq, Properties)]
+pub struct WebhookProps {
+    pub id: i64,
+}
+
+#[function_component(EditWebhook)]
+pub fn edit_webhook(props: &WebhookProps) -> Html {
+    let url = use_state(String::new);
+    let events = use_state(Vec::<String>::new);
+    let secret = use_state(String::new);
+    let active = use_state(|| true);
+
+    let show_modal = use_state(|| false);
+
+    {
+        let url = url.clone();
+        let events = events.clone();
+        let active = active.clone();
+        let path = format!("/api/webhooks/{}", props.id);
+        use_effect_with_deps(
+            move |_| {
+                wasm_bindgen_futures::spawn_local(async move {
+                    match get::<Webhook>(path).await {
+                        Ok(w) => {
+                            url.set(w.url);
+                            events.set(w.events);
+                            active.set(w.active);
+                        }
+                        Err(e) => notify_error(&e.to_string()),
+                    }
+                });
+                || ()
+            },
+            (),
+        );
+    }
+
+    let onsubmit = {
+        let webhook = Webhook {
+            url: (*url).clone(),
+            events: (*events).clone(),
+            secret: (*secret).clone(),
+            active: *active,
+            ..Default::default()
+        };
+        let history = use_history().unwrap();
+        let path = format!("/api/webhooks/{}", props.id);
+        submit_form(
+            path,
+            webhook,
+            history,
+            Route::WebhookDetails { id: props.id },
+        )
+    };
+
+    let delete_action = {
+        let history = use_history().unwrap();
+        let path = format!("/api/webhooks/{}", props.id);
+        ondelete(path, history, Route::Webhooks)
+    };
+
+    let event_options = WEBHOOK_EVENTS.iter().map(|e| {
+        html_nested! {
+            <MultiSelectOption value={e.to_string()} selected={events.contains(&e.to_string())} />
+        }
+    });
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto">
+                <Form title="Edit Webhook" {onsubmit}>
+                    <TextField label="URL" required=true pattern="https?://.+" state={url} />
+                    <MultiSelectField label="Events (none for all events)" required=false state={events.clone()}>
+                        { for event_options }
+                    </MultiSelectField>
+                    <TextField label="Secret (leave blank to keep the current one)" state={secret} />
+                    <CheckboxField label="Active" state={active} />
+                    <Button value="Update Webhook" button_type={ButtonType::Primary} />
+                    {" "}
+                    <DeleteButton
+                        value="Delete Webhook"
+                        route={Route::Webhooks}
+                        show_modal={show_modal.clone()}
+                    />
+                    {" "}
+                    <RouteButton value="Cancel" route={Route::WebhookDetails { id: props.id }} />
+                </Form>
+                <Modal
+                    title="Delete Webhook"
+                    msg="Are you sure you want to delete this webhook? Its delivery log will be deleted too."
+                    confirm_action={delete_action}
+                    {show_modal}
+                />
+            </div>
+        </CheckAuth>
+    }
+}
+
+#[function_component(WebhookTable)]
+pub fn webhook_table() -> Html {
+    let webhooks = use_state(Vec::<Webhook>::new);
+
+    {
+        let webhooks = webhooks.clone();
+        use_effect_with_deps(
+            move |_| {
+                onload("/api/webhooks".into(), webhooks);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let rows = webhooks.iter().map(|w| {
+        html_nested! {
+            <Row>
+                <CellLink value={w.url.clone()} route={Route::WebhookDetails { id: w.id }} />
+                <Cell value={format_events(&w.events)} />
+                <Cell value={if w.active { "Yes" } else { "No" }} />
+            </Row>
+        }
+    });
+
+    html! {
+        <CheckAuth admin=true>
+            <div class="container my-5 mx-auto max-w-4xl">
+                <TableCard
+                    title="Webhooks"
+                    headings={vec!["URL", "Events", "Active"]}
+                    button_label="Add Webhook"
+                    button_route={Route::AddWebhook}
+                >
+                    { for rows }
+                </TableCard>
+            </div>
+        </CheckAuth>
+    }
+}
+
+/// A webhook's settings along with its delivery log. Any delivery can be sent again.
+#[function_component(WebhookDetails)]
+pub fn webhook_details(props: &WebhookProps) -> Html {
+    let webhook = use_state(Webhook::default);
+    let deliveries = use_state(Vec::<WebhookDelivery>::new);
+    let deliveries_url = format!("/api/webhooks/{}/deliveries", props.id);
+
+    {
+        let webhook = webhook.clone();
+        let deliveries = deliveries.clone();
+        let webhook_url = format!("/api/webhooks/{}", props.id);
+        let deliveries_url = deliveries_url.clone();
+        use_effect_with_deps(
+            move |_| {
+                onload(webhook_url, webhook);
+                onload(deliveries_url, deliveries);
+                || ()
+            },
+            (),
+        );
+    }
+
+    let rows = deliveries.iter().map(|d| {
+        let onresend = {
+            let deliveries = deliveries.clone();
+            let deliveries_url = deliveries_url.clone();
+            let path = format!("/api/webhook-deliveries/{}/resend", d.id);
+            Callback::from(move |_: MouseEvent| {
+                let deliveries = deliveries.clone();
+                let deliveries_url = deliveries_url.clone();
+                let path = path.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    match post::<(), String>(path, ()).await {
+                        Ok(data) => {
+                            notify_info(&data);
+                            onload(deliveries_url, deliveries);
+                        }
+                        Err(e) => notify_error(&e.to_string()),
+                    }
+                })
+            })
+        };
+        let status = match (d.delivered_at, &d.last_error) {
+            (Some(t), _) => format!("Delivered {}", format_timestamp(t)),
+            (None, Some(e)) => format!("Failed: {}", e),
+            (None, None) => "Pending".into(),
+        };
+        html_nested! {
+            <Row>
+                <Cell value={format_timestamp(d.created_at)} />
+                <Cell value={d.event.clone()} />
+                <Cell value={d.attempts.to_string()} />
+                <Cell value={d.status_code.map(|c| c.to_string()).unwrap_or_else(|| "-".into())} />
+                <Cell value={status} />
+                <td class="py-4 px-6">
+                    <button class={classes!(BTN, BTN_PRIMARY)} onclick={onresend}>
+                        {"Resend"}
+                    </button>
+                </td>
+            </Row>
+        }
+    });
+
+    let webhook = (*webhook).clone();
+    html! {
+        <CheckAuth admin=true>
+            <DetailsCard
+                title={format!("Webhook {}", webhook.id)}
+                edit_route={Route::EditWebhook { id: props.id }}
+            >
+                <DetailsHeader>
+                    <DetailsHeaderItem content={format!("URL: {}", webhook.url)} />
+                    <DetailsHeaderItem content={format!("Events: {}", format_events(&webhook.events))} />
+                    <DetailsHeaderItem content={format!("Active: {}", webhook.active)} />
+                </DetailsHeader>
+                <Table headings={vec!["Created", "Event", "Attempts", "Status Code", "Result", ""]}>
+                    { for rows }
+                </Table>
+                <DetailsFooter/>
+            </DetailsCard>
+        </CheckAuth>
+    }
+}
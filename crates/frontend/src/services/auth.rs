@@ -15,6 +15,8 @@ pub fn set_session_info(ui: SessionInfo) {
         s.username = ui.username;
         s.is_auth = ui.is_auth;
         s.is_admin = ui.is_admin;
+        s.org_id = ui.org_id;
+        s.orgs = ui.orgs;
         s.fetched = true;
     });
 }
@@ -25,6 +27,8 @@ pub async fn clear_session_info() {
         s.username = None;
         s.is_auth = false;
         s.is_admin = false;
+        s.org_id = None;
+        s.orgs = Vec::new();
         s.fetched = false;
     });
 }
@@ -72,7 +72,7 @@ where
 pub fn get_options(users: UseStateHandle<Vec<String>>, keys: UseStateHandle<Vec<String>>) {
     wasm_bindgen_futures::spawn_local(async move {
         match get::<Vec<User>>("/api/users".into()).await {
-            Ok(u) => users.set(make_list(u)),
+            Ok(u) => users.set(make_list(u.into_iter().filter(|u| !u.departed).collect())),
             Err(e) => notify_error(&e.to_string()),
         };
         match get::<Vec<Key>>("/api/keys?active=true".into()).await {
@@ -2,11 +2,14 @@ pub mod auth;
 pub mod form_actions;
 pub mod requests;
 
-use chrono::NaiveDate;
+use chrono::{DateTime, Local, NaiveDate, Utc};
 
-use crate::types::User;
+use crate::types::{Assignment, Slot, User};
 
 static DATE_FMT: &str = "%Y-%m-%d";
+static TIMESTAMP_FMT: &str = "%Y-%m-%d %H:%M";
+pub static MONEY_PATTERN: &str = "[0-9]*(\\.[0-9]{1,2})?";
+pub static CURRENCY_PATTERN: &str = "[A-Za-z]{3}";
 
 pub fn parse_date(date_string: String) -> NaiveDate {
     NaiveDate::parse_from_str(&date_string, DATE_FMT).unwrap_or_default()
@@ -16,6 +19,14 @@ pub fn format_date(date: NaiveDate) -> String {
     date.format(DATE_FMT).to_string()
 }
 
+/// Formats a timestamp in the browser's local time.
+pub fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp
+        .with_timezone(&Local)
+        .format(TIMESTAMP_FMT)
+        .to_string()
+}
+
 /// Returns the given user's display name. If it isn't found, it will return the user's username.
 pub fn get_display_name(users: &[User], username: String) -> String {
     users
@@ -26,6 +37,14 @@ pub fn get_display_name(users: &[User], username: String) -> String {
         .unwrap_or(username)
 }
 
+/// Returns the display name of the user holding the assignment, or the name of its group.
+pub fn holder_name(users: &[User], assignment: &Assignment) -> String {
+    match &assignment.user {
+        Some(u) => get_display_name(users, u.clone()),
+        None => format!("{} (group)", assignment.holder()),
+    }
+}
+
 /// Converts the given string to an option. Returns None if the string is empty.
 pub fn to_option(s: String) -> Option<String> {
     match s.is_empty() {
@@ -33,3 +52,42 @@ pub fn to_option(s: String) -> Option<String> {
         false => Some(s),
     }
 }
+
+/// Returns a human readable description of where the slot is.
+pub fn slot_label(slot: &Slot) -> String {
+    format!("Cabinet {}, hook {}", slot.cabinet, slot.number)
+}
+
+/// Formats an amount in minor units, e.g. `1250` and `"USD"` becomes `12.50 USD`.
+pub fn format_money(amount: i64, currency: &str) -> String {
+    let sign = if amount < 0 { "-" } else { "" };
+    let amount = amount.abs();
+    format!("{}{}.{:02} {}", sign, amount / 100, amount % 100, currency)
+}
+
+/// Formats an amount in minor units for a form field, e.g. `1250` becomes `12.50`.
+pub fn money_field(amount: Option<i64>) -> String {
+    amount
+        .map(|a| format!("{}.{:02}", a / 100, a % 100))
+        .unwrap_or_default()
+}
+
+/// Parses an amount such as `12.5` into minor units. Returns None if the string is empty or
+/// isn't a valid amount.
+pub fn parse_money(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if whole.is_empty() && fraction.is_empty() || fraction.len() > 2 {
+        return None;
+    }
+    let whole: i64 = match whole {
+        "" => 0,
+        w => w.parse().ok()?,
+    };
+    let fraction: i64 = match fraction.len() {
+        0 => 0,
+        1 => fraction.parse::<i64>().ok()? * 10,
+        _ => fraction.parse().ok()?,
+    };
+    Some(whole * 100 + fraction)
+}
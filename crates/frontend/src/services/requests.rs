@@ -1,7 +1,20 @@
 use crate::error::Error;
 use gloo_net::http::{Method, Request};
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+/// One of the reasons the backend lists when it refuses a request.
+#[derive(Deserialize)]
+struct Problem {
+    message: String,
+}
+
+/// Joins the messages of a list of problems, if that's what the response body is.
+fn problems(body: &str) -> Option<String> {
+    let problems: Vec<Problem> = serde_json::from_str(body).ok()?;
+    let messages: Vec<String> = problems.into_iter().map(|p| p.message).collect();
+    Some(messages.join(". "))
+}
 
 pub async fn request<T, U>(method: Method, url: String, body: T) -> Result<U, Error>
 where
@@ -27,7 +40,7 @@ where
             } else {
                 let resp_text = data.text().await.unwrap_or_else(|_| "unknown error".into());
                 Err(match data.status() {
-                    400 => Error::BadRequest(resp_text),
+                    400 => Error::BadRequest(problems(&resp_text).unwrap_or(resp_text)),
                     401 => Error::Unauthorized,
                     404 => Error::NotFound(resp_text),
                     500 => Error::InternalServerError(resp_text),
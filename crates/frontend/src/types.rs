@@ -1,4 +1,4 @@
-use chrono::NaiveDate;
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use std::string::String;
 use yewdux::store::Store;
@@ -12,6 +12,10 @@ pub struct Key {
     pub name: String,
     pub description: Option<String>,
     pub active: bool,
+    pub slot_id: Option<i64>,
+    pub deposit_amount: Option<i64>,
+    pub deposit_currency: Option<String>,
+    pub restricted: bool,
 }
 
 impl PrimaryKey for Key {
@@ -28,6 +32,7 @@ pub struct User {
     pub email: Option<String>,
     pub can_login: bool,
     pub admin: bool,
+    pub departed: bool,
 }
 
 impl PrimaryKey for User {
@@ -39,10 +44,180 @@ impl PrimaryKey for User {
 #[derive(PartialEq, Eq, Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Assignment {
     pub id: i64,
-    pub user: String,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub group: Option<String>,
     pub key: String,
     pub date_out: NaiveDate,
     pub date_in: Option<NaiveDate>,
+    pub date_due: Option<NaiveDate>,
+    pub deposit_collected: Option<i64>,
+    pub deposit_refunded: Option<i64>,
+    pub deposit_currency: Option<String>,
+    pub pending: bool,
+    pub created_by: Option<String>,
+    pub confirmed_by: Option<String>,
+    #[serde(default)]
+    pub lost: bool,
+}
+
+impl Assignment {
+    /// The user or group holding the key.
+    pub fn holder(&self) -> String {
+        self.user
+            .clone()
+            .or_else(|| self.group.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct AssignmentUpdate {
+    #[serde(flatten)]
+    pub assignment: Assignment,
+    pub slot_id: Option<i64>,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Cabinet {
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct CabinetPayload {
+    pub name: String,
+    pub description: Option<String>,
+    pub slots: Option<i32>,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Group {
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    pub members: Vec<String>,
+}
+
+impl PrimaryKey for Group {
+    fn primary_key(&self) -> String {
+        self.name.clone()
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct GroupPayload {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct MemberPayload {
+    pub username: String,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Slot {
+    pub id: i64,
+    pub cabinet_id: i64,
+    pub cabinet: String,
+    pub number: i32,
+    pub key: Option<String>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KeyLocation {
+    User {
+        username: String,
+    },
+    Group {
+        name: String,
+    },
+    Slot {
+        cabinet_id: i64,
+        cabinet: String,
+        number: i32,
+    },
+    Unknown,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Charge {
+    pub id: i64,
+    pub user: String,
+    pub key: Option<String>,
+    pub amount: i64,
+    pub currency: String,
+    pub date: NaiveDate,
+    pub description: Option<String>,
+    pub paid: bool,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, Deserialize)]
+pub struct Balance {
+    pub user: String,
+    pub currency: String,
+    pub deposits_held: i64,
+    pub charges_outstanding: i64,
+    pub charges_paid: i64,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, Deserialize)]
+pub struct GroupBalance {
+    pub group: String,
+    pub currency: String,
+    pub deposits_held: i64,
+    pub charges_outstanding: i64,
+    pub charges_paid: i64,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestStatus {
+    #[default]
+    Pending,
+    Approved,
+    Denied,
+    Fulfilled,
+}
+
+impl std::fmt::Display for RequestStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            RequestStatus::Pending => "Pending",
+            RequestStatus::Approved => "Approved",
+            RequestStatus::Denied => "Denied",
+            RequestStatus::Fulfilled => "Fulfilled",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRequest {
+    pub id: i64,
+    pub user: String,
+    pub keys: Vec<String>,
+    pub justification: String,
+    pub date_from: NaiveDate,
+    pub date_to: Option<NaiveDate>,
+    pub approver: Option<String>,
+    pub status: RequestStatus,
+    pub decided_by: Option<String>,
+    pub decision_note: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct DecisionPayload {
+    pub note: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct FulfillPayload {
+    pub date_out: NaiveDate,
 }
 
 #[derive(Serialize, Clone)]
@@ -56,10 +231,21 @@ pub struct SessionInfo {
     pub username: Option<String>,
     pub is_auth: bool,
     pub is_admin: bool,
+    #[serde(default)]
+    pub org_id: Option<i64>,
+    #[serde(default)]
+    pub orgs: Vec<Membership>,
     #[serde(skip)]
     pub fetched: bool,
 }
 
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq, Default)]
+pub struct Membership {
+    pub org_id: i64,
+    pub org: String,
+    pub admin: bool,
+}
+
 #[derive(Clone, Default, PartialEq, Eq, Store)]
 pub struct Notification {
     pub msg: Option<String>,
@@ -70,3 +256,168 @@ pub struct Notification {
 pub struct SetPasswdPayload {
     pub new_password: String,
 }
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationSettings {
+    pub key_assigned: bool,
+    pub key_due: bool,
+    pub key_overdue: bool,
+    pub admin_summary: bool,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct JobStatus {
+    pub name: String,
+    pub schedule: String,
+    pub next_run: DateTime<Utc>,
+    pub last_started: Option<DateTime<Utc>>,
+    pub last_finished: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+}
+
+/// The events a webhook can subscribe to.
+pub static WEBHOOK_EVENTS: [&str; 11] = [
+    "key.created",
+    "key.updated",
+    "key.deleted",
+    "user.created",
+    "user.updated",
+    "user.deleted",
+    "assignment.created",
+    "assignment.updated",
+    "assignment.deleted",
+    "assignment.returned",
+    "assignment.lost",
+];
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    pub events: Vec<String>,
+    #[serde(default)]
+    pub secret: String,
+    pub active: bool,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub next_attempt: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditResult {
+    Verified,
+    Missing,
+    WrongHolder,
+}
+
+impl std::fmt::Display for AuditResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditResult::Verified => write!(f, "Verified"),
+            AuditResult::Missing => write!(f, "Missing"),
+            AuditResult::WrongHolder => write!(f, "Wrong holder"),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Audit {
+    pub id: i64,
+    pub name: String,
+    pub started_at: Option<DateTime<Utc>>,
+    pub started_by: Option<String>,
+    pub closed_at: Option<DateTime<Utc>>,
+    pub closed_by: Option<String>,
+    pub items: i64,
+    pub unchecked: i64,
+    pub discrepancies: i64,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct AuditItem {
+    pub id: i64,
+    pub audit_id: i64,
+    pub assignment_id: Option<i64>,
+    pub user: String,
+    pub key: String,
+    pub result: Option<AuditResult>,
+    pub actual_holder: Option<String>,
+    pub note: Option<String>,
+    pub checked_by: Option<String>,
+    pub checked_at: Option<DateTime<Utc>>,
+    pub lost_reported: bool,
+}
+
+#[derive(Clone, Serialize)]
+pub struct AuditCheck {
+    pub result: AuditResult,
+    pub actual_holder: Option<String>,
+    pub note: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct AuditClose {
+    pub report_missing: bool,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrashKind {
+    Key,
+    User,
+    Assignment,
+}
+
+impl TrashKind {
+    /// The kind as it appears in `/api/trash` paths.
+    pub fn as_path(&self) -> &'static str {
+        match self {
+            TrashKind::Key => "key",
+            TrashKind::User => "user",
+            TrashKind::Assignment => "assignment",
+        }
+    }
+}
+
+impl std::fmt::Display for TrashKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrashKind::Key => write!(f, "Key"),
+            TrashKind::User => write!(f, "User"),
+            TrashKind::Assignment => write!(f, "Assignment"),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct TrashItem {
+    pub kind: TrashKind,
+    pub id: String,
+    pub description: String,
+    pub deleted_at: DateTime<Utc>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct AssignmentChange {
+    pub id: i64,
+    pub assignment_id: i64,
+    pub changed_at: DateTime<Utc>,
+    pub changed_by: Option<String>,
+    pub action: String,
+    pub field: Option<String>,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}